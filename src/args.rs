@@ -0,0 +1,1188 @@
+//! The command line: the options of syncing and of each subcommand, and how they are read from
+//! the config file and the environment as well.
+
+use std::{ffi::OsString, fs, io::{self, ErrorKind}, path::{Component, Path, PathBuf}, str::FromStr, time::{Duration, SystemTime, UNIX_EPOCH}};
+use log::*;
+use structopt::{clap, StructOpt};
+use m3trans::{filter::Filter, PlaylistKind, format::{DurationStyle, Encoding, EntryComments, Format, LineEndings, M3uExtension, PlayerHints, TitleFormat}, names::{Normalization, Sanitize}, plan::{EntryOrder, EntryPaths, OnLongPath, PathSeparator, PathTemplate, TrackNames}};
+
+use crate::context;
+use crate::discover;
+use crate::zip;
+
+const TRACKS_DIR: &str = "tracks";
+
+const PLAYLISTS_DIR: &str = "playlists";
+
+#[derive(StructOpt, Clone)]
+#[structopt(after_help = "Run `m3trans diff OLD NEW` to compare two exports of a library, `m3trans list LIBRARY` \
+to print its playlists, `m3trans export LIBRARY` to print all of it as JSON, `m3trans verify LIBRARY` to check \
+the files of its tracks, `m3trans stats LIBRARY` to print totals over it, or `m3trans verify-output DIR` to \
+check an output against the checksums written with --checksums, instead. `m3trans repair LIBRARY OUTPUT` syncs \
+like m3trans itself, but first moves the copies of tracks already in the output to where they go now, as after \
+changing --names, rather than copying them again. `m3trans self-test` syncs a small made-up library into a \
+temporary folder and checks what it writes, to tell problems with this machine from problems with a library.")]
+pub(crate) struct Args {
+    // Not required by clap itself, as they may come from the config file instead.
+    /// The exported library, as XML or a binary property list, or - to read it from standard input.
+    #[structopt(parse(from_os_str), env = "M3TRANS_LIBRARY", default_value = "", hide_default_value = true)]
+    pub(crate) library_file: PathBuf,
+    /// Folders to sync to, each getting all of the tracks and playlists. The library is read and
+    /// the tracks picked out only once for them all. [env: M3TRANS_OUTPUT]
+    // Its environment variable is read by `merge_config`, as clap would add it to the folders given.
+    #[structopt(name = "output-path", parse(from_os_str), default_value = "", hide_default_value = true)]
+    pub(crate) output_paths: Vec<PathBuf>,
+    /// The one of `output_paths` being synced to.
+    #[structopt(skip)]
+    pub(crate) output_path: PathBuf,
+    /// Use the library iTunes or Music.app keeps in the home folder, as when no library file is
+    /// given at all. Every path given is then an output path.
+    #[structopt(long = "auto-library")]
+    pub(crate) auto_library: bool,
+    /// TOML file of default options, with keys named like the long options, e.g.
+    /// `output-path = "/media/player"`. Defaults to m3trans.toml in the current directory, if any.
+    #[structopt(long, parse(from_os_str))]
+    pub(crate) config: Option<PathBuf>,
+    /// Where to write the log, by default m3trans.log in the output directory except in a dry run.
+    #[structopt(short, long = "log-file", parse(from_os_str), env = "M3TRANS_LOG_FILE")]
+    pub(crate) log_file: Option<PathBuf>,
+    /// Don't write a log file.
+    #[structopt(long = "no-log-file", conflicts_with = "log-file")]
+    pub(crate) no_log_file: bool,
+    /// Don't lock the output folders (or archive) against other runs syncing into them at the same
+    /// time, as done with a .m3trans.lock file there.
+    #[structopt(long = "no-lock")]
+    pub(crate) no_lock: bool,
+    /// Take over a lock left by a run that started more than this many hours ago, even if its
+    /// process still seems to be running. Locks of processes that have exited are always taken over.
+    #[structopt(long = "lock-max-age", default_value = "24")]
+    pub(crate) lock_max_age: u64,
+    /// Add to the end of the log file instead of replacing it.
+    #[structopt(long = "log-append", conflicts_with = "log-keep")]
+    pub(crate) log_append: bool,
+    /// Keep the logs of this many earlier runs, renaming the log file to m3trans.log.1 and older
+    /// ones to .2 and so on before starting, and deleting the oldest.
+    #[structopt(long = "log-keep")]
+    pub(crate) log_keep: Option<usize>,
+    /// The most detailed messages to write to the log file: error, warn, info, debug or trace.
+    #[structopt(long = "log-level", default_value = "info")]
+    pub(crate) log_level: LevelFilter,
+    /// How to write the log file: text, or json for one object per line.
+    ///
+    /// Each JSON line has a timestamp, level, message and event, along with fields depending on the
+    /// event. The events, which are kept stable, are run_started, track_copied, track_copy_planned,
+    /// track_copy_failed, track_linked, track_skipped (with a reason), tracks_copied, playlist_write_planned,
+    /// playlist_write_failed, folder_create_failed, entry_not_encodable, index_write_failed,
+    /// stale_removed, stale_remove_failed, no_duration, destination_collisions and interrupted. Other
+    /// lines have the event "message".
+    #[structopt(long = "log-format", default_value = "text", possible_values = &["text", "json"])]
+    pub(crate) log_format: OutputFormat,
+    /// Show info messages on the terminal as well, or debug messages too when given twice.
+    #[structopt(short, long, parse(from_occurrences), conflicts_with = "quiet")]
+    pub(crate) verbose: u8,
+    /// Log every warning as a warning. Otherwise past the first few of a kind, such as tracks
+    /// with unknown locations, the rest are only written to the log file, and counted at the end.
+    #[structopt(long = "verbose-warnings")]
+    pub(crate) verbose_warnings: bool,
+    /// Print what was done, or with --dry-run what would be done, to standard output: text prints
+    /// nothing beyond the log, json prints an array of actions.
+    #[structopt(long = "output-format", default_value = "text", possible_values = &["text", "json"])]
+    pub(crate) output_format: OutputFormat,
+    /// Write a spreadsheet of what happened to each track to this file, and of each playlist file
+    /// to one named after it with -playlists added. They are tab-separated if it ends in .tsv, or
+    /// comma-separated otherwise.
+    #[structopt(long, parse(from_os_str))]
+    pub(crate) report: Option<PathBuf>,
+    /// Compare what this dry run would do with the --report of an earlier sync, listing the tracks
+    /// synced now that weren't then, those since removed from the library, the playlists whose
+    /// entries changed and the files the report says were in the output that no longer are. With
+    /// --output-format json, standard output is an object with the "actions" and this "drift".
+    #[structopt(long = "compare-report", parse(from_os_str), requires = "dry-run")]
+    pub(crate) compare_report: Option<PathBuf>,
+    /// Only show errors on the terminal.
+    #[structopt(short, long)]
+    pub(crate) quiet: bool,
+    /// Print what would be done without changing anything. Implies --preflight.
+    #[structopt(short, long = "dry-run")]
+    pub(crate) dry_run: bool,
+    /// Check that the file of every track is there and not empty before copying any, and leave
+    /// out those that aren't, listing them together.
+    #[structopt(long = "preflight")]
+    pub(crate) preflight: bool,
+    /// Go ahead with deleting files, or writing into a folder that isn't an earlier output,
+    /// without asking first.
+    #[structopt(short, long)]
+    pub(crate) yes: bool,
+    /// File of rules for playlists to leave out, defaulting to .m3ignore at the root of each
+    /// output folder if it has one, and otherwise next to the library.
+    #[structopt(long = "ignore-file", parse(from_os_str), env = "M3TRANS_IGNORE_FILE")]
+    pub(crate) ignore_file: Option<PathBuf>,
+    /// TOML file of names to give playlists and folders on disk instead of their own, by their
+    /// paths in the library, e.g. `"Chill/Evening Wind-Down" = "Evening"`. Titles in playlists and
+    /// matching ignore patterns still use their own names.
+    #[structopt(long = "rename-file", parse(from_os_str))]
+    pub(crate) rename_file: Option<PathBuf>,
+    /// What `rename_file` holds, by normalized path.
+    #[structopt(skip)]
+    pub(crate) renames: Vec<(String, String)>,
+    /// Whether this is `m3trans repair`.
+    #[structopt(skip)]
+    pub(crate) repair: bool,
+    /// The arguments as merged from the command line and the config file, for the fingerprint of
+    /// the sync.
+    #[structopt(skip)]
+    pub(crate) given_arguments: Vec<OsString>,
+    /// Sync even when the library, the ignore, include and rename files and the options are all
+    /// the same as for the last sync into an output that succeeded, which is otherwise skipped.
+    /// Options that only change what is shown or logged, like --verbose or --log-file, don't count.
+    #[structopt(long = "force")]
+    pub(crate) force: bool,
+    /// Also leave out playlists matching this rule, written like a line of the ignore file and
+    /// applied after its rules. May be given more than once.
+    #[structopt(name = "ignore", long = "ignore", number_of_values = 1)]
+    pub(crate) ignores: Vec<String>,
+    /// Match ignore rules, include patterns and --playlist patterns regardless of case, so that
+    /// `workouts/*` also matches `Workouts/Run`. Only ASCII letters are compared this way.
+    #[structopt(long = "ignore-case")]
+    pub(crate) ignore_case: bool,
+    /// File of patterns selecting the only playlists to export, defaulting to .m3include at the
+    /// root of each output folder if it has one, and otherwise next to the library. Ignore
+    /// patterns still apply on top of it.
+    #[structopt(long = "include-file", parse(from_os_str))]
+    pub(crate) include_file: Option<PathBuf>,
+    /// Only export the playlists matching this pattern, matched like include patterns, and only
+    /// copy the tracks in them. May be given more than once; replaces the include file.
+    #[structopt(name = "playlist", long = "playlist", number_of_values = 1)]
+    pub(crate) playlists: Vec<String>,
+    /// Only sync what is in this top-level folder, playlists and tracks alike, with its playlists
+    /// and folders written at the top level. May be given more than once. Ignore and include
+    /// patterns still match the full paths, folder included.
+    #[structopt(name = "root", long = "root", number_of_values = 1, conflicts_with = "export-master")]
+    pub(crate) roots: Vec<String>,
+    /// Only copy the tracks in the playlists that are exported, leaving out those only in ignored
+    /// or excluded ones, or in none at all. This is always the case with --playlist.
+    #[structopt(long = "only-referenced")]
+    pub(crate) only_referenced: bool,
+    /// Never overwrite, move or delete anything in the output directory: existing files are kept,
+    /// playlists that changed are written under a new versioned name, such as "Mix (2).m3u8", and
+    /// the log is added to. Can't be used with the options that replace or remove files, such as
+    /// --prune-playlists or --mode folders.
+    #[structopt(long = "append-only")]
+    pub(crate) append_only: bool,
+    /// Remove playlist files and folders left in the playlists directory by earlier runs that
+    /// weren't written this time.
+    #[structopt(long = "prune-playlists")]
+    pub(crate) prune_playlists: bool,
+    /// Write every playlist directly into the playlists directory, encoding its folder path in
+    /// the file name instead of creating subdirectories.
+    #[structopt(long = "flat-playlists")]
+    pub(crate) flat_playlists: bool,
+    /// Mirror at most this many levels of playlist folders as directories. Playlists in deeper
+    /// folders are written to the deepest directory, with the remaining folder names in their
+    /// file names.
+    #[structopt(long = "max-depth", conflicts_with = "flat-playlists")]
+    pub(crate) max_depth: Option<usize>,
+    /// Don't write playlists without any copied tracks, nor folders left without playlists.
+    #[structopt(long = "skip-empty")]
+    pub(crate) skip_empty: bool,
+    /// Start M3U playlists with a UTF-8 byte order mark.
+    #[structopt(long = "m3u-bom")]
+    pub(crate) m3u_bom: bool,
+    /// Write plain M3U playlists containing only paths, without #EXTM3U and #EXTINF lines.
+    #[structopt(long = "no-extinf")]
+    pub(crate) no_extinf: bool,
+    /// Note in each playlist that this version of m3trans generated it.
+    #[structopt(long)]
+    pub(crate) stamp: bool,
+    /// Also note when in the --stamp note. This changes every playlist on every run, so none are
+    /// ever left unchanged.
+    #[structopt(long = "stamp-time", requires = "stamp")]
+    pub(crate) stamp_time: bool,
+    /// How lines of playlists end: lf, or crlf for players that need Windows line endings.
+    #[structopt(long = "line-endings", default_value = "lf", possible_values = &["lf", "crlf"])]
+    pub(crate) line_endings: LineEndings,
+    /// Write an index.html at the root of the output, listing the playlists as a tree with links to
+    /// their files, to browse the output in a web browser.
+    #[structopt(long = "html-index")]
+    pub(crate) html_index: bool,
+    /// Also write a page listing the tracks of each playlist next to its files, linked from
+    /// index.html. There are none with --mode folders.
+    #[structopt(long = "html-pages", requires = "html-index")]
+    pub(crate) html_pages: bool,
+    /// Percent-encode the paths of M3U entries, e.g. writing spaces as %20, for players that take
+    /// them as URIs. Only the entries change, not the names of the files.
+    #[structopt(long = "encode-entries")]
+    pub(crate) encode_entries: bool,
+    /// Leave out the #EXTINF line of tracks whose duration is unknown, instead of giving -1.
+    #[structopt(long = "omit-unknown-extinf", conflicts_with = "no-extinf")]
+    pub(crate) omit_unknown_extinf: bool,
+    /// How to write the durations of entries in M3U and PLS playlists: integer in whole seconds,
+    /// fractional to a tenth of a second, or none to give -1 for all of them, for players that
+    /// can't take durations. XSPF playlists give milliseconds, or nothing with none.
+    #[structopt(long = "duration-style", default_value = "integer", possible_values = &["integer", "fractional", "none"])]
+    pub(crate) duration_style: DurationStyle,
+    /// Write a comment before each entry of M3U playlists for tools to tell which library track it
+    /// is: with id, #M3TRANS:track= and its persistent ID, or #M3TRANS:track_id= and its track ID
+    /// for tracks without one. Players ignore them.
+    #[structopt(long = "entry-comments", possible_values = &["id"])]
+    pub(crate) entry_comments: Option<EntryComments>,
+    /// Leave M3U playlists that only differ in their entry comments as they are, rather than
+    /// writing them again.
+    #[structopt(long = "ignore-entry-comments", requires = "entry-comments")]
+    pub(crate) ignore_entry_comments: bool,
+    /// Write options for a player in M3U playlists: with vlc, #EXTVLCOPT lines so that tracks set
+    /// to start or stop partway through their file only play that part of it. Other players play
+    /// the whole file, though #EXTINF always gives the length of the part.
+    #[structopt(long = "player-hints", possible_values = &["vlc"])]
+    pub(crate) player_hints: Option<PlayerHints>,
+    /// Encoding of M3U and PLS playlists. Anything but UTF-8 writes .m3u instead of .m3u8, unless
+    /// --playlist-ext says otherwise.
+    #[structopt(
+        long = "playlist-encoding",
+        default_value = "utf8",
+        possible_values = &["utf8", "latin1", "windows-1252"],
+    )]
+    pub(crate) playlist_encoding: Encoding,
+    /// Extension of M3U playlists, whatever their encoding: m3u or m3u8.
+    #[structopt(long = "playlist-ext", possible_values = &["m3u", "m3u8"])]
+    pub(crate) playlist_ext: Option<M3uExtension>,
+    /// Written in place of characters the playlist encoding can't represent.
+    #[structopt(long = "encoding-substitute", default_value = "?")]
+    pub(crate) encoding_substitute: String,
+    /// Also write a playlist for every folder combining the tracks of all playlists inside it, each
+    /// once, where it first appears.
+    #[structopt(long = "folder-playlists")]
+    pub(crate) folder_playlists: bool,
+    /// Leave out the entries of a playlist for tracks that appear in it earlier. Otherwise a track
+    /// a playlist has more than once is written as many times, as the library has it.
+    #[structopt(long = "dedupe-entries")]
+    pub(crate) dedupe_entries: bool,
+    /// Playlist format to write; may be given more than once to write several.
+    #[structopt(name = "format", long = "format", default_value = "m3u8", possible_values = &["m3u8", "pls", "xspf", "json"], number_of_values = 1)]
+    pub(crate) formats: Vec<Format>,
+    /// Separator used between path components in playlist entries.
+    #[structopt(long = "path-separator", default_value = "slash", possible_values = &["slash", "backslash"])]
+    pub(crate) path_separator: PathSeparator,
+    /// System playlists to export like regular ones, e.g. "music,purchased".
+    #[structopt(long = "include-system", use_delimiter = true, parse(try_from_str = parse_system_kind))]
+    pub(crate) include_system: Vec<PlaylistKind>,
+    /// Folder inside the playlists folder to write system playlists given to --include-system
+    /// into, apart from the library's own. An empty name writes them among the others.
+    #[structopt(long = "system-playlists-dir", default_value = "_System")]
+    pub(crate) system_playlists_dir: String,
+    /// Also export playlists that iTunes marks as hidden, and those inside them.
+    #[structopt(long = "include-hidden")]
+    pub(crate) include_hidden: bool,
+    /// Don't export smart playlists.
+    #[structopt(long = "skip-smart", conflicts_with = "only-smart")]
+    pub(crate) skip_smart: bool,
+    /// Only export smart playlists (and the folders containing them).
+    #[structopt(long = "only-smart")]
+    pub(crate) only_smart: bool,
+    /// Only sync tracks rated at least this many stars (0-5).
+    #[structopt(long = "min-rating", parse(try_from_str = parse_stars))]
+    pub(crate) min_rating: Option<u32>,
+    /// Only sync tracks played at least this many times.
+    #[structopt(long = "min-playcount")]
+    pub(crate) min_playcount: Option<u32>,
+    /// Only sync tracks added to the library on or after this date (YYYY-MM-DD, UTC), or within
+    /// this long before now, like "14d" or "2w". Playlists only list the tracks that are synced,
+    /// and with --skip-empty are left out when that is none of them.
+    #[structopt(long = "added-since", alias = "since", parse(try_from_str = parse_since))]
+    pub(crate) added_since: Option<SystemTime>,
+    /// Only sync tracks matching this expression, e.g. 'genre = "Jazz" and rating >= 4 and not
+    /// protected'. Fields are name, artist, album, genre, kind, rating (in stars), playcount,
+    /// size (like "10MB"), added (YYYY-MM-DD), disabled, protected and loved; text is compared ignoring
+    /// case, with =, !=, contains or matches (a glob). Combine tests with and, or, not and
+    /// parentheses.
+    #[structopt(long)]
+    pub(crate) filter: Option<Filter>,
+    /// Also sync DRM-protected tracks, which only Apple's software can play.
+    #[structopt(long = "include-protected")]
+    pub(crate) include_protected: bool,
+    /// Also sync tracks that are unchecked in iTunes.
+    #[structopt(long = "include-disabled")]
+    pub(crate) include_disabled: bool,
+    /// Only sync tracks that are loved, as marked with a heart in Music.app.
+    #[structopt(long = "only-loved")]
+    pub(crate) only_loved: bool,
+    /// Let tracks that lack the rating, play count or date a filter asks for through it, instead
+    /// of leaving them out.
+    #[structopt(long = "include-missing")]
+    pub(crate) include_missing: bool,
+    /// Don't copy any tracks; playlists refer to them where they already are.
+    #[structopt(long = "no-copy", conflicts_with = "mode")]
+    pub(crate) no_copy: bool,
+    /// Always copy the bytes of tracks, instead of cloning them copy-on-write where the output is
+    /// on the same APFS, Btrfs or XFS volume as the library.
+    #[structopt(long = "no-reflink")]
+    pub(crate) no_reflink: bool,
+    /// Copy tracks through a buffer of this size, e.g. "4MiB" or "512KiB", instead of the
+    /// platform's own copy. Larger writes can be much faster on devices mounted over MTP.
+    #[structopt(long = "copy-buffer", parse(try_from_str = parse_size))]
+    pub(crate) copy_buffer: Option<usize>,
+    /// Copy tracks at no more than this rate overall, e.g. "10MiB/s" or "500k", so as not to
+    /// saturate a network link to the output. Tracks are copied through a buffer to pace them.
+    #[structopt(long = "bwlimit", parse(try_from_str = parse_rate), conflicts_with = "output-archive")]
+    pub(crate) bwlimit: Option<u64>,
+    /// Try copying a track up to this many more times when it fails, for destinations with
+    /// passing errors like those mounted over a flaky network. Each retry waits twice as long as
+    /// the last, starting at a quarter of a second.
+    #[structopt(long, default_value = "0")]
+    pub(crate) retries: u32,
+    /// Copy tracks whose files have identical contents only once, hard linking the others to the
+    /// copy. Files of the same size are compared by hash, which is kept in the output directory
+    /// for later runs.
+    #[structopt(long = "dedupe-content")]
+    pub(crate) dedupe_content: bool,
+    /// Compare the contents of files already in the output with those of their tracks by hash,
+    /// keeping them if they match and copying the track over them if not. With --append-only, a
+    /// file that differs is kept as a conflict instead. Hashes are kept in the output directory
+    /// for later runs.
+    #[structopt(long = "check-content", conflicts_with_all = &["output-archive", "no-copy"])]
+    pub(crate) check_content: bool,
+    /// Write the SHA-256 digest of every track file in the output to SHA256SUMS there, which
+    /// `m3trans verify-output` or `sha256sum -c` can check later. Digests of files that haven't
+    /// changed since an earlier run are reused rather than read again.
+    #[structopt(long = "checksums", conflicts_with_all = &["output-archive", "no-copy"])]
+    pub(crate) checksums: bool,
+    /// Don't write manifest.json to the output, which lists every track file with the ID of its
+    /// track, and every playlist file with the ID of its playlist, for other programs to read.
+    #[structopt(long = "no-manifest")]
+    pub(crate) no_manifest: bool,
+    /// Write the tracks and playlists into this zip archive instead of an output folder, laid out
+    /// the same. Doesn't work with --dry-run, --append-only, --prune-playlists or --dedupe-content.
+    #[structopt(
+        long = "output-archive",
+        parse(from_os_str),
+        conflicts_with_all = &["dry-run", "append-only", "prune-playlists", "dedupe-content", "mode"],
+    )]
+    pub(crate) output_archive: Option<PathBuf>,
+    /// Instead of copying the tracks into the output folder, put symbolic links to their files
+    /// there, and write this list of every track and playlist file in it, for rsync to transfer:
+    /// `rsync -a --copy-links --files-from=LIST OUTPUT DESTINATION`. Everything is named and
+    /// filtered as when copying. With --dry-run, neither the links nor the list are written.
+    #[structopt(long = "emit-file-list", parse(from_os_str), conflicts_with_all = &["no-copy", "output-archive"])]
+    pub(crate) emit_file_list: Option<PathBuf>,
+    /// How to compress files in the archive: store, or deflate those that come out smaller.
+    #[structopt(long = "compression", default_value = "deflate", possible_values = &["store", "deflate"])]
+    pub(crate) compression: zip::Compression,
+    /// Copy tracks even if they look like they won't fit in the space left on the output's volume.
+    #[structopt(long = "ignore-space-check")]
+    pub(crate) ignore_space_check: bool,
+    /// With --no-copy, write track paths relative to this directory instead of in full.
+    #[structopt(long = "base-dir", parse(from_os_str), requires = "no-copy")]
+    pub(crate) base_dir: Option<PathBuf>,
+    /// Read tracks on a network share from a local mount instead, as HOST=PATH, e.g.
+    /// `mynas._smb._tcp.local=/Volumes/music`. May be given multiple times.
+    #[structopt(name = "remap-host", long = "remap-host", number_of_values = 1, parse(try_from_str = parse_host_remap))]
+    pub(crate) remap_hosts: Vec<(String, PathBuf)>,
+    /// Sync even if the output folder is inside the library's media folder, or tracks would be
+    /// copied over their own files. Either usually means the output was given by mistake.
+    #[structopt(long = "allow-overlap")]
+    pub(crate) allow_overlap: bool,
+    /// Stop at the first track, playlist or folder that fails to sync, or playlist entry for a
+    /// track that isn't in the output or the library, instead of leaving it out and going on.
+    #[structopt(long = "strict")]
+    pub(crate) strict: bool,
+    /// Also write a playlist of every copied track, named "All Tracks" unless a name is given.
+    /// It is sorted like --library-playlist.
+    #[structopt(long = "all-tracks-playlist")]
+    pub(crate) all_tracks_playlist: Option<Option<String>>,
+    /// Also write a Library playlist of every copied track, sorted by artist, album and track number.
+    #[structopt(long = "library-playlist")]
+    pub(crate) library_playlist: bool,
+    /// Also write a playlist of every copied track that is loved, named "Loved" unless a name is
+    /// given. It is sorted like --library-playlist.
+    #[structopt(long = "loved-playlist")]
+    pub(crate) loved_playlist: Option<Option<String>>,
+    /// Also write iTunes' master playlist of the whole library in its own order, named "Library"
+    /// unless a name is given. Its entries are left out and capped like any other playlist's.
+    #[structopt(long = "export-master")]
+    pub(crate) export_master: Option<Option<String>>,
+    /// Copy podcast episodes to podcasts/<show>/ instead of the tracks folder, named after the
+    /// dates they came out, whatever playlists they are in. A playlist of each show, from its
+    /// oldest episode to its newest, is written to a Podcasts folder of playlists, along with one
+    /// of the New Episodes of every show.
+    #[structopt(long = "podcasts")]
+    pub(crate) podcasts: bool,
+    /// How many days back an episode must have been added to be in the New Episodes playlist of
+    /// --podcasts. [default: 7]
+    #[structopt(long = "new-episodes-days", requires = "podcasts")]
+    pub(crate) new_episodes_days: Option<u64>,
+    /// Leave out entries past the first N of each playlist.
+    #[structopt(long = "max-tracks-per-playlist")]
+    pub(crate) max_tracks_per_playlist: Option<usize>,
+    /// Leave out entries once a playlist would run longer than this, e.g. "2h", "1h30m" or "45m".
+    #[structopt(long = "max-playlist-duration", parse(try_from_str = parse_duration))]
+    pub(crate) max_playlist_duration: Option<u64>,
+    /// How to export playlists: playlists for files in the selected formats, or folders for a
+    /// folder of each playlist's tracks, for players that ignore playlist files and play folders
+    /// in alphabetical order. Their files are numbered to keep that order, as in
+    /// "001 - Song.mp3", and hard linked to the copies in the tracks folder where the file system
+    /// allows, so that they take up no more space; otherwise they are copied.
+    #[structopt(long = "mode", default_value = "playlists", possible_values = &["playlists", "folders"])]
+    pub(crate) mode: Mode,
+    /// Whether entries refer to tracks relative to the playlist, or by their absolute path from
+    /// the root of the output directory.
+    #[structopt(long = "entry-paths", default_value = "relative", possible_values = &["relative", "absolute"])]
+    pub(crate) entry_paths: EntryPaths,
+    /// Name of the directory playlists are written to, inside the output directory, or "." to
+    /// write them into the output directory itself.
+    #[structopt(long = "playlists-dir", default_value = PLAYLISTS_DIR)]
+    pub(crate) playlists_dir: String,
+    /// Name of the directory tracks are copied to, inside the output directory.
+    #[structopt(long = "tracks-dir", default_value = TRACKS_DIR)]
+    pub(crate) tracks_dir: String,
+    /// Which characters to replace in playlist file names: "fat" also avoids those FAT file
+    /// systems reject, and "windows" those and the names Windows keeps for devices, like CON.
+    /// On Windows "default" is "windows".
+    #[structopt(long = "sanitize", default_value = "default", possible_values = &["default", "fat", "windows"])]
+    pub(crate) sanitize: Sanitize,
+    /// What to name copied tracks: id for their track ID, persistent-id for their persistent ID,
+    /// which survives rebuilding the library, original for the name of their file, or album for
+    /// the name of their file in a folder for their album artist and album, as iTunes lays them
+    /// out. Names several files share are followed by -ID for all but the one with the lowest ID,
+    /// and all keep their extension. Tracks of the same file share one copy of it.
+    #[structopt(long = "names", default_value = "id", possible_values = &["id", "persistent-id", "original", "album"])]
+    pub(crate) track_names: TrackNames,
+    /// Template for where to copy tracks inside the tracks folder instead of --names, e.g.
+    /// "{album_artist|artist}/{album}/{disc}-{track:02} {name}.{ext}". Fields: name, artist,
+    /// album_artist, album, genre, composer, work, movement, movement_number, disc, disc_count,
+    /// track, track_count, id, persistent_id and ext.
+    /// A field may fall back to others or to quoted text, as in {genre|"Misc"}; otherwise a
+    /// missing one becomes e.g. "Unknown Album", or 0 for numbers, which {track:02} pads. Without
+    /// {ext} the extension is added to the end. Paths several tracks share are followed by -ID.
+    #[structopt(long = "path-template", conflicts_with = "track-names")]
+    pub(crate) path_template: Option<PathTemplate>,
+    /// With --names album, the folder albums of various artists go in unless they have an album
+    /// artist.
+    #[structopt(long = "compilations-folder", default_value = "Compilations")]
+    pub(crate) compilations_folder: String,
+    /// With --names album, put the tracks of compilations in folders for their own artists.
+    #[structopt(long = "no-compilation-grouping")]
+    pub(crate) no_compilation_grouping: bool,
+    /// Write the picture embedded in the first track of each folder of tracks to cover.jpg in it,
+    /// or cover.png for PNG pictures, for players that ignore embedded art. Folders that have a
+    /// cover already are left alone. Needs tracks in folders, as with --names album.
+    #[cfg(feature = "art")]
+    #[structopt(long = "extract-art", conflicts_with = "no-copy")]
+    pub(crate) extract_art: bool,
+    /// With --extract-art, leave out pictures wider or taller than this many pixels, which
+    /// m3trans can't shrink.
+    #[cfg(feature = "art")]
+    #[structopt(long = "art-max-size", requires = "extract-art")]
+    pub(crate) art_max_size: Option<u32>,
+    /// Defaults for a particular kind of device; options given explicitly still take precedence.
+    #[structopt(long = "preset", possible_values = &["rockbox"])]
+    pub(crate) preset: Option<Preset>,
+    /// Write the entries of every playlist in a shuffled order, optionally from a given seed so
+    /// that the order can be reproduced. The seed used is logged.
+    #[structopt(long = "shuffle", conflicts_with = "sort-entries")]
+    pub(crate) shuffle: Option<Option<u64>>,
+    /// Write the entries of every playlist sorted by these fields, e.g. "artist,album,track", out of
+    /// name, artist, album_artist, album, genre, disc, track, rating, play_count and date_added.
+    /// Entries that tie keep their order in the playlist. "none" keeps the playlist's order.
+    #[structopt(long = "sort-entries", default_value = "none")]
+    pub(crate) sort_entries: EntryOrder,
+    /// Unicode normalization form for names written to disk and into playlists, and for matching
+    /// ignore and include patterns.
+    #[structopt(long = "normalize", default_value = "nfc", possible_values = &["nfc", "nfd", "none"])]
+    pub(crate) normalize: Normalization,
+    /// Transliterate playlist file names, the folder and file names of copied tracks and entry
+    /// titles to ASCII.
+    #[structopt(long = "ascii")]
+    pub(crate) ascii: bool,
+    /// Shorten playlist file and folder names, and the folder and file names of copied tracks,
+    /// longer than this many bytes, extension included. Shortened names end in part of the
+    /// playlist's persistent ID, or of a digest of a track's name, to keep them apart.
+    #[structopt(long = "max-name-len")]
+    pub(crate) max_name_len: Option<usize>,
+    /// Keep the paths of playlist files and folders to at most this many characters from the
+    /// output folder, for devices that can't handle longer ones, like those limited to Windows'
+    /// 260. What is done about longer ones is up to --on-long-path.
+    #[structopt(long = "max-path-len")]
+    pub(crate) max_path_len: Option<usize>,
+    /// With --max-path-len, what to do about playlists whose paths would be longer: shorten the
+    /// names of the playlist and the folders it is in as far as needed, ending them in part of
+    /// their persistent IDs; skip the playlist; or stop with an error at the first. A dry run
+    /// finds them without writing anything. [default: shorten]
+    #[structopt(long = "on-long-path", requires = "max-path-len", possible_values = &["shorten", "skip", "error"])]
+    pub(crate) on_long_path: Option<OnLongPath>,
+    /// Template for entry titles, e.g. "{artist} - {name}" or "{composer}: {work} - {name}". Fields:
+    /// name, artist, album_artist, album, composer, work, movement and movement_number.
+    #[structopt(long = "title-format", default_value = "{name}")]
+    pub(crate) title_format: TitleFormat,
+}
+
+impl Args {
+    /// Fills in the defaults of the selected preset for every option that wasn't given explicitly.
+    fn apply_preset(&mut self, matches: &clap::ArgMatches) {
+        let implicit = |name| matches.occurrences_of(name) == 0;
+        match self.preset {
+            None => {},
+            Some(Preset::Rockbox) => {
+                // Rockbox resolves playlist entries from the root of the volume.
+                if implicit("entry-paths") { self.entry_paths = EntryPaths::Absolute; }
+                if implicit("playlists-dir") { self.playlists_dir = "Playlists".to_owned(); }
+                if implicit("sanitize") { self.sanitize = Sanitize::Fat; }
+                if implicit("format") { self.formats = vec![Format::M3u]; }
+                if implicit("playlist-encoding") { self.playlist_encoding = Encoding::Utf8; }
+            },
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+pub(crate) enum Preset {
+    Rockbox,
+}
+
+impl FromStr for Preset {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "rockbox" => Ok(Preset::Rockbox),
+            other => Err(format!("unknown preset \"{}\"", other)),
+        }
+    }
+}
+
+/// How playlists are exported.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Mode {
+    /// As playlist files in the selected formats.
+    Playlists,
+    /// As folders holding their tracks, for players that only play folders.
+    Folders,
+}
+
+impl FromStr for Mode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "playlists" => Ok(Mode::Playlists),
+            "folders" => Ok(Mode::Folders),
+            other => Err(format!("unknown mode \"{}\"", other)),
+        }
+    }
+}
+
+fn parse_system_kind(name: &str) -> Result<PlaylistKind, String> {
+    PlaylistKind::from_system_name(name).ok_or_else(|| format!(
+        "unknown system playlist \"{}\", expected one of: {}",
+        name, PlaylistKind::system_names().collect::<Vec<_>>().join(", ")
+    ))
+}
+
+/// Parses a `HOST=PATH` rule for --remap-host.
+fn parse_host_remap(s: &str) -> Result<(String, PathBuf), String> {
+    match s.split_once('=') {
+        Some((host, path)) if !host.is_empty() && !path.is_empty() => {
+            let host = percent_encoding::percent_decode_str(host).decode_utf8_lossy();
+            Ok((host.to_lowercase(), PathBuf::from(path)))
+        }
+        _ => Err(format!("invalid host remapping \"{}\", expected HOST=PATH", s)),
+    }
+}
+
+/// Parses a star rating into the 0-100 scale of the library.
+fn parse_stars(s: &str) -> Result<u32, String> {
+    match s.parse::<u32>() {
+        Ok(stars) if stars <= 5 => Ok(stars * 20),
+        _ => Err(format!("invalid rating \"{}\", expected 0 to 5 stars", s)),
+    }
+}
+
+/// Parses a `YYYY-MM-DD` date as midnight UTC.
+fn parse_date(s: &str) -> Result<SystemTime, String> {
+    let invalid = || format!("invalid date \"{}\", expected YYYY-MM-DD", s);
+    let parts: Vec<&str> = s.split('-').collect();
+    let (year, month, day) = match parts.as_slice() {
+        [year, month, day] => (
+            year.parse::<i64>().map_err(|_| invalid())?,
+            month.parse::<i64>().map_err(|_| invalid())?,
+            day.parse::<i64>().map_err(|_| invalid())?,
+        ),
+        _ => return Err(invalid()),
+    };
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return Err(invalid());
+    }
+
+    let days = crate::date::days_from_civil(year, month, day);
+    if days < 0 {
+        return Err(format!("invalid date \"{}\", dates before 1970 aren't supported", s));
+    }
+    Ok(UNIX_EPOCH + Duration::from_secs(days as u64 * 86_400))
+}
+
+/// Parses either a date for `parse_date`, or an age for `parse_age` counted back from now.
+fn parse_since(s: &str) -> Result<SystemTime, String> {
+    if !s.ends_with(|c: char| c.is_ascii_alphabetic()) {
+        return parse_date(s);
+    }
+    let invalid = || format!("invalid date or age \"{}\", expected YYYY-MM-DD or e.g. \"14d\"", s);
+    let age = parse_age(s).map_err(|()| invalid())?;
+    SystemTime::now().checked_sub(age).ok_or_else(invalid)
+}
+
+/// Parses an age like "14d", "2w" or "1w3d", in weeks, days and hours.
+fn parse_age(s: &str) -> Result<Duration, ()> {
+    let mut total = 0u64;
+    let mut rest = s;
+    while !rest.is_empty() {
+        let digits = rest.find(|c: char| !c.is_ascii_digit()).ok_or(())?;
+        let value: u64 = rest[..digits].parse().map_err(|_| ())?;
+        let unit_secs = match rest[digits..].chars().next() {
+            Some('w') => 7 * 86_400,
+            Some('d') => 86_400,
+            Some('h') => 3600,
+            _ => return Err(()),
+        };
+        total = value.checked_mul(unit_secs).and_then(|secs| total.checked_add(secs)).ok_or(())?;
+        rest = &rest[digits + 1..];
+    }
+    Ok(Duration::from_secs(total))
+}
+
+/// Parses a duration like "2h", "1h30m", "90m" or "45s" into milliseconds. Bare numbers are seconds.
+fn parse_duration(s: &str) -> Result<u64, String> {
+    let invalid = || format!("invalid duration \"{}\", expected e.g. \"2h\" or \"1h30m\"", s);
+    if let Ok(secs) = s.parse::<u64>() {
+        return Ok(secs * 1000);
+    }
+
+    let mut total = 0;
+    let mut rest = s;
+    while !rest.is_empty() {
+        let digits = rest.find(|c: char| !c.is_ascii_digit()).ok_or_else(invalid)?;
+        let value: u64 = rest[..digits].parse().map_err(|_| invalid())?;
+        let unit_ms = match rest[digits..].chars().next() {
+            Some('h') => 3_600_000,
+            Some('m') => 60_000,
+            Some('s') => 1000,
+            _ => return Err(invalid()),
+        };
+        total += value * unit_ms;
+        rest = &rest[digits + 1..];
+    }
+    match total {
+        0 => Err(invalid()),
+        total => Ok(total),
+    }
+}
+
+/// Parses a number of bytes, optionally followed by a unit: K, M or G, also spelled KB or KiB and
+/// so on, all counting in powers of 1024.
+fn parse_size(s: &str) -> Result<usize, String> {
+    let invalid = || format!("invalid size \"{}\", expected e.g. \"4MiB\" or \"65536\"", s);
+    let digits = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let value: usize = s[..digits].parse().map_err(|_| invalid())?;
+    let unit = match s[digits..].trim_start().to_ascii_uppercase().as_str() {
+        "" | "B" => 1,
+        "K" | "KB" | "KIB" => 1 << 10,
+        "M" | "MB" | "MIB" => 1 << 20,
+        "G" | "GB" | "GIB" => 1 << 30,
+        _ => return Err(invalid()),
+    };
+    match value.checked_mul(unit) {
+        Some(0) => Err(format!("size \"{}\" must be more than 0 bytes", s)),
+        Some(size) => Ok(size),
+        None => Err(invalid()),
+    }
+}
+
+/// Parses a rate in bytes per second, like a size with an optional "/s" after it.
+fn parse_rate(s: &str) -> Result<u64, String> {
+    let size = s.strip_suffix("/s").unwrap_or(s);
+    parse_size(size)
+        .map(|bytes| bytes as u64)
+        .map_err(|_| format!("invalid rate \"{}\", expected e.g. \"10MiB/s\" or \"500k\"", s))
+}
+
+/// Compares two exports of a library: `m3trans diff OLD NEW`.
+#[derive(StructOpt)]
+pub(crate) struct DiffArgs {
+    #[structopt(parse(from_os_str))]
+    pub(crate) old_library: PathBuf,
+    #[structopt(parse(from_os_str))]
+    pub(crate) new_library: PathBuf,
+    /// Print the changes as JSON instead of text.
+    #[structopt(long)]
+    pub(crate) json: bool,
+}
+
+/// Prints a library as JSON, with its tracks and playlists: `m3trans export LIBRARY`.
+#[derive(StructOpt)]
+pub(crate) struct ExportArgs {
+    #[structopt(parse(from_os_str))]
+    pub(crate) library_file: PathBuf,
+    /// Write the JSON to this file instead of standard output.
+    #[structopt(short, long, parse(from_os_str))]
+    pub(crate) output: Option<PathBuf>,
+}
+
+/// Checks that the file of every track is there: `m3trans verify LIBRARY`.
+#[derive(StructOpt)]
+pub(crate) struct VerifyArgs {
+    #[structopt(parse(from_os_str))]
+    pub(crate) library_file: PathBuf,
+    /// Read tracks on a network share from a local mount instead, as HOST=PATH, as when syncing.
+    #[structopt(name = "remap-host", long = "remap-host", number_of_values = 1, parse(try_from_str = parse_host_remap))]
+    pub(crate) remap_hosts: Vec<(String, PathBuf)>,
+}
+
+/// Checks the track files in an output against their checksums: `m3trans verify-output DIR`.
+#[derive(StructOpt)]
+pub(crate) struct VerifyOutputArgs {
+    /// The output folder, written with --checksums.
+    #[structopt(parse(from_os_str))]
+    pub(crate) output_path: PathBuf,
+}
+
+/// Prints totals over a library: `m3trans stats LIBRARY`.
+#[derive(StructOpt)]
+pub(crate) struct StatsArgs {
+    #[structopt(parse(from_os_str))]
+    pub(crate) library_file: PathBuf,
+    /// Print the totals as JSON instead of text.
+    #[structopt(long)]
+    pub(crate) json: bool,
+}
+
+/// Syncs a made-up library into a temporary folder and checks the result: `m3trans self-test`.
+#[derive(StructOpt)]
+pub(crate) struct SelfTestArgs {
+    /// Leave the temporary folder in place to look at, as happens anyway if a check fails.
+    #[structopt(long)]
+    pub(crate) keep: bool,
+}
+
+/// Parses the command line with `app`, filling in options it doesn't give from the config file.
+pub(crate) fn parse_args(cli: Vec<OsString>, app: impl Fn() -> clap::App<'static, 'static>) -> Result<Args, io::Error> {
+    let cli_matches = app().get_matches_from(&cli);
+    let cli_args = Args::from_clap(&cli_matches);
+    let config_path = match &cli_args.config {
+        Some(path) => Some(path.clone()),
+        None => Some(PathBuf::from("m3trans.toml")).filter(|path| path.is_file()),
+    };
+    let config = match &config_path {
+        Some(path) => read_config(path)?,
+        None => Vec::new(),
+    };
+    let merged = merge_config(&cli, &cli_matches, config)?;
+    let given_arguments = fingerprinted_arguments(&merged[1..]);
+    let (mut args, matches) = match &config_path {
+        Some(path) => {
+            let matches = app().get_matches_from_safe(merged).map_err(|e| {
+                let message = e.message.lines().next().unwrap_or_default().trim_start_matches("error: ");
+                let message = match e.kind {
+                    clap::ErrorKind::UnknownArgument => format!(
+                        "unknown key {:?}",
+                        e.info.as_ref().and_then(|info| info.first()).map_or("", |arg| arg.trim_start_matches('-')),
+                    ),
+                    _ => message.to_owned(),
+                };
+                io::Error::new(ErrorKind::InvalidInput, format!("invalid config file {:?}: {}", path, message))
+            })?;
+            (Args::from_clap(&matches), matches)
+        }
+        None if merged.len() > cli.len() => {
+            let matches = app().get_matches_from(merged);
+            (Args::from_clap(&matches), matches)
+        }
+        None => (cli_args, cli_matches),
+    };
+    args.apply_preset(&matches);
+    args.given_arguments = given_arguments;
+    if args.auto_library || args.library_file.as_os_str().is_empty() {
+        // Taken by clap as the library file, for want of knowing better.
+        if args.auto_library && matches.occurrences_of("library-file") > 0 {
+            args.output_paths.retain(|path| !path.as_os_str().is_empty());
+            args.output_paths.insert(0, std::mem::take(&mut args.library_file));
+        }
+        args.library_file = find_library()?;
+        args.auto_library = true;
+    }
+    if let Some(archive) = &args.output_archive {
+        if args.output_paths.iter().any(|path| !path.as_os_str().is_empty()) {
+            return Err(io::Error::new(
+                ErrorKind::InvalidInput,
+                "--output-archive takes the place of output paths, so give only one of them",
+            ));
+        }
+        // Standing in for the output directory, as the root of the paths in the archive.
+        args.output_paths = vec![archive.clone()];
+    }
+    if args.output_paths.iter().all(|path| path.as_os_str().is_empty()) {
+        return Err(io::Error::new(
+            ErrorKind::InvalidInput,
+            "no output path given on the command line, in the environment or in a config file",
+        ));
+    }
+    check_dir_name("--playlists-dir", &args.playlists_dir)?;
+    check_dir_name("--tracks-dir", &args.tracks_dir)?;
+    if relative_dir(&args.tracks_dir).as_os_str().is_empty() {
+        return Err(io::Error::new(ErrorKind::InvalidInput, "--tracks-dir must name a folder inside the output folder"));
+    }
+    if relative_dir(&args.tracks_dir) == relative_dir(&args.playlists_dir) {
+        return Err(io::Error::new(ErrorKind::InvalidInput, "--tracks-dir and --playlists-dir must name different folders"));
+    }
+    if let Some(path) = &args.rename_file {
+        args.renames = read_renames(path, args.normalize)?;
+    }
+    if args.append_only {
+        let overwriting = [
+            (args.prune_playlists, "--prune-playlists", "deletes playlists"),
+            (args.mode == Mode::Folders, "--mode folders", "replaces and deletes the files in playlist folders"),
+            (args.log_keep.is_some(), "--log-keep", "renames and deletes logs"),
+            (args.checksums, "--checksums", "rewrites SHA256SUMS"),
+            (args.html_index, "--html-index", "rewrites index.html"),
+        ];
+        if let Some((_, option, what)) = overwriting.iter().find(|(given, _, _)| *given) {
+            return Err(io::Error::new(
+                ErrorKind::InvalidInput,
+                format!("--append-only never overwrites, moves or deletes anything in the output, so it can't be used with {}, which {}", option, what),
+            ));
+        }
+    }
+    if args.emit_file_list.is_some() && args.output_paths.len() > 1 {
+        return Err(io::Error::new(
+            ErrorKind::InvalidInput,
+            "--emit-file-list lists the files of a single output folder, so give only one",
+        ));
+    }
+    Ok(args)
+}
+
+/// Fails unless `name`, given for `option`, names a folder inside the output folder, or the output
+/// folder itself.
+fn check_dir_name(option: &str, name: &str) -> Result<(), io::Error> {
+    let inside = Path::new(name).components().all(|component| matches!(component, Component::Normal(_) | Component::CurDir));
+    match inside && !name.is_empty() {
+        true => Ok(()),
+        false => Err(io::Error::new(
+            ErrorKind::InvalidInput,
+            format!("{} {:?} isn't inside the output folder; give a relative path without \"..\"", option, name),
+        )),
+    }
+}
+
+/// A folder name given on the command line as a path relative to the output folder, without any
+/// "." in it, so that "." stands for the output folder itself.
+pub(crate) fn relative_dir(name: &str) -> PathBuf {
+    Path::new(name).components().filter(|component| *component != Component::CurDir).collect()
+}
+
+/// The path of the folder `name` in the output folder.
+pub(crate) fn output_dir(output_path: &Path, name: &str) -> PathBuf {
+    match relative_dir(name) {
+        relative if relative.as_os_str().is_empty() => output_path.to_owned(),
+        relative => output_path.join(relative),
+    }
+}
+
+/// Flags that only change what is shown or logged, or how the run goes about syncing, and not what
+/// ends up in the output, so that a sync isn't run again for changing them.
+const UNFINGERPRINTED_FLAGS: &[&str] = &[
+    "--force", "--no-log-file", "--log-append", "--verbose", "--verbose-warnings", "--quiet", "--yes", "--no-lock",
+];
+
+/// Likewise for options with a value, with their short names.
+const UNFINGERPRINTED_OPTIONS: &[&str] = &[
+    "--config", "--log-file", "-l", "--log-keep", "--log-level", "--log-format", "--output-format", "--lock-max-age",
+];
+
+/// The arguments of a sync that go into its fingerprint: all of `arguments` but those whose flags
+/// are in `UNFINGERPRINTED_FLAGS` or `UNFINGERPRINTED_OPTIONS`.
+fn fingerprinted_arguments(arguments: &[OsString]) -> Vec<OsString> {
+    let mut fingerprinted = Vec::new();
+    let mut arguments = arguments.iter();
+    while let Some(argument) = arguments.next() {
+        let text = argument.to_string_lossy();
+        if text == "--" {
+            fingerprinted.push(argument.clone());
+            fingerprinted.extend(arguments.cloned());
+            break;
+        }
+        let (flag, has_value) = match text.split_once('=') {
+            Some((flag, _)) if flag.starts_with("--") => (flag, true),
+            _ => (&*text, false),
+        };
+        if UNFINGERPRINTED_FLAGS.contains(&flag) {
+            continue;
+        }
+        if UNFINGERPRINTED_OPTIONS.contains(&flag) {
+            if !has_value {
+                arguments.next();
+            }
+            continue;
+        }
+        // Short flags, which may be run together like -vvy, or be -l with its value attached.
+        if let Some(short) = text.strip_prefix('-').filter(|short| !short.is_empty() && !short.starts_with('-')) {
+            if short.starts_with('l') {
+                continue;
+            }
+            if short.chars().all(|c| matches!(c, 'v' | 'q' | 'y')) {
+                continue;
+            }
+        }
+        fingerprinted.push(argument.clone());
+    }
+    fingerprinted
+}
+
+/// Sets up a sync for `m3trans repair`, which compares what it finds in place by content to tell
+/// whether it needs copying again.
+pub(crate) fn repair_args(args: Args) -> Result<Args, io::Error> {
+    // Repairing moves files around, which --append-only never does.
+    let unsupported = [(args.output_archive.is_some(), "--output-archive"), (args.no_copy, "--no-copy"), (args.append_only, "--append-only")];
+    for (given, option) in unsupported {
+        if given {
+            return Err(io::Error::new(ErrorKind::InvalidInput, format!("m3trans repair can't be used with {}", option)));
+        }
+    }
+    Ok(Args { repair: true, check_content: true, ..args })
+}
+
+fn find_library() -> Result<PathBuf, io::Error> {
+    let home = discover::home_dir().ok_or_else(|| io::Error::new(
+        ErrorKind::NotFound,
+        "no library file given, and no home folder to look for one in",
+    ))?;
+    discover::find_library(&home).map_err(|tried| {
+        let tried: Vec<String> = tried.iter().map(|path| format!("{:?}", path)).collect();
+        io::Error::new(ErrorKind::NotFound, format!(
+            "no library file given, and none found at {}. Music.app only saves one with \"Share Library \
+            XML with other applications\" turned on in its advanced settings; otherwise, export one with \
+            File > Library > Export Library and give its path.",
+            tried.join(", ")
+        ))
+    })
+}
+
+fn read_config(path: &Path) -> Result<Vec<(String, crate::toml::Value)>, io::Error> {
+    let text = fs::read_to_string(path).map_err(context(format!("failed to read config file {:?}", path)))?;
+    crate::toml::parse(&text).map_err(|e| {
+        io::Error::new(ErrorKind::InvalidData, format!("failed to parse config file {:?}: {}", path, e))
+    })
+}
+
+/// Reads a file of `"path" = "name"` pairs for --rename-file, with paths normalized to match those
+/// of playlists.
+fn read_renames(path: &Path, normalize: Normalization) -> Result<Vec<(String, String)>, io::Error> {
+    let text = fs::read_to_string(path).map_err(context(format!("failed to read rename file {:?}", path)))?;
+    let invalid = |message: String| io::Error::new(ErrorKind::InvalidData, format!("failed to parse rename file {:?}: {}", path, message));
+    crate::toml::parse(&text)
+        .map_err(|e| invalid(e.to_string()))?
+        .into_iter()
+        .map(|(key, value)| match value {
+            crate::toml::Value::String(name) if !name.trim().is_empty() => Ok((normalize.apply(&key), name)),
+            _ => Err(invalid(format!("the new name of {:?} isn't a non-empty string", key))),
+        })
+        .collect()
+}
+
+/// The environment variables that options default to, which the command line overrides and which
+/// override config files in turn. Must match the `env` attributes of `Args`.
+const ENV_DEFAULTS: &[(&str, &str)] = &[
+    ("library-file", "M3TRANS_LIBRARY"),
+    ("output-path", "M3TRANS_OUTPUT"),
+    ("ignore-file", "M3TRANS_IGNORE_FILE"),
+    ("log-file", "M3TRANS_LOG_FILE"),
+];
+
+fn env_default(key: &str) -> Option<OsString> {
+    ENV_DEFAULTS.iter()
+        .find(|(option, _)| *option == key)
+        .and_then(|(_, var)| std::env::var_os(var))
+        .filter(|value| !value.is_empty())
+}
+
+/// Adds the options of the config file to the command line, where it doesn't give them itself and
+/// neither does the environment.
+fn merge_config(
+    cli: &[OsString],
+    cli_matches: &clap::ArgMatches,
+    config: Vec<(String, crate::toml::Value)>,
+) -> Result<Vec<OsString>, io::Error> {
+    use crate::toml::Value;
+
+    fn scalar(key: &str, value: &Value) -> Result<String, io::Error> {
+        match value {
+            Value::String(string) => Ok(string.clone()),
+            Value::Integer(integer) => Ok(integer.to_string()),
+            Value::Boolean(_) | Value::Array(_) => Err(io::Error::new(
+                ErrorKind::InvalidInput,
+                format!("config key {:?} takes a string or a number", key),
+            )),
+        }
+    }
+
+    let mut options = Vec::new();
+    let mut positionals = Vec::new();
+    for (key, value) in config {
+        match key.as_str() {
+            "config" => return Err(io::Error::new(ErrorKind::InvalidInput, "config files can't include other config files")),
+            // Positionals can only be appended after the ones the command line gives.
+            // Given again from the environment below, so that positionals stay in order.
+            "library-file" | "output-path" if env_default(&key).is_some() => continue,
+            "library-file" | "output-path" => {
+                if cli_matches.occurrences_of(&key) == 0 {
+                    match value {
+                        Value::Array(items) if key == "output-path" => for item in &items {
+                            positionals.push((key.clone(), OsString::from(scalar(&key, item)?)));
+                        },
+                        value => positionals.push((key.clone(), OsString::from(scalar(&key, &value)?))),
+                    }
+                }
+                continue;
+            }
+            _ if cli_matches.occurrences_of(&key) > 0 || env_default(&key).is_some() => continue,
+            _ => {}
+        }
+        match value {
+            Value::Boolean(true) => options.push(OsString::from(format!("--{}", key))),
+            Value::Boolean(false) => {},
+            Value::Array(items) => for item in &items {
+                options.push(OsString::from(format!("--{}={}", key, scalar(&key, item)?)));
+            },
+            value => options.push(OsString::from(format!("--{}={}", key, scalar(&key, &value)?))),
+        }
+    }
+    for key in ["library-file", "output-path"] {
+        if let Some(value) = env_default(key).filter(|_| cli_matches.occurrences_of(key) == 0) {
+            positionals.push((key.to_owned(), value));
+        }
+    }
+    positionals.sort_by_key(|(key, _)| *key != "library-file");
+    // An empty library file holds the place of one to be found, so that output paths stay outputs.
+    if cli_matches.occurrences_of("library-file") == 0 && positionals.first().is_some_and(|(key, _)| key == "output-path") {
+        positionals.insert(0, ("library-file".to_owned(), OsString::new()));
+    }
+
+    let mut merged = vec![cli[0].clone()];
+    merged.extend(options);
+    merged.extend(cli[1..].iter().cloned());
+    merged.extend(positionals.into_iter().map(|(_, value)| value));
+    Ok(merged)
+}
+
+/// Prints the playlist tree of a library.
+#[derive(StructOpt)]
+pub(crate) struct ListArgs {
+    #[structopt(parse(from_os_str))]
+    pub(crate) library_file: PathBuf,
+    /// Which playlists to print: generic, folder or all. Others still count towards their folders.
+    #[structopt(long, default_value = "all", possible_values = &["generic", "folder", "all"])]
+    pub(crate) kind: ListKind,
+    /// Print only the path of each playlist, as ignore and include patterns are matched against.
+    #[structopt(long)]
+    pub(crate) paths: bool,
+    /// Unicode normalization applied to the names in --paths, as with the option of the same name
+    /// when syncing.
+    #[structopt(long = "normalize", default_value = "nfc", possible_values = &["nfc", "nfd", "none"])]
+    pub(crate) normalize: Normalization,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub(crate) enum ListKind {
+    Generic,
+    Folder,
+    All,
+}
+
+impl FromStr for ListKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "generic" => Ok(ListKind::Generic),
+            "folder" => Ok(ListKind::Folder),
+            "all" => Ok(ListKind::All),
+            other => Err(format!("unknown playlist kind \"{}\"", other)),
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub(crate) enum OutputFormat {
+    Text,
+    Json,
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(OutputFormat::Text),
+            "json" => Ok(OutputFormat::Json),
+            other => Err(format!("unknown output format \"{}\"", other)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ages_add_up_their_weeks_days_and_hours() {
+        let cases: [(&str, Option<u64>); 13] = [
+            ("14d", Some(14 * 86_400)),
+            ("2w", Some(14 * 86_400)),
+            ("1w3d", Some(10 * 86_400)),
+            ("3d1w", Some(10 * 86_400)),
+            ("12h", Some(12 * 3600)),
+            ("1w1d1h", Some(8 * 86_400 + 3600)),
+            ("0d", Some(0)),
+            ("d", None),
+            ("14x", None),
+            ("1dd", None),
+            ("1.5d", None),
+            ("14D", None),
+            // More seconds than fit in a u64.
+            ("30500568904944w", None),
+        ];
+        for (age, secs) in cases {
+            assert_eq!(parse_age(age).ok(), secs.map(Duration::from_secs), "{}", age);
+        }
+    }
+
+    #[test]
+    fn since_takes_a_date_or_an_age() {
+        assert_eq!(parse_since("2024-05-01"), Ok(UNIX_EPOCH + Duration::from_secs(19_844 * 86_400)));
+        let since = parse_since("2w").unwrap();
+        let age = SystemTime::now().duration_since(since).unwrap();
+        assert!(age >= Duration::from_secs(14 * 86_400) && age < Duration::from_secs(14 * 86_400 + 60), "{:?}", age);
+        assert!(parse_since("14x").unwrap_err().contains("expected YYYY-MM-DD or e.g. \"14d\""));
+        assert!(parse_since("14").is_err());
+    }
+
+    #[test]
+    fn rates_are_sizes_per_second() {
+        let cases: [(&str, Option<u64>); 13] = [
+            ("500", Some(500)),
+            ("500k", Some(500 << 10)),
+            ("500K/s", Some(500 << 10)),
+            ("10MiB/s", Some(10 << 20)),
+            ("10 MB/s", Some(10 << 20)),
+            ("1gib", Some(1 << 30)),
+            ("64B/s", Some(64)),
+            ("0", None),
+            ("0k/s", None),
+            ("/s", None),
+            ("10Mbit/s", None),
+            ("10MiB/h", None),
+            ("-5k", None),
+        ];
+        for (rate, bytes) in cases {
+            assert_eq!(parse_rate(rate).ok(), bytes, "{}", rate);
+        }
+        assert_eq!(parse_rate("fast"), Err("invalid rate \"fast\", expected e.g. \"10MiB/s\" or \"500k\"".to_owned()));
+    }
+}
@@ -0,0 +1,329 @@
+//! Reading the pictures embedded in audio files, for players that only show a cover file next to
+//! them: those of ID3v2 tags, as in MP3s, `covr` atoms in MP4s and FLAC's PICTURE blocks. Only
+//! JPEG and PNG pictures are read, as those are what players show.
+
+use std::{convert::TryFrom, fs::File, io::{self, ErrorKind, Read, Seek, SeekFrom}, path::Path};
+
+/// The formats pictures are read in, told apart by their contents rather than what the tag says.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ImageFormat {
+    Jpeg,
+    Png,
+}
+
+impl ImageFormat {
+    fn of(data: &[u8]) -> Option<ImageFormat> {
+        if data.starts_with(&[0xFF, 0xD8, 0xFF]) {
+            Some(ImageFormat::Jpeg)
+        }
+        else if data.starts_with(b"\x89PNG\r\n\x1A\n") {
+            Some(ImageFormat::Png)
+        }
+        else {
+            None
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            ImageFormat::Jpeg => "jpg",
+            ImageFormat::Png => "png",
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Picture {
+    pub format: ImageFormat,
+    pub data: Vec<u8>,
+}
+
+impl Picture {
+    /// The width and height of the picture in pixels, if its header says.
+    pub fn dimensions(&self) -> Option<(u32, u32)> {
+        match self.format {
+            ImageFormat::Png => {
+                let ihdr = self.data.get(16..24)?;
+                Some((u32_be(&ihdr[..4]), u32_be(&ihdr[4..])))
+            }
+            ImageFormat::Jpeg => jpeg_dimensions(&self.data),
+        }
+    }
+}
+
+/// The picture to show for the audio file at `path`: its front cover if it says which that is, or
+/// else the first it has. `None` if it has none, or is in none of the formats read.
+pub fn front_cover(path: &Path) -> Result<Option<Picture>, io::Error> {
+    let mut file = File::open(path)?;
+    let mut header = [0; 10];
+    let read = read_up_to(&mut file, &mut header)?;
+    let header = &header[..read];
+    if header.starts_with(b"ID3") && header.len() == 10 {
+        let size = syncsafe(&header[6..10]);
+        let mut tag = vec![0; usize::try_from(size).unwrap_or(usize::MAX)];
+        file.read_exact(&mut tag)?;
+        if let Some(picture) = id3_picture(header[3], header[5], tag) {
+            return Ok(Some(picture));
+        }
+        // FLAC files sometimes start with an ID3 tag too.
+        let mut marker = [0; 4];
+        if read_up_to(&mut file, &mut marker)? == 4 && &marker == b"fLaC" {
+            return flac_picture(&mut file);
+        }
+        Ok(None)
+    }
+    else if header.starts_with(b"fLaC") {
+        file.seek(SeekFrom::Start(4))?;
+        flac_picture(&mut file)
+    }
+    else if header.get(4..8) == Some(b"ftyp") {
+        mp4_picture(&mut file)
+    }
+    else {
+        Ok(None)
+    }
+}
+
+/// Fills as much of `buf` as there is left of `reader`, returning how much that was.
+fn read_up_to(reader: &mut impl Read, buf: &mut [u8]) -> Result<usize, io::Error> {
+    let mut read = 0;
+    while read < buf.len() {
+        match reader.read(&mut buf[read..]) {
+            Ok(0) => break,
+            Ok(n) => read += n,
+            Err(e) if e.kind() == ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(read)
+}
+
+fn u32_be(bytes: &[u8]) -> u32 {
+    bytes.iter().fold(0, |n, &b| (n << 8) | u32::from(b))
+}
+
+/// A number stored 7 bits to a byte, as ID3v2 does so that it never looks like a sync signal.
+fn syncsafe(bytes: &[u8]) -> u32 {
+    bytes.iter().fold(0, |n, &b| (n << 7) | u32::from(b & 0x7F))
+}
+
+/// Undoes ID3v2's unsynchronisation, which follows every 0xFF with a 0x00.
+fn resynchronize(data: &[u8]) -> Vec<u8> {
+    let mut resynced = Vec::with_capacity(data.len());
+    for (i, &b) in data.iter().enumerate() {
+        if !(b == 0 && i > 0 && data[i - 1] == 0xFF) {
+            resynced.push(b);
+        }
+    }
+    resynced
+}
+
+/// The picture in the frames of an ID3v2 tag of this major version, after its header.
+fn id3_picture(version: u8, flags: u8, tag: Vec<u8>) -> Option<Picture> {
+    // Version 2.4 unsynchronises frame by frame instead.
+    let mut tag = match flags & 0x80 != 0 && version < 4 {
+        true => resynchronize(&tag),
+        false => tag,
+    };
+    if flags & 0x40 != 0 && version >= 3 {
+        let size = u32_be(tag.get(..4)?);
+        let skipped = match version {
+            3 => size as usize + 4,
+            _ => syncsafe(&tag[..4]) as usize,
+        };
+        tag.drain(..skipped.min(tag.len()));
+    }
+    let (id_len, header_len) = match version {
+        2 => (3, 6),
+        3 | 4 => (4, 10),
+        _ => return None,
+    };
+
+    let mut first = None;
+    let mut rest = &tag[..];
+    while rest.len() >= header_len && rest[0] != 0 {
+        let id = &rest[..id_len];
+        let size = match version {
+            2 => u32_be(&rest[3..6]),
+            3 => u32_be(&rest[4..8]),
+            _ => syncsafe(&rest[4..8]),
+        } as usize;
+        let frame_flags = match version {
+            2 => 0,
+            _ => u32_be(&rest[8..10]),
+        };
+        let body = match rest.get(header_len..header_len + size) {
+            Some(body) => body,
+            None => break,
+        };
+        rest = &rest[header_len + size..];
+        if id != b"APIC" && id != b"PIC" {
+            continue;
+        }
+        // Compressed or encrypted frames are left alone.
+        let unreadable = match version {
+            3 => frame_flags & 0x00C0 != 0,
+            4 => frame_flags & 0x000C != 0,
+            _ => false,
+        };
+        if unreadable {
+            continue;
+        }
+        let body = match version {
+            4 => {
+                let body = if frame_flags & 0x0001 != 0 { body.get(4..)? } else { body };
+                if frame_flags & 0x0002 != 0 { resynchronize(body) } else { body.to_vec() }
+            }
+            _ => body.to_vec(),
+        };
+        if let Some((picture_type, picture)) = id3_picture_frame(version, &body) {
+            if picture_type == FRONT_COVER {
+                return Some(picture);
+            }
+            first = first.or(Some(picture));
+        }
+    }
+    first
+}
+
+/// The type ID3v2 and FLAC give front covers.
+const FRONT_COVER: u32 = 3;
+
+/// The type and picture of an APIC frame, or the PIC frame of version 2.2.
+fn id3_picture_frame(version: u8, body: &[u8]) -> Option<(u32, Picture)> {
+    let encoding = *body.first()?;
+    let rest = match version {
+        2 => body.get(4..)?,
+        _ => {
+            let mime_end = body[1..].iter().position(|&b| b == 0)?;
+            &body[mime_end + 2..]
+        }
+    };
+    let picture_type = u32::from(*rest.first()?);
+    let description = &rest[1..];
+    let data_start = match encoding {
+        // UTF-16, ending in a double zero on a character boundary.
+        1 | 2 => description.chunks(2).position(|c| c == [0, 0])? * 2 + 2,
+        _ => description.iter().position(|&b| b == 0)? + 1,
+    };
+    let data = description.get(data_start..)?;
+    let format = ImageFormat::of(data)?;
+    Some((picture_type, Picture { format, data: data.to_vec() }))
+}
+
+/// The picture among a FLAC file's metadata blocks, read from just after its marker.
+fn flac_picture(file: &mut File) -> Result<Option<Picture>, io::Error> {
+    const PICTURE: u8 = 6;
+    let mut first = None;
+    loop {
+        let mut header = [0; 4];
+        if read_up_to(file, &mut header)? < 4 {
+            return Ok(first);
+        }
+        let (last, kind, length) = (header[0] & 0x80 != 0, header[0] & 0x7F, u32_be(&header[1..]));
+        if kind == PICTURE {
+            let mut block = vec![0; length as usize];
+            file.read_exact(&mut block)?;
+            if let Some((picture_type, picture)) = flac_picture_block(&block) {
+                if picture_type == FRONT_COVER {
+                    return Ok(Some(picture));
+                }
+                first = first.or(Some(picture));
+            }
+        }
+        else {
+            file.seek(SeekFrom::Current(i64::from(length)))?;
+        }
+        if last {
+            return Ok(first);
+        }
+    }
+}
+
+fn flac_picture_block(block: &[u8]) -> Option<(u32, Picture)> {
+    // The type, then the MIME type and description, each after their length.
+    let picture_type = u32_be(block.get(..4)?);
+    let mut at = 4;
+    for _ in 0..2 {
+        at += 4 + u32_be(block.get(at..at + 4)?) as usize;
+    }
+    // The width, height, color depth and number of colors, which the picture itself tells.
+    at += 16;
+    let length = u32_be(block.get(at..at + 4)?) as usize;
+    let data = block.get(at + 4..at + 4 + length)?;
+    let format = ImageFormat::of(data)?;
+    Some((picture_type, Picture { format, data: data.to_vec() }))
+}
+
+/// The first picture of an MP4 file's `covr` atom, which says nothing of which is the front cover.
+fn mp4_picture(file: &mut File) -> Result<Option<Picture>, io::Error> {
+    file.seek(SeekFrom::Start(0))?;
+    // The metadata is all in `moov`, which is read in whole, but the media data can be skipped.
+    let moov = loop {
+        let mut header = [0; 8];
+        if read_up_to(file, &mut header)? < 8 {
+            return Ok(None);
+        }
+        let (size, header_len) = match u32_be(&header[..4]) {
+            1 => {
+                let mut large = [0; 8];
+                file.read_exact(&mut large)?;
+                (u64::from(u32_be(&large[..4])) << 32 | u64::from(u32_be(&large[4..])), 16)
+            }
+            0 => return Ok(None),
+            size => (u64::from(size), 8),
+        };
+        let body_len = size.checked_sub(header_len).ok_or_else(|| io::Error::new(ErrorKind::InvalidData, "an MP4 atom is too short"))?;
+        if &header[4..] == b"moov" {
+            let mut moov = vec![0; usize::try_from(body_len).unwrap_or(usize::MAX)];
+            file.read_exact(&mut moov)?;
+            break moov;
+        }
+        file.seek(SeekFrom::Current(i64::try_from(body_len).unwrap_or(i64::MAX)))?;
+    };
+    let picture = find_atom(&moov, b"udta")
+        .and_then(|udta| find_atom(udta, b"meta"))
+        // `meta` has a version and flags before its children.
+        .and_then(|meta| find_atom(meta.get(4..)?, b"ilst"))
+        .and_then(|ilst| find_atom(ilst, b"covr"))
+        .and_then(|covr| find_atom(covr, b"data"))
+        // After the type of the data and its locale.
+        .and_then(|data| data.get(8..))
+        .and_then(|data| Some(Picture { format: ImageFormat::of(data)?, data: data.to_vec() }));
+    Ok(picture)
+}
+
+/// The contents of the first atom of this kind among those in `atoms`.
+fn find_atom<'a>(mut atoms: &'a [u8], kind: &[u8; 4]) -> Option<&'a [u8]> {
+    while atoms.len() >= 8 {
+        let size = u32_be(&atoms[..4]) as usize;
+        let atom = atoms.get(8..size)?;
+        if &atoms[4..8] == kind {
+            return Some(atom);
+        }
+        atoms = &atoms[size..];
+    }
+    None
+}
+
+/// The size of a JPEG from its start-of-frame marker.
+fn jpeg_dimensions(data: &[u8]) -> Option<(u32, u32)> {
+    let mut at = 2;
+    loop {
+        if *data.get(at)? != 0xFF {
+            return None;
+        }
+        let marker = *data.get(at + 1)?;
+        match marker {
+            // Padding, and markers without a length.
+            0xFF => at += 1,
+            0x01 | 0xD0..=0xD8 => at += 2,
+            // Start of frame, except for the markers in that range that are something else.
+            0xC0..=0xCF if !matches!(marker, 0xC4 | 0xC8 | 0xCC) => {
+                let frame = data.get(at + 5..at + 9)?;
+                return Some((u32_be(&frame[2..]), u32_be(&frame[..2])));
+            }
+            _ => at += 2 + u32_be(data.get(at + 2..at + 4)?) as usize,
+        }
+    }
+}
@@ -1,35 +1,231 @@
-use std::{fs::File, io::BufReader, path::PathBuf};
+use std::{convert::Infallible, fs::File, io::BufReader, ops::ControlFlow, path::PathBuf, str::FromStr};
 use structopt::StructOpt;
-use m3trans::Library;
+use m3trans::{AudioFormat, Library, PlaylistKind};
+
+// Only for formatting when the library was exported.
+#[path = "../date.rs"]
+#[allow(dead_code)]
+mod date;
 
 fn main() {
     let args = Args::from_args();
-    let file = File::open(&args.library_file).unwrap();
-    let library = Library::from_raw(
-        plist::from_reader_xml(BufReader::new(file)).unwrap()
-    ).unwrap();
-
-    let mut last_depth = None;
-    let mut current_path = PathBuf::from("/");
-    library.visit_playlists(|id, depth| {
-        if let Some(last_depth) = last_depth {
-            if depth <= last_depth {
-                for _ in 0..=last_depth - depth {
-                    current_path.pop();
+    let library = match args.streaming {
+        false => Library::from_path(&args.library_file),
+        true => File::open(&args.library_file)
+            .map_err(m3trans::LoadError::Io)
+            .and_then(|file| Library::from_reader_streaming(BufReader::new(file))),
+    };
+    let library = match library {
+        Ok(library) => library,
+        Err(e) => {
+            eprintln!("Failed to read library {:?}: {}", args.library_file, e);
+            std::process::exit(1);
+        }
+    };
+
+    if let Some(path) = &args.write_xml {
+        let written = match File::create(path) {
+            Ok(file) => library.to_writer_xml(std::io::BufWriter::new(file)).map_err(|e| e.to_string()),
+            Err(e) => Err(e.to_string()),
+        };
+        if let Err(e) = written {
+            eprintln!("Failed to write library to {:?}: {}", path, e);
+            std::process::exit(1);
+        }
+    }
+
+    // Only above the list of playlists, so that what the other modes print stays easy to read.
+    let lists_playlists = !(args.formats || args.validate || args.unknown || args.find.is_some() || args.counts);
+    if lists_playlists {
+        let unknown = || "unknown".to_owned();
+        println!(
+            "Library: {} Music Folder: {} Version: {} Exported: {}",
+            library.persistent_id.map_or_else(unknown, |id| format!("{:016X}", id)),
+            library.music_folder.clone().unwrap_or_else(unknown),
+            library.application_version.clone().unwrap_or_else(unknown),
+            library.exported_at.map_or_else(unknown, date::rfc3339)
+        );
+    }
+
+    if args.tracks {
+        let mut ids: Vec<_> = library.tracks.keys().copied().collect();
+        ids.sort_unstable();
+        for id in ids {
+            let track = &library.tracks[&id];
+            println!(
+                "Track: {} Name: {:?} Artist: {:?} Album Artist: {:?} Album: {:?} Genre: {:?} Track: {:?}/{:?} Disc: {:?}/{:?} Kind: {:?} Format: {:?} Protected: {} Disabled: {} Path: {:?}",
+                id, track.name, track.artist, track.album_artist, track.album, track.genre,
+                track.track_number, track.track_count, track.disc_number, track.disc_count, track.kind,
+                track.file_format(), track.is_protected(), track.disabled, track.local_path()
+            );
+        }
+    }
+
+    if args.formats {
+        let mut ids: Vec<_> = library.tracks.keys().copied().collect();
+        ids.sort_unstable();
+        let mut counts: Vec<(Option<AudioFormat>, usize)> = Vec::new();
+        for id in ids {
+            let format = library.tracks[&id].file_format();
+            match counts.iter_mut().find(|(known, _)| *known == format) {
+                Some((_, count)) => *count += 1,
+                None => counts.push((format, 1)),
+            }
+        }
+        // Most common first, and otherwise in the order of the first track with each.
+        counts.sort_by(|(_, a), (_, b)| b.cmp(a));
+        for (format, count) in counts {
+            let lossless = format.is_some_and(AudioFormat::is_lossless);
+            match format {
+                Some(format) => println!("Format: {} Lossless: {} Count: {}", format, lossless, count),
+                None => println!("Format: unknown Count: {}", count),
+            }
+        }
+        return;
+    }
+
+    if args.validate {
+        let issues = library.validate();
+        for issue in &issues {
+            println!("Issue: {}", issue);
+        }
+        std::process::exit(if issues.is_empty() { 0 } else { 1 });
+    }
+
+    if args.unknown {
+        // Each unknown Distinguished Kind value with how many playlists have it and the first of them.
+        let mut unknown: Vec<(u64, usize, &str)> = Vec::new();
+        library.visit_playlists(|id, _| {
+            let playlist = &library.playlists[&id];
+            if let PlaylistKind::Unknown(kind) = playlist.kind {
+                match unknown.iter_mut().find(|(known, _, _)| *known == kind) {
+                    Some((_, count, _)) => *count += 1,
+                    None => unknown.push((kind, 1, &playlist.name)),
                 }
             }
+        });
+        unknown.sort_by(|(a, a_count, _), (b, b_count, _)| b_count.cmp(a_count).then(a.cmp(b)));
+        for (kind, count, example) in unknown {
+            println!("Unknown Kind: {} Count: {} Example: {:?}", kind, count, example);
         }
+        return;
+    }
 
-        let playlist = &library.playlists[&id];     
-        current_path.push(playlist.name.to_string());
-        println!("Path: {:?} Kind: {:?}", current_path, playlist.kind);
+    if let Some(path) = &args.find {
+        for playlist in library.find_playlists_by_path(path, args.ignore_case) {
+            println!("Found: {:?} Id: {:016X}", library.virtual_path(playlist), playlist.persistent_id);
+        }
+        return;
+    }
 
-        last_depth = Some(depth);
-    });
+    let mut printed = 0;
+    let mut counts: Vec<(PlaylistKind, usize)> = Vec::new();
+    library.try_visit_playlists_with_path(|path| {
+        let playlist = path[path.len() - 1];
+        if !args.kind.as_ref().is_none_or(|kind| kind.matches(playlist.kind)) {
+            return Ok::<_, Infallible>(ControlFlow::Continue(()));
+        }
+        if args.counts {
+            match counts.iter_mut().find(|(kind, _)| *kind == playlist.kind) {
+                Some((_, count)) => *count += 1,
+                None => counts.push((playlist.kind, 1)),
+            }
+            return Ok(ControlFlow::Continue(()));
+        }
+
+        if args.limit.is_some_and(|limit| printed >= limit) {
+            return Ok(ControlFlow::Break(()));
+        }
+        let names: Vec<&str> = path.iter().map(|playlist| playlist.name.as_str()).collect();
+        println!(
+            "Path: {:?} Id: {:016X} Kind: {:?} Smart: {} Visible: {} Items: {}",
+            format!("/{}", names.join("/")), playlist.persistent_id, playlist.kind, playlist.is_smart,
+            playlist.is_visible, playlist.items.len()
+        );
+        printed += 1;
+        Ok(ControlFlow::Continue(()))
+    }).unwrap_or_else(|never| match never {});
+
+    // Most common first, and otherwise in the order they were first seen.
+    counts.sort_by(|(_, a), (_, b)| b.cmp(a));
+    for (kind, count) in counts {
+        println!("Kind: {:?} Count: {}", kind, count);
+    }
+}
+
+/// Which playlists to print by their kind: unknown, master, generic, folder, system for any known
+/// system playlist, or the name of one kind of system playlist.
+enum KindFilter {
+    Unknown,
+    System,
+    Is(PlaylistKind),
+}
+
+impl KindFilter {
+    fn matches(&self, kind: PlaylistKind) -> bool {
+        match self {
+            KindFilter::Unknown => matches!(kind, PlaylistKind::Unknown(_)),
+            KindFilter::System => kind.is_system(),
+            KindFilter::Is(expected) => kind == *expected,
+        }
+    }
+}
+
+impl FromStr for KindFilter {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "unknown" => Ok(KindFilter::Unknown),
+            "system" => Ok(KindFilter::System),
+            "master" => Ok(KindFilter::Is(PlaylistKind::Master)),
+            "generic" => Ok(KindFilter::Is(PlaylistKind::Generic)),
+            "folder" => Ok(KindFilter::Is(PlaylistKind::Folder)),
+            other => PlaylistKind::from_system_name(other).map(KindFilter::Is).ok_or_else(|| format!(
+                "unknown playlist kind \"{}\"; expected unknown, master, generic, folder, system or one of {}",
+                other, PlaylistKind::system_names().collect::<Vec<_>>().join(", ")
+            )),
+        }
+    }
 }
 
 #[derive(StructOpt)]
 struct Args {
     #[structopt(parse(from_os_str))]
     library_file: PathBuf,
+    /// Also print every track with its metadata.
+    #[structopt(long)]
+    tracks: bool,
+    /// Load the library with the streaming parser.
+    #[structopt(long)]
+    streaming: bool,
+    /// Write the library back out as XML to this path, e.g. to check that it reads back the same.
+    #[structopt(long = "write-xml", parse(from_os_str))]
+    write_xml: Option<PathBuf>,
+    /// Only print the problems found by validating the library, exiting with 1 if there are any.
+    #[structopt(long)]
+    validate: bool,
+    /// Only print the playlists with this slash-separated path of names.
+    #[structopt(long)]
+    find: Option<String>,
+    /// Compare names case-insensitively for --find.
+    #[structopt(long = "ignore-case")]
+    ignore_case: bool,
+    /// Stop after printing this many playlists.
+    #[structopt(long)]
+    limit: Option<usize>,
+    /// Only print playlists of this kind: unknown, master, generic, folder, system, or the name of
+    /// a system playlist kind such as podcasts.
+    #[structopt(long)]
+    kind: Option<KindFilter>,
+    /// Only print each unknown Distinguished Kind value, with how many playlists have it and the
+    /// name of one, most common first.
+    #[structopt(long)]
+    unknown: bool,
+    /// Print how many playlists there are of each kind instead of the playlists themselves.
+    #[structopt(long)]
+    counts: bool,
+    /// Only print how many tracks there are in each file format, most common first.
+    #[structopt(long)]
+    formats: bool,
 }
\ No newline at end of file
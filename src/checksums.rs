@@ -0,0 +1,62 @@
+//! Manifests of the SHA-256 digests of files, in the format `sha256sum` writes, so that
+//! `sha256sum -c` can check them too.
+//!
+//! Each line is a digest in hex, two spaces and a path relative to the manifest. Paths holding a
+//! backslash or line break have them escaped, and the line starts with a backslash to say so.
+
+use std::io::{self, Write};
+
+use crate::sha256::Digest;
+
+/// The name of the manifest, in the output directory.
+pub(crate) const FILE_NAME: &str = "SHA256SUMS";
+
+/// Writes a line for each file, by its path with `/` between components.
+pub(crate) fn write(mut writer: impl Write, entries: &[(String, Digest)]) -> Result<(), io::Error> {
+    for (path, digest) in entries {
+        match path.contains(['\\', '\n', '\r']) {
+            true => {
+                let escaped = path.replace('\\', "\\\\").replace('\n', "\\n").replace('\r', "\\r");
+                writeln!(writer, "\\{}  {}", digest, escaped)?;
+            }
+            false => writeln!(writer, "{}  {}", digest, path)?,
+        }
+    }
+    Ok(())
+}
+
+/// Parses a line of a manifest into the path and digest of a file, or returns `None` if it isn't
+/// one. Lines for files read in binary mode, with `*` before the path, are read the same.
+pub(crate) fn parse_line(line: &str) -> Option<(String, Digest)> {
+    let (escaped, line) = match line.strip_prefix('\\') {
+        Some(rest) => (true, rest),
+        None => (false, line),
+    };
+    let digest = line.get(..64)?.parse().ok()?;
+    let path = line[64..].strip_prefix(" ")?;
+    let path = path.strip_prefix([' ', '*'])?;
+    if path.is_empty() {
+        return None;
+    }
+    match escaped {
+        true => unescape(path).map(|path| (path, digest)),
+        false => Some((path.to_owned(), digest)),
+    }
+}
+
+fn unescape(path: &str) -> Option<String> {
+    let mut unescaped = String::with_capacity(path.len());
+    let mut chars = path.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => unescaped.push(match chars.next()? {
+                '\\' => '\\',
+                'n' => '\n',
+                'r' => '\r',
+                _ => return None,
+            }),
+            c => unescaped.push(c),
+        }
+    }
+    Some(unescaped)
+}
@@ -0,0 +1,338 @@
+//! The subcommands that only read a library or an output, like `m3trans list` and `m3trans verify`.
+
+use std::{fs::{self, File}, io::{self, ErrorKind, Read, Write}, ops::ControlFlow, path::Path};
+use log::*;
+use simplelog::{ColorChoice, Config, TermLogger, TerminalMode};
+use m3trans::{Library, LoadError, Playlist, Track};
+
+use crate::args::{DiffArgs, ExportArgs, ListArgs, ListKind, StatsArgs, VerifyArgs, VerifyOutputArgs};
+use crate::checksums;
+use crate::context;
+use crate::sha256;
+use crate::sync::copy::format_size;
+
+pub(crate) fn run_list(args: &ListArgs) -> Result<(), io::Error> {
+    TermLogger::init(LevelFilter::Warn, Config::default(), TerminalMode::Stderr, ColorChoice::Auto)
+        .map_err(|e| io::Error::other(format!("failed to set up logging: {}", e)))?;
+    let library = read_library(&args.library_file)?;
+
+    /// The number of items in a playlist, or all playlists inside a folder, and their total duration.
+    /// Entries for tracks that aren't in the library aren't counted.
+    fn totals(library: &Library, playlist: &Playlist) -> (usize, u64) {
+        let tracks: Vec<&Track> = library.playlist_tracks(playlist).filter_map(Result::ok).map(|(_, track)| track).collect();
+        let duration = tracks.iter().filter_map(|track| track.duration_ms).map(u64::from).sum();
+        library.children(Some(playlist.persistent_id))
+            .map(|child| totals(library, child))
+            .fold((tracks.len(), duration), |(items, duration), (child_items, child_duration)| {
+                (items + child_items, duration + child_duration)
+            })
+    }
+
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    library.try_visit_playlists_with_path(|path| {
+        let depth = path.len() - 1;
+        let playlist = path[depth];
+        let listed = match args.kind {
+            ListKind::Generic => playlist.kind.is_generic(),
+            ListKind::Folder => playlist.kind.is_folder(),
+            ListKind::All => true,
+        };
+        if !listed {
+            return Ok(ControlFlow::Continue(()));
+        }
+
+        if args.paths {
+            let names: Vec<String> = path.iter().map(|playlist| args.normalize.apply(&playlist.name)).collect();
+            writeln!(out, "{}", names.join("/"))?;
+        }
+        else {
+            let (items, duration_ms) = totals(&library, playlist);
+            let seconds = duration_ms / 1000;
+            writeln!(
+                out, "{}{} ({:?}{}{}, {:016X}): {} tracks, {}:{:02}:{:02}",
+                "  ".repeat(depth), playlist.name, playlist.kind, if playlist.is_smart { ", smart" } else { "" },
+                if playlist.is_visible { "" } else { ", hidden" }, playlist.persistent_id, items, seconds / 3600, seconds / 60 % 60, seconds % 60
+            )?;
+        }
+        Ok::<_, io::Error>(ControlFlow::Continue(()))
+    })
+}
+
+pub(crate) fn run_export(args: &ExportArgs) -> Result<(), io::Error> {
+    TermLogger::init(LevelFilter::Warn, Config::default(), TerminalMode::Stderr, ColorChoice::Auto)
+        .map_err(|e| io::Error::other(format!("failed to set up logging: {}", e)))?;
+    let library = read_library(&args.library_file)?;
+    match &args.output {
+        Some(path) => {
+            let failed = || context(format!("failed to write library to {:?}", path));
+            let mut out = io::BufWriter::new(File::create(path).map_err(failed())?);
+            crate::json::to_writer_pretty(&mut out, &library).map_err(|e| failed()(e.into()))?;
+            writeln!(out).and_then(|()| out.flush()).map_err(failed())
+        }
+        None => {
+            let stdout = io::stdout();
+            let mut out = stdout.lock();
+            crate::json::to_writer_pretty(&mut out, &library)?;
+            writeln!(out)
+        }
+    }
+}
+
+pub(crate) fn run_verify(args: &VerifyArgs) -> Result<(), io::Error> {
+    TermLogger::init(LevelFilter::Warn, Config::default(), TerminalMode::Stderr, ColorChoice::Auto)
+        .map_err(|e| io::Error::other(format!("failed to set up logging: {}", e)))?;
+    let library = read_library(&args.library_file)?;
+
+    /// Sizes that differ by more than a tenth are more than edited tags would explain.
+    fn differs_wildly(expected: u64, actual: u64) -> bool {
+        expected.abs_diff(actual) > expected.max(actual) / 10
+    }
+
+    // Each problem, counted and then described, with the tracks that have it.
+    let mut problems: [(&str, &str, Vec<String>); 5] = [
+        ("missing", "are missing", Vec::new()),
+        ("unreadable", "can't be read", Vec::new()),
+        ("empty", "are empty", Vec::new()),
+        ("resized", "are a much different size than the library says", Vec::new()),
+        ("not local", "are somewhere other than a local file", Vec::new()),
+    ];
+    let mut track_ids: Vec<u64> = library.tracks.keys().copied().collect();
+    track_ids.sort_unstable();
+    let mut checked = 0;
+    for track_id in track_ids {
+        let track = &library.tracks[&track_id];
+        // Tracks only in the cloud have no file to check.
+        let location = match &track.location {
+            Some(location) => location,
+            None => continue,
+        };
+        checked += 1;
+        let local_path = match track.local_path_remapped(&args.remap_hosts) {
+            Ok(Some(local_path)) => local_path,
+            Ok(None) => {
+                problems[4].2.push(format!("{} ({}) at {}", track.name, track_id, location));
+                continue;
+            }
+            Err(e) => {
+                problems[4].2.push(format!("{} ({}): {}", track.name, track_id, e));
+                continue;
+            }
+        };
+        let described = format!("{} ({}) at {:?}", track.name, track_id, local_path);
+        let metadata = match fs::metadata(&local_path) {
+            Ok(metadata) if metadata.is_dir() => {
+                problems[1].2.push(format!("{}: is a folder", described));
+                continue;
+            }
+            Ok(metadata) => metadata,
+            Err(e) if e.kind() == ErrorKind::NotFound => {
+                problems[0].2.push(described);
+                continue;
+            }
+            Err(e) => {
+                problems[1].2.push(format!("{}: {}", described, e));
+                continue;
+            }
+        };
+        if let Err(e) = File::open(&local_path) {
+            problems[1].2.push(format!("{}: {}", described, e));
+        }
+        else if metadata.len() == 0 {
+            problems[2].2.push(described);
+        }
+        else if let Some(expected) = track.size_bytes.filter(|&expected| differs_wildly(expected, metadata.len())) {
+            problems[3].2.push(format!(
+                "{}: {} instead of {}", described, format_size(metadata.len()), format_size(expected)
+            ));
+        }
+    }
+
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    for (_, problem, tracks) in problems.iter().filter(|(_, _, tracks)| !tracks.is_empty()) {
+        writeln!(out, "{} tracks whose files {}:", tracks.len(), problem)?;
+        for track in tracks {
+            writeln!(out, "    {}", track)?;
+        }
+    }
+    let found: usize = problems.iter().map(|(_, _, tracks)| tracks.len()).sum();
+    let counts: Vec<String> = problems.iter().map(|(problem, _, tracks)| format!("{} {}", tracks.len(), problem)).collect();
+    writeln!(out, "Checked the files of {} tracks: {}.", checked, counts.join(", "))?;
+    match found {
+        0 => Ok(()),
+        found => Err(io::Error::other(format!("{} tracks have problems with their files", found))),
+    }
+}
+
+pub(crate) fn run_verify_output(args: &VerifyOutputArgs) -> Result<(), io::Error> {
+    TermLogger::init(LevelFilter::Warn, Config::default(), TerminalMode::Stderr, ColorChoice::Auto)
+        .map_err(|e| io::Error::other(format!("failed to set up logging: {}", e)))?;
+    let manifest_path = args.output_path.join(checksums::FILE_NAME);
+    let manifest = fs::read_to_string(&manifest_path).map_err(context(format!(
+        "failed to read the checksums at {:?}, which syncing with --checksums writes", manifest_path
+    )))?;
+
+    let mut problems: [(&str, &str, Vec<String>); 3] = [
+        ("missing", "are missing", Vec::new()),
+        ("unreadable", "can't be read", Vec::new()),
+        ("changed", "don't match their checksums", Vec::new()),
+    ];
+    let mut checked = 0;
+    for (number, line) in manifest.lines().enumerate() {
+        let (name, digest) = match checksums::parse_line(line) {
+            Some(entry) => entry,
+            None => {
+                warn!("Skipping line {} of {:?}, which isn't a checksum and a path.", number + 1, manifest_path);
+                continue;
+            }
+        };
+        checked += 1;
+        match File::open(args.output_path.join(&name)).and_then(sha256::digest_reader) {
+            Ok(actual) if actual == digest => {},
+            Ok(_) => problems[2].2.push(name),
+            Err(e) if e.kind() == ErrorKind::NotFound => problems[0].2.push(name),
+            Err(e) => problems[1].2.push(format!("{}: {}", name, e)),
+        }
+    }
+
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    for (_, problem, files) in problems.iter().filter(|(_, _, files)| !files.is_empty()) {
+        writeln!(out, "{} files that {}:", files.len(), problem)?;
+        for file in files {
+            writeln!(out, "    {}", file)?;
+        }
+    }
+    let found: usize = problems.iter().map(|(_, _, files)| files.len()).sum();
+    let counts: Vec<String> = problems.iter().map(|(problem, _, files)| format!("{} {}", files.len(), problem)).collect();
+    writeln!(out, "Checked {} files: {}.", checked, counts.join(", "))?;
+    match found {
+        0 => Ok(()),
+        found => Err(io::Error::other(format!("{} files are missing, unreadable or changed", found))),
+    }
+}
+
+pub(crate) fn run_stats(args: &StatsArgs) -> Result<(), io::Error> {
+    TermLogger::init(LevelFilter::Warn, Config::default(), TerminalMode::Stderr, ColorChoice::Auto)
+        .map_err(|e| io::Error::other(format!("failed to set up logging: {}", e)))?;
+    let stats = crate::stats::stats(&read_library(&args.library_file)?);
+
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    if args.json {
+        crate::json::to_writer_pretty(&mut out, &stats)?;
+        return writeln!(out);
+    }
+
+    let duration = |duration_ms: u64| {
+        let seconds = duration_ms / 1000;
+        format!("{}:{:02}:{:02}", seconds / 3600, seconds / 60 % 60, seconds % 60)
+    };
+    writeln!(out, "{} tracks, {}, {}", stats.tracks, duration(stats.duration_ms), format_size(stats.size_bytes))?;
+    let sections = [("Genres", &stats.genres, "no genre"), ("Composers", &stats.composers, "no composer"), ("Formats", &stats.formats, "unknown format")];
+    for (heading, groups, unnamed) in sections {
+        // Most libraries don't have composers, which would only make a heading of one nameless group.
+        if heading == "Composers" && groups.iter().all(|group| group.name.is_none()) {
+            continue;
+        }
+        writeln!(out, "{}:", heading)?;
+        for group in groups {
+            writeln!(
+                out, "    {}: {} tracks, {}, {}",
+                group.name.as_deref().unwrap_or(unnamed), group.tracks, duration(group.duration_ms), format_size(group.size_bytes)
+            )?;
+        }
+    }
+    let kinds: Vec<String> = stats.playlist_kinds.iter().map(|kind| format!("{} {}", kind.playlists, kind.kind)).collect();
+    let playlists: usize = stats.playlist_kinds.iter().map(|kind| kind.playlists).sum();
+    writeln!(
+        out, "{} playlists ({}), with {:.1} tracks each on average",
+        playlists, kinds.join(", "), stats.average_playlist_tracks
+    )?;
+    if !stats.largest_tracks.is_empty() {
+        writeln!(out, "Largest tracks:")?;
+        for track in &stats.largest_tracks {
+            match &track.artist {
+                Some(artist) => writeln!(out, "    {}: {} by {} ({})", format_size(track.size_bytes), track.name, artist, track.track_id)?,
+                None => writeln!(out, "    {}: {} ({})", format_size(track.size_bytes), track.name, track.track_id)?,
+            }
+        }
+    }
+    Ok(())
+}
+
+pub(crate) fn run_diff(args: &DiffArgs) -> Result<(), io::Error> {
+    TermLogger::init(LevelFilter::Warn, Config::default(), TerminalMode::Stderr, ColorChoice::Auto)
+        .map_err(|e| io::Error::other(format!("failed to set up logging: {}", e)))?;
+    let diff = crate::diff::diff(&read_library(&args.old_library)?, &read_library(&args.new_library)?);
+
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    if args.json {
+        crate::json::to_writer_pretty(&mut out, &diff)?;
+        return writeln!(out);
+    }
+
+    let location = |location: &Option<String>| location.clone().unwrap_or_else(|| "<no location>".to_owned());
+    for track in &diff.added_tracks {
+        writeln!(out, "+ track {} ({})", track.name, location(&track.location))?;
+    }
+    for track in &diff.removed_tracks {
+        writeln!(out, "- track {} ({})", track.name, location(&track.location))?;
+    }
+    for track in &diff.moved_tracks {
+        writeln!(out, "~ track {} moved from {} to {}", track.name, location(&track.old_location), location(&track.new_location))?;
+    }
+    for playlist in &diff.added_playlists {
+        writeln!(out, "+ playlist {} ({:016X})", playlist.name, playlist.persistent_id)?;
+    }
+    for playlist in &diff.removed_playlists {
+        writeln!(out, "- playlist {} ({:016X})", playlist.name, playlist.persistent_id)?;
+    }
+    for playlist in &diff.renamed_playlists {
+        writeln!(out, "~ playlist {} renamed to {} ({:016X})", playlist.old_name, playlist.new_name, playlist.persistent_id)?;
+    }
+    for playlist in &diff.reordered_playlists {
+        writeln!(out, "~ playlist {} reordered ({:016X})", playlist.name, playlist.persistent_id)?;
+    }
+    for playlist in &diff.changed_playlists {
+        writeln!(
+            out, "~ playlist {} gained {} and lost {} tracks ({:016X})",
+            playlist.name, playlist.added_tracks, playlist.removed_tracks, playlist.persistent_id
+        )?;
+    }
+    if diff.is_empty() {
+        writeln!(out, "No changes.")?;
+    }
+    Ok(())
+}
+
+/// Whether a library path of `-` asks for the library to be read from standard input.
+pub(crate) fn is_stdin(path: &Path) -> bool {
+    path == Path::new("-")
+}
+
+pub(crate) fn read_library(path: &Path) -> Result<Library, io::Error> {
+    let library = match is_stdin(path) {
+        // Property lists are read with seeking, which standard input can't do, so it's read whole.
+        true => {
+            let mut bytes = Vec::new();
+            io::stdin().lock().read_to_end(&mut bytes)
+                .map_err(context("failed to read library from standard input".to_owned()))?;
+            Library::from_reader(io::Cursor::new(bytes))
+        }
+        false => Library::from_path(path),
+    };
+    library.map_err(|e| {
+        let kind = match &e {
+            LoadError::Io(e) => e.kind(),
+            _ => ErrorKind::InvalidData,
+        };
+        let source = match is_stdin(path) {
+            true => "from standard input".to_owned(),
+            false => format!("{:?}", path),
+        };
+        io::Error::new(kind, format!("failed to read library {}: {}", source, e))
+    })
+}
@@ -0,0 +1,204 @@
+//! Comparison of two exports of a library.
+
+use std::collections::HashMap;
+
+use serde::{Serialize, Serializer};
+
+use m3trans::{Library, Playlist, Track};
+
+/// The changes from one export of a library to another.
+#[derive(Debug, Default, Serialize)]
+pub(crate) struct LibraryDiff {
+    pub(crate) added_tracks: Vec<TrackRef>,
+    pub(crate) removed_tracks: Vec<TrackRef>,
+    pub(crate) moved_tracks: Vec<MovedTrack>,
+    pub(crate) added_playlists: Vec<PlaylistRef>,
+    pub(crate) removed_playlists: Vec<PlaylistRef>,
+    pub(crate) renamed_playlists: Vec<RenamedPlaylist>,
+    /// Playlists whose tracks are the same, but in a different order.
+    pub(crate) reordered_playlists: Vec<PlaylistRef>,
+    /// Playlists that gained or lost tracks.
+    pub(crate) changed_playlists: Vec<ChangedPlaylist>,
+}
+
+impl LibraryDiff {
+    pub(crate) fn is_empty(&self) -> bool {
+        self.added_tracks.is_empty()
+            && self.removed_tracks.is_empty()
+            && self.moved_tracks.is_empty()
+            && self.added_playlists.is_empty()
+            && self.removed_playlists.is_empty()
+            && self.renamed_playlists.is_empty()
+            && self.reordered_playlists.is_empty()
+            && self.changed_playlists.is_empty()
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct TrackRef {
+    /// The ID of the track in the library it is from.
+    pub(crate) track_id: u64,
+    pub(crate) name: String,
+    pub(crate) location: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct MovedTrack {
+    pub(crate) old_track_id: u64,
+    pub(crate) new_track_id: u64,
+    pub(crate) name: String,
+    pub(crate) old_location: Option<String>,
+    pub(crate) new_location: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct PlaylistRef {
+    #[serde(serialize_with = "hex_id")]
+    pub(crate) persistent_id: u64,
+    pub(crate) name: String,
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct RenamedPlaylist {
+    #[serde(serialize_with = "hex_id")]
+    pub(crate) persistent_id: u64,
+    pub(crate) old_name: String,
+    pub(crate) new_name: String,
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct ChangedPlaylist {
+    #[serde(serialize_with = "hex_id")]
+    pub(crate) persistent_id: u64,
+    pub(crate) name: String,
+    pub(crate) added_tracks: usize,
+    pub(crate) removed_tracks: usize,
+}
+
+fn hex_id<S: Serializer>(id: &u64, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&format!("{:016X}", id))
+}
+
+/// What identifies the same track in both libraries: its persistent ID where it has one, as track
+/// IDs are renumbered between exports, and its location otherwise.
+#[derive(Clone, PartialEq, Eq, Hash)]
+enum TrackKey<'a> {
+    Persistent(u64),
+    Location(&'a str),
+}
+
+fn track_key(track: &Track) -> Option<TrackKey<'_>> {
+    match (track.persistent_id, &track.location) {
+        (Some(id), _) => Some(TrackKey::Persistent(id)),
+        (None, Some(location)) => Some(TrackKey::Location(location)),
+        // Tracks with neither can't be told apart from others, so they aren't compared.
+        (None, None) => None,
+    }
+}
+
+fn track_keys(library: &Library) -> HashMap<TrackKey<'_>, u64> {
+    library.tracks.iter()
+        .filter_map(|(id, track)| track_key(track).map(|key| (key, *id)))
+        .collect()
+}
+
+/// The keys of the tracks in a playlist, so that renumbered track IDs don't count as changes.
+/// Items that aren't in the library, or can't be compared, are left out.
+fn item_keys<'a>(library: &'a Library, playlist: &Playlist) -> Vec<TrackKey<'a>> {
+    playlist.items.iter()
+        .filter_map(|id| library.tracks.get(id).and_then(track_key))
+        .collect()
+}
+
+fn track_ref(id: u64, track: &Track) -> TrackRef {
+    TrackRef { track_id: id, name: track.name.clone(), location: track.location.clone() }
+}
+
+fn playlist_ref(playlist: &Playlist) -> PlaylistRef {
+    PlaylistRef { persistent_id: playlist.persistent_id, name: playlist.name.clone() }
+}
+
+/// Compares two libraries, matching tracks by persistent ID or else by location, and playlists by
+/// persistent ID. Changes are listed by track ID and persistent ID, for reproducible output.
+pub(crate) fn diff(old: &Library, new: &Library) -> LibraryDiff {
+    let mut diff = LibraryDiff::default();
+    let old_keys = track_keys(old);
+    let new_keys = track_keys(new);
+
+    for (key, &new_id) in &new_keys {
+        let new_track = &new.tracks[&new_id];
+        match old_keys.get(key) {
+            None => diff.added_tracks.push(track_ref(new_id, new_track)),
+            Some(&old_id) => {
+                let old_track = &old.tracks[&old_id];
+                if old_track.location != new_track.location {
+                    diff.moved_tracks.push(MovedTrack {
+                        old_track_id: old_id,
+                        new_track_id: new_id,
+                        name: new_track.name.clone(),
+                        old_location: old_track.location.clone(),
+                        new_location: new_track.location.clone(),
+                    });
+                }
+            }
+        }
+    }
+    for (key, &old_id) in &old_keys {
+        if !new_keys.contains_key(key) {
+            diff.removed_tracks.push(track_ref(old_id, &old.tracks[&old_id]));
+        }
+    }
+
+    for (id, new_playlist) in &new.playlists {
+        let old_playlist = match old.playlists.get(id) {
+            Some(old_playlist) => old_playlist,
+            None => {
+                diff.added_playlists.push(playlist_ref(new_playlist));
+                continue;
+            }
+        };
+        if old_playlist.name != new_playlist.name {
+            diff.renamed_playlists.push(RenamedPlaylist {
+                persistent_id: *id,
+                old_name: old_playlist.name.clone(),
+                new_name: new_playlist.name.clone(),
+            });
+        }
+
+        let old_items = item_keys(old, old_playlist);
+        let new_items = item_keys(new, new_playlist);
+        if old_items == new_items {
+            continue;
+        }
+        // Counted per occurrence, as a track can be in a playlist more than once.
+        let mut counts: HashMap<&TrackKey, isize> = HashMap::new();
+        for key in &new_items { *counts.entry(key).or_default() += 1; }
+        for key in &old_items { *counts.entry(key).or_default() -= 1; }
+        let added_tracks = counts.values().filter(|&&count| count > 0).sum::<isize>() as usize;
+        let removed_tracks = -counts.values().filter(|&&count| count < 0).sum::<isize>() as usize;
+        match added_tracks + removed_tracks {
+            0 => diff.reordered_playlists.push(playlist_ref(new_playlist)),
+            _ => diff.changed_playlists.push(ChangedPlaylist {
+                persistent_id: *id,
+                name: new_playlist.name.clone(),
+                added_tracks,
+                removed_tracks,
+            }),
+        }
+    }
+    for (id, old_playlist) in &old.playlists {
+        if !new.playlists.contains_key(id) {
+            diff.removed_playlists.push(playlist_ref(old_playlist));
+        }
+    }
+
+    diff.added_tracks.sort_by_key(|track| track.track_id);
+    diff.removed_tracks.sort_by_key(|track| track.track_id);
+    diff.moved_tracks.sort_by_key(|track| track.new_track_id);
+    diff.added_playlists.sort_by_key(|playlist| playlist.persistent_id);
+    diff.removed_playlists.sort_by_key(|playlist| playlist.persistent_id);
+    diff.renamed_playlists.sort_by_key(|playlist| playlist.persistent_id);
+    diff.reordered_playlists.sort_by_key(|playlist| playlist.persistent_id);
+    diff.changed_playlists.sort_by_key(|playlist| playlist.persistent_id);
+    diff
+}
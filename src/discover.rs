@@ -0,0 +1,45 @@
+//! Finding the library that iTunes or Music.app keeps up to date in a user's home directory.
+
+use std::{fs, path::{Path, PathBuf}, time::SystemTime};
+
+/// Where iTunes and Music.app write the library XML, relative to the home directory. Older
+/// versions of iTunes used the first name, and Music.app only writes the last when "Share Library
+/// XML with other applications" is turned on. iTunes for Windows uses the same paths under the
+/// user's profile folder.
+const LOCATIONS: &[&str] = &[
+    "Music/iTunes/iTunes Music Library.xml",
+    "Music/iTunes/iTunes Library.xml",
+    "Music/Music/Library.xml",
+];
+
+/// The places under `home` a library is looked for, in the order they are tried.
+pub(crate) fn candidates(home: &Path) -> Vec<PathBuf> {
+    LOCATIONS.iter().map(|location| home.join(location)).collect()
+}
+
+/// Finds the most recently modified of the candidate libraries under `home`, or if there are none
+/// returns the paths that were tried.
+pub(crate) fn find_library(home: &Path) -> Result<PathBuf, Vec<PathBuf>> {
+    let candidates = candidates(home);
+    let newest = candidates.iter()
+        .filter_map(|path| {
+            let metadata = fs::metadata(path).ok().filter(|metadata| metadata.is_file())?;
+            Some((metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH), path))
+        })
+        // The first candidate wins ties, by coming last in reverse.
+        .rev()
+        .max_by_key(|(modified, _)| *modified);
+    match newest {
+        Some((_, path)) => Ok(path.clone()),
+        None => Err(candidates),
+    }
+}
+
+/// The current user's home directory, from `HOME`, or `USERPROFILE` on Windows.
+pub(crate) fn home_dir() -> Option<PathBuf> {
+    let var = match cfg!(windows) {
+        true => "USERPROFILE",
+        false => "HOME",
+    };
+    std::env::var_os(var).filter(|home| !home.is_empty()).map(PathBuf::from)
+}
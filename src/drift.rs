@@ -0,0 +1,231 @@
+//! Comparison of the report of an earlier sync with what a sync would do now, to tell what has
+//! drifted apart between the library and the device since.
+
+use std::{collections::{HashMap, HashSet}, path::Path};
+
+use serde::Serialize;
+
+use m3trans::Library;
+
+/// The report written by `--report`: a row for each track, and one for each playlist file.
+#[derive(Debug, Default)]
+pub(crate) struct Report {
+    pub(crate) tracks: Vec<TrackRow>,
+    pub(crate) playlists: Vec<PlaylistRow>,
+}
+
+#[derive(Debug)]
+pub(crate) struct TrackRow {
+    pub(crate) track_id: u64,
+    pub(crate) name: String,
+    pub(crate) destination: String,
+    /// What was done with the track, like `copied` or `ignored`.
+    pub(crate) action: String,
+    pub(crate) reason: String,
+}
+
+#[derive(Debug)]
+pub(crate) struct PlaylistRow {
+    /// The persistent ID of the playlist, in hex, or empty for files that belong to none.
+    pub(crate) playlist_id: String,
+    pub(crate) name: String,
+    pub(crate) destination: String,
+    pub(crate) action: String,
+    pub(crate) reason: String,
+    /// The track IDs of its entries, in order, if the report lists them.
+    pub(crate) tracks: Option<Vec<u64>>,
+}
+
+impl Report {
+    /// Reads a report from the text of its two files, whose fields are separated by `delimiter`.
+    /// Columns are found by name, so that reports with more or fewer of them can be read; reports
+    /// of earlier versions have no `tracks` column for playlists.
+    pub(crate) fn parse(tracks: &str, playlists: &str, delimiter: char) -> Result<Report, String> {
+        let mut report = Report::default();
+        for row in Rows::parse(tracks, delimiter, &["track_id", "destination", "action"])?.iter() {
+            let track_id = row.get("track_id");
+            report.tracks.push(TrackRow {
+                track_id: track_id.parse().map_err(|_| format!("the track ID \"{}\" isn't a number", track_id))?,
+                name: row.get("name").to_owned(),
+                destination: row.get("destination").to_owned(),
+                action: row.get("action").to_owned(),
+                reason: row.get("reason").to_owned(),
+            });
+        }
+        let rows = Rows::parse(playlists, delimiter, &["playlist_id", "destination", "action"])?;
+        let has_tracks = rows.columns.contains_key("tracks");
+        for row in rows.iter() {
+            let tracks = match has_tracks {
+                true => Some(row.get("tracks").split_whitespace()
+                    .map(|id| id.parse().map_err(|_| format!("the track ID \"{}\" isn't a number", id)))
+                    .collect::<Result<Vec<u64>, String>>()?),
+                false => None,
+            };
+            report.playlists.push(PlaylistRow {
+                playlist_id: row.get("playlist_id").to_owned(),
+                name: row.get("name").to_owned(),
+                destination: row.get("destination").to_owned(),
+                action: row.get("action").to_owned(),
+                reason: row.get("reason").to_owned(),
+                tracks,
+            });
+        }
+        Ok(report)
+    }
+}
+
+/// The records of a report file after its header, with where each column is.
+struct Rows {
+    columns: HashMap<String, usize>,
+    records: Vec<Vec<String>>,
+}
+
+struct Row<'a> {
+    columns: &'a HashMap<String, usize>,
+    fields: &'a [String],
+}
+
+impl Rows {
+    fn parse(text: &str, delimiter: char, required: &[&str]) -> Result<Rows, String> {
+        let mut records = crate::csv::parse(text, delimiter)?.into_iter();
+        let header = records.next().ok_or_else(|| "it is empty".to_owned())?;
+        let columns: HashMap<String, usize> = header.into_iter().enumerate().map(|(i, name)| (name, i)).collect();
+        if let Some(missing) = required.iter().find(|column| !columns.contains_key(**column)) {
+            return Err(format!("it has no {} column", missing));
+        }
+        Ok(Rows { columns, records: records.collect() })
+    }
+
+    fn iter(&self) -> impl Iterator<Item = Row<'_>> {
+        self.records.iter().map(move |fields| Row { columns: &self.columns, fields })
+    }
+}
+
+impl Row<'_> {
+    /// The field in `column`, or an empty one if the report or this row has none there.
+    fn get(&self, column: &str) -> &str {
+        self.columns.get(column).and_then(|&i| self.fields.get(i)).map_or("", String::as_str)
+    }
+}
+
+/// What changed from the report of an earlier sync to what a sync would do now.
+#[derive(Debug, Default, Serialize)]
+pub(crate) struct Drift {
+    /// Tracks synced now that weren't then.
+    pub(crate) new_tracks: Vec<TrackRef>,
+    /// Tracks synced then that are no longer in the library.
+    pub(crate) removed_tracks: Vec<TrackRef>,
+    /// Playlists whose entries are different, or in a different order. Always empty if the
+    /// earlier report doesn't list the entries of playlists.
+    pub(crate) changed_playlists: Vec<ChangedPlaylist>,
+    /// Files the earlier report says are in the output that no longer are.
+    pub(crate) missing_files: Vec<MissingFile>,
+    /// Whether the entries of playlists could be compared.
+    pub(crate) compared_playlists: bool,
+}
+
+impl Drift {
+    pub(crate) fn is_empty(&self) -> bool {
+        self.new_tracks.is_empty() && self.removed_tracks.is_empty() && self.changed_playlists.is_empty() && self.missing_files.is_empty()
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct TrackRef {
+    pub(crate) track_id: u64,
+    pub(crate) name: String,
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct ChangedPlaylist {
+    pub(crate) playlist_id: String,
+    pub(crate) name: String,
+    /// How many of its tracks weren't in it then.
+    pub(crate) added_tracks: usize,
+    /// How many of the tracks in it then no longer are.
+    pub(crate) removed_tracks: usize,
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct MissingFile {
+    pub(crate) path: String,
+    /// The track or playlist the file is of, if any.
+    pub(crate) name: String,
+}
+
+/// Outcomes of things left out on purpose, which put nothing in the output.
+const IGNORED: &str = "ignored";
+
+/// Whether a row of a report says its file was in the output once the sync was done.
+fn left_in_output(action: &str, reason: &str) -> bool {
+    match action {
+        "copied" | "linked" | "moved" | "written" | "created" => true,
+        "skipped" => matches!(reason, "exists" | "unchanged"),
+        _ => false,
+    }
+}
+
+/// Compares the report of an earlier sync, `old`, with `new`, made from what a sync of `library`
+/// would do now. `exists` tells whether a file the old report names is still there.
+pub(crate) fn compare(old: &Report, new: &Report, library: &Library, exists: impl Fn(&Path) -> bool) -> Drift {
+    let mut drift = Drift::default();
+    let synced_then: HashSet<u64> = old.tracks.iter().filter(|row| row.action != IGNORED).map(|row| row.track_id).collect();
+
+    let mut seen = HashSet::new();
+    for row in new.tracks.iter().filter(|row| row.action != IGNORED) {
+        if !synced_then.contains(&row.track_id) && seen.insert(row.track_id) {
+            drift.new_tracks.push(TrackRef { track_id: row.track_id, name: row.name.clone() });
+        }
+    }
+    seen.clear();
+    for row in old.tracks.iter().filter(|row| row.action != IGNORED) {
+        if !library.tracks.contains_key(&row.track_id) && seen.insert(row.track_id) {
+            drift.removed_tracks.push(TrackRef { track_id: row.track_id, name: row.name.clone() });
+        }
+    }
+
+    // A playlist written in several formats has a row for each, all with the same entries.
+    let entries = |report: &Report| -> HashMap<String, Vec<u64>> {
+        let mut entries = HashMap::new();
+        for row in report.playlists.iter().filter(|row| !row.playlist_id.is_empty()) {
+            if let Some(tracks) = &row.tracks {
+                entries.entry(row.playlist_id.clone()).or_insert_with(|| tracks.clone());
+            }
+        }
+        entries
+    };
+    drift.compared_playlists = old.playlists.iter().all(|row| row.tracks.is_some());
+    if drift.compared_playlists {
+        let then = entries(old);
+        let mut seen = HashSet::new();
+        for row in &new.playlists {
+            let (now, then) = match (&row.tracks, then.get(&row.playlist_id)) {
+                (Some(now), Some(then)) => (now, then),
+                _ => continue,
+            };
+            if now == then || !seen.insert(row.playlist_id.as_str()) {
+                continue;
+            }
+            let (now_set, then_set): (HashSet<_>, HashSet<_>) = (now.iter().collect(), then.iter().collect());
+            drift.changed_playlists.push(ChangedPlaylist {
+                playlist_id: row.playlist_id.clone(),
+                name: row.name.clone(),
+                added_tracks: now_set.difference(&then_set).count(),
+                removed_tracks: then_set.difference(&now_set).count(),
+            });
+        }
+    }
+
+    let old_rows = old.tracks.iter().map(|row| (&row.destination, &row.name, &row.action, &row.reason))
+        .chain(old.playlists.iter().map(|row| (&row.destination, &row.name, &row.action, &row.reason)));
+    let mut seen = HashSet::new();
+    for (destination, name, action, reason) in old_rows {
+        if destination.is_empty() || !left_in_output(action, reason) || !seen.insert(destination.as_str()) {
+            continue;
+        }
+        if !exists(Path::new(destination)) {
+            drift.missing_files.push(MissingFile { path: destination.clone(), name: name.clone() });
+        }
+    }
+    drift
+}
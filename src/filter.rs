@@ -0,0 +1,659 @@
+//! Expressions choosing tracks, such as `genre = "Jazz" and rating >= 4 and not disabled`.
+
+use std::{cmp::Ordering, fmt, iter::Peekable, str::{CharIndices, FromStr}, time::{SystemTime, UNIX_EPOCH}};
+
+use crate::{date::days_from_civil, Track};
+
+/// A condition on tracks, parsed from an expression.
+///
+/// An expression compares fields with `=`, `!=`, `<`, `<=`, `>` and `>=`, tests text fields with
+/// `contains` or `matches` (a glob, like `"*live*"`), and combines these with `and`, `or`, `not`
+/// and parentheses. Values are quoted when they hold spaces or brackets.
+///
+/// - `name`, `artist`, `album`, `genre` and `kind` are text, compared ignoring case. A missing
+///   value is the same as an empty one.
+/// - `rating` is in stars from 0 to 5, and `playcount` a number; both are 0 when missing.
+/// - `size` is in bytes, optionally with a unit like `10MB` (in powers of 1024), and `added` is a
+///   `YYYY-MM-DD` date in UTC, compared by day. Tracks missing these fail any comparison of them.
+/// - `disabled`, `protected` and `loved` stand on their own, as in `not protected`.
+#[derive(Clone, Debug)]
+pub struct Filter {
+    expr: Expr,
+}
+
+#[derive(Clone, Debug)]
+enum Expr {
+    Any(Vec<Expr>),
+    All(Vec<Expr>),
+    Not(Box<Expr>),
+    Flag(Flag),
+    Text { field: TextField, test: TextTest },
+    Number { field: NumberField, comparison: Comparison, value: u64 },
+    Added { comparison: Comparison, day: i64 },
+}
+
+#[derive(Clone, Debug)]
+enum TextTest {
+    /// Against the lowercased value, by `=` or `!=`.
+    Compare(Comparison, String),
+    Contains(String),
+    Matches(glob::Pattern),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Comparison {
+    Equal,
+    NotEqual,
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Field {
+    Text(TextField),
+    Number(NumberField),
+    Added,
+    Flag(Flag),
+}
+
+#[derive(Clone, Copy, Debug)]
+enum TextField {
+    Name,
+    Artist,
+    Album,
+    Genre,
+    Kind,
+}
+
+#[derive(Clone, Copy, Debug)]
+enum NumberField {
+    Rating,
+    PlayCount,
+    Size,
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Flag {
+    Disabled,
+    Protected,
+    Loved,
+}
+
+const FIELDS: &[(&str, Field)] = &[
+    ("name", Field::Text(TextField::Name)),
+    ("title", Field::Text(TextField::Name)),
+    ("artist", Field::Text(TextField::Artist)),
+    ("album", Field::Text(TextField::Album)),
+    ("genre", Field::Text(TextField::Genre)),
+    ("kind", Field::Text(TextField::Kind)),
+    ("rating", Field::Number(NumberField::Rating)),
+    ("playcount", Field::Number(NumberField::PlayCount)),
+    ("size", Field::Number(NumberField::Size)),
+    ("added", Field::Added),
+    ("disabled", Field::Flag(Flag::Disabled)),
+    ("protected", Field::Flag(Flag::Protected)),
+    ("loved", Field::Flag(Flag::Loved)),
+];
+
+impl Filter {
+    /// Whether `track` satisfies the filter.
+    pub fn matches(&self, track: &Track) -> bool {
+        self.expr.matches(track)
+    }
+}
+
+impl Expr {
+    fn matches(&self, track: &Track) -> bool {
+        match self {
+            Expr::Any(exprs) => exprs.iter().any(|expr| expr.matches(track)),
+            Expr::All(exprs) => exprs.iter().all(|expr| expr.matches(track)),
+            Expr::Not(expr) => !expr.matches(track),
+            Expr::Flag(Flag::Disabled) => track.disabled,
+            Expr::Flag(Flag::Protected) => track.is_protected(),
+            Expr::Flag(Flag::Loved) => track.loved,
+            Expr::Text { field, test } => {
+                let value = field.value(track).to_lowercase();
+                match test {
+                    TextTest::Compare(comparison, expected) => comparison.holds(value.as_str().cmp(expected)),
+                    TextTest::Contains(part) => value.contains(part.as_str()),
+                    TextTest::Matches(pattern) => pattern.matches(&value),
+                }
+            }
+            Expr::Number { field, comparison, value } => {
+                field.value(track).is_some_and(|actual| comparison.holds(actual.cmp(value)))
+            }
+            Expr::Added { comparison, day } => {
+                track.date_added.is_some_and(|added| comparison.holds(day_of(added).cmp(day)))
+            }
+        }
+    }
+}
+
+impl TextField {
+    fn value(self, track: &Track) -> &str {
+        match self {
+            TextField::Name => &track.name,
+            TextField::Artist => track.artist.as_deref().unwrap_or_default(),
+            TextField::Album => track.album.as_deref().unwrap_or_default(),
+            TextField::Genre => track.genre.as_deref().unwrap_or_default(),
+            TextField::Kind => track.kind.as_deref().unwrap_or_default(),
+        }
+    }
+}
+
+impl NumberField {
+    fn value(self, track: &Track) -> Option<u64> {
+        match self {
+            NumberField::Rating => Some(track.rating.unwrap_or(0).into()),
+            NumberField::PlayCount => Some(track.play_count.unwrap_or(0).into()),
+            NumberField::Size => track.size_bytes,
+        }
+    }
+
+    /// Parses a value to compare the field with, as it is stored in `Track`.
+    fn parse(self, s: &str) -> Result<u64, String> {
+        match self {
+            NumberField::Rating => match s.parse::<u64>() {
+                Ok(stars) if stars <= 5 => Ok(stars * 20),
+                _ => Err(format!("invalid rating \"{}\", expected 0 to 5 stars", s)),
+            },
+            NumberField::PlayCount => s.parse().map_err(|_| format!("invalid play count \"{}\"", s)),
+            NumberField::Size => parse_size(s).ok_or_else(|| format!("invalid size \"{}\", expected e.g. \"10MB\"", s)),
+        }
+    }
+}
+
+impl Comparison {
+    fn holds(self, ordering: Ordering) -> bool {
+        match self {
+            Comparison::Equal => ordering == Ordering::Equal,
+            Comparison::NotEqual => ordering != Ordering::Equal,
+            Comparison::Less => ordering == Ordering::Less,
+            Comparison::LessOrEqual => ordering != Ordering::Greater,
+            Comparison::Greater => ordering == Ordering::Greater,
+            Comparison::GreaterOrEqual => ordering != Ordering::Less,
+        }
+    }
+}
+
+/// The day of `time` in UTC, counted from 1970-01-01.
+fn day_of(time: SystemTime) -> i64 {
+    match time.duration_since(UNIX_EPOCH) {
+        Ok(since) => (since.as_secs() / 86_400) as i64,
+        Err(e) => -(e.duration().as_secs().div_ceil(86_400) as i64),
+    }
+}
+
+/// Parses a `YYYY-MM-DD` date into the day it is, counted from 1970-01-01.
+fn parse_day(s: &str) -> Option<i64> {
+    let mut parts = s.splitn(3, '-').map(str::parse::<i64>);
+    let (year, month, day) = (parts.next()?.ok()?, parts.next()?.ok()?, parts.next()?.ok()?);
+    match (1..=12).contains(&month) && (1..=31).contains(&day) {
+        true => Some(days_from_civil(year, month, day)),
+        false => None,
+    }
+}
+
+/// Parses a number of bytes, optionally followed by K, M or G (or KB, KiB...), in powers of 1024.
+fn parse_size(s: &str) -> Option<u64> {
+    let digits = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let value: u64 = s[..digits].parse().ok()?;
+    let unit = match s[digits..].to_ascii_uppercase().as_str() {
+        "" | "B" => 1,
+        "K" | "KB" | "KIB" => 1 << 10,
+        "M" | "MB" | "MIB" => 1 << 20,
+        "G" | "GB" | "GIB" => 1 << 30,
+        _ => return None,
+    };
+    value.checked_mul(unit)
+}
+
+/// Why an expression isn't a valid filter, and where in it.
+#[derive(Debug)]
+pub struct SyntaxError {
+    expression: String,
+    /// The byte range of the offending token.
+    start: usize,
+    end: usize,
+    message: String,
+}
+
+impl fmt::Display for SyntaxError {
+    /// Shows the message, then the expression with carets under the offending token.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let indent = self.expression[..self.start].chars().count();
+        let width = self.expression[self.start..self.end].chars().count().max(1);
+        writeln!(f, "{}", self.message)?;
+        writeln!(f, "  {}", self.expression)?;
+        write!(f, "  {}{}", " ".repeat(indent), "^".repeat(width))
+    }
+}
+
+impl std::error::Error for SyntaxError {}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum TokenKind {
+    Open,
+    Close,
+    Compare(Comparison),
+    /// A bare word: a field, keyword or unquoted value.
+    Word,
+    /// Quoted text, with its escapes resolved.
+    Text,
+}
+
+#[derive(Clone, Debug)]
+struct Token {
+    kind: TokenKind,
+    text: String,
+    start: usize,
+    end: usize,
+}
+
+impl Token {
+    fn is_keyword(&self, keyword: &str) -> bool {
+        self.kind == TokenKind::Word && self.text.eq_ignore_ascii_case(keyword)
+    }
+
+    fn is_operator(&self) -> bool {
+        matches!(self.kind, TokenKind::Compare(_)) || self.is_keyword("contains") || self.is_keyword("matches")
+    }
+}
+
+impl FromStr for Filter {
+    type Err = SyntaxError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parser = Parser { source: s, tokens: tokenize(s)?, next: 0 };
+        if parser.tokens.is_empty() {
+            return Err(parser.error_at_end("empty expression"));
+        }
+        let expr = parser.parse_any()?;
+        match parser.peek() {
+            Some(token) => Err(parser.error(token, "expected \"and\", \"or\" or the end")),
+            None => Ok(Filter { expr }),
+        }
+    }
+}
+
+fn tokenize(s: &str) -> Result<Vec<Token>, SyntaxError> {
+    let error = |start: usize, end: usize, message: &str| SyntaxError {
+        expression: s.to_owned(),
+        start,
+        end,
+        message: message.to_owned(),
+    };
+    let mut tokens = Vec::new();
+    let mut chars = s.char_indices().peekable();
+    while let Some((start, c)) = chars.next() {
+        let kind = match c {
+            c if c.is_whitespace() => continue,
+            '(' => TokenKind::Open,
+            ')' => TokenKind::Close,
+            '=' => {
+                chars.next_if(|&(_, c)| c == '=');
+                TokenKind::Compare(Comparison::Equal)
+            }
+            '!' if next_is(&mut chars, '=') => TokenKind::Compare(Comparison::NotEqual),
+            '<' if next_is(&mut chars, '=') => TokenKind::Compare(Comparison::LessOrEqual),
+            '<' => TokenKind::Compare(Comparison::Less),
+            '>' if next_is(&mut chars, '=') => TokenKind::Compare(Comparison::GreaterOrEqual),
+            '>' => TokenKind::Compare(Comparison::Greater),
+            '!' => return Err(error(start, start + 1, "'!' without '='")),
+            '"' => {
+                let mut text = String::new();
+                let end = loop {
+                    match chars.next() {
+                        Some((i, '"')) => break i + 1,
+                        Some((i, '\\')) => match chars.next() {
+                            Some((_, c @ ('"' | '\\'))) => text.push(c),
+                            _ => return Err(error(i, i + 1, "invalid escape")),
+                        },
+                        Some((_, c)) => text.push(c),
+                        None => return Err(error(start, s.len(), "unclosed quotes")),
+                    }
+                };
+                tokens.push(Token { kind: TokenKind::Text, text, start, end });
+                continue;
+            }
+            _ => {
+                let mut end = start + c.len_utf8();
+                while let Some((i, c)) = chars.next_if(|&(_, c)| !c.is_whitespace() && !"()=!<>\"".contains(c)) {
+                    end = i + c.len_utf8();
+                }
+                tokens.push(Token { kind: TokenKind::Word, text: s[start..end].to_owned(), start, end });
+                continue;
+            }
+        };
+        let end = chars.peek().map_or(s.len(), |&(i, _)| i);
+        tokens.push(Token { kind, text: s[start..end].to_owned(), start, end });
+    }
+    Ok(tokens)
+}
+
+fn next_is(chars: &mut Peekable<CharIndices<'_>>, expected: char) -> bool {
+    chars.next_if(|&(_, c)| c == expected).is_some()
+}
+
+struct Parser<'a> {
+    source: &'a str,
+    tokens: Vec<Token>,
+    next: usize,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.next)
+    }
+
+    fn advance(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.next).cloned();
+        self.next += 1;
+        token
+    }
+
+    fn eat_keyword(&mut self, keyword: &str) -> bool {
+        let found = self.peek().is_some_and(|token| token.is_keyword(keyword));
+        if found {
+            self.next += 1;
+        }
+        found
+    }
+
+    fn error(&self, token: &Token, message: &str) -> SyntaxError {
+        SyntaxError { expression: self.source.to_owned(), start: token.start, end: token.end, message: message.to_owned() }
+    }
+
+    fn error_at_end(&self, message: &str) -> SyntaxError {
+        let end = self.source.len();
+        SyntaxError { expression: self.source.to_owned(), start: end, end, message: message.to_owned() }
+    }
+
+    /// An error at the next token, or the end of the expression if there are none left.
+    fn error_at_next(&self, message: &str) -> SyntaxError {
+        match self.peek() {
+            Some(token) => self.error(token, message),
+            None => self.error_at_end(message),
+        }
+    }
+
+    fn parse_any(&mut self) -> Result<Expr, SyntaxError> {
+        let mut exprs = vec![self.parse_all()?];
+        while self.eat_keyword("or") {
+            exprs.push(self.parse_all()?);
+        }
+        Ok(match exprs.len() {
+            1 => exprs.remove(0),
+            _ => Expr::Any(exprs),
+        })
+    }
+
+    fn parse_all(&mut self) -> Result<Expr, SyntaxError> {
+        let mut exprs = vec![self.parse_not()?];
+        while self.eat_keyword("and") {
+            exprs.push(self.parse_not()?);
+        }
+        Ok(match exprs.len() {
+            1 => exprs.remove(0),
+            _ => Expr::All(exprs),
+        })
+    }
+
+    fn parse_not(&mut self) -> Result<Expr, SyntaxError> {
+        match self.eat_keyword("not") {
+            true => Ok(Expr::Not(Box::new(self.parse_not()?))),
+            false => self.parse_primary(),
+        }
+    }
+
+    fn parse_primary(&mut self) -> Result<Expr, SyntaxError> {
+        let token = self.advance().ok_or_else(|| self.error_at_end("expected a field or '('"))?;
+        match token.kind {
+            TokenKind::Open => {
+                let expr = self.parse_any()?;
+                match self.advance() {
+                    Some(close) if close.kind == TokenKind::Close => Ok(expr),
+                    Some(other) => Err(self.error(&other, "expected ')'")),
+                    None => Err(self.error(&token, "unclosed '('")),
+                }
+            }
+            TokenKind::Word => {
+                let field = FIELDS.iter()
+                    .find(|(name, _)| token.text.eq_ignore_ascii_case(name))
+                    .map(|&(_, field)| field)
+                    .ok_or_else(|| self.error(&token, &format!("unknown field \"{}\"", token.text)))?;
+                self.parse_test(&token, field)
+            }
+            _ => Err(self.error(&token, "expected a field or '('")),
+        }
+    }
+
+    /// Parses what follows `field`, which is its name in `name`.
+    fn parse_test(&mut self, name: &Token, field: Field) -> Result<Expr, SyntaxError> {
+        if let Field::Flag(flag) = field {
+            return match self.peek() {
+                Some(token) if token.is_operator() => {
+                    Err(self.error(token, &format!("{} can't be compared, use it alone or after \"not\"", name.text)))
+                }
+                _ => Ok(Expr::Flag(flag)),
+            };
+        }
+
+        let operator = match self.peek() {
+            Some(token) if token.is_operator() => self.advance().unwrap(),
+            _ => return Err(self.error_at_next(&format!("expected a comparison after {}", name.text))),
+        };
+        let value = match self.advance() {
+            Some(token) if matches!(token.kind, TokenKind::Word | TokenKind::Text) => token,
+            Some(token) => return Err(self.error(&token, "expected a value")),
+            None => return Err(self.error_at_end("expected a value")),
+        };
+        let invalid_value = |message: String| self.error(&value, &message);
+
+        match (field, &operator.kind) {
+            (Field::Text(field), TokenKind::Compare(comparison @ (Comparison::Equal | Comparison::NotEqual))) => {
+                Ok(Expr::Text { field, test: TextTest::Compare(*comparison, value.text.to_lowercase()) })
+            }
+            (Field::Text(_), TokenKind::Compare(_)) => {
+                Err(self.error(&operator, &format!("{} is text, which can only be tested with =, !=, contains or matches", name.text)))
+            }
+            (Field::Text(field), _) if operator.is_keyword("contains") => {
+                Ok(Expr::Text { field, test: TextTest::Contains(value.text.to_lowercase()) })
+            }
+            (Field::Text(field), _) => {
+                let pattern = glob::Pattern::new(&value.text.to_lowercase())
+                    .map_err(|e| invalid_value(format!("invalid pattern ({})", e.msg)))?;
+                Ok(Expr::Text { field, test: TextTest::Matches(pattern) })
+            }
+            (Field::Number(field), &TokenKind::Compare(comparison)) => {
+                let parsed = field.parse(&value.text).map_err(invalid_value)?;
+                Ok(Expr::Number { field, comparison, value: parsed })
+            }
+            (Field::Added, &TokenKind::Compare(comparison)) => {
+                let day = parse_day(&value.text)
+                    .ok_or_else(|| invalid_value(format!("invalid date \"{}\", expected YYYY-MM-DD", value.text)))?;
+                Ok(Expr::Added { comparison, day })
+            }
+            (Field::Number(_), _) => {
+                Err(self.error(&operator, &format!("{} is a number, which can't be tested with {}", name.text, operator.text)))
+            }
+            (Field::Added, _) => {
+                Err(self.error(&operator, &format!("{} is a date, which can't be tested with {}", name.text, operator.text)))
+            }
+            (Field::Flag(_), _) => unreachable!("flags are handled above"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    fn filter(expression: &str) -> Filter {
+        expression.parse().unwrap_or_else(|e| panic!("{:?} doesn't parse: {}", expression, e))
+    }
+
+    fn error(expression: &str) -> SyntaxError {
+        match expression.parse::<Filter>() {
+            Ok(filter) => panic!("{:?} parses, as {:?}", expression, filter),
+            Err(e) => e,
+        }
+    }
+
+    fn track() -> Track {
+        Track {
+            name: "So What".to_owned(),
+            artist: Some("Miles Davis".to_owned()),
+            album: Some("Kind of Blue".to_owned()),
+            genre: Some("Jazz".to_owned()),
+            kind: Some("MPEG audio file".to_owned()),
+            rating: Some(80),
+            play_count: Some(12),
+            size_bytes: Some(9 << 20),
+            // 2020-02-29.
+            date_added: Some(UNIX_EPOCH + Duration::from_secs(18_321 * 86_400 + 3600)),
+            ..Track::default()
+        }
+    }
+
+    fn matches(expression: &str) -> bool {
+        filter(expression).matches(&track())
+    }
+
+    #[test]
+    fn text_fields_compare_ignoring_case() {
+        assert!(matches("genre = jazz"));
+        assert!(matches("GENRE == \"JAZZ\""));
+        assert!(!matches("genre != jazz"));
+        assert!(matches("title = \"so what\""));
+        assert!(matches("artist contains davis"));
+        assert!(matches("album matches \"kind*\""));
+        assert!(!matches("album matches \"blue*\""));
+        assert!(matches("kind contains mpeg"));
+    }
+
+    #[test]
+    fn missing_text_is_empty() {
+        let track = Track::default();
+        assert!(filter("genre = \"\"").matches(&track));
+        assert!(!filter("genre contains jazz").matches(&track));
+    }
+
+    #[test]
+    fn ratings_are_in_stars() {
+        assert!(matches("rating = 4"));
+        assert!(matches("rating >= 4"));
+        assert!(!matches("rating > 4"));
+        assert!(filter("rating = 0").matches(&Track::default()));
+    }
+
+    #[test]
+    fn numbers_compare() {
+        assert!(matches("playcount < 13"));
+        assert!(matches("playcount <= 12"));
+        assert!(!matches("playcount < 12"));
+        assert!(matches("size > 8MB"));
+        assert!(matches("size = 9MiB"));
+        assert!(matches("size < 1g"));
+        assert!(matches("size = 9437184"));
+    }
+
+    #[test]
+    fn tracks_missing_a_size_or_date_fail_every_comparison() {
+        let track = Track::default();
+        assert!(!filter("size > 0").matches(&track));
+        assert!(!filter("size != 0").matches(&track));
+        assert!(!filter("added < 2100-01-01").matches(&track));
+    }
+
+    #[test]
+    fn dates_compare_by_day() {
+        assert!(matches("added = 2020-02-29"));
+        assert!(matches("added > 2020-02-28"));
+        assert!(matches("added < 2020-03-01"));
+        assert!(!matches("added != 2020-02-29"));
+    }
+
+    #[test]
+    fn flags_stand_alone() {
+        assert!(!matches("loved"));
+        assert!(matches("not loved"));
+        assert!(!matches("disabled"));
+        assert!(!matches("protected"));
+        let protected = Track { kind: Some("Protected AAC audio file".to_owned()), loved: true, disabled: true, ..Track::default() };
+        assert!(filter("protected and loved and disabled").matches(&protected));
+    }
+
+    #[test]
+    fn and_binds_tighter_than_or() {
+        // Parsed as `loved or (genre = jazz and rating = 5)`.
+        assert!(!matches("loved or genre = jazz and rating = 5"));
+        assert!(matches("genre = jazz and rating = 5 or rating = 4"));
+        assert!(!matches("(loved or genre = jazz) and rating = 5"));
+        assert!(matches("loved or (genre = jazz and rating = 4)"));
+    }
+
+    #[test]
+    fn not_binds_tighter_than_and() {
+        assert!(matches("not loved and genre = jazz"));
+        assert!(!matches("not (loved or genre = jazz)"));
+        assert!(matches("not not genre = jazz"));
+    }
+
+    #[test]
+    fn keywords_and_fields_ignore_case() {
+        assert!(matches("Genre = Jazz AND NOT Loved"));
+    }
+
+    #[test]
+    fn quotes_hold_spaces_brackets_and_escapes() {
+        assert!(matches("album = \"Kind of Blue\""));
+        let track = Track { name: "Say \"(Hi)\" \\ Bye".to_owned(), ..Track::default() };
+        assert!(filter(r#"name = "say \"(hi)\" \\ bye""#).matches(&track));
+    }
+
+    #[test]
+    fn bad_expressions_point_at_the_problem() {
+        let e = error("genre = jazz and");
+        assert_eq!(e.message, "expected a field or '('");
+        assert_eq!((e.start, e.end), (16, 16));
+        let e = error("genre = jazz rating = 4");
+        assert_eq!(e.message, "expected \"and\", \"or\" or the end");
+        assert_eq!((e.start, e.end), (13, 19));
+        assert_eq!(error("").message, "empty expression");
+        assert_eq!(error("color = red").message, "unknown field \"color\"");
+        assert_eq!(error("(genre = jazz").message, "unclosed '('");
+        assert_eq!(error("genre = \"jazz").message, "unclosed quotes");
+        assert_eq!(error("genre = \"ja\\zz\"").message, "invalid escape");
+        assert_eq!(error("genre ! jazz").message, "'!' without '='");
+        assert_eq!(error("genre").message, "expected a comparison after genre");
+        assert_eq!(error("genre =").message, "expected a value");
+        assert_eq!(error("genre = (").message, "expected a value");
+    }
+
+    #[test]
+    fn values_have_to_suit_their_fields() {
+        assert_eq!(error("rating = 6").message, "invalid rating \"6\", expected 0 to 5 stars");
+        assert_eq!(error("playcount = many").message, "invalid play count \"many\"");
+        assert_eq!(error("size > 10TB").message, "invalid size \"10TB\", expected e.g. \"10MB\"");
+        assert_eq!(error("added > 2020-13-01").message, "invalid date \"2020-13-01\", expected YYYY-MM-DD");
+        assert_eq!(error("genre < jazz").message, "genre is text, which can only be tested with =, !=, contains or matches");
+        assert_eq!(error("rating contains 4").message, "rating is a number, which can't be tested with contains");
+        assert_eq!(error("added matches 2020").message, "added is a date, which can't be tested with matches");
+        assert_eq!(error("loved = 1").message, "loved can't be compared, use it alone or after \"not\"");
+        assert!(error("name matches \"[a\"").message.starts_with("invalid pattern"));
+    }
+
+    #[test]
+    fn errors_show_where_they_are() {
+        assert_eq!(error("genre = jazz or colour = red").to_string(), concat!(
+            "unknown field \"colour\"\n",
+            "  genre = jazz or colour = red\n",
+            "                  ^^^^^^",
+        ));
+    }
+}
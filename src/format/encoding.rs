@@ -0,0 +1,67 @@
+use std::str::FromStr;
+
+/// Text encoding used for the plain-text playlist formats (M3U and PLS).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Encoding {
+    Utf8,
+    Latin1,
+    Windows1252,
+}
+
+/// Characters Windows-1252 places in the range ISO-8859-1 reserves for C1 controls.
+const WINDOWS_1252_HIGH: [(char, u8); 27] = [
+    ('\u{20AC}', 0x80), ('\u{201A}', 0x82), ('\u{0192}', 0x83), ('\u{201E}', 0x84),
+    ('\u{2026}', 0x85), ('\u{2020}', 0x86), ('\u{2021}', 0x87), ('\u{02C6}', 0x88),
+    ('\u{2030}', 0x89), ('\u{0160}', 0x8A), ('\u{2039}', 0x8B), ('\u{0152}', 0x8C),
+    ('\u{017D}', 0x8E), ('\u{2018}', 0x91), ('\u{2019}', 0x92), ('\u{201C}', 0x93),
+    ('\u{201D}', 0x94), ('\u{2022}', 0x95), ('\u{2013}', 0x96), ('\u{2014}', 0x97),
+    ('\u{02DC}', 0x98), ('\u{2122}', 0x99), ('\u{0161}', 0x9A), ('\u{203A}', 0x9B),
+    ('\u{0153}', 0x9C), ('\u{017E}', 0x9E), ('\u{0178}', 0x9F),
+];
+
+impl Encoding {
+    fn encode_char(self, c: char) -> Option<u8> {
+        match (self, c as u32) {
+            (_, code @ 0..=0x7F) | (_, code @ 0xA0..=0xFF) => Some(code as u8),
+            (Encoding::Latin1, code @ 0x80..=0x9F) => Some(code as u8),
+            (Encoding::Windows1252, _) => WINDOWS_1252_HIGH.iter()
+                .find(|(mapped, _)| *mapped == c)
+                .map(|(_, byte)| *byte),
+            _ => None,
+        }
+    }
+
+    /// Whether every character of `text` can be represented in this encoding.
+    pub fn can_encode(self, text: &str) -> bool {
+        self == Encoding::Utf8 || text.chars().all(|c| self.encode_char(c).is_some())
+    }
+
+    /// Appends `text` to `out`, writing `substitute` in place of any character this encoding
+    /// can't represent.
+    pub fn encode(self, text: &str, substitute: &str, out: &mut Vec<u8>) {
+        if self == Encoding::Utf8 {
+            out.extend_from_slice(text.as_bytes());
+            return;
+        }
+
+        for c in text.chars() {
+            match self.encode_char(c) {
+                Some(byte) => out.push(byte),
+                None => self.encode(substitute, "", out),
+            }
+        }
+    }
+}
+
+impl FromStr for Encoding {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "utf8" | "utf-8" => Ok(Encoding::Utf8),
+            "latin1" | "iso-8859-1" => Ok(Encoding::Latin1),
+            "windows-1252" | "cp1252" => Ok(Encoding::Windows1252),
+            other => Err(format!("unknown playlist encoding \"{}\"", other)),
+        }
+    }
+}
@@ -0,0 +1,157 @@
+use std::{collections::HashMap, io::{self, Write}};
+
+use percent_encoding::utf8_percent_encode;
+
+use super::{IndexEntry, IndexKind, Options, xspf::{LOCATION, escape, location}};
+
+pub(super) fn write_index(
+    mut writer: impl Write,
+    index: &[IndexEntry],
+    playlists_dir: &str,
+    pages: bool,
+    options: &Options,
+) -> Result<(), io::Error> {
+    // Entries whose parent wasn't exported, like those of flattened folders, go at the top.
+    let mut children: HashMap<Option<&str>, Vec<&IndexEntry>> = HashMap::new();
+    for entry in index {
+        let parent = entry.parent_id.as_deref().filter(|parent| index.iter().any(|other| other.persistent_id == *parent));
+        children.entry(parent).or_default().push(entry);
+    }
+
+    write_head(&mut writer, "Playlists", options)?;
+    writeln!(writer, "<h1>Playlists</h1>")?;
+    let prefix = playlists_dir.trim_end_matches('/');
+    let link = |path: &str| match prefix.is_empty() {
+        true => href(path),
+        false => format!("{}/{}", href(prefix), href(path)),
+    };
+    write_tree(&mut writer, &children, None, 0, &|entry: &IndexEntry| {
+        let (first, rest) = match entry.files.split_first() {
+            Some(files) => files,
+            None => return format!("{} ({})", escape(&entry.name), summary(entry)),
+        };
+        let mut line = format!("<a href=\"{}\">{}</a> ({})", link(first), escape(&entry.name), summary(entry));
+        for file in rest {
+            line.push_str(&format!(" · <a href=\"{}\">{}</a>", link(file), escape(extension(file))));
+        }
+        if let Some(page) = page_path(entry).filter(|_| pages) {
+            line.push_str(&format!(" · <a href=\"{}\">tracks</a>", link(&page)));
+        }
+        line
+    })?;
+    writeln!(writer, "</body>")?;
+    writeln!(writer, "</html>")?;
+    writer.flush()
+}
+
+fn write_tree(
+    writer: &mut impl Write,
+    children: &HashMap<Option<&str>, Vec<&IndexEntry>>,
+    parent: Option<&str>,
+    depth: usize,
+    playlist: &dyn Fn(&IndexEntry) -> String,
+) -> Result<(), io::Error> {
+    let entries = match children.get(&parent) {
+        Some(entries) => entries,
+        None => return Ok(()),
+    };
+    let indent = "  ".repeat(depth);
+    writeln!(writer, "{}<ul>", indent)?;
+    for entry in entries {
+        match entry.kind {
+            IndexKind::Folder if children.contains_key(&Some(entry.persistent_id.as_str())) => {
+                writeln!(writer, "{}  <li>{}", indent, escape(&entry.name))?;
+                write_tree(writer, children, Some(&entry.persistent_id), depth + 2, playlist)?;
+                writeln!(writer, "{}  </li>", indent)?;
+            }
+            IndexKind::Folder => writeln!(writer, "{}  <li>{}</li>", indent, escape(&entry.name))?,
+            IndexKind::Playlist => writeln!(writer, "{}  <li>{}</li>", indent, playlist(entry))?,
+        }
+    }
+    writeln!(writer, "{}</ul>", indent)
+}
+
+pub(super) fn write_page(mut writer: impl Write, entry: &IndexEntry, home: &str, options: &Options) -> Result<(), io::Error> {
+    write_head(&mut writer, &entry.name, options)?;
+    writeln!(writer, "<p><a href=\"{}\">All playlists</a></p>", href(home))?;
+    writeln!(writer, "<h1>{}</h1>", escape(&entry.name))?;
+    // The page is next to the playlist's files, so they are linked by name.
+    let files: Vec<String> = entry.files.iter()
+        .map(|file| {
+            let name = file.rsplit('/').next().unwrap_or(file);
+            format!("<a href=\"{}\">{}</a>", href(name), escape(extension(file)))
+        })
+        .collect();
+    writeln!(writer, "<p>{} · {}</p>", summary(entry), files.join(" · "))?;
+    writeln!(writer, "<ol>")?;
+    for track in &entry.entries {
+        write!(writer, "  <li><a href=\"{}\">{}</a>", escape(&location(&track.path, options)), escape(&track.title))?;
+        match track.duration_ms {
+            Some(duration_ms) => writeln!(writer, " ({})</li>", duration(u64::from(duration_ms)))?,
+            None => writeln!(writer, "</li>")?,
+        }
+    }
+    writeln!(writer, "</ol>")?;
+    writeln!(writer, "</body>")?;
+    writeln!(writer, "</html>")?;
+    writer.flush()
+}
+
+pub(super) fn page_path(entry: &IndexEntry) -> Option<String> {
+    match entry.kind {
+        IndexKind::Folder => None,
+        IndexKind::Playlist => {
+            let file = entry.files.first()?;
+            let extension = extension(file);
+            let stem = match extension.is_empty() {
+                true => file.as_str(),
+                false => &file[..file.len() - extension.len() - 1],
+            };
+            Some(format!("{}.html", stem))
+        }
+    }
+}
+
+fn write_head(writer: &mut impl Write, title: &str, options: &Options) -> Result<(), io::Error> {
+    writeln!(writer, "<!DOCTYPE html>")?;
+    writeln!(writer, "<html>")?;
+    writeln!(writer, "<head>")?;
+    writeln!(writer, "<meta charset=\"utf-8\">")?;
+    writeln!(writer, "<meta name=\"viewport\" content=\"width=device-width, initial-scale=1\">")?;
+    if let Some(generator) = &options.generator {
+        writeln!(writer, "<meta name=\"generator\" content=\"{}\">", escape(generator))?;
+    }
+    writeln!(writer, "<title>{}</title>", escape(title))?;
+    writeln!(writer, "</head>")?;
+    writeln!(writer, "<body>")
+}
+
+/// How many tracks a playlist has and how long they last, counting only the known durations.
+fn summary(entry: &IndexEntry) -> String {
+    let total_ms: u64 = entry.entries.iter().filter_map(|track| track.duration_ms).map(u64::from).sum();
+    match entry.entries.len() {
+        1 => format!("1 track, {}", duration(total_ms)),
+        count => format!("{} tracks, {}", count, duration(total_ms)),
+    }
+}
+
+fn duration(ms: u64) -> String {
+    let secs = (ms + 500) / 1000;
+    match secs / 3600 {
+        0 => format!("{}:{:02}", secs / 60, secs % 60),
+        hours => format!("{}:{:02}:{:02}", hours, secs / 60 % 60, secs % 60),
+    }
+}
+
+/// The extension of a file name, without the dot, or an empty string if it has none.
+fn extension(file: &str) -> &str {
+    let name = file.rsplit('/').next().unwrap_or(file);
+    match name.rfind('.') {
+        Some(dot) if dot > 0 => &name[dot + 1..],
+        _ => "",
+    }
+}
+
+fn href(path: &str) -> String {
+    escape(&utf8_percent_encode(path, LOCATION).to_string())
+}
@@ -0,0 +1,34 @@
+use std::io::{self, Write};
+
+use serde::Serialize;
+
+use crate::{Playlist, json};
+use super::{Entry, IndexEntry, Options};
+
+#[derive(Serialize)]
+struct JsonPlaylist<'a> {
+    name: &'a str,
+    persistent_id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    generator: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    generated_at: Option<String>,
+    entries: &'a [Entry],
+}
+
+pub(super) fn write(mut writer: impl Write, playlist: &Playlist, entries: &[Entry], options: &Options) -> Result<(), io::Error> {
+    let generator = options.generator.as_deref();
+    json::to_writer_pretty(&mut writer, &JsonPlaylist {
+        name: &playlist.name,
+        persistent_id: format!("{:016X}", playlist.persistent_id),
+        generator,
+        generated_at: generator.and(options.generated_at).map(crate::date::rfc3339),
+        entries,
+    })?;
+    writer.flush()
+}
+
+pub(super) fn write_index(mut writer: impl Write, index: &[IndexEntry]) -> Result<(), io::Error> {
+    json::to_writer_pretty(&mut writer, index)?;
+    writer.flush()
+}
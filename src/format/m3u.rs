@@ -0,0 +1,146 @@
+use std::io::{self, Write};
+
+use percent_encoding::{AsciiSet, NON_ALPHANUMERIC, utf8_percent_encode};
+
+use crate::Playlist;
+use super::{Encoding, Entry, EntryComments, Format, Options, PlayerHints, TrackRef};
+
+/// What the entry comments m3trans writes start with.
+pub(super) const COMMENT_PREFIX: &str = "#M3TRANS:";
+
+/// Every character but the unreserved ones of URIs and path separators, to be safe with players
+/// that are picky about what they accept.
+const PATH: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-').remove(b'.').remove(b'_').remove(b'~').remove(b'/').remove(b'\\');
+
+pub(super) fn write(mut writer: impl Write, playlist: &Playlist, entries: &[Entry], options: &Options) -> Result<(), io::Error> {
+    if options.bom && options.encoding == Encoding::Utf8 {
+        writer.write_all("\u{feff}".as_bytes())?;
+    }
+
+    let mut line = Vec::new();
+    if options.extinf {
+        line.extend_from_slice(b"#EXTM3U\n#PLAYLIST:");
+        let name = playlist.name.replace(['\r', '\n'], " ");
+        #[cfg(feature = "sync")]
+        let name = match options.ascii {
+            true => crate::names::to_ascii(&name),
+            false => name,
+        };
+        options.encode(&name, &mut line);
+        line.push(b'\n');
+        // An .m3u8 extension already says the playlist is UTF-8, but players guess for .m3u.
+        if options.encoding == Encoding::Utf8 && Format::M3u.extension(options) == "m3u" {
+            line.extend_from_slice(b"#EXTENC:UTF-8\n");
+        }
+    }
+    if let Some(stamp) = options.stamp() {
+        line.extend_from_slice(b"# ");
+        options.encode(&stamp, &mut line);
+        line.push(b'\n');
+    }
+    writer.write_all(&line)?;
+
+    for entry in entries {
+        line.clear();
+        // Before #EXTINF, which some players only take as the title right above the path.
+        if let Some(EntryComments::Id) = options.entry_comments {
+            match entry.track {
+                TrackRef::PersistentId(id) => writeln!(line, "{}track={:016X}", COMMENT_PREFIX, id)?,
+                TrackRef::TrackId(id) => writeln!(line, "{}track_id={}", COMMENT_PREFIX, id)?,
+            }
+        }
+        if options.extinf && !(options.omit_unknown_extinf && entry.duration_ms.is_none()) {
+            write!(line, "#EXTINF:{},", options.duration_style.format(entry.duration_ms))?;
+            options.encode(&entry.title, &mut line);
+            line.push(b'\n');
+        }
+        if let Some(PlayerHints::Vlc) = options.player_hints {
+            if let Some(start_ms) = entry.start_ms {
+                writeln!(line, "#EXTVLCOPT:start-time={}", seconds(start_ms))?;
+            }
+            if let Some(stop_ms) = entry.stop_ms {
+                writeln!(line, "#EXTVLCOPT:stop-time={}", seconds(stop_ms))?;
+            }
+        }
+        match options.encode_paths {
+            true => line.extend(utf8_percent_encode(&entry.path, PATH).flat_map(str::bytes)),
+            false => options.encode(&entry.path, &mut line),
+        }
+        line.push(b'\n');
+        writer.write_all(&line)?;
+    }
+    writer.flush()
+}
+
+/// Milliseconds as seconds, with only as many decimals as they need.
+fn seconds(ms: u32) -> String {
+    match ms % 1000 {
+        0 => (ms / 1000).to_string(),
+        fraction => format!("{}.{:03}", ms / 1000, fraction).trim_end_matches('0').to_owned(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::PlaylistKind;
+
+    use super::*;
+
+    fn entry(path: &str, title: &str) -> Entry {
+        Entry {
+            path: path.to_owned(),
+            title: title.to_owned(),
+            duration_ms: Some(241_333),
+            start_ms: None,
+            stop_ms: None,
+            track: TrackRef::TrackId(1),
+        }
+    }
+
+    fn written(entries: &[Entry], options: &Options) -> String {
+        let playlist = Playlist {
+            persistent_id: 1,
+            parent_id: None,
+            name: "Mix #1".to_owned(),
+            kind: PlaylistKind::Generic,
+            is_smart: false,
+            is_visible: true,
+            items: Vec::new(),
+            order_key: 0,
+        };
+        let mut contents = Vec::new();
+        write(&mut contents, &playlist, entries, options).unwrap();
+        String::from_utf8(contents).unwrap()
+    }
+
+    #[test]
+    fn encoded_paths_keep_their_separators_and_prefixes() {
+        let entries = [
+            entry("../tracks/Song #1.m4a", "Song #1"),
+            entry("../../Ümlaut/Trés Bien.m4a", "Trés Bien"),
+            entry("/Users/me/Music/100% [Live]?.flac", "Live"),
+            entry("..\\Odd & Even\\a~b_c-d.mp3", "Odd"),
+        ];
+        let options = Options { encode_paths: true, ..Options::default() };
+        assert_eq!(written(&entries, &options), "\
+#EXTM3U
+#PLAYLIST:Mix #1
+#EXTINF:241,Song #1
+../tracks/Song%20%231.m4a
+#EXTINF:241,Trés Bien
+../../%C3%9Cmlaut/Tr%C3%A9s%20Bien.m4a
+#EXTINF:241,Live
+/Users/me/Music/100%25%20%5BLive%5D%3F.flac
+#EXTINF:241,Odd
+..\\Odd%20%26%20Even\\a~b_c-d.mp3
+");
+    }
+
+    #[test]
+    fn paths_are_written_as_they_are_by_default() {
+        let entries = [entry("../tracks/Song #1.m4a", "Song #1"), entry("../Trés Bien.m4a", "Trés Bien")];
+        let options = Options { extinf: false, ..Options::default() };
+        assert_eq!(written(&entries, &options), "../tracks/Song #1.m4a\n../Trés Bien.m4a\n");
+    }
+}
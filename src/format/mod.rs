@@ -0,0 +1,562 @@
+use std::{io::{self, Write}, str::FromStr, time::SystemTime};
+
+use serde::Serialize;
+
+use crate::Playlist;
+
+pub use encoding::Encoding;
+pub use title::TitleFormat;
+
+mod encoding;
+mod html;
+mod json;
+mod m3u;
+mod pls;
+mod title;
+mod xspf;
+
+/// A single resolved playlist entry, ready to be written in any format.
+#[derive(Clone, Debug, Serialize)]
+pub struct Entry {
+    pub path: String,
+    pub title: String,
+    /// How long the entry plays for, which is only part of its file between `start_ms` and
+    /// `stop_ms` if it has them.
+    pub duration_ms: Option<u32>,
+    /// Where in its file the entry starts playing, if not at the beginning.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub start_ms: Option<u32>,
+    /// Where in its file the entry stops playing, if not at the end.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stop_ms: Option<u32>,
+    /// The library track the entry is, for `Options::entry_comments`.
+    #[serde(skip)]
+    pub track: TrackRef,
+}
+
+/// How an entry refers to its track in a comment: by its persistent ID where it has one, as that
+/// stays the same when the library is rebuilt, or else by its track ID.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TrackRef {
+    PersistentId(u64),
+    TrackId(u64),
+}
+
+/// What comments M3U playlists carry before each entry, for tools to map entries back to the
+/// library. Players skip them like any other comment.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EntryComments {
+    /// `#M3TRANS:track=` and the track's persistent ID in hex, or `#M3TRANS:track_id=` and its
+    /// track ID if it has none.
+    Id,
+}
+
+impl FromStr for EntryComments {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "id" => Ok(EntryComments::Id),
+            other => Err(format!("unknown entry comments \"{}\"", other)),
+        }
+    }
+}
+
+/// Players whose own options M3U playlists carry for the entries that need them. Other players skip
+/// them like any other comment.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PlayerHints {
+    /// `#EXTVLCOPT:start-time=` and `stop-time=` in seconds, for entries that only play part of
+    /// their file.
+    Vlc,
+}
+
+impl FromStr for PlayerHints {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "vlc" => Ok(PlayerHints::Vlc),
+            other => Err(format!("unknown player hints \"{}\"", other)),
+        }
+    }
+}
+
+/// How the durations of entries are written in M3U and PLS playlists, which give them in seconds.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DurationStyle {
+    /// Rounded to whole seconds, as most players expect.
+    Integer,
+    /// Rounded to tenths of a second, like `241.3`.
+    Fractional,
+    /// Always -1, as for unknown durations, for players that choke on any. XSPF playlists leave
+    /// them out.
+    None,
+}
+
+impl FromStr for DurationStyle {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "integer" => Ok(DurationStyle::Integer),
+            "fractional" => Ok(DurationStyle::Fractional),
+            "none" => Ok(DurationStyle::None),
+            other => Err(format!("unknown duration style \"{}\"", other)),
+        }
+    }
+}
+
+impl DurationStyle {
+    /// A duration in seconds, or -1 if it is unknown or not to be given. Halves round up.
+    pub fn format(self, duration_ms: Option<u32>) -> String {
+        match (self, duration_ms) {
+            (DurationStyle::Integer, Some(ms)) => ((u64::from(ms) + 500) / 1000).to_string(),
+            (DurationStyle::Fractional, Some(ms)) => {
+                let tenths = (u64::from(ms) + 50) / 100;
+                format!("{}.{}", tenths / 10, tenths % 10)
+            }
+            (DurationStyle::None, _) | (_, None) => "-1".to_owned(),
+        }
+    }
+}
+
+/// Reads the track an entry comment refers to, from a line of an M3U playlist.
+pub fn parse_entry_comment(line: &str) -> Option<TrackRef> {
+    let comment = line.trim_end().strip_prefix(m3u::COMMENT_PREFIX)?;
+    if let Some(id) = comment.strip_prefix("track=") {
+        return u64::from_str_radix(id, 16).ok().map(TrackRef::PersistentId);
+    }
+    comment.strip_prefix("track_id=")?.parse().ok().map(TrackRef::TrackId)
+}
+
+/// The contents of an M3U playlist without its entry comments, to compare playlists by the rest.
+pub fn without_entry_comments(contents: &[u8]) -> Vec<u8> {
+    contents.split_inclusive(|&b| b == b'\n')
+        .filter(|line| !line.starts_with(m3u::COMMENT_PREFIX.as_bytes()))
+        .flatten()
+        .copied()
+        .collect()
+}
+
+/// Settings that affect how playlists are written.
+#[derive(Clone, Debug)]
+pub struct Options {
+    /// Prepend a UTF-8 byte order mark to M3U playlists.
+    pub bom: bool,
+    /// Write the `#EXTM3U` header and `#EXTINF` lines in M3U playlists.
+    pub extinf: bool,
+    /// Leave out the `#EXTINF` line of entries with an unknown duration, rather than writing -1.
+    pub omit_unknown_extinf: bool,
+    /// Encoding of M3U and PLS playlists; the other formats are always UTF-8.
+    pub encoding: Encoding,
+    /// Written in place of characters that `encoding` can't represent.
+    pub substitute: String,
+    /// Percent-encode the paths of M3U entries, for players that read them as URIs.
+    pub encode_paths: bool,
+    /// The extension of M3U playlists, or if `None` .m3u8 for UTF-8 and .m3u otherwise.
+    pub m3u_extension: Option<M3uExtension>,
+    /// What generated the playlists, such as `m3trans 1.0`, to note in each of them.
+    pub generator: Option<String>,
+    /// When the playlists were generated, to note along with `generator`. Playlists that note it
+    /// differ on every run.
+    pub generated_at: Option<SystemTime>,
+    /// How the lines of playlists end, in every format.
+    pub line_endings: LineEndings,
+    /// Comments to write before each entry of M3U playlists.
+    pub entry_comments: Option<EntryComments>,
+    /// How durations are written.
+    pub duration_style: DurationStyle,
+    /// Options for a player to write in M3U playlists.
+    pub player_hints: Option<PlayerHints>,
+    /// Transliterate the names of playlists written into M3U playlists to ASCII, like their
+    /// titles, for players that can't display anything else.
+    #[cfg(feature = "sync")]
+    pub ascii: bool,
+    /// The separator between the components of entry paths. XSPF playlists, whose locations are
+    /// URIs, always use `/`.
+    pub path_separator: char,
+}
+
+impl Options {
+    fn encode(&self, text: &str, out: &mut Vec<u8>) {
+        self.encoding.encode(text, &self.substitute, out)
+    }
+
+    /// The note on what generated a playlist and when, if there is one.
+    fn stamp(&self) -> Option<String> {
+        let generator = self.generator.as_deref()?;
+        Some(match self.generated_at {
+            Some(time) => format!("Generated by {} at {}", generator, crate::date::rfc3339(time)),
+            None => format!("Generated by {}", generator),
+        })
+    }
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            bom: false,
+            extinf: true,
+            omit_unknown_extinf: false,
+            encoding: Encoding::Utf8,
+            substitute: "?".to_owned(),
+            encode_paths: false,
+            m3u_extension: None,
+            generator: None,
+            generated_at: None,
+            line_endings: LineEndings::Lf,
+            entry_comments: None,
+            duration_style: DurationStyle::Integer,
+            player_hints: None,
+            #[cfg(feature = "sync")]
+            ascii: false,
+            path_separator: '/',
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    M3u,
+    Pls,
+    Xspf,
+    Json,
+}
+
+impl Format {
+    pub fn extension(self, options: &Options) -> &'static str {
+        match self {
+            Format::M3u => match options.m3u_extension {
+                Some(M3uExtension::M3u) => "m3u",
+                Some(M3uExtension::M3u8) => "m3u8",
+                None if options.encoding == Encoding::Utf8 => "m3u8",
+                None => "m3u",
+            },
+            Format::Pls => "pls",
+            Format::Xspf => "xspf",
+            Format::Json => "json",
+        }
+    }
+
+    pub fn write(
+        self,
+        writer: impl Write,
+        playlist: &Playlist,
+        entries: &[Entry],
+        options: &Options,
+    ) -> Result<(), io::Error> {
+        match options.line_endings {
+            LineEndings::Lf => self.write_lines(writer, playlist, entries, options),
+            LineEndings::Crlf => self.write_lines(Crlf(writer), playlist, entries, options),
+        }
+    }
+
+    fn write_lines(
+        self,
+        writer: impl Write,
+        playlist: &Playlist,
+        entries: &[Entry],
+        options: &Options,
+    ) -> Result<(), io::Error> {
+        match self {
+            Format::M3u => m3u::write(writer, playlist, entries, options),
+            Format::Pls => pls::write(writer, entries, options),
+            Format::Xspf => xspf::write(writer, playlist, entries, options),
+            Format::Json => json::write(writer, playlist, entries, options),
+        }
+    }
+}
+
+impl Format {
+    /// Whether playlists in this format are written in `Options::encoding`.
+    pub fn uses_encoding(self) -> bool {
+        matches!(self, Format::M3u | Format::Pls)
+    }
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "m3u8" => Ok(Format::M3u),
+            "pls" => Ok(Format::Pls),
+            "xspf" => Ok(Format::Xspf),
+            "json" => Ok(Format::Json),
+            other => Err(format!("unknown playlist format \"{}\"", other)),
+        }
+    }
+}
+
+/// How lines end: with a line feed as on Unix, or a carriage return and line feed as on Windows,
+/// which some players need.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LineEndings {
+    Lf,
+    Crlf,
+}
+
+impl FromStr for LineEndings {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "lf" => Ok(LineEndings::Lf),
+            "crlf" => Ok(LineEndings::Crlf),
+            other => Err(format!("unknown line endings \"{}\"", other)),
+        }
+    }
+}
+
+/// Passes what is written on to another writer, ending lines with `\r\n` instead of `\n`.
+struct Crlf<W>(W);
+
+impl<W: Write> Write for Crlf<W> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize, io::Error> {
+        // Up to the next line feed at a time, so that the writer never gets half a line ending.
+        match buf.iter().position(|&b| b == b'\n') {
+            Some(0) => self.0.write_all(b"\r\n").map(|()| 1),
+            Some(end) => self.0.write(&buf[..end]),
+            None => self.0.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> Result<(), io::Error> {
+        self.0.flush()
+    }
+}
+
+/// The extensions M3U playlists can have, which players may be particular about whatever the
+/// encoding.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum M3uExtension {
+    M3u,
+    M3u8,
+}
+
+impl FromStr for M3uExtension {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "m3u" => Ok(M3uExtension::M3u),
+            "m3u8" => Ok(M3uExtension::M3u8),
+            other => Err(format!("unknown M3U extension \"{}\"", other)),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum IndexKind {
+    Folder,
+    Playlist,
+}
+
+/// One exported playlist or folder in the `index.json` written alongside JSON playlists.
+#[derive(Serialize)]
+pub struct IndexEntry {
+    pub name: String,
+    pub persistent_id: String,
+    pub parent_id: Option<String>,
+    pub kind: IndexKind,
+    /// Paths of the generated files or directory, relative to the playlists directory.
+    pub files: Vec<String>,
+    /// The entries of a playlist, kept only for the HTML index.
+    #[serde(skip)]
+    pub entries: Vec<Entry>,
+}
+
+pub fn write_index(writer: impl Write, index: &[IndexEntry]) -> Result<(), io::Error> {
+    json::write_index(writer, index)
+}
+
+/// Writes an HTML page listing the playlists of `index` as a tree, to go at the root of the
+/// output, where `playlists_dir` is the relative path of the playlists directory. With `pages`,
+/// each playlist links to its page of tracks as well.
+pub fn write_html_index(
+    writer: impl Write,
+    index: &[IndexEntry],
+    playlists_dir: &str,
+    pages: bool,
+    options: &Options,
+) -> Result<(), io::Error> {
+    html::write_index(writer, index, playlists_dir, pages, options)
+}
+
+/// Writes the HTML page listing the tracks of a playlist, to go at `html_page_path`. `home` is the
+/// relative link to the index from there.
+pub fn write_html_page(writer: impl Write, entry: &IndexEntry, home: &str, options: &Options) -> Result<(), io::Error> {
+    html::write_page(writer, entry, home, options)
+}
+
+/// Where the page of a playlist's tracks goes, relative to the playlists directory: next to its
+/// first file, in place of its extension. Folders have none.
+pub fn html_page_path(entry: &IndexEntry) -> Option<String> {
+    html::page_path(entry)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::PlaylistKind;
+
+    use super::*;
+
+    #[test]
+    fn durations_round_halves_up() {
+        let integer = |ms| DurationStyle::Integer.format(Some(ms));
+        assert_eq!(integer(0), "0");
+        assert_eq!(integer(499), "0");
+        assert_eq!(integer(1499), "1");
+        assert_eq!(integer(1500), "2");
+        assert_eq!(integer(241_333), "241");
+        let fractional = |ms| DurationStyle::Fractional.format(Some(ms));
+        assert_eq!(fractional(0), "0.0");
+        assert_eq!(fractional(241_333), "241.3");
+        assert_eq!(fractional(241_349), "241.3");
+        assert_eq!(fractional(241_350), "241.4");
+        assert_eq!(fractional(999_950), "1000.0");
+    }
+
+    #[test]
+    fn very_long_durations_dont_overflow() {
+        assert_eq!(DurationStyle::Integer.format(Some(u32::MAX)), "4294967");
+        assert_eq!(DurationStyle::Fractional.format(Some(u32::MAX)), "4294967.3");
+        // A day and a half.
+        assert_eq!(DurationStyle::Integer.format(Some(129_600_000)), "129600");
+    }
+
+    #[test]
+    fn unknown_or_unwanted_durations_are_minus_one() {
+        assert_eq!(DurationStyle::Integer.format(None), "-1");
+        assert_eq!(DurationStyle::Fractional.format(None), "-1");
+        assert_eq!(DurationStyle::None.format(Some(241_333)), "-1");
+        assert_eq!("fractional".parse(), Ok(DurationStyle::Fractional));
+        assert!("seconds".parse::<DurationStyle>().is_err());
+    }
+
+    #[test]
+    fn every_format_writes_durations_the_same_way() {
+        let playlist = Playlist {
+            persistent_id: 1,
+            parent_id: None,
+            name: "Mix".to_owned(),
+            kind: PlaylistKind::Generic,
+            is_smart: false,
+            is_visible: true,
+            items: Vec::new(),
+            order_key: 0,
+        };
+        let entries = [Entry {
+            path: "a.mp3".to_owned(),
+            title: "A".to_owned(),
+            duration_ms: Some(241_350),
+            start_ms: None,
+            stop_ms: None,
+            track: TrackRef::TrackId(1),
+        }];
+        let written = |format: Format, duration_style| {
+            let mut contents = Vec::new();
+            format.write(&mut contents, &playlist, &entries, &Options { duration_style, ..Options::default() }).unwrap();
+            String::from_utf8(contents).unwrap()
+        };
+        assert!(written(Format::M3u, DurationStyle::Integer).contains("#EXTINF:241,A\n"));
+        assert!(written(Format::M3u, DurationStyle::Fractional).contains("#EXTINF:241.4,A\n"));
+        assert!(written(Format::M3u, DurationStyle::None).contains("#EXTINF:-1,A\n"));
+        assert!(written(Format::Pls, DurationStyle::Integer).contains("Length1=241\n"));
+        assert!(written(Format::Pls, DurationStyle::Fractional).contains("Length1=241.4\n"));
+        assert!(written(Format::Pls, DurationStyle::None).contains("Length1=-1\n"));
+        // XSPF gives milliseconds, or nothing at all.
+        assert!(written(Format::Xspf, DurationStyle::Integer).contains("<duration>241350</duration>"));
+        assert!(!written(Format::Xspf, DurationStyle::None).contains("<duration>"));
+    }
+
+    /// What `format` writes for a playlist "Mix" of a track "Café" with `options`.
+    fn written_bytes(format: Format, options: &Options) -> Vec<u8> {
+        let playlist = Playlist {
+            persistent_id: 1,
+            parent_id: None,
+            name: "Mix".to_owned(),
+            kind: PlaylistKind::Generic,
+            is_smart: false,
+            is_visible: true,
+            items: Vec::new(),
+            order_key: 0,
+        };
+        let entries = [Entry {
+            path: "tracks/Café.mp3".to_owned(),
+            title: "Café".to_owned(),
+            duration_ms: Some(241_333),
+            start_ms: None,
+            stop_ms: None,
+            track: TrackRef::TrackId(1),
+        }];
+        let mut contents = Vec::new();
+        format.write(&mut contents, &playlist, &entries, options).unwrap();
+        contents
+    }
+
+    #[test]
+    fn m3u_headers_are_written_byte_for_byte() {
+        let cases: [(bool, bool, Encoding, &[u8]); 6] = [
+            (false, true, Encoding::Utf8, b"#EXTM3U\n#PLAYLIST:Mix\n#EXTINF:241,Caf\xc3\xa9\ntracks/Caf\xc3\xa9.mp3\n"),
+            (true, true, Encoding::Utf8, b"\xef\xbb\xbf#EXTM3U\n#PLAYLIST:Mix\n#EXTINF:241,Caf\xc3\xa9\ntracks/Caf\xc3\xa9.mp3\n"),
+            (false, false, Encoding::Utf8, b"tracks/Caf\xc3\xa9.mp3\n"),
+            (true, false, Encoding::Utf8, b"\xef\xbb\xbftracks/Caf\xc3\xa9.mp3\n"),
+            // Byte order marks are only for UTF-8.
+            (true, true, Encoding::Latin1, b"#EXTM3U\n#PLAYLIST:Mix\n#EXTINF:241,Caf\xe9\ntracks/Caf\xe9.mp3\n"),
+            (true, false, Encoding::Windows1252, b"tracks/Caf\xe9.mp3\n"),
+        ];
+        for (bom, extinf, encoding, expected) in cases {
+            let options = Options { bom, extinf, encoding, ..Options::default() };
+            let written = written_bytes(Format::M3u, &options);
+            assert_eq!(written, expected, "bom {}, extinf {}, {:?}: {:?}", bom, extinf, encoding, String::from_utf8_lossy(&written));
+        }
+    }
+
+    #[test]
+    fn crlf_line_endings_are_written_byte_for_byte() {
+        let cases: [(bool, bool, Encoding, &[u8]); 5] = [
+            (false, true, Encoding::Utf8, b"#EXTM3U\r\n#PLAYLIST:Mix\r\n#EXTINF:241,Caf\xc3\xa9\r\ntracks/Caf\xc3\xa9.mp3\r\n"),
+            (true, true, Encoding::Utf8, b"\xef\xbb\xbf#EXTM3U\r\n#PLAYLIST:Mix\r\n#EXTINF:241,Caf\xc3\xa9\r\ntracks/Caf\xc3\xa9.mp3\r\n"),
+            (true, false, Encoding::Utf8, b"\xef\xbb\xbftracks/Caf\xc3\xa9.mp3\r\n"),
+            (false, true, Encoding::Latin1, b"#EXTM3U\r\n#PLAYLIST:Mix\r\n#EXTINF:241,Caf\xe9\r\ntracks/Caf\xe9.mp3\r\n"),
+            (true, false, Encoding::Windows1252, b"tracks/Caf\xe9.mp3\r\n"),
+        ];
+        for (bom, extinf, encoding, expected) in cases {
+            let options = Options { bom, extinf, encoding, line_endings: LineEndings::Crlf, ..Options::default() };
+            let written = written_bytes(Format::M3u, &options);
+            assert_eq!(written, expected, "bom {}, extinf {}, {:?}: {:?}", bom, extinf, encoding, String::from_utf8_lossy(&written));
+        }
+
+        // UTF-8 playlists named .m3u say so, as players guess the encoding of those.
+        let options = Options { m3u_extension: Some(M3uExtension::M3u), line_endings: LineEndings::Crlf, ..Options::default() };
+        assert_eq!(written_bytes(Format::M3u, &options), b"#EXTM3U\r\n#PLAYLIST:Mix\r\n#EXTENC:UTF-8\r\n#EXTINF:241,Caf\xc3\xa9\r\ntracks/Caf\xc3\xa9.mp3\r\n");
+    }
+
+    #[test]
+    fn crlf_line_endings_reach_every_format() {
+        let options = Options { line_endings: LineEndings::Crlf, ..Options::default() };
+        let pls = written_bytes(Format::Pls, &options);
+        assert_eq!(pls, &b"[playlist]\r\nFile1=tracks/Caf\xc3\xa9.mp3\r\nTitle1=Caf\xc3\xa9\r\nLength1=241\r\nNumberOfEntries=1\r\nVersion=2\r\n"[..]);
+        for format in [Format::Xspf, Format::Json] {
+            let lf = written_bytes(format, &Options::default());
+            let crlf = written_bytes(format, &options);
+            assert!(lf.ends_with(b"\n") && crlf.ends_with(b"\r\n"), "{:?}", format);
+            assert!(!lf.contains(&b'\r'), "{:?}", format);
+            assert_eq!(crlf, lf.split_inclusive(|&b| b == b'\n').flat_map(|line| [&line[..line.len() - 1], b"\r\n"].concat()).collect::<Vec<u8>>(), "{:?}", format);
+        }
+    }
+
+    #[test]
+    fn crlf_ends_every_line_feed_however_it_is_written() {
+        let mut out = Crlf(Vec::new());
+        out.write_all(b"one\ntw").unwrap();
+        out.write_all(b"o\n").unwrap();
+        out.write_all(b"\n\nthree").unwrap();
+        out.write_all(b"").unwrap();
+        assert_eq!(out.0, b"one\r\ntwo\r\n\r\n\r\nthree");
+    }
+}
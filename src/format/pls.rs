@@ -0,0 +1,21 @@
+use std::io::{self, Write};
+
+use super::{Entry, Options};
+
+pub(super) fn write(mut writer: impl Write, entries: &[Entry], options: &Options) -> Result<(), io::Error> {
+    writeln!(writer, "[playlist]")?;
+    let mut line = Vec::new();
+    for (i, entry) in entries.iter().enumerate() {
+        let n = i + 1;
+        line.clear();
+        write!(line, "File{}=", n)?;
+        options.encode(&entry.path, &mut line);
+        write!(line, "\nTitle{}=", n)?;
+        options.encode(&entry.title, &mut line);
+        writeln!(line, "\nLength{}={}", n, options.duration_style.format(entry.duration_ms))?;
+        writer.write_all(&line)?;
+    }
+    writeln!(writer, "NumberOfEntries={}", entries.len())?;
+    writeln!(writer, "Version=2")?;
+    writer.flush()
+}
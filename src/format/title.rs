@@ -0,0 +1,119 @@
+use std::{borrow::Cow, str::FromStr};
+
+use crate::Track;
+
+/// A template for the display title of playlist entries, such as `{artist} - {name}`.
+///
+/// Fields that are missing or empty for a track are dropped along with the text separating them
+/// from their neighbours, so `{artist} - {name}` renders as just the name for tracks without an
+/// artist.
+#[derive(Clone, Debug)]
+pub struct TitleFormat {
+    parts: Vec<Part>,
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Field {
+    Name,
+    Artist,
+    AlbumArtist,
+    Album,
+    Composer,
+    Work,
+    Movement,
+    MovementNumber,
+}
+
+#[derive(Clone, Debug)]
+enum Part {
+    Literal(String),
+    Field(Field),
+}
+
+impl Field {
+    fn value(self, track: &Track) -> Option<Cow<'_, str>> {
+        let value = match self {
+            Field::Name => Some(&track.name),
+            Field::Artist => track.artist.as_ref(),
+            Field::AlbumArtist => track.album_artist.as_ref(),
+            Field::Album => track.album.as_ref(),
+            Field::Composer => track.composer.as_ref(),
+            Field::Work => track.work.as_ref(),
+            Field::Movement => track.movement_name.as_ref(),
+            Field::MovementNumber => return track.movement_number.map(|number| Cow::Owned(number.to_string())),
+        };
+        value.map(String::as_str).filter(|value| !value.is_empty()).map(Cow::Borrowed)
+    }
+}
+
+impl TitleFormat {
+    pub fn render(&self, track: &Track) -> String {
+        let mut title = String::new();
+        let mut emitted_field = false;
+        for (i, part) in self.parts.iter().enumerate() {
+            match part {
+                Part::Field(field) => if let Some(value) = field.value(track) {
+                    title.push_str(&value);
+                    emitted_field = true;
+                },
+                Part::Literal(text) => {
+                    let neighbour = |part: Option<&Part>| match part {
+                        Some(Part::Field(field)) => Some(field.value(track).is_some()),
+                        _ => None,
+                    };
+                    let previous = i.checked_sub(1).and_then(|i| neighbour(self.parts.get(i)));
+                    let next = neighbour(self.parts.get(i + 1));
+                    let keep = match (previous, next) {
+                        (None, None) => true,
+                        (None, Some(next)) => next,
+                        (Some(_), Some(next)) => emitted_field && next,
+                        (Some(previous), None) => previous,
+                    };
+                    if keep {
+                        title.push_str(text);
+                    }
+                },
+            }
+        }
+        title
+    }
+}
+
+impl Default for TitleFormat {
+    fn default() -> Self {
+        TitleFormat { parts: vec![Part::Field(Field::Name)] }
+    }
+}
+
+impl FromStr for TitleFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = Vec::new();
+        let mut rest = s;
+        while let Some(start) = rest.find('{') {
+            if start > 0 {
+                parts.push(Part::Literal(rest[..start].to_owned()));
+            }
+            let end = rest[start..].find('}')
+                .ok_or_else(|| format!("unclosed '{{' in title format \"{}\"", s))?;
+            let field = match &rest[start + 1..start + end] {
+                "name" => Field::Name,
+                "artist" => Field::Artist,
+                "album_artist" => Field::AlbumArtist,
+                "album" => Field::Album,
+                "composer" => Field::Composer,
+                "work" => Field::Work,
+                "movement" => Field::Movement,
+                "movement_number" => Field::MovementNumber,
+                other => return Err(format!("unknown field {{{}}} in title format \"{}\"", other, s)),
+            };
+            parts.push(Part::Field(field));
+            rest = &rest[start + end + 1..];
+        }
+        if !rest.is_empty() {
+            parts.push(Part::Literal(rest.to_owned()));
+        }
+        Ok(TitleFormat { parts })
+    }
+}
@@ -0,0 +1,61 @@
+use std::io::{self, Write};
+
+use percent_encoding::{AsciiSet, CONTROLS, utf8_percent_encode};
+
+use crate::Playlist;
+use super::{DurationStyle, Entry, Options};
+
+/// Characters that may not appear literally in the path of a relative URI reference.
+pub(super) const LOCATION: &AsciiSet = &CONTROLS
+    .add(b' ').add(b'"').add(b'#').add(b'%').add(b'<').add(b'>')
+    .add(b'?').add(b'[').add(b']').add(b'\\').add(b'^').add(b'`').add(b'{').add(b'|').add(b'}');
+
+pub(super) fn write(mut writer: impl Write, playlist: &Playlist, entries: &[Entry], options: &Options) -> Result<(), io::Error> {
+    writeln!(writer, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+    writeln!(writer, r#"<playlist version="1" xmlns="http://xspf.org/ns/0/">"#)?;
+    writeln!(writer, "  <title>{}</title>", escape(&playlist.name))?;
+    if let Some(generator) = &options.generator {
+        writeln!(writer, "  <annotation>Generated by {}</annotation>", escape(generator))?;
+        if let Some(time) = options.generated_at {
+            writeln!(writer, "  <date>{}</date>", crate::date::rfc3339(time))?;
+        }
+    }
+    writeln!(writer, "  <trackList>")?;
+    for entry in entries {
+        let location = location(&entry.path, options);
+        writeln!(writer, "    <track>")?;
+        writeln!(writer, "      <location>{}</location>", escape(&location))?;
+        writeln!(writer, "      <title>{}</title>", escape(&entry.title))?;
+        // In milliseconds, as XSPF has them, however M3U and PLS playlists round them.
+        if let Some(duration_ms) = entry.duration_ms.filter(|_| options.duration_style != DurationStyle::None) {
+            writeln!(writer, "      <duration>{}</duration>", duration_ms)?;
+        }
+        writeln!(writer, "    </track>")?;
+    }
+    writeln!(writer, "  </trackList>")?;
+    writeln!(writer, "</playlist>")?;
+    writer.flush()
+}
+
+/// The location of an entry, with `/` between its components whatever separator its path has.
+pub(super) fn location(path: &str, options: &Options) -> String {
+    path.split(options.path_separator)
+        .map(|component| utf8_percent_encode(component, LOCATION).to_string())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+pub(super) fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
@@ -0,0 +1,198 @@
+//! Ignore rules with `.gitignore`-like semantics, matched against the folder path of a playlist.
+
+use std::{borrow::Borrow, fmt};
+
+/// A single line of an ignore file.
+pub(crate) struct Rule {
+    matcher: Matcher,
+    negate: bool,
+    /// The line ended in a slash, so it matches folders and everything beneath them.
+    folder: bool,
+}
+
+enum Matcher {
+    /// A glob that contained a slash is matched against the whole path, any other only against
+    /// the playlist's own name.
+    Glob { pattern: glob::Pattern, anchored: bool, options: glob::MatchOptions },
+    /// An `id:` line, matching the playlist with that persistent ID.
+    Id(u64),
+}
+
+#[derive(Debug)]
+pub(crate) enum RuleError {
+    InvalidPattern(glob::PatternError),
+    InvalidId(std::num::ParseIntError),
+}
+
+impl fmt::Display for RuleError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RuleError::InvalidPattern(e) => write!(f, "invalid pattern: {}", e),
+            RuleError::InvalidId(e) => write!(f, "invalid persistent ID: {}", e),
+        }
+    }
+}
+
+impl Rule {
+    /// Parses a line of an ignore file, returning `None` for blank lines and `#` comments.
+    pub(crate) fn parse(line: &str, ignore_case: bool) -> Result<Option<Rule>, RuleError> {
+        let line = line.trim_end();
+        if line.trim_start().is_empty() || line.starts_with('#') {
+            return Ok(None);
+        }
+
+        let (negate, line) = match line.strip_prefix('!') {
+            Some(rest) => (true, rest),
+            None => (false, line),
+        };
+        let (folder, line) = match line.strip_suffix('/') {
+            Some(rest) => (true, rest),
+            None => (false, line),
+        };
+        let matcher = match line.strip_prefix("id:") {
+            Some(id) => Matcher::Id(u64::from_str_radix(id.trim(), 16).map_err(RuleError::InvalidId)?),
+            None => Matcher::Glob {
+                pattern: glob::Pattern::new(line.strip_prefix('/').unwrap_or(line))
+                    .map_err(RuleError::InvalidPattern)?,
+                anchored: line.contains('/'),
+                options: match_options(ignore_case),
+            },
+        };
+        Ok(Some(Rule { matcher, negate, folder }))
+    }
+
+    /// Whether this rule matches the playlist at `len` levels into `names`/`ids`.
+    fn matches_path<S: Borrow<str>>(&self, names: &[S], ids: &[u64], len: usize) -> bool {
+        match &self.matcher {
+            Matcher::Glob { pattern, anchored: true, options } => pattern.matches_with(&names[..len].join("/"), *options),
+            Matcher::Glob { pattern, anchored: false, options } => pattern.matches_with(names[len - 1].borrow(), *options),
+            Matcher::Id(id) => ids[len - 1] == *id,
+        }
+    }
+
+    /// Whether this rule applies to the playlist whose path from the root consists of `names`,
+    /// with the persistent IDs of the playlists along the way in `ids`.
+    pub(crate) fn matches<S: Borrow<str>>(&self, names: &[S], ids: &[u64], is_folder: bool) -> bool {
+        let depth = names.len();
+        match self.folder {
+            // Everything but the playlist itself is a folder containing it.
+            true => (1..=depth)
+                .filter(|&len| len < depth || is_folder)
+                .any(|len| self.matches_path(names, ids, len)),
+            false => depth > 0 && self.matches_path(names, ids, depth),
+        }
+    }
+}
+
+/// How patterns are matched against playlist paths, the same for ignore rules and include patterns:
+/// `*` also matches slashes, and case matters unless `ignore_case` is given. Only the case of ASCII
+/// letters is ignored, as glob does no more, so `É` still doesn't match `é`.
+pub(crate) fn match_options(ignore_case: bool) -> glob::MatchOptions {
+    glob::MatchOptions { case_sensitive: !ignore_case, ..glob::MatchOptions::new() }
+}
+
+/// Whether the playlist at `names` is ignored by `rules`. Later rules take precedence over
+/// earlier ones, so a negated rule can re-include something an earlier rule ignored.
+pub(crate) fn is_ignored<S: Borrow<str>>(rules: &[Rule], names: &[S], ids: &[u64], is_folder: bool) -> bool {
+    rules.iter().rev()
+        .find(|rule| rule.matches(names, ids, is_folder))
+        .is_some_and(|rule| !rule.negate)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rules(lines: &[&str]) -> Vec<Rule> {
+        lines.iter().filter_map(|line| Rule::parse(line, false).unwrap()).collect()
+    }
+
+    /// Whether the playlist at the slash-separated `path` is ignored, its IDs counting up from 1.
+    fn ignored(rules: &[Rule], path: &str, is_folder: bool) -> bool {
+        let names: Vec<&str> = path.split('/').collect();
+        let ids: Vec<u64> = (1..=names.len() as u64).collect();
+        is_ignored(rules, &names, &ids, is_folder)
+    }
+
+    #[test]
+    fn blank_lines_and_comments_are_no_rules() {
+        assert!(Rule::parse("", false).unwrap().is_none());
+        assert!(Rule::parse("   ", false).unwrap().is_none());
+        assert!(Rule::parse("# Audiobooks", false).unwrap().is_none());
+    }
+
+    #[test]
+    fn bad_patterns_and_ids_are_errors() {
+        assert!(matches!(Rule::parse("[abc", false), Err(RuleError::InvalidPattern(_))));
+        assert!(matches!(Rule::parse("id:XYZ", false), Err(RuleError::InvalidId(_))));
+    }
+
+    #[test]
+    fn patterns_without_a_slash_match_names_at_any_depth() {
+        let rules = rules(&["Old*"]);
+        assert!(ignored(&rules, "Oldies", false));
+        assert!(ignored(&rules, "Rock/Oldies", false));
+        assert!(!ignored(&rules, "Oldies/Rock", false));
+    }
+
+    #[test]
+    fn patterns_with_a_slash_are_anchored_to_the_top() {
+        let rules = rules(&["Rock/Live"]);
+        assert!(ignored(&rules, "Rock/Live", false));
+        assert!(!ignored(&rules, "Pop/Rock/Live", false));
+        assert!(!ignored(&rules, "Live", false));
+        // A leading slash only anchors it.
+        let rules = self::rules(&["/Live"]);
+        assert!(ignored(&rules, "Live", false));
+        assert!(!ignored(&rules, "Rock/Live", false));
+    }
+
+    #[test]
+    fn double_stars_match_any_number_of_folders() {
+        let rules = rules(&["**/Live"]);
+        assert!(ignored(&rules, "Rock/Live", false));
+        assert!(ignored(&rules, "Rock/90s/Live", false));
+        let rules = self::rules(&["Rock/**"]);
+        assert!(ignored(&rules, "Rock/90s/Live", false));
+        assert!(!ignored(&rules, "Pop/Live", false));
+    }
+
+    #[test]
+    fn rules_ending_in_a_slash_only_match_folders_and_what_is_in_them() {
+        let rules = rules(&["Live/"]);
+        assert!(ignored(&rules, "Live", true));
+        assert!(ignored(&rules, "Live/1999", false));
+        assert!(ignored(&rules, "Rock/Live/1999", false));
+        assert!(!ignored(&rules, "Live", false));
+    }
+
+    #[test]
+    fn ids_match_the_playlist_with_that_persistent_id() {
+        let rules = rules(&["id:2"]);
+        assert!(ignored(&rules, "Rock/Live", false));
+        assert!(!ignored(&rules, "Live", false));
+        // IDs are hexadecimal, like those in the library.
+        let rules = self::rules(&["id: a"]);
+        assert!(ignored(&rules, "1/2/3/4/5/6/7/8/9/10", false));
+    }
+
+    #[test]
+    fn negated_rules_re_include_what_earlier_ones_ignored() {
+        let rules = rules(&["Rock/", "!Rock/Keep"]);
+        assert!(ignored(&rules, "Rock/Drop", false));
+        assert!(!ignored(&rules, "Rock/Keep", false));
+        // Only the last rule to match counts.
+        let rules = self::rules(&["!Rock/Keep", "Rock/"]);
+        assert!(ignored(&rules, "Rock/Keep", false));
+        let rules = self::rules(&["Keep", "!Keep", "Keep"]);
+        assert!(ignored(&rules, "Keep", false));
+    }
+
+    #[test]
+    fn case_matters_unless_ignored() {
+        let rule = Rule::parse("live", true).unwrap().unwrap();
+        assert!(rule.matches(&["Live"], &[1], false));
+        let rule = Rule::parse("live", false).unwrap().unwrap();
+        assert!(!rule.matches(&["Live"], &[1], false));
+    }
+}
@@ -0,0 +1,54 @@
+//! Stopping a sync between files on Ctrl-C.
+
+use std::sync::atomic::{self, AtomicBool};
+
+use crate::INTERRUPTED_STATUS;
+
+/// Set by the first Ctrl-C, which asks the sync to stop before the next track or playlist.
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+/// Makes Ctrl-C stop the sync between files rather than partway through one, and a second Ctrl-C
+/// stop it at once. Elsewhere than on Unix and Windows, Ctrl-C keeps its default of stopping at
+/// once.
+#[cfg(unix)]
+pub(crate) fn handle_interrupts() {
+    extern "C" fn on_interrupt(_: libc::c_int) {
+        if INTERRUPTED.swap(true, atomic::Ordering::SeqCst) {
+            // Only async-signal-safe functions may be called here, so nothing is cleaned up.
+            unsafe { libc::_exit(INTERRUPTED_STATUS) };
+        }
+    }
+    let handler: extern "C" fn(libc::c_int) = on_interrupt;
+    unsafe { libc::signal(libc::SIGINT, handler as libc::sighandler_t) };
+}
+
+#[cfg(windows)]
+pub(crate) fn handle_interrupts() {
+    const CTRL_C_EVENT: u32 = 0;
+    const TRUE: i32 = 1;
+
+    #[link(name = "kernel32")]
+    extern "system" {
+        fn SetConsoleCtrlHandler(handler: Option<extern "system" fn(u32) -> i32>, add: i32) -> i32;
+    }
+
+    // Windows calls handlers on a thread of their own, so unlike on Unix the process can exit
+    // normally from one.
+    extern "system" fn on_ctrl(event: u32) -> i32 {
+        if event != CTRL_C_EVENT {
+            return 0;
+        }
+        if INTERRUPTED.swap(true, atomic::Ordering::SeqCst) {
+            std::process::exit(INTERRUPTED_STATUS);
+        }
+        TRUE
+    }
+    unsafe { SetConsoleCtrlHandler(Some(on_ctrl), TRUE) };
+}
+
+#[cfg(not(any(unix, windows)))]
+pub(crate) fn handle_interrupts() {}
+
+pub(crate) fn interrupted() -> bool {
+    INTERRUPTED.load(atomic::Ordering::SeqCst)
+}
@@ -0,0 +1,547 @@
+//! A minimal JSON serializer for `serde::Serialize` types.
+
+use std::{fmt::{self, Display}, io::{self, Write}};
+
+use serde::{ser, Serialize};
+
+#[derive(Debug)]
+pub(crate) enum Error {
+    Io(io::Error),
+    Message(String),
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io(e) => write!(f, "{}", e),
+            Error::Message(message) => f.write_str(message),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl ser::Error for Error {
+    fn custom<T: Display>(msg: T) -> Self {
+        Error::Message(msg.to_string())
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+impl From<Error> for io::Error {
+    fn from(e: Error) -> Self {
+        match e {
+            Error::Io(e) => e,
+            Error::Message(message) => io::Error::new(io::ErrorKind::InvalidData, message),
+        }
+    }
+}
+
+pub(crate) fn to_writer<W: Write, T: Serialize + ?Sized>(writer: W, value: &T) -> Result<(), Error> {
+    value.serialize(&mut Serializer::new(writer, false))
+}
+
+pub(crate) fn to_writer_pretty<W: Write, T: Serialize + ?Sized>(mut writer: W, value: &T) -> Result<(), Error> {
+    value.serialize(&mut Serializer::new(&mut writer, true))?;
+    writer.write_all(b"\n")?;
+    Ok(())
+}
+
+pub(crate) fn to_string<T: Serialize + ?Sized>(value: &T) -> Result<String, Error> {
+    let mut buffer = Vec::new();
+    to_writer(&mut buffer, value)?;
+    Ok(String::from_utf8(buffer).expect("serializer only emits UTF-8"))
+}
+
+pub(crate) fn to_string_pretty<T: Serialize + ?Sized>(value: &T) -> Result<String, Error> {
+    let mut buffer = Vec::new();
+    to_writer_pretty(&mut buffer, value)?;
+    Ok(String::from_utf8(buffer).expect("serializer only emits UTF-8"))
+}
+
+pub(crate) struct Serializer<W> {
+    writer: W,
+    pretty: bool,
+    depth: usize,
+}
+
+impl<W: Write> Serializer<W> {
+    pub(crate) fn new(writer: W, pretty: bool) -> Self {
+        Serializer { writer, pretty, depth: 0 }
+    }
+
+    fn newline(&mut self) -> Result<(), Error> {
+        if self.pretty {
+            self.writer.write_all(b"\n")?;
+            for _ in 0..self.depth {
+                self.writer.write_all(b"  ")?;
+            }
+        }
+        Ok(())
+    }
+
+    fn begin(&mut self, open: u8) -> Result<(), Error> {
+        self.writer.write_all(&[open])?;
+        self.depth += 1;
+        Ok(())
+    }
+
+    fn end(&mut self, close: u8, empty: bool) -> Result<(), Error> {
+        self.depth -= 1;
+        if !empty { self.newline()?; }
+        self.writer.write_all(&[close])?;
+        Ok(())
+    }
+
+    fn separator(&mut self, first: bool) -> Result<(), Error> {
+        if !first { self.writer.write_all(b",")?; }
+        self.newline()
+    }
+
+    fn key(&mut self, key: &str) -> Result<(), Error> {
+        write_str(&mut self.writer, key)?;
+        self.writer.write_all(if self.pretty { b": " } else { b":" })?;
+        Ok(())
+    }
+}
+
+pub(crate) fn write_str(mut writer: impl Write, value: &str) -> Result<(), io::Error> {
+    writer.write_all(b"\"")?;
+    let mut start = 0;
+    for (i, c) in value.char_indices() {
+        let escape = match c {
+            '"' => "\\\"",
+            '\\' => "\\\\",
+            '\n' => "\\n",
+            '\r' => "\\r",
+            '\t' => "\\t",
+            c if (c as u32) < 0x20 => "",
+            _ => continue,
+        };
+        writer.write_all(&value.as_bytes()[start..i])?;
+        match escape {
+            "" => write!(writer, "\\u{:04x}", c as u32)?,
+            escape => writer.write_all(escape.as_bytes())?,
+        }
+        start = i + c.len_utf8();
+    }
+    writer.write_all(&value.as_bytes()[start..])?;
+    writer.write_all(b"\"")
+}
+
+pub(crate) struct Compound<'a, W> {
+    ser: &'a mut Serializer<W>,
+    first: bool,
+    /// Whether a variant wrapper object must be closed after the compound itself.
+    variant: bool,
+}
+
+impl<'a, W: Write> Compound<'a, W> {
+    fn element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        self.ser.separator(self.first)?;
+        self.first = false;
+        value.serialize(&mut *self.ser)
+    }
+
+    fn field<T: Serialize + ?Sized>(&mut self, key: &str, value: &T) -> Result<(), Error> {
+        self.ser.separator(self.first)?;
+        self.first = false;
+        self.ser.key(key)?;
+        value.serialize(&mut *self.ser)
+    }
+
+    fn finish(self, close: u8) -> Result<(), Error> {
+        self.ser.end(close, self.first)?;
+        if self.variant { self.ser.end(b'}', false)?; }
+        Ok(())
+    }
+}
+
+impl<'a, W: Write> ser::Serializer for &'a mut Serializer<W> {
+    type Ok = ();
+    type Error = Error;
+    type SerializeSeq = Compound<'a, W>;
+    type SerializeTuple = Compound<'a, W>;
+    type SerializeTupleStruct = Compound<'a, W>;
+    type SerializeTupleVariant = Compound<'a, W>;
+    type SerializeMap = Compound<'a, W>;
+    type SerializeStruct = Compound<'a, W>;
+    type SerializeStructVariant = Compound<'a, W>;
+
+    fn serialize_bool(self, v: bool) -> Result<(), Error> {
+        self.writer.write_all(if v { b"true" } else { b"false" })?;
+        Ok(())
+    }
+
+    fn serialize_i8(self, v: i8) -> Result<(), Error> { self.serialize_i64(v as i64) }
+    fn serialize_i16(self, v: i16) -> Result<(), Error> { self.serialize_i64(v as i64) }
+    fn serialize_i32(self, v: i32) -> Result<(), Error> { self.serialize_i64(v as i64) }
+    fn serialize_i64(self, v: i64) -> Result<(), Error> {
+        write!(self.writer, "{}", v)?;
+        Ok(())
+    }
+
+    fn serialize_u8(self, v: u8) -> Result<(), Error> { self.serialize_u64(v as u64) }
+    fn serialize_u16(self, v: u16) -> Result<(), Error> { self.serialize_u64(v as u64) }
+    fn serialize_u32(self, v: u32) -> Result<(), Error> { self.serialize_u64(v as u64) }
+    fn serialize_u64(self, v: u64) -> Result<(), Error> {
+        write!(self.writer, "{}", v)?;
+        Ok(())
+    }
+
+    fn serialize_f32(self, v: f32) -> Result<(), Error> { self.serialize_f64(v as f64) }
+    fn serialize_f64(self, v: f64) -> Result<(), Error> {
+        match v.is_finite() {
+            true => write!(self.writer, "{}", v)?,
+            false => self.writer.write_all(b"null")?,
+        }
+        Ok(())
+    }
+
+    fn serialize_char(self, v: char) -> Result<(), Error> {
+        self.serialize_str(v.encode_utf8(&mut [0; 4]))
+    }
+
+    fn serialize_str(self, v: &str) -> Result<(), Error> {
+        Ok(write_str(&mut self.writer, v)?)
+    }
+
+    fn serialize_bytes(self, v: &[u8]) -> Result<(), Error> {
+        use ser::SerializeSeq;
+        let mut seq = self.serialize_seq(Some(v.len()))?;
+        for byte in v {
+            seq.serialize_element(byte)?;
+        }
+        seq.end()
+    }
+
+    fn serialize_none(self) -> Result<(), Error> { self.serialize_unit() }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<(), Error> {
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<(), Error> {
+        self.writer.write_all(b"null")?;
+        Ok(())
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<(), Error> { self.serialize_unit() }
+
+    fn serialize_unit_variant(self, _name: &'static str, _index: u32, variant: &'static str) -> Result<(), Error> {
+        self.serialize_str(variant)
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(self, _name: &'static str, value: &T) -> Result<(), Error> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        value: &T,
+    ) -> Result<(), Error> {
+        self.begin(b'{')?;
+        self.newline()?;
+        self.key(variant)?;
+        value.serialize(&mut *self)?;
+        self.end(b'}', false)
+    }
+
+    fn serialize_seq(self, _len: Option<usize>) -> Result<Compound<'a, W>, Error> {
+        self.begin(b'[')?;
+        Ok(Compound { ser: self, first: true, variant: false })
+    }
+
+    fn serialize_tuple(self, len: usize) -> Result<Compound<'a, W>, Error> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_struct(self, _name: &'static str, len: usize) -> Result<Compound<'a, W>, Error> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        _len: usize,
+    ) -> Result<Compound<'a, W>, Error> {
+        self.begin(b'{')?;
+        self.newline()?;
+        self.key(variant)?;
+        self.begin(b'[')?;
+        Ok(Compound { ser: self, first: true, variant: true })
+    }
+
+    fn serialize_map(self, _len: Option<usize>) -> Result<Compound<'a, W>, Error> {
+        self.begin(b'{')?;
+        Ok(Compound { ser: self, first: true, variant: false })
+    }
+
+    fn serialize_struct(self, _name: &'static str, len: usize) -> Result<Compound<'a, W>, Error> {
+        self.serialize_map(Some(len))
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        _len: usize,
+    ) -> Result<Compound<'a, W>, Error> {
+        self.begin(b'{')?;
+        self.newline()?;
+        self.key(variant)?;
+        self.begin(b'{')?;
+        Ok(Compound { ser: self, first: true, variant: true })
+    }
+}
+
+impl<'a, W: Write> ser::SerializeSeq for Compound<'a, W> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        self.element(value)
+    }
+
+    fn end(self) -> Result<(), Error> { self.finish(b']') }
+}
+
+impl<'a, W: Write> ser::SerializeTuple for Compound<'a, W> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        self.element(value)
+    }
+
+    fn end(self) -> Result<(), Error> { self.finish(b']') }
+}
+
+impl<'a, W: Write> ser::SerializeTupleStruct for Compound<'a, W> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        self.element(value)
+    }
+
+    fn end(self) -> Result<(), Error> { self.finish(b']') }
+}
+
+impl<'a, W: Write> ser::SerializeTupleVariant for Compound<'a, W> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        self.element(value)
+    }
+
+    fn end(self) -> Result<(), Error> { self.finish(b']') }
+}
+
+impl<'a, W: Write> ser::SerializeMap for Compound<'a, W> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_key<T: Serialize + ?Sized>(&mut self, key: &T) -> Result<(), Error> {
+        self.ser.separator(self.first)?;
+        self.first = false;
+        let key = key.serialize(KeySerializer)?;
+        self.ser.key(&key)
+    }
+
+    fn serialize_value<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        value.serialize(&mut *self.ser)
+    }
+
+    fn end(self) -> Result<(), Error> { self.finish(b'}') }
+}
+
+impl<'a, W: Write> ser::SerializeStruct for Compound<'a, W> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, key: &'static str, value: &T) -> Result<(), Error> {
+        self.field(key, value)
+    }
+
+    fn end(self) -> Result<(), Error> { self.finish(b'}') }
+}
+
+impl<'a, W: Write> ser::SerializeStructVariant for Compound<'a, W> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, key: &'static str, value: &T) -> Result<(), Error> {
+        self.field(key, value)
+    }
+
+    fn end(self) -> Result<(), Error> { self.finish(b'}') }
+}
+
+/// Serializes map keys, which JSON requires to be strings.
+struct KeySerializer;
+
+fn key_error() -> Error {
+    Error::Message("map keys must be strings or integers".to_owned())
+}
+
+impl ser::Serializer for KeySerializer {
+    type Ok = String;
+    type Error = Error;
+    type SerializeSeq = ser::Impossible<String, Error>;
+    type SerializeTuple = ser::Impossible<String, Error>;
+    type SerializeTupleStruct = ser::Impossible<String, Error>;
+    type SerializeTupleVariant = ser::Impossible<String, Error>;
+    type SerializeMap = ser::Impossible<String, Error>;
+    type SerializeStruct = ser::Impossible<String, Error>;
+    type SerializeStructVariant = ser::Impossible<String, Error>;
+
+    fn serialize_str(self, v: &str) -> Result<String, Error> { Ok(v.to_owned()) }
+    fn serialize_char(self, v: char) -> Result<String, Error> { Ok(v.to_string()) }
+    fn serialize_i8(self, v: i8) -> Result<String, Error> { Ok(v.to_string()) }
+    fn serialize_i16(self, v: i16) -> Result<String, Error> { Ok(v.to_string()) }
+    fn serialize_i32(self, v: i32) -> Result<String, Error> { Ok(v.to_string()) }
+    fn serialize_i64(self, v: i64) -> Result<String, Error> { Ok(v.to_string()) }
+    fn serialize_u8(self, v: u8) -> Result<String, Error> { Ok(v.to_string()) }
+    fn serialize_u16(self, v: u16) -> Result<String, Error> { Ok(v.to_string()) }
+    fn serialize_u32(self, v: u32) -> Result<String, Error> { Ok(v.to_string()) }
+    fn serialize_u64(self, v: u64) -> Result<String, Error> { Ok(v.to_string()) }
+
+    fn serialize_unit_variant(self, _name: &'static str, _index: u32, variant: &'static str) -> Result<String, Error> {
+        Ok(variant.to_owned())
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(self, _name: &'static str, value: &T) -> Result<String, Error> {
+        value.serialize(self)
+    }
+
+    fn serialize_bool(self, _v: bool) -> Result<String, Error> { Err(key_error()) }
+    fn serialize_f32(self, _v: f32) -> Result<String, Error> { Err(key_error()) }
+    fn serialize_f64(self, _v: f64) -> Result<String, Error> { Err(key_error()) }
+    fn serialize_bytes(self, _v: &[u8]) -> Result<String, Error> { Err(key_error()) }
+    fn serialize_none(self) -> Result<String, Error> { Err(key_error()) }
+    fn serialize_some<T: Serialize + ?Sized>(self, _value: &T) -> Result<String, Error> { Err(key_error()) }
+    fn serialize_unit(self) -> Result<String, Error> { Err(key_error()) }
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<String, Error> { Err(key_error()) }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self, _name: &'static str, _index: u32, _variant: &'static str, _value: &T,
+    ) -> Result<String, Error> {
+        Err(key_error())
+    }
+
+    fn serialize_seq(self, _len: Option<usize>) -> Result<Self::SerializeSeq, Error> { Err(key_error()) }
+    fn serialize_tuple(self, _len: usize) -> Result<Self::SerializeTuple, Error> { Err(key_error()) }
+
+    fn serialize_tuple_struct(self, _name: &'static str, _len: usize) -> Result<Self::SerializeTupleStruct, Error> {
+        Err(key_error())
+    }
+
+    fn serialize_tuple_variant(
+        self, _name: &'static str, _index: u32, _variant: &'static str, _len: usize,
+    ) -> Result<Self::SerializeTupleVariant, Error> {
+        Err(key_error())
+    }
+
+    fn serialize_map(self, _len: Option<usize>) -> Result<Self::SerializeMap, Error> { Err(key_error()) }
+
+    fn serialize_struct(self, _name: &'static str, _len: usize) -> Result<Self::SerializeStruct, Error> {
+        Err(key_error())
+    }
+
+    fn serialize_struct_variant(
+        self, _name: &'static str, _index: u32, _variant: &'static str, _len: usize,
+    ) -> Result<Self::SerializeStructVariant, Error> {
+        Err(key_error())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use serde::Serialize;
+
+    use super::*;
+
+    #[derive(Serialize)]
+    #[serde(rename_all = "snake_case")]
+    enum Kind {
+        Unit,
+        Newtype(u32),
+        Tuple(u8, bool),
+        Struct { name: &'static str },
+    }
+
+    #[derive(Serialize)]
+    struct Record {
+        id: u64,
+        title: &'static str,
+        rating: Option<f64>,
+        tags: Vec<&'static str>,
+        kinds: Vec<Kind>,
+        empty: BTreeMap<String, u8>,
+    }
+
+    fn record() -> Record {
+        Record {
+            id: 18_446_744_073_709_551_615,
+            title: "Caf\u{e9} \"Noir\"",
+            rating: Some(4.5),
+            tags: vec!["a", "b"],
+            kinds: vec![Kind::Unit, Kind::Newtype(7), Kind::Tuple(1, false), Kind::Struct { name: "x" }],
+            empty: BTreeMap::new(),
+        }
+    }
+
+    #[test]
+    fn compact_output() {
+        assert_eq!(
+            to_string(&record()).unwrap(),
+            "{\"id\":18446744073709551615,\"title\":\"Caf\u{e9} \\\"Noir\\\"\",\"rating\":4.5,\"tags\":[\"a\",\"b\"],\
+             \"kinds\":[\"unit\",{\"newtype\":7},{\"tuple\":[1,false]},{\"struct\":{\"name\":\"x\"}}],\"empty\":{}}"
+        );
+        assert_eq!(to_string(&Option::<u8>::None).unwrap(), "null");
+        assert_eq!(to_string(&[f64::NAN, -0.25]).unwrap(), "[null,-0.25]");
+        assert_eq!(to_string(&Vec::<u8>::new()).unwrap(), "[]");
+        assert_eq!(to_string(&'c').unwrap(), "\"c\"");
+    }
+
+    #[test]
+    fn pretty_output() {
+        let mut map = BTreeMap::new();
+        map.insert("one", vec![1]);
+        map.insert("none", Vec::new());
+        assert_eq!(to_string_pretty(&map).unwrap(), "{\n  \"none\": [],\n  \"one\": [\n    1\n  ]\n}\n");
+        assert_eq!(
+            to_string_pretty(&Kind::Struct { name: "x" }).unwrap(),
+            "{\n  \"struct\": {\n    \"name\": \"x\"\n  }\n}\n"
+        );
+    }
+
+    #[test]
+    fn strings_are_escaped() {
+        let escaped = |value: &str| {
+            let mut buffer = Vec::new();
+            write_str(&mut buffer, value).unwrap();
+            String::from_utf8(buffer).unwrap()
+        };
+        assert_eq!(escaped(""), "\"\"");
+        assert_eq!(escaped("plain é ♪"), "\"plain é ♪\"");
+        assert_eq!(escaped("a\"b\\c"), "\"a\\\"b\\\\c\"");
+        assert_eq!(escaped("\n\r\t"), "\"\\n\\r\\t\"");
+        assert_eq!(escaped("\u{0}\u{1f}\u{7f}"), "\"\\u0000\\u001f\u{7f}\"");
+    }
+}
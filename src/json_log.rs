@@ -0,0 +1,109 @@
+//! A logger writing each record as one line of JSON, for tools to read rather than people.
+//!
+//! Besides its timestamp, level and message, a line has the record's key-values as fields. The
+//! `event` key names what happened, and is `"message"` for records without one.
+
+use std::{fmt::Write as _, io::Write, sync::Mutex, time::SystemTime};
+
+use log::{kv::{self, VisitSource}, LevelFilter, Log, Metadata, Record};
+use simplelog::{Config, SharedLogger};
+
+use crate::{date::rfc3339, json};
+
+pub(crate) struct JsonLogger<W: Write + Send> {
+    level: LevelFilter,
+    config: Config,
+    writer: Mutex<W>,
+}
+
+impl<W: Write + Send + 'static> JsonLogger<W> {
+    pub(crate) fn new(level: LevelFilter, writer: W) -> Box<Self> {
+        Box::new(JsonLogger {
+            level,
+                    config: Config::default(),
+            writer: Mutex::new(writer),
+        })
+    }
+}
+
+impl<W: Write + Send> Log for JsonLogger<W> {
+    fn enabled(&self, metadata: &Metadata<'_>) -> bool {
+        metadata.level() <= self.level
+    }
+
+    fn log(&self, record: &Record<'_>) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
+        let mut fields = Fields { event: None, rest: String::new() };
+        // Writing to a string can't fail.
+        let _ = record.key_values().visit(&mut fields);
+        let mut line = format!(
+            "{{\"timestamp\":\"{}\",\"level\":\"{}\",\"event\":{},\"message\":{}",
+            rfc3339(SystemTime::now()),
+            record.level(),
+            string(fields.event.as_deref().unwrap_or("message")),
+            string(&record.args().to_string()),
+        );
+        line.push_str(&fields.rest);
+        line.push_str("}\n");
+
+        if let Ok(mut writer) = self.writer.lock() {
+            let _ = writer.write_all(line.as_bytes());
+        }
+    }
+
+    fn flush(&self) {
+        if let Ok(mut writer) = self.writer.lock() {
+            let _ = writer.flush();
+        }
+    }
+}
+
+impl<W: Write + Send + 'static> SharedLogger for JsonLogger<W> {
+    fn level(&self) -> LevelFilter {
+        self.level
+    }
+
+    fn config(&self) -> Option<&Config> {
+        Some(&self.config)
+    }
+
+    fn as_log(self: Box<Self>) -> Box<dyn Log> {
+        Box::new(*self)
+    }
+}
+
+/// Collects the `event` key, and every other key-value as `,"key":value` JSON.
+struct Fields {
+    event: Option<String>,
+    rest: String,
+}
+
+impl<'kvs> VisitSource<'kvs> for Fields {
+    fn visit_pair(&mut self, key: kv::Key<'kvs>, value: kv::Value<'kvs>) -> Result<(), kv::Error> {
+        if key.as_str() == "event" {
+            self.event = Some(value.to_string());
+            return Ok(());
+        }
+        let value = if let Some(n) = value.to_u64() {
+            n.to_string()
+        }
+        else if let Some(n) = value.to_i64() {
+            n.to_string()
+        }
+        else if let Some(b) = value.to_bool() {
+            b.to_string()
+        }
+        else {
+            string(&value.to_string())
+        };
+        let _ = write!(self.rest, ",{}:{}", string(key.as_str()), value);
+        Ok(())
+    }
+}
+
+fn string(s: &str) -> String {
+    json::to_string(s).expect("strings always serialize")
+}
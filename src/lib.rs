@@ -1,2 +1,24 @@
 mod library;
-pub use library::*;
\ No newline at end of file
+pub use library::*;
+
+#[cfg(feature = "art")]
+pub mod art;
+#[cfg(feature = "sync")]
+pub mod filter;
+pub mod format;
+#[cfg(feature = "sync")]
+pub mod names;
+#[cfg(feature = "sync")]
+pub mod observer;
+#[cfg(feature = "sync")]
+pub mod plan;
+
+// Helpers of the library's own. The binary builds them into itself too, and uses what's left over
+// here.
+#[allow(dead_code)]
+mod date;
+#[allow(dead_code)]
+mod json;
+#[cfg(feature = "sync")]
+#[allow(dead_code)]
+mod sha256;
@@ -0,0 +1,98 @@
+//! Putting together a library from tracks and playlists given directly, e.g. to test with.
+
+use std::collections::HashMap;
+
+use super::{raw, Library, Playlist, PlaylistKind, Track};
+
+/// Builds a `Library` without a property list, as in `LibraryBuilder::new().track(1, "Song",
+/// "file://localhost/Music/Song.mp3", 180_000).folder("Rock", |folder| { folder.playlist("90s",
+/// [1]); }).build()`.
+///
+/// Playlists get persistent IDs counting up from 1 in the order they are added, which is also the
+/// order they are displayed in.
+#[derive(Default)]
+pub struct LibraryBuilder {
+    music_folder: Option<String>,
+    tracks: HashMap<u64, Track>,
+    playlists: HashMap<u64, Playlist>,
+    /// The folder that playlists are being added to.
+    parent: Option<u64>,
+}
+
+impl LibraryBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the root of the media files, as a percent-decoded URL.
+    pub fn music_folder(&mut self, location: &str) -> &mut Self {
+        self.music_folder = Some(location.to_owned());
+        self
+    }
+
+    /// Adds a track at the percent-decoded URL `location`, lasting `duration_ms`.
+    pub fn track(&mut self, id: u64, name: &str, location: &str, duration_ms: u32) -> &mut Self {
+        self.track_with(id, Track {
+            name: name.to_owned(),
+            location: Some(location.to_owned()),
+            duration_ms: Some(duration_ms),
+            ..Track::default()
+        })
+    }
+
+    /// Adds a track with all of its fields given, replacing any with the same ID.
+    pub fn track_with(&mut self, id: u64, track: Track) -> &mut Self {
+        self.tracks.insert(id, track);
+        self
+    }
+
+    /// Adds a regular playlist of the tracks with the IDs in `items`, in order.
+    pub fn playlist(&mut self, name: &str, items: impl IntoIterator<Item = u64>) -> &mut Self {
+        self.add(name, PlaylistKind::Generic, false, items.into_iter().collect());
+        self
+    }
+
+    /// Adds a smart playlist, of the tracks it matched when the library was exported.
+    pub fn smart_playlist(&mut self, name: &str, items: impl IntoIterator<Item = u64>) -> &mut Self {
+        self.add(name, PlaylistKind::Generic, true, items.into_iter().collect());
+        self
+    }
+
+    /// Adds a playlist of another kind, such as the master playlist or a system one.
+    pub fn kind_playlist(&mut self, name: &str, kind: PlaylistKind, items: impl IntoIterator<Item = u64>) -> &mut Self {
+        self.add(name, kind, false, items.into_iter().collect());
+        self
+    }
+
+    /// Adds a folder, with the playlists that `contents` adds inside it.
+    pub fn folder(&mut self, name: &str, contents: impl FnOnce(&mut LibraryBuilder)) -> &mut Self {
+        let id = self.add(name, PlaylistKind::Folder, false, Vec::new());
+        let parent = self.parent.replace(id);
+        contents(self);
+        self.parent = parent;
+        self
+    }
+
+    pub fn build(&mut self) -> Library {
+        let metadata = raw::Metadata::default();
+        let mut library = Library::assemble(metadata, std::mem::take(&mut self.tracks), std::mem::take(&mut self.playlists), Vec::new())
+            .expect("there is no metadata to parse");
+        library.music_folder = self.music_folder.take();
+        library
+    }
+
+    fn add(&mut self, name: &str, kind: PlaylistKind, is_smart: bool, items: Vec<u64>) -> u64 {
+        let persistent_id = self.playlists.len() as u64 + 1;
+        self.playlists.insert(persistent_id, Playlist {
+            persistent_id,
+            parent_id: self.parent,
+            name: name.to_owned(),
+            kind,
+            is_smart,
+            is_visible: true,
+            items,
+            order_key: persistent_id as usize,
+        });
+        persistent_id
+    }
+}
@@ -1,51 +1,364 @@
-use std::collections::HashMap;
+use std::{
+    collections::{hash_map::Entry, HashMap, HashSet},
+    convert::Infallible,
+    fmt,
+    fs::File,
+    io::{self, BufReader, Read, Seek, SeekFrom, Write},
+    num::ParseIntError,
+    ops::ControlFlow,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
 
+mod builder;
 mod raw;
+mod serialize;
+mod streaming;
+mod validate;
+mod xml;
+pub use builder::LibraryBuilder;
 pub use raw::Track;
+pub use validate::ValidationIssue;
 
 #[derive(Debug)]
 pub enum ParseError {
-    InvalidPlaylistId(std::num::ParseIntError),
-    InvalidTrackId(std::num::ParseIntError),
-    NonUtf8Path(std::str::Utf8Error),
+    /// The persistent ID of the named playlist, or the ID of its parent, isn't hexadecimal.
+    InvalidPlaylistId { playlist: String, id: String, source: ParseIntError },
+    InvalidTrackId { id: String, source: ParseIntError },
+    InvalidLibraryId { id: String, source: ParseIntError },
 }
 
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseError::InvalidPlaylistId { playlist, id, source } =>
+                write!(f, "invalid persistent ID {:?} in playlist {:?}: {}", id, playlist, source),
+            ParseError::InvalidTrackId { id, source } => write!(f, "invalid track ID {:?}: {}", id, source),
+            ParseError::InvalidLibraryId { id, source } => write!(f, "invalid library persistent ID {:?}: {}", id, source),
+        }
+    }
+}
+
+impl std::error::Error for ParseError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ParseError::InvalidPlaylistId { source, .. }
+            | ParseError::InvalidTrackId { source, .. }
+            | ParseError::InvalidLibraryId { source, .. } => Some(source),
+        }
+    }
+}
+
+/// An error loading a library file.
 #[derive(Debug)]
+pub enum LoadError {
+    Io(io::Error),
+    /// The file isn't a property list of the format it starts like, or not one shaped like a
+    /// library.
+    Plist(PlistFormat, plist::Error),
+    Parse(ParseError),
+}
+
+/// The format of a property list, as told by how it starts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PlistFormat {
+    Xml,
+    Binary,
+}
+
+impl PlistFormat {
+    const BINARY_MAGIC: &'static [u8] = b"bplist00";
+
+    /// Tells the format of the property list `reader` is at the start of, leaving it there. Files
+    /// too short to tell are taken for XML, as a binary one would be longer.
+    pub fn detect(mut reader: impl Read + Seek) -> Result<PlistFormat, io::Error> {
+        let start = reader.stream_position()?;
+        let mut magic = Vec::with_capacity(PlistFormat::BINARY_MAGIC.len());
+        (&mut reader).take(PlistFormat::BINARY_MAGIC.len() as u64).read_to_end(&mut magic)?;
+        reader.seek(SeekFrom::Start(start))?;
+        Ok(match magic == PlistFormat::BINARY_MAGIC {
+            true => PlistFormat::Binary,
+            false => PlistFormat::Xml,
+        })
+    }
+}
+
+impl fmt::Display for PlistFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            PlistFormat::Xml => "XML",
+            PlistFormat::Binary => "binary",
+        })
+    }
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LoadError::Io(e) => write!(f, "{}", e),
+            LoadError::Plist(format, e) => {
+                // plist only displays the Debug form of its errors. The messages from deserializing
+                // are the part worth showing, and mean that the file isn't shaped like a library.
+                let message = e.to_string();
+                match (e.as_io(), message.strip_prefix("Serde(\"").and_then(|rest| rest.strip_suffix("\")"))) {
+                    (Some(e), _) => write!(f, "invalid {} property list: {}", format, e),
+                    (None, Some(serde)) => write!(f, "not an iTunes library: {}", serde.replace("\\\"", "\"")),
+                    (None, None) => write!(f, "invalid {} property list: {}", format, message),
+                }
+            }
+            LoadError::Parse(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for LoadError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            LoadError::Io(e) => Some(e),
+            LoadError::Plist(_, e) => Some(e),
+            LoadError::Parse(e) => Some(e),
+        }
+    }
+}
+
+impl From<ParseError> for LoadError {
+    fn from(e: ParseError) -> Self {
+        LoadError::Parse(e)
+    }
+}
+
+/// A playlist entry for a track that isn't in the library, as libraries exported while being
+/// edited can have.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MissingTrack {
+    pub track_id: u64,
+}
+
+impl fmt::Display for MissingTrack {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "track {} isn't in the library", self.track_id)
+    }
+}
+
+impl std::error::Error for MissingTrack {}
+
+/// Why the location of a track can't be the path of a file, though it is a `file://` URL.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum LocationError {
+    /// Nothing follows the host.
+    MissingPath { location: String },
+    /// The path doesn't decode to UTF-8, which only Unix file systems can name files with.
+    NonUtf8Path { location: String },
+}
+
+impl fmt::Display for LocationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LocationError::MissingPath { location } => write!(f, "location {:?} has no path", location),
+            LocationError::NonUtf8Path { location } => write!(f, "location {:?} isn't valid UTF-8 once decoded", location),
+        }
+    }
+}
+
+impl std::error::Error for LocationError {}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 pub enum PlaylistKind {
     Master,
     Folder,
     Generic,
+    Movies,
+    TvShows,
+    Music,
+    Audiobooks,
+    Tones,
+    Podcasts,
+    Purchased,
+    ItunesDj,
+    Genius,
+    ItunesU,
+    GeniusMixes,
+    MusicVideos,
+    HomeVideos,
     Unknown(u64),
 }
 
+/// The `Distinguished Kind` values of system playlists, with the names used for them on the command line.
+const DISTINGUISHED_KINDS: &[(u64, PlaylistKind, &str)] = &[
+    (2, PlaylistKind::Movies, "movies"),
+    (3, PlaylistKind::TvShows, "tv-shows"),
+    (4, PlaylistKind::Music, "music"),
+    (5, PlaylistKind::Audiobooks, "audiobooks"),
+    (7, PlaylistKind::Tones, "tones"),
+    (10, PlaylistKind::Podcasts, "podcasts"),
+    (19, PlaylistKind::Purchased, "purchased"),
+    (22, PlaylistKind::ItunesDj, "itunes-dj"),
+    (26, PlaylistKind::Genius, "genius"),
+    (31, PlaylistKind::ItunesU, "itunes-u"),
+    (32, PlaylistKind::GeniusMixes, "genius-mixes"),
+    (47, PlaylistKind::MusicVideos, "music-videos"),
+    (48, PlaylistKind::HomeVideos, "home-videos"),
+];
+
+impl PlaylistKind {
+    pub fn is_folder(self) -> bool {
+        self == PlaylistKind::Folder
+    }
+
+    /// Whether this is the kind of playlists made by the user, rather than iTunes.
+    pub fn is_generic(self) -> bool {
+        self == PlaylistKind::Generic
+    }
+
+    /// Whether this is one of the known kinds of system playlist, like Music or Purchased. The
+    /// master playlist and unknown kinds aren't.
+    pub fn is_system(self) -> bool {
+        self.system_name().is_some()
+    }
+
+    /// Names of the system playlist kinds, as accepted by `from_system_name`.
+    pub fn system_names() -> impl Iterator<Item = &'static str> {
+        DISTINGUISHED_KINDS.iter().map(|(_, _, name)| *name)
+    }
+
+    /// The name of the kind as on the command line: master, folder, generic, unknown, or the name
+    /// of a system playlist kind.
+    pub fn name(self) -> &'static str {
+        match self {
+            PlaylistKind::Master => "master",
+            PlaylistKind::Folder => "folder",
+            PlaylistKind::Generic => "generic",
+            PlaylistKind::Unknown(_) => "unknown",
+            kind => kind.system_name().unwrap_or("unknown"),
+        }
+    }
+
+    /// The name of a system playlist kind, as accepted by `from_system_name`.
+    pub fn system_name(self) -> Option<&'static str> {
+        DISTINGUISHED_KINDS.iter().find(|(_, known, _)| *known == self).map(|(_, _, name)| *name)
+    }
+
+    pub fn from_system_name(name: &str) -> Option<PlaylistKind> {
+        DISTINGUISHED_KINDS.iter().find(|(_, _, known)| *known == name).map(|(_, kind, _)| *kind)
+    }
+
+    /// The `Distinguished Kind` value of a system playlist kind.
+    fn distinguished_kind(self) -> Option<u64> {
+        match self {
+            PlaylistKind::Unknown(value) => Some(value),
+            kind => DISTINGUISHED_KINDS.iter().find(|(_, known, _)| *known == kind).map(|(value, _, _)| *value),
+        }
+    }
+
+    fn from_distinguished_kind(value: u64) -> PlaylistKind {
+        DISTINGUISHED_KINDS.iter()
+            .find(|(known, _, _)| *known == value)
+            .map_or(PlaylistKind::Unknown(value), |(_, kind, _)| *kind)
+    }
+}
+
+/// The format of a track's file, as far as the library tells.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum AudioFormat {
+    Mp3,
+    Aac,
+    AppleLossless,
+    Wav,
+    Aiff,
+    Flac,
+}
+
+/// What the kinds of tracks contain for each format, e.g. "Purchased AAC audio file" or "Apple
+/// Music AAC audio file". Apple Lossless comes first, as its kind can also mention AAC.
+const KIND_FORMATS: &[(&str, AudioFormat)] = &[
+    ("Apple Lossless", AudioFormat::AppleLossless),
+    ("AAC audio", AudioFormat::Aac),
+    ("MPEG audio", AudioFormat::Mp3),
+    ("WAV audio", AudioFormat::Wav),
+    ("AIFF audio", AudioFormat::Aiff),
+    ("FLAC audio", AudioFormat::Flac),
+];
+
+/// The formats of files by their extension, for tracks of unknown kinds. Files ending in .m4a
+/// aren't among them, as they can hold either AAC or Apple Lossless.
+const EXTENSION_FORMATS: &[(&str, AudioFormat)] = &[
+    ("mp3", AudioFormat::Mp3),
+    ("aac", AudioFormat::Aac),
+    ("m4p", AudioFormat::Aac),
+    ("wav", AudioFormat::Wav),
+    ("aif", AudioFormat::Aiff),
+    ("aiff", AudioFormat::Aiff),
+    ("flac", AudioFormat::Flac),
+];
+
+impl AudioFormat {
+    pub fn is_lossless(self) -> bool {
+        match self {
+            AudioFormat::Mp3 | AudioFormat::Aac => false,
+            AudioFormat::AppleLossless | AudioFormat::Wav | AudioFormat::Aiff | AudioFormat::Flac => true,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            AudioFormat::Mp3 => "MP3",
+            AudioFormat::Aac => "AAC",
+            AudioFormat::AppleLossless => "Apple Lossless",
+            AudioFormat::Wav => "WAV",
+            AudioFormat::Aiff => "AIFF",
+            AudioFormat::Flac => "FLAC",
+        }
+    }
+}
+
+impl fmt::Display for AudioFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+#[derive(Clone, Debug)]
 pub struct Playlist {
     pub persistent_id: u64,
     pub parent_id: Option<u64>,
     pub name: String,
     pub kind: PlaylistKind,
+    pub is_smart: bool,
+    /// Unset for playlists iTunes keeps for itself and doesn't show, along with their children.
+    pub is_visible: bool,
     pub items: Vec<u64>,
     pub order_key: usize,
 }
 
 impl Playlist {
+    /// Whether the playlist lists no tracks, as folders never do themselves.
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
     fn from_raw(raw: raw::Playlist, order_key: usize) -> Result<Playlist, ParseError> {
         fn playlist_kind(raw: &raw::Playlist) -> PlaylistKind {
             if raw.is_master { return PlaylistKind::Master; }
             if raw.is_folder { return PlaylistKind::Folder; }
             match raw.distinguished_kind {
                 None => PlaylistKind::Generic,
-                Some(other) => PlaylistKind::Unknown(other),
+                Some(value) => PlaylistKind::from_distinguished_kind(value),
             }
         }
 
+        let parse_id = |id: &str| u64::from_str_radix(id, 16).map_err(|source| ParseError::InvalidPlaylistId {
+            playlist: raw.name.clone(),
+            id: id.to_owned(),
+            source,
+        });
+
         Ok(Playlist {
             kind: playlist_kind(&raw),
-            persistent_id: u64::from_str_radix(&raw.persistent_id, 16)
-                .map_err(ParseError::InvalidPlaylistId)?,
-            parent_id: raw.parent_id
-                .map(|id| u64::from_str_radix(&id, 16))
-                .transpose()
-                .map_err(ParseError::InvalidPlaylistId)?,
+            // Folders carry smart info of their own, but only ever contain their children.
+            is_smart: !raw.is_folder && (raw.smart_info.is_some() || raw.smart_criteria.is_some()),
+            is_visible: raw.is_visible,
+            persistent_id: parse_id(&raw.persistent_id)?,
+            parent_id: raw.parent_id.as_deref().map(parse_id).transpose()?,
             name: raw.name,
             items: raw.items.iter().map(|id| id.inner).collect(),
             order_key
@@ -53,41 +366,308 @@ impl Playlist {
     }
 }
 
+/// A step of a walk through the playlist tree, from `walk_playlists`.
+#[derive(Clone, Copy)]
+pub enum PlaylistEvent<'a> {
+    /// A folder, before its children. Other playlists that have children, which iTunes doesn't
+    /// make, are entered like folders too.
+    EnterFolder(&'a Playlist),
+    /// A playlist without children.
+    Playlist(&'a Playlist),
+    /// A folder, after its children.
+    LeaveFolder(&'a Playlist),
+}
+
 pub struct Library {
+    /// The root of the media files, as a percent-decoded URL like the track locations.
+    pub music_folder: Option<String>,
+    /// The decoded bytes of a music folder that isn't valid UTF-8, in which case `music_folder`
+    /// holds a lossy version of it.
+    pub music_folder_bytes: Option<Vec<u8>>,
+    pub persistent_id: Option<u64>,
+    pub application_version: Option<String>,
+    /// When the library file was written.
+    pub exported_at: Option<SystemTime>,
     pub tracks: HashMap<u64, Track>,
     pub playlists: HashMap<u64, Playlist>,
+    /// Tracks without a location, e.g. because they are only available from the cloud.
+    pub skipped_tracks: Vec<u64>,
     playlist_index: Vec<(Option<u64>, u64)>,
+    /// Problems fixed up while loading, which `validate` reports along with the rest.
+    load_issues: Vec<ValidationIssue>,
 }
 
 impl Library {
+    /// Loads the library file at `path`, which may be either an XML or a binary property list.
+    pub fn from_path(path: impl AsRef<Path>) -> Result<Library, LoadError> {
+        let file = File::open(path).map_err(LoadError::Io)?;
+        Library::from_reader(BufReader::new(file))
+    }
+
+    /// Loads a library from an XML or binary property list.
+    pub fn from_reader(mut reader: impl Read + Seek) -> Result<Library, LoadError> {
+        let format = PlistFormat::detect(&mut reader).map_err(LoadError::Io)?;
+        let raw = plist::from_reader(reader).map_err(|e| LoadError::Plist(format, e))?;
+        Ok(Library::from_raw(raw)?)
+    }
+
+    /// Loads a library like `from_reader`, but processes each track and playlist as it is read
+    /// instead of holding the whole raw library in memory first, which roughly halves the peak
+    /// memory use for large libraries.
+    pub fn from_reader_streaming(reader: impl Read + Seek) -> Result<Library, LoadError> {
+        streaming::read(reader)
+    }
+
+    /// Writes the library as an iTunes-style XML property list. Only what `Library` keeps is
+    /// written, so tracks and playlists lose any keys it doesn't know about, and smart playlists
+    /// keep their smartness but not their rules.
+    pub fn to_writer_xml(&self, writer: impl Write) -> Result<(), plist::Error> {
+        xml::write(self, writer)
+    }
+
     pub fn visit_playlists(&self, mut visitor: impl FnMut(u64, usize)) {
-        self.visit_playlists_inner(None, 0, &mut visitor);
-    }
-
-    fn visit_playlists_inner(&self, node_id: Option<u64>, depth: usize, visitor: &mut impl FnMut(u64, usize)) {
-        let child = self.playlist_index.binary_search_by_key(&node_id, |(parent, _)| *parent);
-        if let Ok(mut child) = child {
-            while child > 0 && self.playlist_index[child - 1].0 == node_id { child -= 1; }
-            while child < self.playlist_index.len() && self.playlist_index[child].0 == node_id {
-                let child_id = self.playlist_index[child].1;
-                visitor(child_id, depth);
-                self.visit_playlists_inner(Some(child_id), depth + 1, visitor);
-                child += 1;
+        self.try_visit_playlists(|playlist, depth| {
+            visitor(playlist.persistent_id, depth);
+            Ok::<_, Infallible>(ControlFlow::Continue(()))
+        }).unwrap_or_else(|never| match never {});
+    }
+
+    /// Visits the playlists depth-first in display order, passing each one along with its depth.
+    /// The visit ends as soon as the visitor fails or returns `ControlFlow::Break`.
+    pub fn try_visit_playlists<'a, E>(
+        &'a self,
+        mut visitor: impl FnMut(&'a Playlist, usize) -> Result<ControlFlow<()>, E>,
+    ) -> Result<(), E> {
+        self.try_visit_playlists_inner(None, 0, &mut visitor).map(|_| ())
+    }
+
+    /// Visits the playlists like `visit_playlists`, passing each one along with its ancestors,
+    /// starting from the root. The path is a list of playlists rather than a `Path`, as names can
+    /// contain slashes.
+    pub fn visit_playlists_with_path<'a>(&'a self, mut visitor: impl FnMut(&[&'a Playlist])) {
+        self.try_visit_playlists_with_path(|path| {
+            visitor(path);
+            Ok::<_, Infallible>(ControlFlow::Continue(()))
+        }).unwrap_or_else(|never| match never {});
+    }
+
+    /// Visits the playlists like `visit_playlists_with_path`, ending early like `try_visit_playlists`.
+    pub fn try_visit_playlists_with_path<'a, E>(
+        &'a self,
+        mut visitor: impl FnMut(&[&'a Playlist]) -> Result<ControlFlow<()>, E>,
+    ) -> Result<(), E> {
+        let mut path = Vec::new();
+        self.try_visit_playlists(|playlist, depth| {
+            path.truncate(depth);
+            path.push(playlist);
+            visitor(&path)
+        })
+    }
+
+    /// Walks the playlists depth-first in display order, entering and leaving each folder around
+    /// its children, so that what is kept about a folder can be set up and torn down in step.
+    pub fn walk_playlists<'a>(&'a self, mut visitor: impl FnMut(PlaylistEvent<'a>)) {
+        self.try_walk_playlists(|event| {
+            visitor(event);
+            Ok::<_, Infallible>(ControlFlow::Continue(()))
+        }).unwrap_or_else(|never| match never {});
+    }
+
+    /// Walks the playlists like `walk_playlists`, ending as soon as the visitor fails or returns
+    /// `ControlFlow::Break`, without leaving the folders it is in.
+    pub fn try_walk_playlists<'a, E>(
+        &'a self,
+        mut visitor: impl FnMut(PlaylistEvent<'a>) -> Result<ControlFlow<()>, E>,
+    ) -> Result<(), E> {
+        self.try_walk_playlists_inner(None, &mut visitor).map(|_| ())
+    }
+
+    /// The playlist with the given persistent ID and its ancestors, starting from the root. The
+    /// path stops early at a parent that isn't in the library.
+    pub fn playlist_path(&self, id: u64) -> Vec<&Playlist> {
+        let mut path = Vec::new();
+        let mut next = Some(id);
+        // Bounded in case the parent links form a cycle.
+        while let Some(playlist) = next.and_then(|id| self.playlists.get(&id)) {
+            if path.len() == self.playlists.len() { break; }
+            path.push(playlist);
+            next = playlist.parent_id;
+        }
+        path.reverse();
+        path
+    }
+
+    /// Checks the library for dangling references, unusable locations, duplicate persistent IDs
+    /// and playlists that share a name within a folder. Loading already works around each of
+    /// these, but they may leave tracks out of a sync or playlists out of place.
+    pub fn validate(&self) -> Vec<ValidationIssue> {
+        validate::validate(self)
+    }
+
+    /// The names of a playlist and its ancestors joined with slashes, e.g. `Workouts/Running`.
+    /// `find_playlist_by_path` finds the playlist again from this, unless a name contains a slash.
+    pub fn virtual_path(&self, playlist: &Playlist) -> String {
+        let names: Vec<&str> = self.playlist_path(playlist.persistent_id).iter()
+            .map(|playlist| playlist.name.as_str())
+            .collect();
+        names.join("/")
+    }
+
+    /// Finds a playlist by the slash-separated names of it and its ancestors, starting from the
+    /// root. Of playlists with the same path, the one displayed first is returned.
+    pub fn find_playlist_by_path(&self, path: &str) -> Option<&Playlist> {
+        self.find_playlists_by_path(path, false).into_iter().next()
+    }
+
+    /// Finds every playlist with the given path, in display order, optionally comparing names
+    /// case-insensitively.
+    pub fn find_playlists_by_path(&self, path: &str, ignore_case: bool) -> Vec<&Playlist> {
+        let same_name = |a: &str, b: &str| match ignore_case {
+            true => a.to_lowercase() == b.to_lowercase(),
+            false => a == b,
+        };
+        let path = path.trim_matches('/');
+        if path.is_empty() {
+            return Vec::new();
+        }
+        let mut parents = vec![None];
+        let mut matches = Vec::new();
+        for name in path.split('/') {
+            matches = parents.iter()
+                .flat_map(|&parent| self.children(parent))
+                .filter(|playlist| same_name(&playlist.name, name))
+                .collect();
+            parents = matches.iter().map(|playlist| Some(playlist.persistent_id)).collect();
+        }
+        matches
+    }
+
+    fn try_visit_playlists_inner<'a, E>(
+        &'a self,
+        node_id: Option<u64>,
+        depth: usize,
+        visitor: &mut impl FnMut(&'a Playlist, usize) -> Result<ControlFlow<()>, E>,
+    ) -> Result<ControlFlow<()>, E> {
+        for &(_, child_id) in self.child_entries(node_id) {
+            if visitor(&self.playlists[&child_id], depth)?.is_break()
+                || self.try_visit_playlists_inner(Some(child_id), depth + 1, visitor)?.is_break()
+            {
+                return Ok(ControlFlow::Break(()));
             }
         }
+        Ok(ControlFlow::Continue(()))
     }
 
-    pub fn from_raw(raw: raw::Library) -> Result<Library, ParseError> {
-        let playlist_count = raw.playlists.len();
-        let playlists = raw.playlists.into_iter().enumerate().try_fold(
-            HashMap::with_capacity(playlist_count), 
-            |mut playlists, (order_key, playlist)| {
-                let playlist = Playlist::from_raw(playlist, order_key)?;
-                playlists.insert(playlist.persistent_id, playlist);
-                Ok(playlists)
+    fn try_walk_playlists_inner<'a, E>(
+        &'a self,
+        node_id: Option<u64>,
+        visitor: &mut impl FnMut(PlaylistEvent<'a>) -> Result<ControlFlow<()>, E>,
+    ) -> Result<ControlFlow<()>, E> {
+        for &(_, child_id) in self.child_entries(node_id) {
+            let playlist = &self.playlists[&child_id];
+            let is_folder = playlist.kind.is_folder() || !self.child_entries(Some(child_id)).is_empty();
+            if !is_folder {
+                if visitor(PlaylistEvent::Playlist(playlist))?.is_break() {
+                    return Ok(ControlFlow::Break(()));
+                }
+                continue;
             }
-        )?;
-        
+            if visitor(PlaylistEvent::EnterFolder(playlist))?.is_break()
+                || self.try_walk_playlists_inner(Some(child_id), visitor)?.is_break()
+                || visitor(PlaylistEvent::LeaveFolder(playlist))?.is_break()
+            {
+                return Ok(ControlFlow::Break(()));
+            }
+        }
+        Ok(ControlFlow::Continue(()))
+    }
+
+    /// The direct children of the folder with the persistent ID `parent`, or the top-level
+    /// playlists for `None`. They come in the order of the library's playlist array, which is the
+    /// order iTunes displays them in.
+    pub fn children(&self, parent: Option<u64>) -> impl Iterator<Item = &Playlist> {
+        self.child_entries(parent).iter().map(move |(_, id)| &self.playlists[id])
+    }
+
+    /// The playlists at the top level of the library, in display order.
+    pub fn root_playlists(&self) -> impl Iterator<Item = &Playlist> {
+        self.children(None)
+    }
+
+    /// The tracks of `playlist` in order, each with its ID, or `MissingTrack` for entries whose
+    /// track isn't in the library.
+    ///
+    /// ```
+    /// use m3trans::{LibraryBuilder, MissingTrack};
+    ///
+    /// let library = LibraryBuilder::new()
+    ///     .track(1, "One", "file://localhost/Music/One.mp3", 1000)
+    ///     .playlist("Top", [1, 9])
+    ///     .build();
+    /// let top = library.find_playlist_by_path("Top").unwrap();
+    /// let names: Vec<_> = library.playlist_tracks(top)
+    ///     .map(|resolved| resolved.map(|(_, track)| track.name.as_str()))
+    ///     .collect();
+    /// assert_eq!(names, [Ok("One"), Err(MissingTrack { track_id: 9 })]);
+    /// ```
+    pub fn playlist_tracks<'a>(&'a self, playlist: &'a Playlist) -> impl Iterator<Item = Result<(u64, &'a Track), MissingTrack>> + 'a {
+        self.tracks_by_id(&playlist.items)
+    }
+
+    /// The tracks with the given IDs like `playlist_tracks`, for lists of tracks that aren't a
+    /// playlist of the library.
+    pub fn tracks_by_id<'a>(&'a self, ids: &'a [u64]) -> impl Iterator<Item = Result<(u64, &'a Track), MissingTrack>> + 'a {
+        ids.iter().map(move |&track_id| match self.tracks.get(&track_id) {
+            Some(track) => Ok((track_id, track)),
+            None => Err(MissingTrack { track_id }),
+        })
+    }
+
+    /// The folder the library keeps its media in, found like the files of tracks with
+    /// `Track::local_path_remapped`.
+    pub fn music_folder_path(&self, remaps: &[(String, PathBuf)]) -> Result<Option<PathBuf>, LocationError> {
+        match &self.music_folder {
+            Some(location) => location_path(location, self.music_folder_bytes.as_deref().unwrap_or(location.as_bytes()), remaps),
+            None => Ok(None),
+        }
+    }
+
+    /// The `(parent, child)` entries of `playlist_index` for the children of `parent`.
+    fn child_entries(&self, parent: Option<u64>) -> &[(Option<u64>, u64)] {
+        let start = self.playlist_index.partition_point(|(other, _)| *other < parent);
+        let end = self.playlist_index.partition_point(|(other, _)| *other <= parent);
+        &self.playlist_index[start..end]
+    }
+
+    pub fn from_raw(raw: raw::Library) -> Result<Library, ParseError> {
+        let mut playlists = HashMap::with_capacity(raw.playlists.len());
+        let mut issues = Vec::new();
+        for (order_key, playlist) in raw.playlists.into_iter().enumerate() {
+            insert_playlist(&mut playlists, Playlist::from_raw(playlist, order_key)?, &mut issues);
+        }
+
+        let tracks: Result<HashMap<_, _>, ParseError> = raw.tracks.into_iter()
+            .map(|(id, track)| track_entry(id, track))
+            .collect();
+
+        let metadata = raw::Metadata {
+            music_folder: raw.music_folder,
+            persistent_id: raw.persistent_id,
+            application_version: raw.application_version,
+            date: raw.date,
+        };
+        Library::assemble(metadata, tracks?, playlists, issues)
+    }
+
+    /// Builds the library from its processed tracks and playlists, and the problems already worked
+    /// around in reading them.
+    fn assemble(
+        metadata: raw::Metadata,
+        tracks: HashMap<u64, Track>,
+        mut playlists: HashMap<u64, Playlist>,
+        mut load_issues: Vec<ValidationIssue>,
+    ) -> Result<Library, ParseError> {
+        load_issues.extend(reparent_unreachable(&mut playlists));
         let mut playlist_index: Vec<_> = playlists.values()
             .map(|p| (p.parent_id, p.persistent_id))
             .collect();
@@ -96,20 +676,555 @@ impl Library {
             playlists[&a.1].order_key.cmp(&playlists[&b.1].order_key)
         }));
 
-        let tracks: Result<HashMap<_, _>, ParseError> = raw.tracks.into_iter()
-            .map(|(id, track)| match u64::from_str_radix(&id, 10) {
-                Ok(id) => Ok((id, process_track(track)?)),
-                Err(e) => Err(ParseError::InvalidTrackId(e)),
-            }).collect();
+        let mut skipped_tracks: Vec<u64> = tracks.iter()
+            .filter(|(_, track)| track.location.is_none())
+            .map(|(id, _)| *id)
+            .collect();
+        skipped_tracks.sort_unstable();
+
+        let (music_folder, music_folder_bytes) = match metadata.music_folder {
+            Some(location) => {
+                let (decoded, bytes) = decode_location(&location);
+                if bytes.is_some() {
+                    log::warn!("The music folder of the library isn't valid UTF-8: {}", location);
+                }
+                (Some(decoded), bytes)
+            }
+            None => (None, None),
+        };
+
+        Ok(Library {
+            music_folder,
+            music_folder_bytes,
+            persistent_id: metadata.persistent_id
+                .map(|id| u64::from_str_radix(&id, 16).map_err(|source| ParseError::InvalidLibraryId { id, source }))
+                .transpose()?,
+            application_version: metadata.application_version,
+            exported_at: metadata.date,
+            tracks,
+            playlists,
+            skipped_tracks,
+            playlist_index,
+            load_issues,
+        })
+    }
+}
+
+/// Adds a playlist read from the library, unless an earlier one has the same persistent ID, in
+/// which case this one is left out. Playlists inside either end up in the one that is kept.
+fn insert_playlist(playlists: &mut HashMap<u64, Playlist>, playlist: Playlist, issues: &mut Vec<ValidationIssue>) {
+    match playlists.entry(playlist.persistent_id) {
+        Entry::Occupied(kept) => {
+            log::warn!(
+                "Playlists {} and {} share the persistent ID {:016X}; leaving out {}, which comes later.",
+                kept.get().name, playlist.name, playlist.persistent_id, playlist.name
+            );
+            issues.push(ValidationIssue::DuplicatePlaylistPersistentId {
+                persistent_id: playlist.persistent_id,
+                kept: kept.get().name.clone(),
+                discarded: playlist.name,
+            });
+        }
+        Entry::Vacant(vacant) => { vacant.insert(playlist); }
+    }
+}
+
+/// Moves playlists that would never be visited to the top level: those whose parent isn't in the
+/// library, and one playlist of each cycle of parents, the one with the lowest persistent ID.
+fn reparent_unreachable(playlists: &mut HashMap<u64, Playlist>) -> Vec<ValidationIssue> {
+    let mut ids: Vec<u64> = playlists.keys().copied().collect();
+    ids.sort_unstable();
+    let mut issues = Vec::new();
+
+    for &id in &ids {
+        let playlist = &playlists[&id];
+        if let Some(parent_id) = playlist.parent_id.filter(|parent_id| !playlists.contains_key(parent_id)) {
+            log::warn!(
+                "Playlist {} ({:016X}) is in folder {:016X}, which isn't in the library; moving it to the top level.",
+                playlist.name, id, parent_id
+            );
+            issues.push(ValidationIssue::OrphanedPlaylist { playlist: playlist.name.clone(), playlist_id: id, parent_id });
+            playlists.get_mut(&id).unwrap().parent_id = None;
+        }
+    }
+
+    // Playlists known to lead up to the top level.
+    let mut rooted = HashSet::new();
+    for &id in &ids {
+        let mut chain = Vec::new();
+        let mut next = Some(id);
+        while let Some(current) = next {
+            if rooted.contains(&current) { break; }
+            if let Some(start) = chain.iter().position(|&seen| seen == current) {
+                let cycle = &chain[start..];
+                let broken = *cycle.iter().min().unwrap();
+                let playlist = playlists.get_mut(&broken).unwrap();
+                log::warn!(
+                    "Playlist {} ({:016X}) is its own ancestor; moving it to the top level.",
+                    playlist.name, broken
+                );
+                issues.push(ValidationIssue::ParentCycle { playlist: playlist.name.clone(), playlist_id: broken });
+                playlist.parent_id = None;
+                break;
+            }
+            chain.push(current);
+            next = playlists[&current].parent_id;
+        }
+        rooted.extend(chain);
+    }
+    issues
+}
+
+impl Track {
+    /// Whether the track is DRM-protected. Older libraries only say so in the kind.
+    pub fn is_protected(&self) -> bool {
+        self.protected || self.kind.as_deref().is_some_and(|kind| kind.contains("Protected"))
+    }
+
+    /// The format of the track's file, from its kind or else the extension of its location.
+    pub fn file_format(&self) -> Option<AudioFormat> {
+        if let Some(kind) = &self.kind {
+            if let Some((_, format)) = KIND_FORMATS.iter().find(|(known, _)| kind.contains(known)) {
+                return Some(*format);
+            }
+        }
+        let extension = Path::new(self.location.as_deref()?).extension()?.to_str()?;
+        EXTENSION_FORMATS.iter()
+            .find(|(known, _)| extension.eq_ignore_ascii_case(known))
+            .map(|(_, format)| *format)
+    }
+
+    /// How long the track plays for, which is less than its file lasts when iTunes is set to start
+    /// or stop partway through it.
+    pub fn played_duration_ms(&self) -> Option<u32> {
+        match (self.start_time_ms, self.stop_time_ms) {
+            (None, None) => self.duration_ms,
+            (start, stop) => stop.or(self.duration_ms)
+                .map(|stop| stop.saturating_sub(start.unwrap_or(0)))
+                .filter(|&duration_ms| duration_ms > 0),
+        }
+    }
+
+    /// Whether the track's file is known to be in a lossless format.
+    pub fn is_lossless(&self) -> bool {
+        self.file_format().is_some_and(AudioFormat::is_lossless)
+    }
+
+    /// The file the track's location points at, if it is a `file://` URL on this machine. Tracks
+    /// without a location, with other kinds of URL or on other hosts have none.
+    pub fn local_path(&self) -> Result<Option<PathBuf>, LocationError> {
+        self.local_path_remapped(&[])
+    }
+
+    /// The file the track's location points at like `local_path`, also finding those on the
+    /// hosts in `remaps` under the folder each is mounted at, e.g. `("nas", "/Volumes/nas")`.
+    /// Hosts are given in lowercase.
+    pub fn local_path_remapped(&self, remaps: &[(String, PathBuf)]) -> Result<Option<PathBuf>, LocationError> {
+        match &self.location {
+            Some(location) => location_path(location, self.location_bytes.as_deref().unwrap_or(location.as_bytes()), remaps),
+            None => Ok(None),
+        }
+    }
+}
+
+/// The file a decoded `file://` URL points at, from its `bytes`, which are those of `location`
+/// unless it isn't UTF-8.
+fn location_path(location: &str, bytes: &[u8], remaps: &[(String, PathBuf)]) -> Result<Option<PathBuf>, LocationError> {
+    let url = match bytes.strip_prefix(b"file://") {
+        Some(url) => url,
+        None => return Ok(None),
+    };
+    let (host, path) = url.split_at(url.iter().position(|&b| b == b'/').unwrap_or(url.len()));
+    if path.len() <= 1 {
+        return Err(LocationError::MissingPath { location: location.to_owned() });
+    }
+    // Windows writes files on network shares as `file://localhost//server/share/...`.
+    let (host, path) = match path.strip_prefix(b"//") {
+        Some(share) if host.is_empty() || host.eq_ignore_ascii_case(b"localhost") => {
+            share.split_at(share.iter().position(|&b| b == b'/').unwrap_or(share.len()))
+        }
+        _ => (host, path),
+    };
+    if !host.is_empty() && !host.eq_ignore_ascii_case(b"localhost") {
+        let lowercase = String::from_utf8_lossy(host).to_lowercase();
+        return match remaps.iter().find(|(remapped, _)| *remapped == lowercase) {
+            Some((_, mount)) => {
+                let relative = &path[path.iter().position(|&b| b != b'/').unwrap_or(path.len())..];
+                Ok(Some(mount.join(path_from_bytes(relative, location)?)))
+            }
+            None => network_path(host, path, location),
+        };
+    }
+    // Windows locations look like `/C:/Users/...`, where the leading slash has to go.
+    let path = match path {
+        [b'/', drive, b':', ..] if drive.is_ascii_alphabetic() => &path[1..],
+        _ => path,
+    };
+    path_from_bytes(path, location).map(Some)
+}
+
+/// The path of a file shared by another host, which Windows reaches as `\\host\share\...`.
+/// Elsewhere shares have to be mounted, and given with `remaps`.
+#[cfg(windows)]
+fn network_path(host: &[u8], path: &[u8], location: &str) -> Result<Option<PathBuf>, LocationError> {
+    let host = std::str::from_utf8(host).map_err(|_| LocationError::NonUtf8Path { location: location.to_owned() })?;
+    let path = std::str::from_utf8(path).map_err(|_| LocationError::NonUtf8Path { location: location.to_owned() })?;
+    Ok(Some(PathBuf::from(format!(r"\\{}{}", host, path.replace('/', r"\")))))
+}
+
+#[cfg(not(windows))]
+fn network_path(_host: &[u8], _path: &[u8], _location: &str) -> Result<Option<PathBuf>, LocationError> {
+    Ok(None)
+}
 
-        Ok(Library { tracks: tracks?, playlists, playlist_index })
+/// Builds a path from the bytes of a decoded location, with `/` between its components.
+#[cfg(unix)]
+fn path_from_bytes(bytes: &[u8], _location: &str) -> Result<PathBuf, LocationError> {
+    use std::os::unix::ffi::OsStrExt;
+    Ok(PathBuf::from(std::ffi::OsStr::from_bytes(bytes)))
+}
+
+/// Builds a path from the bytes of a decoded location, which elsewhere have to be UTF-8.
+#[cfg(not(unix))]
+fn path_from_bytes(bytes: &[u8], location: &str) -> Result<PathBuf, LocationError> {
+    use path_slash::PathBufExt;
+    std::str::from_utf8(bytes)
+        .map(PathBuf::from_slash)
+        .map_err(|_| LocationError::NonUtf8Path { location: location.to_owned() })
+}
+
+/// Processes a raw track, keyed by its ID as it appears in the library.
+fn track_entry(id: String, track: Track) -> Result<(u64, Track), ParseError> {
+    match id.parse::<u64>() {
+        Ok(id) => Ok((id, process_track(track)?)),
+        Err(source) => Err(ParseError::InvalidTrackId { id, source }),
+    }
+}
+
+/// Percent-decodes a location, returning it with its decoded bytes as well if they aren't valid
+/// UTF-8, in which case the string is a lossy version of them. Old libraries can hold paths in a
+/// legacy encoding; the raw bytes let the file still be found where the filesystem allows such
+/// names.
+fn decode_location(location: &str) -> (String, Option<Vec<u8>>) {
+    let bytes: Vec<u8> = percent_encoding::percent_decode_str(location).collect();
+    match String::from_utf8(bytes) {
+        Ok(decoded) => (decoded, None),
+        Err(e) => (String::from_utf8_lossy(e.as_bytes()).into_owned(), Some(e.into_bytes())),
     }
 }
 
 fn process_track(track: Track) -> Result<Track, ParseError> {
-    let location = percent_encoding::percent_decode_str(&track.location)
-        .decode_utf8()
-        .map_err(ParseError::NonUtf8Path)?
-        .into_owned();
-    Ok(Track { location, .. track })
-}
\ No newline at end of file
+    let mut location_bytes = None;
+    let location = track.location.as_deref().map(|location| {
+        let (decoded, bytes) = decode_location(location);
+        if bytes.is_some() {
+            log::warn!("Track {} has a location that isn't valid UTF-8: {}", track.name, location);
+        }
+        location_bytes = bytes;
+        decoded
+    });
+    let name = match track.name.is_empty() {
+        false => track.name,
+        true => {
+            let stem = location.as_ref()
+                .and_then(|location| std::path::Path::new(location).file_stem())
+                .map(|stem| stem.to_string_lossy().into_owned());
+            let name = stem.unwrap_or_else(|| "Unknown".to_owned());
+            log::warn!("Track at {} has no name; calling it {}.", location.as_deref().unwrap_or("<no location>"), name);
+            name
+        }
+    };
+    // A duration of zero comes from broken imports, and means as little as a missing one.
+    let duration_ms = track.duration_ms.filter(|&duration_ms| duration_ms > 0);
+    // Cloud-only tracks often lack a duration as well, but they are never synced anyway.
+    if duration_ms.is_none() && location.is_some() {
+        log::warn!(event = "no_duration"; "Track {} at {} has no duration.", name, location.as_deref().unwrap_or_default());
+    }
+    Ok(Track { name, location, location_bytes, duration_ms, .. track })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn playlist(persistent_id: u64, parent_id: Option<u64>, kind: PlaylistKind) -> Playlist {
+        Playlist {
+            persistent_id,
+            parent_id,
+            name: format!("P{}", persistent_id),
+            kind,
+            is_smart: false,
+            is_visible: true,
+            items: Vec::new(),
+            order_key: persistent_id as usize,
+        }
+    }
+
+    fn library(playlists: impl IntoIterator<Item = Playlist>) -> Library {
+        let playlists = playlists.into_iter().map(|playlist| (playlist.persistent_id, playlist)).collect();
+        Library::assemble(raw::Metadata::default(), HashMap::new(), playlists, Vec::new()).unwrap()
+    }
+
+    /// Each playlist visited, with its depth.
+    fn visited(library: &Library) -> Vec<(u64, usize)> {
+        let mut visited = Vec::new();
+        library.visit_playlists(|id, depth| visited.push((id, depth)));
+        visited
+    }
+
+    #[test]
+    fn orphans_move_to_the_top_level() {
+        let library = library([
+            playlist(1, None, PlaylistKind::Folder),
+            playlist(2, Some(1), PlaylistKind::Generic),
+            playlist(3, Some(99), PlaylistKind::Folder),
+            playlist(4, Some(3), PlaylistKind::Generic),
+        ]);
+        assert_eq!(library.playlists[&3].parent_id, None);
+        assert_eq!(library.playlists[&4].parent_id, Some(3));
+        assert_eq!(visited(&library), [(1, 0), (2, 1), (3, 0), (4, 1)]);
+        assert_eq!(library.validate(), [ValidationIssue::OrphanedPlaylist { playlist: "P3".to_owned(), playlist_id: 3, parent_id: 99 }]);
+    }
+
+    #[test]
+    fn cycles_are_broken_at_their_lowest_id() {
+        let library = library([
+            playlist(5, Some(6), PlaylistKind::Folder),
+            playlist(6, Some(5), PlaylistKind::Folder),
+            playlist(7, Some(6), PlaylistKind::Generic),
+        ]);
+        assert_eq!(library.playlists[&5].parent_id, None);
+        assert_eq!(library.playlists[&6].parent_id, Some(5));
+        assert_eq!(visited(&library), [(5, 0), (6, 1), (7, 2)]);
+        assert_eq!(library.validate(), [ValidationIssue::ParentCycle { playlist: "P5".to_owned(), playlist_id: 5 }]);
+    }
+
+    #[test]
+    fn the_first_playlist_of_a_persistent_id_is_kept() {
+        let mut playlists = HashMap::new();
+        let mut issues = Vec::new();
+        let named = |name: &str, kind| Playlist { name: name.to_owned(), ..playlist(1, None, kind) };
+        let loaded = [
+            named("First", PlaylistKind::Folder),
+            named("Second", PlaylistKind::Generic),
+            named("Third", PlaylistKind::Generic),
+            playlist(2, Some(1), PlaylistKind::Generic),
+        ];
+        for playlist in loaded {
+            insert_playlist(&mut playlists, playlist, &mut issues);
+        }
+        assert_eq!(playlists.len(), 2);
+        assert_eq!(playlists[&1].name, "First");
+        assert_eq!(playlists[&1].kind, PlaylistKind::Folder);
+        let discarded = |name: &str| ValidationIssue::DuplicatePlaylistPersistentId { persistent_id: 1, kept: "First".to_owned(), discarded: name.to_owned() };
+        assert_eq!(issues, [discarded("Second"), discarded("Third")]);
+    }
+
+    #[test]
+    fn playlists_that_are_their_own_parent_move_to_the_top_level() {
+        let library = library([playlist(8, Some(8), PlaylistKind::Folder), playlist(9, Some(8), PlaylistKind::Generic)]);
+        assert_eq!(library.playlists[&8].parent_id, None);
+        assert_eq!(visited(&library), [(8, 0), (9, 1)]);
+        assert_eq!(library.playlist_path(9).len(), 2);
+        assert_eq!(library.validate(), [ValidationIssue::ParentCycle { playlist: "P8".to_owned(), playlist_id: 8 }]);
+    }
+
+    /// The track at `location` as it appears in a library, before it is decoded.
+    fn track_at(location: &str) -> Track {
+        process_track(Track { name: "Song".to_owned(), location: Some(location.to_owned()), ..Track::default() }).unwrap()
+    }
+
+    #[test]
+    fn distinguished_kinds_are_those_of_music_app_exports() {
+        // The names Music.app and iTunes show these playlists under.
+        let cases = [
+            (2, "Movies", PlaylistKind::Movies, "movies"),
+            (3, "TV Shows", PlaylistKind::TvShows, "tv-shows"),
+            (4, "Music", PlaylistKind::Music, "music"),
+            (5, "Audiobooks", PlaylistKind::Audiobooks, "audiobooks"),
+            (7, "Tones", PlaylistKind::Tones, "tones"),
+            (10, "Podcasts", PlaylistKind::Podcasts, "podcasts"),
+            (19, "Purchased", PlaylistKind::Purchased, "purchased"),
+            (22, "iTunes DJ", PlaylistKind::ItunesDj, "itunes-dj"),
+            (26, "Genius", PlaylistKind::Genius, "genius"),
+            (31, "iTunes U", PlaylistKind::ItunesU, "itunes-u"),
+            (32, "Genius Mixes", PlaylistKind::GeniusMixes, "genius-mixes"),
+            (47, "Music Videos", PlaylistKind::MusicVideos, "music-videos"),
+            (48, "Home Videos", PlaylistKind::HomeVideos, "home-videos"),
+            (65, "Downloaded", PlaylistKind::Unknown(65), "unknown"),
+        ];
+        let playlists: String = cases.iter().enumerate()
+            .map(|(i, (value, name, _, _))| format!(
+                "<dict><key>Name</key><string>{}</string><key>Playlist Persistent ID</key><string>{:016X}</string>\
+                 <key>Distinguished Kind</key><integer>{}</integer></dict>",
+                name, i + 1, value
+            ))
+            .collect();
+        let xml = format!(
+            r#"<?xml version="1.0" encoding="UTF-8"?><plist version="1.0"><dict><key>Tracks</key><dict/><key>Playlists</key><array>{}</array></dict></plist>"#,
+            playlists
+        );
+        let library = Library::from_reader(io::Cursor::new(xml)).unwrap();
+        for (i, (value, name, kind, cli_name)) in cases.iter().enumerate() {
+            assert_eq!(library.playlists[&(i as u64 + 1)].kind, *kind, "{}", name);
+            assert_eq!(kind.distinguished_kind(), Some(*value), "{}", name);
+            assert_eq!(kind.name(), *cli_name, "{}", name);
+            assert_eq!(kind.is_system(), !matches!(kind, PlaylistKind::Unknown(_)), "{}", name);
+            if kind.is_system() {
+                assert_eq!(PlaylistKind::from_system_name(cli_name), Some(*kind), "{}", name);
+            }
+        }
+        assert_eq!(PlaylistKind::system_names().count(), DISTINGUISHED_KINDS.len());
+        assert_eq!(PlaylistKind::from_system_name("unknown"), None);
+        assert!(!PlaylistKind::Master.is_system() && !PlaylistKind::Folder.is_system() && !PlaylistKind::Generic.is_system());
+    }
+
+    #[test]
+    fn formats_come_from_the_kinds_music_app_gives_tracks() {
+        let cases = [
+            ("MPEG audio file", Some(AudioFormat::Mp3)),
+            ("AAC audio file", Some(AudioFormat::Aac)),
+            ("Purchased AAC audio file", Some(AudioFormat::Aac)),
+            ("Protected AAC audio file", Some(AudioFormat::Aac)),
+            ("Matched AAC audio file", Some(AudioFormat::Aac)),
+            ("Apple Music AAC audio file", Some(AudioFormat::Aac)),
+            ("Apple Lossless audio file", Some(AudioFormat::AppleLossless)),
+            ("WAV audio file", Some(AudioFormat::Wav)),
+            ("AIFF audio file", Some(AudioFormat::Aiff)),
+            ("FLAC audio file", Some(AudioFormat::Flac)),
+            ("Internet audio stream", None),
+            ("MPEG-4 video file", None),
+            ("Purchased MPEG-4 video file", None),
+        ];
+        for (kind, format) in cases {
+            // A location whose extension says nothing, so that only the kind can.
+            let track = Track { kind: Some(kind.to_owned()), location: Some("file:///Music/Song.m4a".to_owned()), ..Track::default() };
+            assert_eq!(track.file_format(), format, "{}", kind);
+            assert_eq!(track.is_lossless(), format.is_some_and(AudioFormat::is_lossless), "{}", kind);
+        }
+        let protected = Track { kind: Some("Protected AAC audio file".to_owned()), ..Track::default() };
+        assert!(protected.is_protected());
+        // Every kind the table knows is among those above.
+        assert!(KIND_FORMATS.iter().all(|(known, format)| cases.iter().any(|(kind, found)| kind.contains(known) && *found == Some(*format))));
+    }
+
+    #[test]
+    fn formats_come_from_extensions_for_tracks_of_unknown_kinds() {
+        let cases = [
+            ("Song.mp3", Some(AudioFormat::Mp3)),
+            ("Song.MP3", Some(AudioFormat::Mp3)),
+            ("Song.aac", Some(AudioFormat::Aac)),
+            ("Song.m4p", Some(AudioFormat::Aac)),
+            ("Song.wav", Some(AudioFormat::Wav)),
+            ("Song.aif", Some(AudioFormat::Aiff)),
+            ("Song.aiff", Some(AudioFormat::Aiff)),
+            ("Song.flac", Some(AudioFormat::Flac)),
+            // Either AAC or Apple Lossless.
+            ("Song.m4a", None),
+            ("Song.ogg", None),
+            ("Song", None),
+        ];
+        for (file, format) in cases {
+            for kind in [None, Some("Ogg Vorbis file")] {
+                let track = Track { kind: kind.map(str::to_owned), location: Some(format!("file:///Music/{}", file)), ..Track::default() };
+                assert_eq!(track.file_format(), format, "{} of kind {:?}", file, kind);
+            }
+        }
+        assert!(EXTENSION_FORMATS.iter().all(|(extension, format)| cases.contains(&(format!("Song.{}", extension).as_str(), Some(*format)))));
+        // The kind wins over the extension.
+        let track = Track { kind: Some("MPEG audio file".to_owned()), location: Some("file:///Music/Song.wav".to_owned()), ..Track::default() };
+        assert_eq!(track.file_format(), Some(AudioFormat::Mp3));
+        assert_eq!(Track::default().file_format(), None);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn locations_lead_to_the_files_of_tracks() {
+        let remaps = [("nas".to_owned(), PathBuf::from("/Volumes/nas")), ("my nas".to_owned(), PathBuf::from("/Volumes/my nas"))];
+        let cases: [(&str, Option<&str>); 14] = [
+            ("file://localhost/Music/One.mp3", Some("/Music/One.mp3")),
+            ("file:///Music/One.mp3", Some("/Music/One.mp3")),
+            ("file://LOCALHOST/Music/One.mp3", Some("/Music/One.mp3")),
+            ("file://localhost/C:/Users/Me/One.mp3", Some("C:/Users/Me/One.mp3")),
+            ("file:///c:/One.mp3", Some("c:/One.mp3")),
+            ("file://localhost/Music/Caf%C3%A9%20%231%3F.mp3", Some("/Music/Café #1?.mp3")),
+            ("file://localhost/Music/100%25.mp3", Some("/Music/100%.mp3")),
+            ("file://nas/music/One.mp3", Some("/Volumes/nas/music/One.mp3")),
+            ("file://NAS/music/One.mp3", Some("/Volumes/nas/music/One.mp3")),
+            ("file://localhost//nas/music/One.mp3", Some("/Volumes/nas/music/One.mp3")),
+            ("file://my%20nas/Caf%C3%A9/One%20%26%20Two.mp3", Some("/Volumes/my nas/Café/One & Two.mp3")),
+            // Hosts that aren't remapped can't be reached outside Windows.
+            ("file://other/music/One.mp3", None),
+            ("file://localhost//other/music/One.mp3", None),
+            ("http://example.com/One.mp3", None),
+        ];
+        for (location, expected) in cases {
+            let path = track_at(location).local_path_remapped(&remaps);
+            assert_eq!(path, Ok(expected.map(PathBuf::from)), "{}", location);
+        }
+        // Without remaps, only the files on this machine are found.
+        assert_eq!(track_at("file://nas/music/One.mp3").local_path(), Ok(None));
+        assert_eq!(track_at("file:///Music/One.mp3").local_path(), Ok(Some(PathBuf::from("/Music/One.mp3"))));
+        assert_eq!(Track::default().local_path(), Ok(None));
+
+        for location in ["file://localhost", "file://localhost/", "file://nas"] {
+            let error = track_at(location).local_path_remapped(&remaps);
+            assert_eq!(error, Err(LocationError::MissingPath { location: location.to_owned() }), "{}", location);
+        }
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn locations_lead_to_drives_and_shares_on_windows() {
+        let remaps = [("nas".to_owned(), PathBuf::from(r"Z:\"))];
+        let cases = [
+            ("file://localhost/C:/Users/Me/Music/One.mp3", r"C:\Users\Me\Music\One.mp3"),
+            ("file:///d:/Music/Caf%C3%A9%20%231.mp3", r"d:\Music\Café #1.mp3"),
+            ("file://localhost//server/share/Music/One.mp3", r"\\server\share\Music\One.mp3"),
+            ("file://server/share/Music/One.mp3", r"\\server\share\Music\One.mp3"),
+            ("file://my%20server/My%20Share/One.mp3", r"\\my server\My Share\One.mp3"),
+            // Shares that are mounted are read from there instead.
+            ("file://nas/music/One.mp3", r"Z:\music\One.mp3"),
+        ];
+        for (location, expected) in cases {
+            let path = track_at(location).local_path_remapped(&remaps).unwrap().unwrap();
+            assert_eq!(path.as_os_str(), expected, "{}", location);
+        }
+        // Paths have to be UTF-8 to name files on Windows.
+        let track = track_at("file:///C:/Music/Caf%E9.mp3");
+        assert_eq!(track.local_path(), Err(LocationError::NonUtf8Path { location: "file:///C:/Music/Caf\u{FFFD}.mp3".to_owned() }));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn locations_in_a_legacy_encoding_are_kept_as_bytes() {
+        use std::os::unix::ffi::OsStrExt;
+
+        let xml = r#"<?xml version="1.0" encoding="UTF-8"?>
+<plist version="1.0"><dict>
+<key>Music Folder</key><string>file:///Music/Caf%E9/</string>
+<key>Tracks</key><dict>
+<key>1</key><dict><key>Track ID</key><integer>1</integer><key>Name</key><string>Song</string>
+<key>Total Time</key><integer>1000</integer><key>Location</key><string>file:///Music/Caf%E9/Song.mp3</string></dict>
+<key>2</key><dict><key>Track ID</key><integer>2</integer><key>Name</key><string>Other</string>
+<key>Total Time</key><integer>1000</integer><key>Location</key><string>file:///Music/Other.mp3</string></dict>
+</dict>
+<key>Playlists</key><array/>
+</dict></plist>"#;
+        let library = Library::from_reader(io::Cursor::new(xml)).unwrap();
+        assert_eq!(library.music_folder.as_deref(), Some("file:///Music/Caf\u{FFFD}/"));
+        let folder = library.music_folder_path(&[]).unwrap().unwrap();
+        assert_eq!(folder.as_os_str().as_bytes(), b"/Music/Caf\xE9/");
+        let song = library.tracks[&1].local_path().unwrap().unwrap();
+        assert_eq!(song.as_os_str().as_bytes(), b"/Music/Caf\xE9/Song.mp3");
+        assert!(song.starts_with(&folder));
+        assert_eq!(library.tracks[&2].local_path().unwrap().unwrap(), Path::new("/Music/Other.mp3"));
+
+        // And written back as they were.
+        let mut written = Vec::new();
+        library.to_writer_xml(&mut written).unwrap();
+        let written = String::from_utf8(written).unwrap();
+        assert!(written.contains("file:///Music/Caf%E9/</string>"), "{}", written);
+        assert!(written.contains("file:///Music/Caf%E9/Song.mp3"), "{}", written);
+    }
+}
@@ -1,17 +1,133 @@
-use std::collections::HashMap;
+use std::{collections::HashMap, time::SystemTime};
 
-use serde::Deserialize;
+use serde::{de::IgnoredAny, Deserialize, Deserializer};
 
-#[derive(Deserialize)]
+#[derive(Clone, Debug, Default, Deserialize)]
 pub struct Track {
-    #[serde(rename = "Name")]
+    /// Unlike the track ID, stays the same across exports of the library.
+    #[serde(rename = "Persistent ID", default, deserialize_with = "hex_id")]
+    pub persistent_id: Option<u64>,
+
+    #[serde(rename = "Name", default)]
     pub name: String,
 
-    #[serde(rename = "Location")]
-    pub location: String,
+    #[serde(rename = "Artist", default)]
+    pub artist: Option<String>,
+
+    #[serde(rename = "Album Artist", default)]
+    pub album_artist: Option<String>,
+
+    #[serde(rename = "Album", default)]
+    pub album: Option<String>,
+
+    /// Set for albums by various artists, which iTunes keeps together in a Compilations folder.
+    #[serde(rename = "Compilation", default)]
+    pub compilation: bool,
+
+    #[serde(rename = "Genre", default)]
+    pub genre: Option<String>,
+
+    #[serde(rename = "Composer", default)]
+    pub composer: Option<String>,
+
+    /// The classical work the track is a movement of, as Music.app shows it.
+    #[serde(rename = "Work", default)]
+    pub work: Option<String>,
+
+    #[serde(rename = "Movement Name", default)]
+    pub movement_name: Option<String>,
+
+    #[serde(rename = "Movement Number", default)]
+    pub movement_number: Option<u32>,
+
+    #[serde(rename = "Disc Number", default)]
+    pub disc_number: Option<u32>,
+
+    #[serde(rename = "Disc Count", default)]
+    pub disc_count: Option<u32>,
+
+    #[serde(rename = "Track Number", default)]
+    pub track_number: Option<u32>,
+
+    #[serde(rename = "Track Count", default)]
+    pub track_count: Option<u32>,
+
+    /// From 0 to 100, in steps of 20 per star.
+    #[serde(rename = "Rating", default)]
+    pub rating: Option<u32>,
+
+    #[serde(rename = "Play Count", default)]
+    pub play_count: Option<u32>,
+
+    #[serde(rename = "Date Added", default, deserialize_with = "date")]
+    pub date_added: Option<SystemTime>,
+
+    /// When the track came out, or for podcasts when the episode was published.
+    #[serde(rename = "Release Date", default, deserialize_with = "date")]
+    pub release_date: Option<SystemTime>,
+
+    /// Set for podcast episodes, whose show is their album.
+    #[serde(rename = "Podcast", default)]
+    pub podcast: bool,
+
+    /// The file type as iTunes describes it, e.g. "MPEG audio file" or "Protected AAC audio file".
+    #[serde(rename = "Kind", default)]
+    pub kind: Option<String>,
+
+    /// Set for FairPlay-protected purchases, which only Apple's software can play.
+    #[serde(rename = "Protected", default)]
+    pub protected: bool,
+
+    /// Set for tracks that are unchecked in iTunes, which it leaves out of device syncs.
+    #[serde(rename = "Disabled", default)]
+    pub disabled: bool,
 
-    #[serde(rename = "Total Time")]
-    pub duration_ms: u32,
+    /// Set for tracks marked with a heart in Music.app, or loved in iTunes.
+    #[serde(rename = "Loved", default)]
+    pub loved: bool,
+
+    /// Set for tracks whose album is loved as a whole.
+    #[serde(rename = "Album Loved", default)]
+    pub album_loved: bool,
+
+    /// Missing for tracks that only exist in the cloud.
+    #[serde(rename = "Location", default)]
+    pub location: Option<String>,
+
+    /// The decoded bytes of a location that isn't valid UTF-8, in which case `location` holds a
+    /// lossy version of it.
+    #[serde(skip)]
+    pub location_bytes: Option<Vec<u8>>,
+
+    #[serde(rename = "Total Time", default)]
+    pub duration_ms: Option<u32>,
+
+    /// Where in its file iTunes starts playing the track, for tracks that are only part of it.
+    #[serde(rename = "Start Time", default)]
+    pub start_time_ms: Option<u32>,
+
+    /// Where in its file iTunes stops playing the track, for tracks that are only part of it.
+    #[serde(rename = "Stop Time", default)]
+    pub stop_time_ms: Option<u32>,
+
+    /// The size of the file in bytes, as of the export.
+    #[serde(rename = "Size", default)]
+    pub size_bytes: Option<u64>,
+}
+
+fn hex_id<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<u64>, D::Error> {
+    Option::<String>::deserialize(deserializer)?
+        .map(|id| u64::from_str_radix(&id, 16)
+            .map_err(|e| serde::de::Error::custom(format!("invalid track persistent ID {:?}: {}", id, e))))
+        .transpose()
+}
+
+fn visible_by_default() -> bool {
+    true
+}
+
+pub(super) fn date<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<SystemTime>, D::Error> {
+    Option::<plist::Date>::deserialize(deserializer).map(|date| date.map(Into::into))
 }
 
 #[derive(Deserialize)]
@@ -37,12 +153,42 @@ pub(super) struct Playlist {
     #[serde(rename = "Master", default)] // bool::default() returns false
     pub is_master: bool,
 
+    #[serde(rename = "Visible", default = "visible_by_default")]
+    pub is_visible: bool,
+
     #[serde(rename = "Distinguished Kind", default)]
     pub distinguished_kind: Option<u64>,
+
+    #[serde(rename = "Smart Info", default)]
+    pub smart_info: Option<IgnoredAny>,
+
+    #[serde(rename = "Smart Criteria", default)]
+    pub smart_criteria: Option<IgnoredAny>,
+}
+
+/// The top-level keys of a library besides its tracks and playlists.
+#[derive(Default)]
+pub(super) struct Metadata {
+    pub music_folder: Option<String>,
+    pub persistent_id: Option<String>,
+    pub application_version: Option<String>,
+    pub date: Option<SystemTime>,
 }
 
 #[derive(Deserialize)]
 pub struct Library {
+    #[serde(rename = "Music Folder", default)]
+    pub(super) music_folder: Option<String>,
+
+    #[serde(rename = "Library Persistent ID", default)]
+    pub(super) persistent_id: Option<String>,
+
+    #[serde(rename = "Application Version", default)]
+    pub(super) application_version: Option<String>,
+
+    #[serde(rename = "Date", default, deserialize_with = "date")]
+    pub(super) date: Option<SystemTime>,
+
     #[serde(rename = "Tracks")]
     pub(super) tracks: HashMap<String, Track>,
 
@@ -0,0 +1,171 @@
+//! `Serialize` for the parsed library, as printed by `m3trans export`, with names meant for
+//! scripts rather than the ones in the property list.
+
+use std::ops::ControlFlow;
+
+use serde::{ser::{SerializeMap, SerializeSeq}, Serialize, Serializer};
+
+use crate::date::rfc3339;
+
+use super::{xml::hex_id, Library, Playlist, PlaylistKind, Track};
+
+impl Serialize for Library {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(None)?;
+        map.serialize_entry("persistent_id", &self.persistent_id.map(hex_id))?;
+        map.serialize_entry("application_version", &self.application_version)?;
+        map.serialize_entry("exported_at", &self.exported_at.map(rfc3339))?;
+        map.serialize_entry("music_folder", &self.music_folder)?;
+        map.serialize_entry("tracks", &Tracks(self))?;
+        map.serialize_entry("playlists", &Playlists(self))?;
+        map.end()
+    }
+}
+
+/// The tracks keyed by their ID, in order of it.
+struct Tracks<'a>(&'a Library);
+
+impl Serialize for Tracks<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut ids: Vec<u64> = self.0.tracks.keys().copied().collect();
+        ids.sort_unstable();
+        let mut map = serializer.serialize_map(Some(ids.len()))?;
+        for id in ids {
+            map.serialize_entry(&id.to_string(), &self.0.tracks[&id])?;
+        }
+        map.end()
+    }
+}
+
+/// The playlists in display order, each with the names of it and its ancestors from the root.
+struct Playlists<'a>(&'a Library);
+
+impl Serialize for Playlists<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut seq = serializer.serialize_seq(None)?;
+        self.0.try_visit_playlists_with_path(|path| {
+            let names = path.iter().map(|playlist| playlist.name.as_str()).collect();
+            seq.serialize_element(&json_playlist(path[path.len() - 1], Some(names)))?;
+            Ok(ControlFlow::Continue(()))
+        })?;
+        seq.end()
+    }
+}
+
+impl Serialize for Playlist {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        json_playlist(self, None).serialize(serializer)
+    }
+}
+
+/// By name, with unknown kinds' playlists giving the number iTunes has for them.
+impl Serialize for PlaylistKind {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.name())
+    }
+}
+
+impl Serialize for Track {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        JsonTrack {
+            persistent_id: self.persistent_id.map(hex_id),
+            name: &self.name,
+            artist: self.artist.as_deref(),
+            album_artist: self.album_artist.as_deref(),
+            album: self.album.as_deref(),
+            compilation: self.compilation,
+            genre: self.genre.as_deref(),
+            composer: self.composer.as_deref(),
+            work: self.work.as_deref(),
+            movement_name: self.movement_name.as_deref(),
+            movement_number: self.movement_number,
+            disc_number: self.disc_number,
+            disc_count: self.disc_count,
+            track_number: self.track_number,
+            track_count: self.track_count,
+            rating: self.rating,
+            play_count: self.play_count,
+            date_added: self.date_added.map(rfc3339),
+            release_date: self.release_date.map(rfc3339),
+            podcast: self.podcast,
+            duration_ms: self.duration_ms,
+            start_time_ms: self.start_time_ms,
+            stop_time_ms: self.stop_time_ms,
+            size_bytes: self.size_bytes,
+            kind: self.kind.as_deref(),
+            format: self.file_format().map(|format| format.name()),
+            lossless: self.is_lossless(),
+            protected: self.is_protected(),
+            disabled: self.disabled,
+            loved: self.loved,
+            album_loved: self.album_loved,
+            location: self.location.as_deref(),
+        }.serialize(serializer)
+    }
+}
+
+#[derive(Serialize)]
+struct JsonTrack<'a> {
+    persistent_id: Option<String>,
+    name: &'a str,
+    artist: Option<&'a str>,
+    album_artist: Option<&'a str>,
+    album: Option<&'a str>,
+    compilation: bool,
+    genre: Option<&'a str>,
+    composer: Option<&'a str>,
+    work: Option<&'a str>,
+    movement_name: Option<&'a str>,
+    movement_number: Option<u32>,
+    disc_number: Option<u32>,
+    disc_count: Option<u32>,
+    track_number: Option<u32>,
+    track_count: Option<u32>,
+    rating: Option<u32>,
+    play_count: Option<u32>,
+    date_added: Option<String>,
+    release_date: Option<String>,
+    podcast: bool,
+    duration_ms: Option<u32>,
+    start_time_ms: Option<u32>,
+    stop_time_ms: Option<u32>,
+    size_bytes: Option<u64>,
+    kind: Option<&'a str>,
+    format: Option<&'static str>,
+    lossless: bool,
+    protected: bool,
+    disabled: bool,
+    loved: bool,
+    album_loved: bool,
+    /// Percent-decoded, and lossy where that isn't UTF-8.
+    location: Option<&'a str>,
+}
+
+#[derive(Serialize)]
+struct JsonPlaylist<'a> {
+    persistent_id: String,
+    parent_id: Option<String>,
+    name: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    path: Option<Vec<&'a str>>,
+    kind: PlaylistKind,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    distinguished_kind: Option<u64>,
+    smart: bool,
+    visible: bool,
+    items: &'a [u64],
+}
+
+fn json_playlist<'a>(playlist: &'a Playlist, path: Option<Vec<&'a str>>) -> JsonPlaylist<'a> {
+    JsonPlaylist {
+        persistent_id: hex_id(playlist.persistent_id),
+        parent_id: playlist.parent_id.map(hex_id),
+        name: &playlist.name,
+        path,
+        kind: playlist.kind,
+        distinguished_kind: playlist.kind.distinguished_kind(),
+        smart: playlist.is_smart,
+        visible: playlist.is_visible,
+        items: &playlist.items,
+    }
+}
@@ -0,0 +1,134 @@
+//! Loading of a library as it is read, processing tracks and playlists one at a time rather than
+//! deserializing the whole raw library first.
+
+use std::{collections::HashMap, fmt, io::{Read, Seek}};
+
+use serde::de::{self, Deserialize, DeserializeSeed, Deserializer, IgnoredAny, MapAccess, SeqAccess, Visitor};
+
+use super::{insert_playlist, raw, track_entry, Library, LoadError, ParseError, PlistFormat, Playlist, Track, ValidationIssue};
+
+pub(super) fn read(mut reader: impl Read + Seek) -> Result<Library, LoadError> {
+    let format = PlistFormat::detect(&mut reader).map_err(LoadError::Io)?;
+    let Streamed(library) = plist::from_reader(reader).map_err(|e| LoadError::Plist(format, e))?;
+    Ok(library?)
+}
+
+/// A library built while its plist is deserialized.
+struct Streamed(Result<Library, ParseError>);
+
+impl<'de> Deserialize<'de> for Streamed {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_map(LibraryVisitor).map(Streamed)
+    }
+}
+
+/// Visits the top-level dictionary. Problems with the contents are returned rather than raised as
+/// deserialization errors, so that they keep their `ParseError`.
+struct LibraryVisitor;
+
+impl<'de> Visitor<'de> for LibraryVisitor {
+    type Value = Result<Library, ParseError>;
+
+    fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("a library dictionary")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+        let mut metadata = raw::Metadata::default();
+        let mut tracks = None;
+        let mut playlists = None;
+        let mut error = None;
+        while let Some(key) = map.next_key::<String>()? {
+            match key.as_str() {
+                "Music Folder" => metadata.music_folder = Some(map.next_value()?),
+                "Library Persistent ID" => metadata.persistent_id = Some(map.next_value()?),
+                "Application Version" => metadata.application_version = Some(map.next_value()?),
+                "Date" => metadata.date = Some(map.next_value::<plist::Date>()?.into()),
+                "Tracks" => tracks = Some(map.next_value_seed(Tracks { error: &mut error })?),
+                "Playlists" => playlists = Some(map.next_value_seed(Playlists { error: &mut error })?),
+                _ => { map.next_value::<IgnoredAny>()?; }
+            }
+        }
+
+        let tracks = tracks.ok_or_else(|| de::Error::missing_field("Tracks"))?;
+        let (playlists, issues) = playlists.ok_or_else(|| de::Error::missing_field("Playlists"))?;
+        Ok(match error {
+            Some(e) => Err(e),
+            None => Library::assemble(metadata, tracks, playlists, issues),
+        })
+    }
+}
+
+/// Processes the `Tracks` dictionary entry by entry. After the first error, the remaining entries
+/// are only read past.
+struct Tracks<'a> {
+    error: &'a mut Option<ParseError>,
+}
+
+impl<'de> DeserializeSeed<'de> for Tracks<'_> {
+    type Value = HashMap<u64, Track>;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<Self::Value, D::Error> {
+        deserializer.deserialize_map(self)
+    }
+}
+
+impl<'de> Visitor<'de> for Tracks<'_> {
+    type Value = HashMap<u64, Track>;
+
+    fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("a dictionary of tracks")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+        let mut tracks = HashMap::with_capacity(map.size_hint().unwrap_or(0));
+        while let Some(id) = map.next_key::<String>()? {
+            let track = map.next_value::<Track>()?;
+            if self.error.is_none() {
+                match track_entry(id, track) {
+                    Ok((id, track)) => { tracks.insert(id, track); }
+                    Err(e) => *self.error = Some(e),
+                }
+            }
+        }
+        Ok(tracks)
+    }
+}
+
+/// Processes the `Playlists` array element by element, like `Tracks`.
+struct Playlists<'a> {
+    error: &'a mut Option<ParseError>,
+}
+
+impl<'de> DeserializeSeed<'de> for Playlists<'_> {
+    /// The playlists, and the problems with them that were worked around.
+    type Value = (HashMap<u64, Playlist>, Vec<ValidationIssue>);
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<Self::Value, D::Error> {
+        deserializer.deserialize_seq(self)
+    }
+}
+
+impl<'de> Visitor<'de> for Playlists<'_> {
+    type Value = (HashMap<u64, Playlist>, Vec<ValidationIssue>);
+
+    fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("an array of playlists")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+        let mut playlists = HashMap::with_capacity(seq.size_hint().unwrap_or(0));
+        let mut issues = Vec::new();
+        let mut order_key = 0;
+        while let Some(playlist) = seq.next_element::<raw::Playlist>()? {
+            if self.error.is_none() {
+                match Playlist::from_raw(playlist, order_key) {
+                    Ok(playlist) => insert_playlist(&mut playlists, playlist, &mut issues),
+                    Err(e) => *self.error = Some(e),
+                }
+            }
+            order_key += 1;
+        }
+        Ok((playlists, issues))
+    }
+}
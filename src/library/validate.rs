@@ -0,0 +1,122 @@
+//! Checks of a library for references and names that don't add up.
+
+use std::{collections::HashMap, fmt};
+
+use super::{Library, MissingTrack};
+
+/// A problem with a library that doesn't keep it from loading, but may leave tracks or playlists
+/// out of a sync, or not where they were expected.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ValidationIssue {
+    /// A playlist item refers to a track ID that isn't in the library's tracks.
+    MissingTrack { playlist: String, playlist_id: u64, track_id: u64 },
+    /// The location of a track isn't a `file://` URL.
+    UnsupportedLocation { track_id: u64, location: String },
+    /// The location of a track doesn't decode to UTF-8, so it is kept only as raw bytes.
+    NonUtf8Location { track_id: u64, location: String },
+    /// Several tracks share a persistent ID.
+    DuplicateTrackPersistentId { persistent_id: u64, track_ids: Vec<u64> },
+    /// Several playlists share a persistent ID, so only the first of them was kept when loading.
+    DuplicatePlaylistPersistentId { persistent_id: u64, kept: String, discarded: String },
+    /// A playlist's parent isn't in the library, so it was moved to the top level when loading.
+    OrphanedPlaylist { playlist: String, playlist_id: u64, parent_id: u64 },
+    /// A playlist is among its own ancestors, so it was moved to the top level when loading.
+    ParentCycle { playlist: String, playlist_id: u64 },
+    /// Several playlists in the same folder have the same name, so their files need telling apart.
+    DuplicateName { path: String, playlist_ids: Vec<u64> },
+}
+
+impl fmt::Display for ValidationIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ValidationIssue::MissingTrack { playlist, playlist_id, track_id } => write!(
+                f, "playlist {:?} ({:016X}) contains track {}, which isn't in the library",
+                playlist, playlist_id, track_id
+            ),
+            ValidationIssue::UnsupportedLocation { track_id, location } =>
+                write!(f, "track {} has location {:?}, which isn't a file URL", track_id, location),
+            ValidationIssue::NonUtf8Location { track_id, location } =>
+                write!(f, "track {} has location {:?}, which isn't valid UTF-8 once decoded", track_id, location),
+            ValidationIssue::DuplicateTrackPersistentId { persistent_id, track_ids } => write!(
+                f, "tracks {} share the persistent ID {:016X}",
+                join(track_ids.iter().map(u64::to_string)), persistent_id
+            ),
+            ValidationIssue::DuplicatePlaylistPersistentId { persistent_id, kept, discarded } => write!(
+                f, "playlists {:?} and {:?} share the persistent ID {:016X}, so only the first was kept",
+                kept, discarded, persistent_id
+            ),
+            ValidationIssue::OrphanedPlaylist { playlist, playlist_id, parent_id } => write!(
+                f, "playlist {:?} ({:016X}) is in folder {:016X}, which isn't in the library",
+                playlist, playlist_id, parent_id
+            ),
+            ValidationIssue::ParentCycle { playlist, playlist_id } =>
+                write!(f, "playlist {:?} ({:016X}) is its own ancestor", playlist, playlist_id),
+            ValidationIssue::DuplicateName { path, playlist_ids } => write!(
+                f, "playlists {} all have the path {:?}",
+                join(playlist_ids.iter().map(|id| format!("{:016X}", id))), path
+            ),
+        }
+    }
+}
+
+fn join(items: impl Iterator<Item = String>) -> String {
+    items.collect::<Vec<_>>().join(", ")
+}
+
+pub(super) fn validate(library: &Library) -> Vec<ValidationIssue> {
+    let mut issues = library.load_issues.clone();
+
+    let mut track_ids: Vec<u64> = library.tracks.keys().copied().collect();
+    track_ids.sort_unstable();
+    let mut persistent_ids: HashMap<u64, Vec<u64>> = HashMap::new();
+    for &track_id in &track_ids {
+        let track = &library.tracks[&track_id];
+        if let Some(location) = &track.location {
+            if track.location_bytes.is_some() {
+                issues.push(ValidationIssue::NonUtf8Location { track_id, location: location.clone() });
+            }
+            else if !location.starts_with("file://") {
+                issues.push(ValidationIssue::UnsupportedLocation { track_id, location: location.clone() });
+            }
+        }
+        if let Some(persistent_id) = track.persistent_id {
+            persistent_ids.entry(persistent_id).or_default().push(track_id);
+        }
+    }
+    let mut duplicates: Vec<_> = persistent_ids.into_iter().filter(|(_, ids)| ids.len() > 1).collect();
+    duplicates.sort_unstable();
+    issues.extend(duplicates.into_iter().map(|(persistent_id, track_ids)| {
+        ValidationIssue::DuplicateTrackPersistentId { persistent_id, track_ids }
+    }));
+
+    // Every playlist as the possible parent of others, in display order, so that duplicate names
+    // are reported in that order too.
+    let mut parents = vec![None];
+    library.visit_playlists(|id, _| {
+        parents.push(Some(id));
+        let playlist = &library.playlists[&id];
+        for MissingTrack { track_id } in library.playlist_tracks(playlist).filter_map(Result::err) {
+            issues.push(ValidationIssue::MissingTrack {
+                playlist: playlist.name.clone(),
+                playlist_id: id,
+                track_id,
+            });
+        }
+    });
+
+    for parent in parents {
+        let mut by_name: Vec<(&str, Vec<u64>)> = Vec::new();
+        for child in library.children(parent) {
+            match by_name.iter_mut().find(|(name, _)| *name == child.name) {
+                Some((_, ids)) => ids.push(child.persistent_id),
+                None => by_name.push((&child.name, vec![child.persistent_id])),
+            }
+        }
+        for (_, playlist_ids) in by_name.into_iter().filter(|(_, ids)| ids.len() > 1) {
+            let path = library.virtual_path(&library.playlists[&playlist_ids[0]]);
+            issues.push(ValidationIssue::DuplicateName { path, playlist_ids });
+        }
+    }
+
+    issues
+}
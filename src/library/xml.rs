@@ -0,0 +1,243 @@
+//! Writing of a library back to an iTunes-style XML property list.
+
+use std::{io::Write, time::SystemTime};
+
+use percent_encoding::{AsciiSet, CONTROLS};
+use serde::{ser::SerializeMap, Serialize, Serializer};
+
+use super::{Library, Playlist, PlaylistKind, Track};
+
+/// The characters iTunes percent-encodes in the paths of locations, besides all non-ASCII ones.
+const LOCATION: &AsciiSet = &CONTROLS
+    .add(b' ').add(b'"').add(b'#').add(b'%').add(b'<').add(b'>').add(b'?')
+    .add(b'[').add(b'\\').add(b']').add(b'^').add(b'`').add(b'{').add(b'|').add(b'}');
+
+pub(super) fn write(library: &Library, writer: impl Write) -> Result<(), plist::Error> {
+    let mut playlists: Vec<&Playlist> = library.playlists.values().collect();
+    playlists.sort_by_key(|playlist| (playlist.order_key, playlist.persistent_id));
+
+    plist::to_writer_xml(writer, &XmlLibrary {
+        major_version: 1,
+        minor_version: 1,
+        date: library.exported_at.map(plist::Date::from),
+        application_version: library.application_version.as_deref(),
+        music_folder: match &library.music_folder_bytes {
+            Some(bytes) => Some(encode_location(bytes)),
+            None => library.music_folder.as_ref().map(|folder| encode_location(folder.as_bytes())),
+        },
+        persistent_id: library.persistent_id.map(hex_id),
+        tracks: XmlTracks(library),
+        playlists: playlists.into_iter().map(xml_playlist).collect(),
+    })
+}
+
+#[derive(Serialize)]
+struct XmlLibrary<'a> {
+    #[serde(rename = "Major Version")]
+    major_version: u64,
+    #[serde(rename = "Minor Version")]
+    minor_version: u64,
+    // Missing values are left out by the plist serializer.
+    #[serde(rename = "Date")]
+    date: Option<plist::Date>,
+    #[serde(rename = "Application Version")]
+    application_version: Option<&'a str>,
+    #[serde(rename = "Music Folder")]
+    music_folder: Option<String>,
+    #[serde(rename = "Library Persistent ID")]
+    persistent_id: Option<String>,
+    #[serde(rename = "Tracks")]
+    tracks: XmlTracks<'a>,
+    #[serde(rename = "Playlists")]
+    playlists: Vec<XmlPlaylist<'a>>,
+}
+
+/// The tracks of a library, keyed by ID in ascending order.
+struct XmlTracks<'a>(&'a Library);
+
+impl Serialize for XmlTracks<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut ids: Vec<u64> = self.0.tracks.keys().copied().collect();
+        ids.sort_unstable();
+        let mut map = serializer.serialize_map(Some(ids.len()))?;
+        for id in ids {
+            map.serialize_entry(&id.to_string(), &xml_track(id, &self.0.tracks[&id]))?;
+        }
+        map.end()
+    }
+}
+
+#[derive(Serialize)]
+struct XmlTrack<'a> {
+    #[serde(rename = "Track ID")]
+    id: u64,
+    #[serde(rename = "Persistent ID")]
+    persistent_id: Option<String>,
+    #[serde(rename = "Name")]
+    name: &'a str,
+    #[serde(rename = "Artist")]
+    artist: Option<&'a str>,
+    #[serde(rename = "Album Artist")]
+    album_artist: Option<&'a str>,
+    #[serde(rename = "Album")]
+    album: Option<&'a str>,
+    #[serde(rename = "Compilation")]
+    compilation: Option<bool>,
+    #[serde(rename = "Genre")]
+    genre: Option<&'a str>,
+    #[serde(rename = "Composer")]
+    composer: Option<&'a str>,
+    #[serde(rename = "Work")]
+    work: Option<&'a str>,
+    #[serde(rename = "Movement Name")]
+    movement_name: Option<&'a str>,
+    #[serde(rename = "Movement Number")]
+    movement_number: Option<u32>,
+    #[serde(rename = "Disc Number")]
+    disc_number: Option<u32>,
+    #[serde(rename = "Disc Count")]
+    disc_count: Option<u32>,
+    #[serde(rename = "Track Number")]
+    track_number: Option<u32>,
+    #[serde(rename = "Track Count")]
+    track_count: Option<u32>,
+    #[serde(rename = "Rating")]
+    rating: Option<u32>,
+    #[serde(rename = "Play Count")]
+    play_count: Option<u32>,
+    #[serde(rename = "Date Added")]
+    date_added: Option<plist::Date>,
+    #[serde(rename = "Release Date")]
+    release_date: Option<plist::Date>,
+    #[serde(rename = "Podcast")]
+    podcast: Option<bool>,
+    #[serde(rename = "Total Time")]
+    duration_ms: Option<u32>,
+    #[serde(rename = "Start Time")]
+    start_time_ms: Option<u32>,
+    #[serde(rename = "Stop Time")]
+    stop_time_ms: Option<u32>,
+    #[serde(rename = "Size")]
+    size_bytes: Option<u64>,
+    #[serde(rename = "Kind")]
+    kind: Option<&'a str>,
+    #[serde(rename = "Protected")]
+    protected: Option<bool>,
+    #[serde(rename = "Disabled")]
+    disabled: Option<bool>,
+    #[serde(rename = "Loved")]
+    loved: Option<bool>,
+    #[serde(rename = "Album Loved")]
+    album_loved: Option<bool>,
+    #[serde(rename = "Location")]
+    location: Option<String>,
+}
+
+fn xml_track(id: u64, track: &Track) -> XmlTrack<'_> {
+    let location = match &track.location_bytes {
+        Some(bytes) => Some(encode_location(bytes)),
+        None => track.location.as_ref().map(|location| encode_location(location.as_bytes())),
+    };
+    XmlTrack {
+        id,
+        persistent_id: track.persistent_id.map(hex_id),
+        name: &track.name,
+        artist: track.artist.as_deref(),
+        album_artist: track.album_artist.as_deref(),
+        album: track.album.as_deref(),
+        compilation: track.compilation.then_some(true),
+        genre: track.genre.as_deref(),
+        composer: track.composer.as_deref(),
+        work: track.work.as_deref(),
+        movement_name: track.movement_name.as_deref(),
+        movement_number: track.movement_number,
+        disc_number: track.disc_number,
+        disc_count: track.disc_count,
+        track_number: track.track_number,
+        track_count: track.track_count,
+        rating: track.rating,
+        play_count: track.play_count,
+        date_added: track.date_added.map(|date: SystemTime| date.into()),
+        release_date: track.release_date.map(|date: SystemTime| date.into()),
+        podcast: track.podcast.then_some(true),
+        duration_ms: track.duration_ms,
+        start_time_ms: track.start_time_ms,
+        stop_time_ms: track.stop_time_ms,
+        size_bytes: track.size_bytes,
+        kind: track.kind.as_deref(),
+        protected: track.protected.then_some(true),
+        disabled: track.disabled.then_some(true),
+        loved: track.loved.then_some(true),
+        album_loved: track.album_loved.then_some(true),
+        location,
+    }
+}
+
+#[derive(Serialize)]
+struct XmlPlaylist<'a> {
+    #[serde(rename = "Name")]
+    name: &'a str,
+    #[serde(rename = "Master")]
+    master: Option<bool>,
+    #[serde(rename = "Playlist Persistent ID")]
+    persistent_id: String,
+    #[serde(rename = "Parent Persistent ID")]
+    parent_id: Option<String>,
+    #[serde(rename = "Distinguished Kind")]
+    distinguished_kind: Option<u64>,
+    #[serde(rename = "Folder")]
+    folder: Option<bool>,
+    #[serde(rename = "Visible")]
+    visible: Option<bool>,
+    /// Written empty for smart playlists, just to mark them as such.
+    #[serde(rename = "Smart Info")]
+    smart_info: Option<EmptyData>,
+    #[serde(rename = "Playlist Items")]
+    items: Option<Vec<XmlItem>>,
+}
+
+#[derive(Serialize)]
+struct XmlItem {
+    #[serde(rename = "Track ID")]
+    id: u64,
+}
+
+struct EmptyData;
+
+impl Serialize for EmptyData {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_bytes(&[])
+    }
+}
+
+fn xml_playlist(playlist: &Playlist) -> XmlPlaylist<'_> {
+    XmlPlaylist {
+        name: &playlist.name,
+        master: (playlist.kind == PlaylistKind::Master).then_some(true),
+        persistent_id: hex_id(playlist.persistent_id),
+        parent_id: playlist.parent_id.map(hex_id),
+        distinguished_kind: playlist.kind.distinguished_kind(),
+        folder: playlist.kind.is_folder().then_some(true),
+        visible: (!playlist.is_visible).then_some(false),
+        smart_info: playlist.is_smart.then_some(EmptyData),
+        items: (!playlist.is_empty()).then(|| playlist.items.iter().map(|&id| XmlItem { id }).collect()),
+    }
+}
+
+pub(super) fn hex_id(id: u64) -> String {
+    format!("{:016X}", id)
+}
+
+/// Percent-encodes a decoded location again, leaving its `scheme://host` prefix as it is.
+fn encode_location(location: &[u8]) -> String {
+    let path_start = location.windows(3)
+        .position(|window| window == b"://")
+        .and_then(|scheme_end| location[scheme_end + 3..].iter().position(|&b| b == b'/').map(|i| scheme_end + 3 + i))
+        .unwrap_or(0);
+    let (prefix, path) = location.split_at(path_start);
+    format!(
+        "{}{}",
+        String::from_utf8_lossy(prefix),
+        percent_encoding::percent_encode(path, LOCATION),
+    )
+}
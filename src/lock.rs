@@ -0,0 +1,183 @@
+//! Lock files that keep two runs from syncing into the same output at once.
+//!
+//! A lock file holds the PID of the run that made it and when that run started, in seconds since
+//! the Unix epoch, separated by a space. Locks left behind by runs that can't still be going are
+//! taken over.
+
+use std::{fmt, fs::{self, OpenOptions}, io::{self, ErrorKind, Write}, path::{Path, PathBuf}, time::{Duration, SystemTime, UNIX_EPOCH}};
+
+use crate::date::rfc3339;
+
+/// The name of the lock file, in the output directory.
+pub(crate) const FILE_NAME: &str = ".m3trans.lock";
+
+/// A lock taken by this run, which removes its file once dropped.
+#[derive(Debug)]
+pub(crate) struct Lock {
+    path: PathBuf,
+}
+
+/// The run a lock file says holds it.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Holder {
+    pub(crate) pid: u32,
+    pub(crate) started: SystemTime,
+}
+
+impl fmt::Display for Holder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "process {}, which started at {}", self.pid, rfc3339(self.started))
+    }
+}
+
+/// Why a lock file was taken over.
+#[derive(Debug)]
+pub(crate) enum Stale {
+    /// Its process isn't running any more.
+    Exited(Holder),
+    /// Its run started longer ago than any should take.
+    TooOld(Holder),
+    /// It doesn't say who holds it.
+    Unreadable,
+}
+
+impl fmt::Display for Stale {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Stale::Exited(holder) => write!(f, "it was left by {}, which is no longer running", holder),
+            Stale::TooOld(holder) => write!(f, "it was left by {}, too long ago to still be syncing", holder),
+            Stale::Unreadable => f.write_str("it doesn't say which process holds it"),
+        }
+    }
+}
+
+impl Lock {
+    /// Takes the lock at `path`, taking it over if the run holding it has exited or started more
+    /// than `max_age` ago, in which case the reason is returned too. Fails with
+    /// `ErrorKind::WouldBlock` if another run holds it.
+    pub(crate) fn acquire(path: &Path, max_age: Duration) -> Result<(Lock, Option<Stale>), io::Error> {
+        let mut stale = None;
+        // A second try, after removing a stale lock, may still lose to another run doing the same.
+        for retry in [false, true] {
+            match OpenOptions::new().write(true).create_new(true).open(path) {
+                Ok(mut file) => {
+                    let lock = Lock { path: path.to_owned() };
+                    let started = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+                    writeln!(file, "{} {}", std::process::id(), started.as_secs())?;
+                    file.sync_all()?;
+                    return Ok((lock, stale));
+                }
+                Err(e) if e.kind() == ErrorKind::AlreadyExists && !retry => {}
+                Err(e) => return Err(e),
+            }
+            let holder = parse(&fs::read_to_string(path)?);
+            stale = match holder {
+                Some(holder) if !is_running(holder.pid) => Some(Stale::Exited(holder)),
+                Some(holder) if SystemTime::now().duration_since(holder.started).is_ok_and(|age| age > max_age) => {
+                    Some(Stale::TooOld(holder))
+                }
+                Some(holder) => return Err(io::Error::new(
+                    ErrorKind::WouldBlock,
+                    format!("it is held by m3trans {}; if no other sync is running, delete the lock file", holder),
+                )),
+                None => Some(Stale::Unreadable),
+            };
+            fs::remove_file(path)?;
+        }
+        Err(io::Error::new(ErrorKind::WouldBlock, "another run of m3trans took it over first"))
+    }
+}
+
+impl Drop for Lock {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+fn parse(contents: &str) -> Option<Holder> {
+    let (pid, started) = contents.trim().split_once(' ')?;
+    Some(Holder {
+        pid: pid.parse().ok()?,
+        started: UNIX_EPOCH + Duration::from_secs(started.parse().ok()?),
+    })
+}
+
+/// Whether a process with this PID exists. Signal 0 checks that without sending one.
+#[cfg(unix)]
+fn is_running(pid: u32) -> bool {
+    use std::convert::TryFrom;
+    match libc::pid_t::try_from(pid) {
+        // Processes of other users can't be signalled, but are still running.
+        Ok(pid) => (unsafe { libc::kill(pid, 0) } == 0) || io::Error::last_os_error().raw_os_error() == Some(libc::EPERM),
+        Err(_) => false,
+    }
+}
+
+/// Elsewhere than on Unix, there's no telling, so only the age of a lock makes it stale.
+#[cfg(not(unix))]
+fn is_running(_pid: u32) -> bool {
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MAX_AGE: Duration = Duration::from_secs(3600);
+
+    fn lock_path(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("m3trans-lock-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir.join(FILE_NAME)
+    }
+
+    #[test]
+    fn a_second_run_is_kept_out_until_the_first_is_done() {
+        let path = lock_path("held");
+        let (lock, stale) = Lock::acquire(&path, MAX_AGE).unwrap();
+        assert!(stale.is_none());
+        let holder = parse(&fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(holder.pid, std::process::id());
+
+        let e = Lock::acquire(&path, MAX_AGE).unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::WouldBlock);
+        assert!(e.to_string().contains(&format!("process {}", std::process::id())), "{}", e);
+
+        drop(lock);
+        assert!(!path.exists());
+        let (_lock, stale) = Lock::acquire(&path, MAX_AGE).unwrap();
+        assert!(stale.is_none());
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn the_lock_of_an_exited_run_is_taken_over() {
+        let path = lock_path("exited");
+        let mut child = std::process::Command::new("true").spawn().unwrap();
+        child.wait().unwrap();
+        let started = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+        fs::write(&path, format!("{} {}\n", child.id(), started)).unwrap();
+
+        let (_lock, stale) = Lock::acquire(&path, MAX_AGE).unwrap();
+        assert!(matches!(stale, Some(Stale::Exited(holder)) if holder.pid == child.id()), "{:?}", stale);
+        assert_eq!(parse(&fs::read_to_string(&path).unwrap()).unwrap().pid, std::process::id());
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn old_and_unreadable_locks_are_taken_over() {
+        let path = lock_path("old");
+        // Held by this very process, but since the epoch.
+        fs::write(&path, format!("{} 0\n", std::process::id())).unwrap();
+        let (lock, stale) = Lock::acquire(&path, MAX_AGE).unwrap();
+        assert!(matches!(stale, Some(Stale::TooOld(_))), "{:?}", stale);
+        drop(lock);
+
+        fs::write(&path, "garbage").unwrap();
+        let (_lock, stale) = Lock::acquire(&path, MAX_AGE).unwrap();
+        assert!(matches!(stale, Some(Stale::Unreadable)), "{:?}", stale);
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+}
@@ -0,0 +1,147 @@
+//! The manifest a sync writes to the root of the output, telling other programs which file there
+//! is which track of the library, and which playlist each playlist file is of.
+//!
+//! Unlike the state m3trans keeps for itself, its format only changes along with
+//! `format_version`, and then only by adding fields. It looks like this:
+//!
+//! ```text
+//! {
+//!   "format_version": 1,
+//!   "tracks": [
+//!     {
+//!       "track_id": 1234,                    // The track's ID in the library.
+//!       "persistent_id": "0123456789ABCDEF", // In hex, or null if the library has none.
+//!       "source": "/Users/me/Music/Song.mp3", // The track's own file.
+//!       "path": "tracks/Artist/Album/01 Song.mp3", // Relative to the output, with slashes.
+//!       "size_bytes": 4021312,               // Or null if the library doesn't say.
+//!       "duration_ms": 241000                // Or null if the library doesn't say.
+//!     }
+//!   ],
+//!   "playlists": [
+//!     {
+//!       "persistent_id": "00000000000000A1",
+//!       "name": "Road Trip",
+//!       "path": "playlists/Road Trip.m3u8"   // Relative to the output, with slashes.
+//!     }
+//!   ]
+//! }
+//! ```
+//!
+//! Tracks are in the order of their paths, and a playlist written in several formats is listed
+//! once for each file.
+
+use serde::{Deserialize, Serialize};
+
+/// The name of the manifest, in the output directory.
+pub(crate) const FILE_NAME: &str = "manifest.json";
+
+/// The version of the format written.
+pub(crate) const FORMAT_VERSION: u32 = 1;
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct Manifest {
+    pub(crate) format_version: u32,
+    pub(crate) tracks: Vec<ManifestTrack>,
+    pub(crate) playlists: Vec<ManifestPlaylist>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct ManifestTrack {
+    pub(crate) track_id: u64,
+    pub(crate) persistent_id: Option<String>,
+    pub(crate) source: String,
+    pub(crate) path: String,
+    pub(crate) size_bytes: Option<u64>,
+    pub(crate) duration_ms: Option<u32>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct ManifestPlaylist {
+    pub(crate) persistent_id: String,
+    pub(crate) name: String,
+    pub(crate) path: String,
+}
+
+impl Manifest {
+    pub(crate) fn new(tracks: Vec<ManifestTrack>, playlists: Vec<ManifestPlaylist>) -> Manifest {
+        Manifest { format_version: FORMAT_VERSION, tracks, playlists }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    fn manifest() -> Manifest {
+        Manifest::new(
+            vec![
+                ManifestTrack {
+                    track_id: 1234,
+                    persistent_id: Some("0123456789ABCDEF".to_owned()),
+                    source: "/Users/me/Music/Song.mp3".to_owned(),
+                    path: "tracks/Artist/Album/01 Song.mp3".to_owned(),
+                    size_bytes: Some(4_021_312),
+                    duration_ms: Some(241_000),
+                },
+                ManifestTrack {
+                    track_id: 7,
+                    persistent_id: None,
+                    source: "C:\\Music\\Añejo.m4a".to_owned(),
+                    path: "tracks/7.m4a".to_owned(),
+                    size_bytes: None,
+                    duration_ms: None,
+                },
+            ],
+            vec![ManifestPlaylist {
+                persistent_id: "00000000000000A1".to_owned(),
+                name: "Road \"Trip\"".to_owned(),
+                path: "playlists/Road _Trip_.m3u8".to_owned(),
+            }],
+        )
+    }
+
+    #[test]
+    fn written_as_documented() {
+        assert_eq!(crate::json::to_string_pretty(&manifest()).unwrap(), r#"{
+  "format_version": 1,
+  "tracks": [
+    {
+      "track_id": 1234,
+      "persistent_id": "0123456789ABCDEF",
+      "source": "/Users/me/Music/Song.mp3",
+      "path": "tracks/Artist/Album/01 Song.mp3",
+      "size_bytes": 4021312,
+      "duration_ms": 241000
+    },
+    {
+      "track_id": 7,
+      "persistent_id": null,
+      "source": "C:\\Music\\Añejo.m4a",
+      "path": "tracks/7.m4a",
+      "size_bytes": null,
+      "duration_ms": null
+    }
+  ],
+  "playlists": [
+    {
+      "persistent_id": "00000000000000A1",
+      "name": "Road \"Trip\"",
+      "path": "playlists/Road _Trip_.m3u8"
+    }
+  ]
+}
+"#);
+    }
+
+    #[test]
+    fn reads_back_what_is_written() {
+        // Through a property list, as nothing here reads JSON; the fields are the same either way.
+        let mut written = Vec::new();
+        plist::to_writer_xml(&mut written, &manifest()).unwrap();
+        let read: Manifest = plist::from_reader(Cursor::new(written)).unwrap();
+        assert_eq!(read, manifest());
+        assert_eq!(read.format_version, FORMAT_VERSION);
+    }
+}
@@ -0,0 +1,276 @@
+//! Helpers for turning names from the library into names that are safe to create on disk.
+
+use std::str::FromStr;
+
+use unicode_normalization::{char::is_combining_mark, UnicodeNormalization};
+
+/// The Unicode normalization form names are converted to. Names in libraries exported on macOS
+/// are usually decomposed (NFD), while most other systems expect them composed (NFC).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Normalization {
+    Nfc,
+    Nfd,
+    None,
+}
+
+impl Normalization {
+    pub fn apply(self, name: &str) -> String {
+        match self {
+            Normalization::Nfc => name.nfc().collect(),
+            Normalization::Nfd => name.nfd().collect(),
+            Normalization::None => name.to_owned(),
+        }
+    }
+}
+
+impl FromStr for Normalization {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "nfc" => Ok(Normalization::Nfc),
+            "nfd" => Ok(Normalization::Nfd),
+            "none" => Ok(Normalization::None),
+            other => Err(format!("unknown normalization form \"{}\"", other)),
+        }
+    }
+}
+
+/// Which characters are replaced in names written to disk, for the file systems of the output.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Sanitize {
+    /// With `sanitize`, or on Windows `sanitize_windows`.
+    Default,
+    /// With `sanitize_fat`.
+    Fat,
+    /// With `sanitize_windows`.
+    Windows,
+}
+
+impl Sanitize {
+    pub fn apply(self, name: &str) -> String {
+        match self {
+            Sanitize::Default if cfg!(windows) => sanitize_windows(name),
+            Sanitize::Default => sanitize(name),
+            Sanitize::Fat => sanitize_fat(name),
+            Sanitize::Windows => sanitize_windows(name),
+        }
+    }
+}
+
+impl FromStr for Sanitize {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "default" => Ok(Sanitize::Default),
+            "fat" => Ok(Sanitize::Fat),
+            "windows" => Ok(Sanitize::Windows),
+            other => Err(format!("unknown sanitization \"{}\"", other)),
+        }
+    }
+}
+
+/// Makes `name` usable as a single path component: path separators, `:` and control characters
+/// are replaced with `_`, and leading or trailing whitespace and dots are removed.
+pub fn sanitize(name: &str) -> String {
+    let replaced: String = name.chars()
+        .map(|c| match c {
+            '/' | '\\' | ':' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .collect();
+
+    match replaced.trim_matches(|c: char| c.is_whitespace() || c == '.') {
+        "" => "_".to_owned(),
+        trimmed => trimmed.to_owned(),
+    }
+}
+
+/// Like `sanitize`, but also replaces the characters FAT file systems reject, for devices that
+/// are formatted with one.
+pub fn sanitize_fat(name: &str) -> String {
+    let replaced: String = name.chars()
+        .map(|c| match c {
+            '"' | '*' | '<' | '>' | '?' | '|' => '_',
+            c => c,
+        })
+        .collect();
+    sanitize(&replaced)
+}
+
+/// Like `sanitize_fat`, but also renames the names Windows keeps for devices, like `CON` or
+/// `com1.mp3`, which it won't create files with on any file system.
+pub fn sanitize_windows(name: &str) -> String {
+    let sanitized = sanitize_fat(name);
+    let stem = sanitized.split('.').next().unwrap_or_default().trim_end();
+    let upper = stem.to_ascii_uppercase();
+    let reserved = matches!(upper.as_str(), "CON" | "PRN" | "AUX" | "NUL")
+        || upper.len() == 4 && (upper.starts_with("COM") || upper.starts_with("LPT")) && upper.as_bytes()[3].is_ascii_digit();
+    match reserved {
+        true => format!("{}_{}", stem, &sanitized[stem.len()..]),
+        false => sanitized,
+    }
+}
+
+/// Shortens `name` to at most `max_len` bytes if it is longer, cutting at a character boundary
+/// and ending it with `suffix` so that names which only differ after the cut stay distinct.
+pub fn truncate(name: &str, max_len: usize, suffix: &str) -> String {
+    if name.len() <= max_len {
+        return name.to_owned();
+    }
+
+    let budget = max_len.saturating_sub(suffix.len() + 1);
+    let cut = (0..=budget).rev().find(|&i| name.is_char_boundary(i)).unwrap_or(0);
+    let kept = name[..cut].trim_end_matches(|c: char| c.is_whitespace() || c == '.');
+    format!("{}~{}", kept, suffix)
+}
+
+/// Appends `suffix` to `name`, first cutting `name` at a character boundary if both together would
+/// be longer than `max_len` bytes, so that numbering a name doesn't take it past a length limit.
+pub fn with_suffix(name: &str, suffix: &str, max_len: Option<usize>) -> String {
+    let name = match max_len {
+        Some(max_len) if name.len() + suffix.len() > max_len => {
+            let budget = max_len.saturating_sub(suffix.len());
+            let cut = (0..=budget).rev().find(|&i| name.is_char_boundary(i)).unwrap_or(0);
+            name[..cut].trim_end_matches(|c: char| c.is_whitespace() || c == '.')
+        }
+        _ => name,
+    };
+    format!("{}{}", name, suffix)
+}
+
+/// Transliterates `name` to ASCII for devices that can't display anything else: accents are
+/// stripped, common letters and punctuation without an ASCII decomposition are spelled out, and
+/// whatever remains is replaced with `_`.
+pub fn to_ascii(name: &str) -> String {
+    let mut ascii = String::with_capacity(name.len());
+    for c in name.nfd().filter(|&c| !is_combining_mark(c)) {
+        let replacement = match c {
+            c if c.is_ascii() => { ascii.push(c); continue; },
+            'ß' => "ss",
+            'æ' => "ae",
+            'Æ' => "AE",
+            'œ' => "oe",
+            'Œ' => "OE",
+            'ø' => "o",
+            'Ø' => "O",
+            'đ' | 'ð' => "d",
+            'Đ' | 'Ð' => "D",
+            'ł' => "l",
+            'Ł' => "L",
+            'þ' => "th",
+            'Þ' => "Th",
+            'ı' => "i",
+            '\u{2018}' | '\u{2019}' | '\u{201A}' | '\u{2032}' | '´' | '`' => "'",
+            '\u{201C}' | '\u{201D}' | '\u{201E}' | '\u{2033}' => "\"",
+            '«' => "<<",
+            '»' => ">>",
+            '\u{2010}'..='\u{2015}' | '\u{2212}' => "-",
+            '\u{2026}' => "...",
+            '\u{2022}' | '·' => "*",
+            '×' => "x",
+            '÷' => "/",
+            '©' => "(C)",
+            '®' => "(R)",
+            '\u{2122}' => "(TM)",
+            '€' => "EUR",
+            '£' => "GBP",
+            '¥' => "JPY",
+            '¡' => "!",
+            '¿' => "?",
+            '°' => "deg",
+            '½' => "1/2",
+            '¼' => "1/4",
+            '¾' => "3/4",
+            c if c.is_whitespace() => " ",
+            _ => "_",
+        };
+        ascii.push_str(replacement);
+    }
+    ascii
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sanitize_replaces_separators_and_trims() {
+        assert_eq!(sanitize("AC/DC: Live"), "AC_DC_ Live");
+        assert_eq!(sanitize("back\\slash\ttab"), "back_slash_tab");
+        assert_eq!(sanitize("  .hidden. "), "hidden");
+        assert_eq!(sanitize("..."), "_");
+        assert_eq!(sanitize(""), "_");
+        assert_eq!(sanitize("What?"), "What?");
+    }
+
+    #[test]
+    fn sanitize_fat_also_replaces_reserved_characters() {
+        assert_eq!(sanitize_fat("What? <Live> \"1999\" *|*"), "What_ _Live_ _1999_ ___");
+        assert_eq!(sanitize_fat("a/b:c"), "a_b_c");
+    }
+
+    #[test]
+    fn sanitize_windows_renames_device_names() {
+        assert_eq!(sanitize_windows("CON"), "CON_");
+        assert_eq!(sanitize_windows("con.mp3"), "con_.mp3");
+        assert_eq!(sanitize_windows("Com1.tar.gz"), "Com1_.tar.gz");
+        assert_eq!(sanitize_windows("lpt9"), "lpt9_");
+        assert_eq!(sanitize_windows("COM"), "COM");
+        assert_eq!(sanitize_windows("COM10"), "COM10");
+        assert_eq!(sanitize_windows("Console"), "Console");
+        assert_eq!(sanitize_windows("NUL?"), "NUL_");
+    }
+
+    #[test]
+    fn truncate_cuts_at_a_character_boundary() {
+        assert_eq!(truncate("short", 10, "1a2b"), "short");
+        assert_eq!(truncate("exactly10!", 10, "1a2b"), "exactly10!");
+        assert_eq!(truncate("a rather long name", 10, "1a2b"), "a rat~1a2b");
+        // The é takes two bytes, which can't be split.
+        assert_eq!(truncate("abcdéfgh", 8, "xy"), "abcd~xy");
+        assert_eq!(truncate("abcé.   xyz", 10, "z"), "abcé~z");
+        assert!(truncate("ééééééééé", 7, "s").len() <= 7);
+    }
+
+    #[test]
+    fn with_suffix_stays_within_the_limit() {
+        assert_eq!(with_suffix("Name", " (2)", None), "Name (2)");
+        assert_eq!(with_suffix("Name", " (2)", Some(8)), "Name (2)");
+        assert_eq!(with_suffix("Longer name", " (2)", Some(8)), "Long (2)");
+        assert_eq!(with_suffix("ab. cd", " (10)", Some(8)), "ab (10)");
+        assert_eq!(with_suffix("ééé", "~1", Some(5)), "é~1");
+    }
+
+    #[test]
+    fn to_ascii_strips_accents_and_spells_out_the_rest() {
+        assert_eq!(to_ascii("Beyoncé"), "Beyonce");
+        assert_eq!(to_ascii("Sigur Rós – Ágætis byrjun"), "Sigur Ros - Agaetis byrjun");
+        assert_eq!(to_ascii("Straße ½ © œuvre"), "Strasse 1/2 (C) oeuvre");
+        assert_eq!(to_ascii("\u{201C}Don\u{2019}t\u{201D}\u{2026}"), "\"Don't\"...");
+        assert_eq!(to_ascii("東京"), "__");
+        assert_eq!(to_ascii("non\u{a0}breaking"), "non breaking");
+    }
+
+    #[test]
+    fn normalization_forms() {
+        let decomposed = "Cafe\u{301}";
+        assert_eq!(Normalization::Nfc.apply(decomposed), "Caf\u{e9}");
+        assert_eq!(Normalization::Nfd.apply("Caf\u{e9}"), decomposed);
+        assert_eq!(Normalization::None.apply(decomposed), decomposed);
+        assert_eq!("nfd".parse(), Ok(Normalization::Nfd));
+        assert!("NFC".parse::<Normalization>().is_err());
+    }
+
+    #[test]
+    fn sanitize_modes() {
+        assert_eq!("fat".parse(), Ok(Sanitize::Fat));
+        assert_eq!("windows".parse(), Ok(Sanitize::Windows));
+        assert_eq!("default".parse(), Ok(Sanitize::Default));
+        assert_eq!("unknown".parse::<Sanitize>(), Err("unknown sanitization \"unknown\"".to_owned()));
+        assert_eq!(Sanitize::Fat.apply("a?b"), "a_b");
+        assert_eq!(Sanitize::Windows.apply("aux"), "aux_");
+    }
+}
@@ -0,0 +1,53 @@
+//! Hooks for following a sync as it goes, for programs that show its progress.
+//!
+//! A sync calls its observer on the thread it runs on, as tracks are copied one at a time, so
+//! calls never overlap and come in the order things happen. The sync waits for each, so they
+//! should return quickly. Every method does nothing unless implemented.
+
+use std::path::Path;
+
+use crate::{Playlist, Track};
+
+/// The stages of syncing to an output, entered in this order, each at most once. A sync that
+/// fails or is interrupted stops without reaching `Finished`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Phase {
+    CopyingTracks,
+    WritingPlaylists,
+    Finished,
+}
+
+/// What came of syncing a track.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TrackOutcome {
+    /// Its file was copied, taking this many bytes.
+    Copied(u64),
+    /// Its copy is a link to that of another track with the same contents.
+    Linked,
+    /// A file already in the output was kept as its copy.
+    Kept,
+    /// It would have been copied, but this is a dry run.
+    Planned,
+    Failed,
+}
+
+pub trait SyncObserver {
+    /// Called as syncing into the output at `output` enters `phase`.
+    fn on_phase_changed(&mut self, _output: &Path, _phase: Phase) {}
+
+    /// Called before a track is synced, as the `index`th of `total`, counting from 0.
+    fn on_track_started(&mut self, _track_id: u64, _track: &Track, _index: usize, _total: usize) {}
+
+    /// Called once for every track started, before the next one is, even if the sync then stops.
+    fn on_track_finished(&mut self, _track_id: u64, _outcome: TrackOutcome) {}
+
+    /// Called after a playlist file is written to `path`, with whether it changed. Not called in a
+    /// dry run, which writes nothing.
+    fn on_playlist_written(&mut self, _playlist: &Playlist, _path: &Path, _changed: bool) {}
+}
+
+/// An observer that does nothing, for syncs no one follows.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct NoObserver;
+
+impl SyncObserver for NoObserver {}
@@ -0,0 +1,216 @@
+//! Carrying out a plan into an output.
+
+use std::{collections::{HashMap, HashSet}, fs, io, path::{Path, PathBuf}};
+use log::*;
+
+use crate::{Library, Playlist, format::{self, Entry}, observer::{Phase, SyncObserver, TrackOutcome}};
+use super::{PlannedPlaylist, PlaylistStep, SyncPlan, TrackCopy};
+
+/// Where a plan is carried out, which does the work of each step. Paths are relative to the
+/// output. Failing to sync a single track or playlist is up to the target to report; an error
+/// returned from any method stops the sync.
+pub trait Target {
+    /// Whether to stop before the next step, as when the user asked to.
+    fn stopped(&self) -> bool { false }
+
+    /// Called before the first track is copied.
+    fn start_tracks(&mut self, _plan: &SyncPlan) -> Result<(), io::Error> { Ok(()) }
+
+    /// Copies a track, or for one that shares the file of another only takes note of it.
+    fn copy_track(&mut self, copy: &TrackCopy) -> Result<TrackOutcome, io::Error>;
+
+    /// Called once the tracks are copied, or the sync stopped while copying them.
+    fn finish_tracks(&mut self, _report: &SyncReport) -> Result<(), io::Error> { Ok(()) }
+
+    /// Called before the first playlist is written.
+    fn start_playlists(&mut self, _plan: &SyncPlan) -> Result<(), io::Error> { Ok(()) }
+
+    /// Creates the folder of a playlist folder.
+    fn create_folder(&mut self, playlist: &Playlist, path: &Path) -> Result<(), io::Error>;
+
+    /// Creates a folder that isn't one of the library's, like that of system playlists, along with
+    /// any it is in.
+    fn create_dir(&mut self, path: &Path) -> Result<(), io::Error>;
+
+    /// Takes note of a playlist or folder left out for `reason`.
+    fn skip_playlist(&mut self, _playlist: &Playlist, _reason: &'static str) {}
+
+    /// Takes note of a playlist left out as none of its tracks were synced.
+    fn skip_empty(&mut self, _playlist: &PlannedPlaylist) {}
+
+    /// Takes note of an entry left out of a playlist as its track failed to sync.
+    fn drop_entry(&mut self, _playlist: &PlannedPlaylist, _track_id: u64) -> Result<(), io::Error> { Ok(()) }
+
+    /// Writes a playlist with `entries` in each of its formats, returning the files written and
+    /// whether each changed.
+    fn write_playlist(&mut self, playlist: &PlannedPlaylist, entries: &[(u64, Entry)]) -> Result<Vec<(PathBuf, bool)>, io::Error>;
+
+    /// Called once everything is done, or the sync stopped.
+    fn finish(&mut self, _report: &SyncReport) -> Result<(), io::Error> { Ok(()) }
+}
+
+/// What came of carrying out a plan.
+#[derive(Debug, Default)]
+pub struct SyncReport {
+    /// Where each track that made it to the output is, relative to it if it was copied.
+    pub synced: HashMap<u64, PathBuf>,
+    /// What came of each track copied, in order.
+    pub outcomes: Vec<(u64, TrackOutcome)>,
+    /// The playlist files written, relative to the output.
+    pub playlists: Vec<PathBuf>,
+    /// Whether the target stopped the sync before it was done.
+    pub stopped: bool,
+}
+
+/// Carries out `plan` into the output at `output` through `target`, telling `observer` how it
+/// goes.
+pub fn execute(
+    plan: &SyncPlan,
+    library: &Library,
+    output: &Path,
+    target: &mut dyn Target,
+    observer: &mut dyn SyncObserver,
+) -> Result<SyncReport, io::Error> {
+    let mut report = SyncReport::default();
+    match plan.copy_tracks {
+        true => {
+            observer.on_phase_changed(output, Phase::CopyingTracks);
+            target.start_tracks(plan)?;
+            for (index, copy) in plan.tracks.iter().enumerate() {
+                if target.stopped() {
+                    report.stopped = true;
+                    break;
+                }
+                observer.on_track_started(copy.track_id, &library.tracks[&copy.track_id], index, plan.tracks.len());
+                let outcome = match target.copy_track(copy) {
+                    Ok(outcome) => outcome,
+                    Err(e) => {
+                        observer.on_track_finished(copy.track_id, TrackOutcome::Failed);
+                        return Err(e);
+                    }
+                };
+                observer.on_track_finished(copy.track_id, outcome);
+                report.outcomes.push((copy.track_id, outcome));
+                if outcome != TrackOutcome::Failed && copy.shares_with.is_none() {
+                    report.synced.insert(copy.track_id, copy.destination.clone());
+                }
+            }
+            // Tracks sharing a file are in the output as far as the copy they share is.
+            for copy in &plan.tracks {
+                if copy.shares_with.is_some_and(|owner| report.synced.contains_key(&owner)) {
+                    report.synced.insert(copy.track_id, copy.destination.clone());
+                }
+            }
+            target.finish_tracks(&report)?;
+        }
+        false => {
+            report.synced = plan.in_place.iter().map(|track| (track.track_id, track.path.clone())).collect();
+        }
+    }
+
+    report.stopped = report.stopped || target.stopped();
+    if !report.stopped {
+        observer.on_phase_changed(output, Phase::WritingPlaylists);
+        target.start_playlists(plan)?;
+        let mut created_folders = HashSet::new();
+        let mut created_dirs = HashSet::new();
+        for step in &plan.playlists {
+            if target.stopped() {
+                report.stopped = true;
+                break;
+            }
+            match step {
+                PlaylistStep::CreateFolder(folder) => {
+                    if created_folders.insert(folder.playlist_id) {
+                        target.create_folder(&library.playlists[&folder.playlist_id], &folder.path)?;
+                    }
+                }
+                PlaylistStep::Skip { playlist_id, reason } => target.skip_playlist(&library.playlists[playlist_id], reason),
+                PlaylistStep::Write(planned) => {
+                    if planned.skip_if_empty && !planned.items.iter().any(|id| report.synced.contains_key(id)) {
+                        target.skip_empty(planned);
+                        continue;
+                    }
+                    for folder in &planned.folders {
+                        if created_folders.insert(folder.playlist_id) {
+                            target.create_folder(&library.playlists[&folder.playlist_id], &folder.path)?;
+                        }
+                    }
+                    if let Some(dir) = planned.dir.as_ref().filter(|dir| created_dirs.insert(dir.to_path_buf())) {
+                        target.create_dir(dir)?;
+                    }
+                    let mut entries = Vec::with_capacity(planned.entries.len());
+                    for (track_id, entry) in &planned.entries {
+                        match report.synced.contains_key(track_id) {
+                            true => entries.push((*track_id, entry.clone())),
+                            false if planned.only_synced => {}
+                            false => target.drop_entry(planned, *track_id)?,
+                        }
+                    }
+                    for (path, changed) in target.write_playlist(planned, &entries)? {
+                        observer.on_playlist_written(&planned.playlist, &output.join(&path), changed);
+                        report.playlists.push(path);
+                    }
+                }
+            }
+        }
+    }
+
+    target.finish(&report)?;
+    if !report.stopped {
+        observer.on_phase_changed(output, Phase::Finished);
+    }
+    Ok(report)
+}
+
+/// A folder to sync into directly, copying every track and writing every playlist anew, without
+/// the checks and records of the m3trans command.
+pub struct Output {
+    pub path: PathBuf,
+    pub options: format::Options,
+}
+
+impl Target for Output {
+    fn copy_track(&mut self, copy: &TrackCopy) -> Result<TrackOutcome, io::Error> {
+        if copy.shares_with.is_some() {
+            return Ok(TrackOutcome::Kept);
+        }
+        let destination = self.path.join(&copy.destination);
+        let result = destination.parent().map_or(Ok(()), fs::create_dir_all)
+            .and_then(|()| fs::copy(&copy.source, &destination));
+        match result {
+            Ok(bytes) => Ok(TrackOutcome::Copied(bytes)),
+            Err(e) => {
+                warn!("Failed to copy file at {:?} to {:?}: {:?}", copy.source, destination, e);
+                Ok(TrackOutcome::Failed)
+            }
+        }
+    }
+
+    fn create_folder(&mut self, _playlist: &Playlist, path: &Path) -> Result<(), io::Error> {
+        fs::create_dir_all(self.path.join(path))
+    }
+
+    fn create_dir(&mut self, path: &Path) -> Result<(), io::Error> {
+        fs::create_dir_all(self.path.join(path))
+    }
+
+    fn write_playlist(&mut self, planned: &PlannedPlaylist, entries: &[(u64, Entry)]) -> Result<Vec<(PathBuf, bool)>, io::Error> {
+        let entries: Vec<Entry> = entries.iter().map(|(_, entry)| entry.clone()).collect();
+        let mut written = Vec::with_capacity(planned.files.len());
+        for file in &planned.files {
+            let mut contents = Vec::new();
+            file.format.write(&mut contents, &planned.playlist, &entries, &self.options)?;
+            let path = self.path.join(&file.path);
+            if let Some(dir) = path.parent() {
+                fs::create_dir_all(dir)?;
+            }
+            let changed = fs::read(&path).map_or(true, |existing| existing != contents);
+            if changed {
+                fs::write(&path, &contents)?;
+            }
+            written.push((file.path.clone(), changed));
+        }
+        Ok(written)
+    }
+}
@@ -0,0 +1,654 @@
+//! Planning a sync: which tracks are copied where, and which playlists are written where with
+//! which entries, worked out from the library and the options. A plan depends on nothing in the
+//! output, so `build_plan` makes one for any number of them, and `execute` carries it out into
+//! each.
+//!
+//! Planning needs the `sync` feature, but none of what only the binary is built with.
+
+use std::{cmp::Ordering, collections::{HashMap, HashSet}, fmt, path::{Component, Path, PathBuf}, str::FromStr, time::SystemTime};
+
+use serde::Serialize;
+
+use crate::{Library, Playlist, PlaylistKind, Track, filter::Filter, format::{self, Entry, Format, TitleFormat}, names::{Normalization, Sanitize}};
+
+pub use execute::{execute, Output, SyncReport, Target};
+pub use playlists::root_prefix;
+pub use template::PathTemplate;
+pub use tracks::{unique_destinations, Destinations};
+
+mod execute;
+mod playlists;
+mod template;
+mod tracks;
+
+/// Where podcast episodes go with `Options::podcasts`, instead of the tracks folder.
+pub const PODCASTS_DIR: &str = "podcasts";
+
+/// Whether a playlist is ignored, by the normalized names and the persistent IDs of it and the
+/// folders it is in, and whether it is a folder itself.
+pub type IsIgnored = dyn Fn(&[String], &[u64], bool) -> bool;
+
+/// What to sync and how to name it. The defaults are those of the m3trans command.
+pub struct Options {
+    /// Also sync tracks that are unchecked in iTunes.
+    pub include_disabled: bool,
+    /// Also sync DRM-protected tracks.
+    pub include_protected: bool,
+    /// Only sync tracks rated at least this, on the library's scale of 0 to 100.
+    pub min_rating: Option<u32>,
+    pub min_playcount: Option<u32>,
+    pub added_since: Option<SystemTime>,
+    /// Let tracks that lack the rating, play count or date asked for through.
+    pub include_missing: bool,
+    pub only_loved: bool,
+    pub filter: Option<Filter>,
+    /// Only sync the tracks of the playlists that are exported.
+    pub only_referenced: bool,
+    /// Where to read the tracks of network shares from instead, by host.
+    pub remap_hosts: Vec<(String, PathBuf)>,
+    /// Look up the file of every track, leaving out those that are missing or empty.
+    pub check_sources: bool,
+
+    /// Copy tracks into the output, rather than have playlists refer to them where they are.
+    pub copy_tracks: bool,
+    /// Without copying, what the paths of tracks are written relative to.
+    pub base_dir: Option<PathBuf>,
+    /// The folder tracks are copied to, relative to the output.
+    pub tracks_dir: PathBuf,
+    pub track_names: TrackNames,
+    /// Where to copy tracks to in the tracks folder instead of `track_names`.
+    pub path_template: Option<PathTemplate>,
+    /// With `TrackNames::Album`, the folder albums of various artists go in.
+    pub compilations_folder: String,
+    /// With `TrackNames::Album`, put compilations without an album artist in `compilations_folder`.
+    pub group_compilations: bool,
+    /// Copy podcast episodes to `PODCASTS_DIR` whatever playlists they are in, and write a playlist of
+    /// each show and one of the episodes added in the last `new_episodes_days`.
+    pub podcasts: bool,
+    pub new_episodes_days: u64,
+
+    /// How names written to disk and into playlists are normalized, as are those ignore rules and
+    /// renames are matched against.
+    pub normalize: Normalization,
+    /// Transliterate names written to disk and titles to ASCII.
+    pub ascii: bool,
+    pub sanitize: Sanitize,
+    /// The longest file and folder names of tracks and playlists, in bytes.
+    pub max_name_len: Option<usize>,
+
+    /// The folder playlists are written to, relative to the output, or empty for the output itself.
+    pub playlists_dir: PathBuf,
+    /// The only playlists to export, by persistent ID, if not all of them.
+    pub included: Option<HashSet<u64>>,
+    pub is_ignored: Box<IsIgnored>,
+    /// Top-level folders to sync only what is in, written as if each were the top level.
+    pub roots: Vec<String>,
+    /// Match `roots` regardless of case.
+    pub ignore_case: bool,
+    pub include_hidden: bool,
+    /// System playlists to export like regular ones.
+    pub include_system: Vec<PlaylistKind>,
+    /// The folder in the playlists folder that system playlists go in, or empty to write them
+    /// among the others.
+    pub system_playlists_dir: String,
+    pub skip_smart: bool,
+    pub only_smart: bool,
+    /// How many levels of folders to mirror as directories, the names of deeper ones going into
+    /// the file names of their playlists.
+    pub max_depth: Option<usize>,
+    /// The longest paths of playlist files and folders, in characters from the output.
+    pub max_path_len: Option<usize>,
+    pub on_long_path: OnLongPath,
+    /// Names to give playlists and folders on disk, by their normalized paths in the library.
+    pub renames: Vec<(String, String)>,
+    pub formats: Vec<Format>,
+    pub format: format::Options,
+    /// Also write a playlist of every folder, of the tracks of all the playlists in it.
+    pub folder_playlists: bool,
+    /// Leave out playlists without any synced tracks, and folders without playlists.
+    pub skip_empty: bool,
+    /// Also write the master playlist, under this name.
+    pub export_master: Option<String>,
+    /// Also write a playlist of every synced track under this name.
+    pub all_tracks_playlist: Option<String>,
+    /// Also write a Library playlist of every synced track.
+    pub library_playlist: bool,
+    /// Also write a playlist of every synced track that is loved under this name.
+    pub loved_playlist: Option<String>,
+
+    pub entry_paths: EntryPaths,
+    pub path_separator: PathSeparator,
+    pub title_format: TitleFormat,
+    /// Leave out entries for tracks that come earlier in the same playlist.
+    pub dedupe_entries: bool,
+    pub max_tracks_per_playlist: Option<usize>,
+    pub max_playlist_duration: Option<u64>,
+    pub sort_entries: EntryOrder,
+    /// Shuffle the entries of every playlist from this seed.
+    pub shuffle_seed: Option<u64>,
+    /// Fail on entries for tracks that aren't synced or aren't in the library, instead of leaving
+    /// them out.
+    pub strict: bool,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            include_disabled: false,
+            include_protected: false,
+            min_rating: None,
+            min_playcount: None,
+            added_since: None,
+            include_missing: false,
+            only_loved: false,
+            filter: None,
+            only_referenced: false,
+            remap_hosts: Vec::new(),
+            check_sources: false,
+            copy_tracks: true,
+            base_dir: None,
+            tracks_dir: PathBuf::from("tracks"),
+            track_names: TrackNames::Id,
+            path_template: None,
+            compilations_folder: "Compilations".to_owned(),
+            group_compilations: true,
+            podcasts: false,
+            new_episodes_days: 7,
+            normalize: Normalization::Nfc,
+            ascii: false,
+            sanitize: Sanitize::Default,
+            max_name_len: None,
+            playlists_dir: PathBuf::from("playlists"),
+            included: None,
+            is_ignored: Box::new(|_, _, _| false),
+            roots: Vec::new(),
+            ignore_case: false,
+            include_hidden: false,
+            include_system: Vec::new(),
+            system_playlists_dir: "_System".to_owned(),
+            skip_smart: false,
+            only_smart: false,
+            max_depth: None,
+            max_path_len: None,
+            on_long_path: OnLongPath::Shorten,
+            renames: Vec::new(),
+            formats: vec![Format::M3u],
+            format: format::Options::default(),
+            folder_playlists: false,
+            skip_empty: false,
+            export_master: None,
+            all_tracks_playlist: None,
+            library_playlist: false,
+            loved_playlist: None,
+            entry_paths: EntryPaths::Relative,
+            path_separator: PathSeparator::Slash,
+            title_format: TitleFormat::default(),
+            dedupe_entries: false,
+            max_tracks_per_playlist: None,
+            max_playlist_duration: None,
+            sort_entries: EntryOrder::default(),
+            shuffle_seed: None,
+            strict: false,
+        }
+    }
+}
+
+impl Options {
+    /// Why `track` is left out of the sync whatever playlists it is in, if it is.
+    pub fn exclusion(&self, track: &Track) -> Option<Exclusion> {
+        if track.disabled && !self.include_disabled {
+            Some(Exclusion::Disabled)
+        }
+        else if track.is_protected() && !self.include_protected {
+            Some(Exclusion::Protected)
+        }
+        else if !self.selects(track) {
+            Some(Exclusion::Filtered)
+        }
+        else {
+            None
+        }
+    }
+
+    /// Whether `track` passes the rating, play count, date and loved filters, and `filter`.
+    fn selects(&self, track: &Track) -> bool {
+        fn at_least<T: PartialOrd>(value: Option<T>, min: Option<T>, include_missing: bool) -> bool {
+            match (value, min) {
+                (_, None) => true,
+                (Some(value), Some(min)) => value >= min,
+                (None, Some(_)) => include_missing,
+            }
+        }
+
+        at_least(track.rating, self.min_rating, self.include_missing)
+            && at_least(track.play_count, self.min_playcount, self.include_missing)
+            && at_least(track.date_added, self.added_since, self.include_missing)
+            && (track.loved || !self.only_loved)
+            && self.filter.as_ref().is_none_or(|filter| filter.matches(track))
+    }
+}
+
+/// Why a track that has a location is left out of the sync.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Exclusion {
+    Filtered,
+    Disabled,
+    Protected,
+}
+
+/// The fields playlist entries are sorted by, in order of precedence.
+#[derive(Clone, Debug, Default)]
+pub struct EntryOrder(pub Vec<SortKey>);
+
+#[derive(Clone, Copy, Debug)]
+pub enum SortKey {
+    Name,
+    Artist,
+    AlbumArtist,
+    Album,
+    Genre,
+    Disc,
+    Track,
+    Rating,
+    PlayCount,
+    DateAdded,
+}
+
+impl FromStr for EntryOrder {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s == "none" {
+            return Ok(EntryOrder(Vec::new()));
+        }
+        s.split(',')
+            .map(|key| match key.trim() {
+                "name" => Ok(SortKey::Name),
+                "artist" => Ok(SortKey::Artist),
+                "album_artist" => Ok(SortKey::AlbumArtist),
+                "album" => Ok(SortKey::Album),
+                "genre" => Ok(SortKey::Genre),
+                "disc" => Ok(SortKey::Disc),
+                "track" => Ok(SortKey::Track),
+                "rating" => Ok(SortKey::Rating),
+                "play_count" => Ok(SortKey::PlayCount),
+                "date_added" => Ok(SortKey::DateAdded),
+                other => Err(format!("unknown sort field \"{}\"", other)),
+            })
+            .collect::<Result<_, _>>()
+            .map(EntryOrder)
+    }
+}
+
+impl EntryOrder {
+    /// Compares two tracks by each field in turn, ignoring case. Tracks missing a field sort after
+    /// the ones that have it.
+    pub fn compare(&self, a: &Track, b: &Track) -> Ordering {
+        fn text(value: &Option<String>) -> Option<String> {
+            value.as_deref().map(str::trim).filter(|value| !value.is_empty()).map(str::to_lowercase)
+        }
+        self.0.iter().fold(Ordering::Equal, |ordering, key| ordering.then_with(|| match key {
+            SortKey::Name => {
+                let name = |track: &Track| Some(track.name.trim().to_lowercase()).filter(|name| !name.is_empty());
+                missing_last(name(a), name(b))
+            }
+            SortKey::Artist => missing_last(text(&a.artist), text(&b.artist)),
+            SortKey::AlbumArtist => missing_last(text(&a.album_artist), text(&b.album_artist)),
+            SortKey::Album => missing_last(text(&a.album), text(&b.album)),
+            SortKey::Genre => missing_last(text(&a.genre), text(&b.genre)),
+            SortKey::Disc => missing_last(a.disc_number, b.disc_number),
+            SortKey::Track => missing_last(a.track_number, b.track_number),
+            SortKey::Rating => missing_last(a.rating, b.rating),
+            SortKey::PlayCount => missing_last(a.play_count, b.play_count),
+            SortKey::DateAdded => missing_last(a.date_added, b.date_added),
+        }))
+    }
+}
+
+/// Orders present values before missing ones.
+fn missing_last<T: Ord>(a: Option<T>, b: Option<T>) -> Ordering {
+    match (a, b) {
+        (Some(a), Some(b)) => a.cmp(&b),
+        (a, b) => b.is_some().cmp(&a.is_some()),
+    }
+}
+
+/// What to do about playlists whose paths would be longer than `Options::max_path_len`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OnLongPath {
+    Shorten,
+    Skip,
+    Error,
+}
+
+impl FromStr for OnLongPath {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "shorten" => Ok(OnLongPath::Shorten),
+            "skip" => Ok(OnLongPath::Skip),
+            "error" => Ok(OnLongPath::Error),
+            other => Err(format!("unknown way of handling long paths \"{}\"", other)),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EntryPaths {
+    Relative,
+    Absolute,
+}
+
+impl FromStr for EntryPaths {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "relative" => Ok(EntryPaths::Relative),
+            "absolute" => Ok(EntryPaths::Absolute),
+            other => Err(format!("unknown entry path style \"{}\"", other)),
+        }
+    }
+}
+
+/// What copied tracks are named in the tracks folder.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TrackNames {
+    /// Their track ID.
+    Id,
+    /// Their persistent ID, which unlike the track ID stays the same when the library is rebuilt.
+    PersistentId,
+    /// The name of their file in the library.
+    Original,
+    /// The name of their file, in folders for their album artist and album.
+    Album,
+}
+
+impl FromStr for TrackNames {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "id" => Ok(TrackNames::Id),
+            "persistent-id" => Ok(TrackNames::PersistentId),
+            "original" => Ok(TrackNames::Original),
+            "album" => Ok(TrackNames::Album),
+            other => Err(format!("unknown track naming \"{}\"", other)),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PathSeparator {
+    Slash,
+    Backslash,
+}
+
+impl PathSeparator {
+    pub fn as_char(self) -> char {
+        match self {
+            PathSeparator::Slash => '/',
+            PathSeparator::Backslash => '\\',
+        }
+    }
+
+    /// Renders `path` with this separator between components, regardless of the platform's own.
+    pub fn render(self, path: &Path) -> String {
+        let mut rendered = String::new();
+        for component in path.components() {
+            match component {
+                // Network shares are written like `\\server\share`, which takes the separator too.
+                Component::Prefix(prefix) => rendered.push_str(&prefix.as_os_str().to_string_lossy().replace('\\', &self.as_char().to_string())),
+                Component::RootDir => rendered.push(self.as_char()),
+                other => {
+                    if !rendered.is_empty() && !rendered.ends_with(self.as_char()) {
+                        rendered.push(self.as_char());
+                    }
+                    rendered.push_str(&other.as_os_str().to_string_lossy());
+                }
+            }
+        }
+        rendered
+    }
+}
+
+impl FromStr for PathSeparator {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "slash" => Ok(PathSeparator::Slash),
+            "backslash" => Ok(PathSeparator::Backslash),
+            other => Err(format!("unknown path separator \"{}\"", other)),
+        }
+    }
+}
+
+/// What a sync does, in the order it does it. Paths in the output are relative to it.
+#[derive(Debug, Serialize)]
+pub struct SyncPlan {
+    /// Whether tracks are copied, or playlists refer to them where they are.
+    pub copy_tracks: bool,
+    pub tracks_dir: PathBuf,
+    pub playlists_dir: PathBuf,
+    /// The tracks to copy, in order of their IDs.
+    pub tracks: Vec<TrackCopy>,
+    /// Without copying, where playlists find each track.
+    pub in_place: Vec<InPlaceTrack>,
+    /// The tracks left out of the sync, in order of their IDs.
+    pub skipped: Vec<SkippedTrack>,
+    pub playlists: Vec<PlaylistStep>,
+}
+
+/// A track to copy into the output.
+#[derive(Clone, Debug, Serialize)]
+pub struct TrackCopy {
+    pub track_id: u64,
+    pub source: PathBuf,
+    pub destination: PathBuf,
+    /// Tracks that were added to the library by hand may lack a size, as may files that are gone.
+    pub size_bytes: Option<u64>,
+    /// The track with the lowest ID of those whose file this track's is too, as with the parts of
+    /// one file that iTunes makes with start and stop times. Only that one is copied, and the rest
+    /// share its copy.
+    pub shares_with: Option<u64>,
+}
+
+/// A track that playlists refer to where it is.
+#[derive(Clone, Debug, Serialize)]
+pub struct InPlaceTrack {
+    pub track_id: u64,
+    pub path: PathBuf,
+}
+
+/// A track left out of the sync, and why: unselected, filtered, disabled, protected,
+/// missing_source, empty_source, unknown_location or invalid_location.
+#[derive(Clone, Debug, Serialize)]
+pub struct SkippedTrack {
+    pub track_id: u64,
+    pub reason: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub source: Option<PathBuf>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// A step of writing the playlists, in the order they are taken.
+#[derive(Debug, Serialize)]
+#[serde(tag = "step", rename_all = "snake_case")]
+pub enum PlaylistStep {
+    /// Create the folder of a playlist folder.
+    CreateFolder(PlannedFolder),
+    Write(Box<PlannedPlaylist>),
+    /// Leave out a playlist or folder, for a reason of not_included, hidden, ignored,
+    /// smart_filtered, path_too_long or unknown_kind.
+    Skip { playlist_id: u64, reason: &'static str },
+}
+
+/// The folder of a playlist folder of the library.
+#[derive(Clone, Debug, Serialize)]
+pub struct PlannedFolder {
+    pub playlist_id: u64,
+    pub path: PathBuf,
+}
+
+/// A playlist to write, in each of the formats.
+#[derive(Debug, Serialize)]
+pub struct PlannedPlaylist {
+    /// The playlist as it is written, which for those made up by the sync isn't in the library.
+    pub playlist: Playlist,
+    /// Its path in the library, of the names of it and its folders.
+    pub virtual_path: String,
+    pub origin: PlaylistOrigin,
+    /// Its path without an extension, which each format adds its own to.
+    pub stem: PathBuf,
+    pub files: Vec<PlaylistFile>,
+    /// The tracks it is made of, which are those of all its playlists for a folder's.
+    pub items: Vec<u64>,
+    /// Its entries, with the IDs of their tracks.
+    pub entries: Vec<(u64, Entry)>,
+    /// How many entries were left out for tracks that aren't synced or aren't in the library.
+    pub dropped: usize,
+    /// Leave it out if none of `items` end up synced.
+    pub skip_if_empty: bool,
+    /// Leave the entries of tracks that fail to sync out without counting them as dropped, as
+    /// for playlists made of the synced tracks.
+    pub only_synced: bool,
+    /// The folders it is in that are only created once a playlist is written into them.
+    pub folders: Vec<PlannedFolder>,
+    /// The folder it is in that isn't one of the library's, like that of system playlists, to
+    /// create first.
+    pub dir: Option<PathBuf>,
+}
+
+/// Where a playlist comes from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PlaylistOrigin {
+    Library,
+    /// The combined playlist of a folder.
+    Folder,
+    Master,
+    /// A playlist of all or the loved synced tracks.
+    Generated,
+    /// A playlist of a podcast show, or of the new episodes of all of them.
+    Podcast,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct PlaylistFile {
+    pub format: Format,
+    pub path: PathBuf,
+}
+
+/// Why a sync can't be planned.
+#[derive(Debug)]
+pub enum PlanError {
+    /// One of `Options::roots` isn't a top-level folder of the library, whose top-level folders
+    /// are listed.
+    UnknownRoot { root: String, top_folders: Vec<String> },
+    /// With `OnLongPath::Error`, a playlist would be written at a path longer than
+    /// `Options::max_path_len`.
+    PathTooLong { playlist: String, virtual_path: String, path: PathBuf, len: usize },
+    /// With `Options::strict`, a playlist has an entry for a track that isn't synced, or isn't in
+    /// the library and has no name.
+    DanglingEntry { playlist: String, track_id: u64, track: Option<String> },
+}
+
+impl fmt::Display for PlanError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PlanError::UnknownRoot { root, top_folders } if top_folders.is_empty() =>
+                write!(f, "root {:?} isn't a folder of the library, which has none at the top level", root),
+            PlanError::UnknownRoot { root, top_folders } => {
+                let top_folders: Vec<String> = top_folders.iter().map(|name| format!("{:?}", name)).collect();
+                write!(f, "root {:?} isn't a top-level folder of the library; those are {}", root, top_folders.join(", "))
+            }
+            PlanError::PathTooLong { playlist, virtual_path, path, len } => write!(
+                f, "playlist {} with path {:?} would be written at {:?}, {} characters long, more than the longest allowed",
+                playlist, virtual_path, path, len
+            ),
+            PlanError::DanglingEntry { playlist, track_id, track: Some(track) } =>
+                write!(f, "playlist {} references track {} ({}), which isn't in the output", playlist, track_id, track),
+            PlanError::DanglingEntry { playlist, track_id, track: None } =>
+                write!(f, "playlist {} references track {}, which isn't in the library", playlist, track_id),
+        }
+    }
+}
+
+impl std::error::Error for PlanError {}
+
+/// Plans a sync of `library`, logging what is left out of it and why.
+pub fn build_plan(library: &Library, options: &Options) -> Result<SyncPlan, PlanError> {
+    let included = match options.roots.is_empty() {
+        true => options.included.clone(),
+        false => {
+            let rooted = playlists::rooted_playlists(library, options)?;
+            Some(match &options.included {
+                Some(included) => included.intersection(&rooted).copied().collect(),
+                None => rooted,
+            })
+        }
+    };
+    let selected = match options.only_referenced {
+        true => {
+            let mut selected = playlists::referenced_tracks(library, included.as_ref(), options);
+            // Episodes are wanted for podcasts whatever playlists they are in.
+            if options.podcasts {
+                selected.extend(library.tracks.iter().filter(|(_, track)| track.podcast).map(|(id, _)| *id));
+            }
+            Some(selected)
+        }
+        false => None,
+    };
+
+    let (tracks, in_place, skipped) = match options.copy_tracks {
+        true => {
+            let (tracks, skipped) = tracks::plan_tracks(library, options, selected.as_ref(), included.is_some());
+            (tracks, Vec::new(), skipped)
+        }
+        false => (Vec::new(), tracks::in_place_tracks(library, options, selected.as_ref()), Vec::new()),
+    };
+    let paths: HashMap<u64, PathBuf> = match options.copy_tracks {
+        true => tracks.iter().map(|copy| (copy.track_id, copy.destination.clone())).collect(),
+        false => in_place.iter().map(|track| (track.track_id, track.path.clone())).collect(),
+    };
+    let playlists = playlists::plan_playlists(library, options, included.as_ref(), &paths)?;
+    Ok(SyncPlan {
+        copy_tracks: options.copy_tracks,
+        tracks_dir: options.tracks_dir.clone(),
+        playlists_dir: options.playlists_dir.clone(),
+        tracks,
+        in_place,
+        skipped,
+        playlists,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn paths_are_rendered_with_either_separator() {
+        let relative = Path::new("..").join("..").join("tracks").join("Song One.mp3");
+        assert_eq!(PathSeparator::Slash.render(&relative), "../../tracks/Song One.mp3");
+        assert_eq!(PathSeparator::Backslash.render(&relative), r"..\..\tracks\Song One.mp3");
+        let absolute = Path::new("/tracks/1.mp3");
+        assert_eq!(PathSeparator::Slash.render(absolute), "/tracks/1.mp3");
+        assert_eq!(PathSeparator::Backslash.render(absolute), r"\tracks\1.mp3");
+        assert_eq!(PathSeparator::Slash.render(Path::new("")), "");
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn drives_and_shares_are_rendered_with_either_separator() {
+        let drive = Path::new(r"C:\Users\Me\Music\One.mp3");
+        assert_eq!(PathSeparator::Slash.render(drive), "C:/Users/Me/Music/One.mp3");
+        assert_eq!(PathSeparator::Backslash.render(drive), r"C:\Users\Me\Music\One.mp3");
+        let share = Path::new(r"\\server\share\Music\One.mp3");
+        assert_eq!(PathSeparator::Slash.render(share), "//server/share/Music/One.mp3");
+        assert_eq!(PathSeparator::Backslash.render(share), r"\\server\share\Music\One.mp3");
+    }
+}
@@ -0,0 +1,727 @@
+//! Laying out the playlists of the library, and those made up from it, in the output, and working
+//! out their entries.
+
+use std::{cmp::Ordering, collections::{BTreeMap, HashMap, HashSet}, ffi::OsString, ops::ControlFlow, path::{Component, Path, PathBuf}, time::{Duration, SystemTime, UNIX_EPOCH}};
+use log::*;
+
+use crate::{Library, MissingTrack, Playlist, PlaylistEvent, PlaylistKind, Track, format::{Entry, TrackRef}, names};
+use super::{EntryPaths, OnLongPath, Options, PlanError, PlannedFolder, PlannedPlaylist, PlaylistFile, PlaylistOrigin, PlaylistStep, missing_last, tracks::show_name};
+
+/// Returns `stem` itself if it is not taken, otherwise the first untaken `<stem> (N)` for N >= 2,
+/// with the name cut short so that it is at most `max_len` bytes if given.
+fn first_free_stem(stem: &Path, max_len: Option<usize>, mut is_taken: impl FnMut(&Path) -> bool) -> PathBuf {
+    if !is_taken(stem) { return stem.to_owned(); }
+    let name = stem.file_name().unwrap_or_default().to_string_lossy();
+    (2..).map(|n| stem.with_file_name(names::with_suffix(&name, &format!(" ({})", n), max_len)))
+        .find(|candidate| !is_taken(candidate))
+        .unwrap()
+}
+
+/// The shortest `Options::max_path_len` shortens names to, most of it the end of the persistent ID.
+const MIN_SHORTENED_LEN: usize = 12;
+
+/// How many levels of folders and playlists each folder of the library has below it.
+fn folder_heights(library: &Library) -> HashMap<u64, usize> {
+    let mut heights = HashMap::new();
+    let mut open: Vec<(u64, usize)> = Vec::new();
+    library.walk_playlists(|event| match event {
+        PlaylistEvent::EnterFolder(folder) => open.push((folder.persistent_id, 0)),
+        PlaylistEvent::Playlist(_) => {
+            if let Some((_, height)) = open.last_mut() {
+                *height = (*height).max(1);
+            }
+        }
+        PlaylistEvent::LeaveFolder(_) => {
+            if let Some((id, height)) = open.pop() {
+                heights.insert(id, height);
+                if let Some((_, parent_height)) = open.last_mut() {
+                    *parent_height = (*parent_height).max(height + 1);
+                }
+            }
+        }
+    });
+    heights
+}
+
+/// Encodes the folder hierarchy of a playlist into a single file name, e.g. `Rock - 90s`.
+fn flat_file_name(relative_path: &Path) -> OsString {
+    let mut name = OsString::new();
+    for (i, component) in relative_path.iter().enumerate() {
+        if i > 0 { name.push(" - "); }
+        name.push(component);
+    }
+    name
+}
+
+/// Lays out the playlists `included`, or all of them if it is `None`, with entries for the tracks
+/// at `paths`, in the order they are to be written.
+pub(super) fn plan_playlists(
+    library: &Library,
+    options: &Options,
+    included: Option<&HashSet<u64>>,
+    paths: &HashMap<u64, PathBuf>,
+) -> Result<Vec<PlaylistStep>, PlanError> {
+    let planner = Planner { library, options, paths };
+    let playlists_dir = options.playlists_dir.as_path();
+    // Names are limited so that they still fit once the longest extension is appended.
+    let longest_extension = options.formats.iter()
+        .map(|format| format.extension(&options.format))
+        .max_by_key(|extension| extension.len())
+        .unwrap_or_default();
+    let extension_len = longest_extension.len() + 1;
+    let max_stem_len = options.max_name_len.map(|max_len| max_len.saturating_sub(extension_len));
+    let limit_name = |name: &str, id: u64| match max_stem_len {
+        Some(max_len) => names::truncate(name, max_len, &format!("{:06X}", id & 0xFF_FFFF)),
+        None => name.to_owned(),
+    };
+    let disk_name = |name: &str, id: u64| {
+        let mut normalized = options.normalize.apply(name);
+        if options.ascii {
+            let ascii = names::to_ascii(&normalized);
+            if ascii != normalized {
+                info!("Using ASCII name {:?} for playlist {}.", ascii, name);
+            }
+            normalized = ascii;
+        }
+        limit_name(&options.sanitize.apply(&normalized), id)
+    };
+    let dir_depth = options.max_depth.unwrap_or(usize::MAX);
+    let on_long_path = options.on_long_path;
+    let path_len = |path: &Path| path.to_string_lossy().chars().count();
+    let heights = match (options.max_path_len, on_long_path) {
+        (Some(_), OnLongPath::Shorten) => folder_heights(library),
+        _ => HashMap::new(),
+    };
+    // Shortens `name`, of a playlist or folder in `dir` with `levels` of folders and files below
+    // it, so that they still fit when each gets a shortened name too.
+    let fit_name = |dir: &Path, name: String, id: u64, levels: usize| match options.max_path_len {
+        Some(max_len) if on_long_path == OnLongPath::Shorten => {
+            let room = max_len.saturating_sub(path_len(dir) + 1 + levels * (MIN_SHORTENED_LEN + 1) + extension_len);
+            match name.chars().count() > room {
+                true => names::truncate(&name, room.max(MIN_SHORTENED_LEN), &format!("{:06X}", id & 0xFF_FFFF)),
+                false => name,
+            }
+        }
+        _ => name,
+    };
+    let skip = |playlist: &Playlist, reason| PlaylistStep::Skip { playlist_id: playlist.persistent_id, reason };
+
+    let mut steps = Vec::new();
+    // The paths of the ancestors of the current playlist.
+    let mut parent_dirs: Vec<PathBuf> = Vec::new();
+    // The current playlist and its ancestors, starting from the root.
+    let mut path: Vec<&Playlist> = Vec::new();
+    // The folders that are only created once a playlist is written into them.
+    let mut pending_folders: Vec<PlannedFolder> = Vec::new();
+    let mut open_folders: Vec<OpenFolder<'_>> = Vec::new();
+    let mut stems = HashSet::new();
+    // Distinguished Kind values, one for each playlist skipped for having one that isn't known.
+    let mut unknown_kinds = Vec::new();
+    let system_dir = playlists_dir.join(options.sanitize.apply(&options.normalize.apply(&options.system_playlists_dir)));
+    // The paths of every playlist, and which renames were used, to point out those that weren't.
+    let mut virtual_paths = Vec::new();
+    let mut used_renames = vec![false; options.renames.len()];
+    library.try_walk_playlists(|event| {
+        let (playlist, entered) = match event {
+            PlaylistEvent::EnterFolder(folder) => (folder, true),
+            PlaylistEvent::Playlist(playlist) => (playlist, false),
+            PlaylistEvent::LeaveFolder(folder) => {
+                path.pop();
+                parent_dirs.pop();
+                pending_folders.retain(|pending| pending.playlist_id != folder.persistent_id);
+                if open_folders.last().is_some_and(|open| open.playlist.persistent_id == folder.persistent_id) {
+                    steps.push(PlaylistStep::Write(Box::new(planner.close_folder(open_folders.pop().unwrap())?)));
+                }
+                return Ok(ControlFlow::Continue(()));
+            }
+        };
+        path.push(playlist);
+        // The folders given as roots stand in for the top level, and aren't written themselves.
+        if path.len() == 1 && root_of(&path, options).is_some() {
+            parent_dirs.push(playlists_dir.to_owned());
+            return Ok(ControlFlow::Continue(()));
+        }
+        let depth = (path.len() - 1).saturating_sub(usize::from(!options.roots.is_empty()));
+        let id = playlist.persistent_id;
+        // Ignore patterns are matched against the original names (normalized like the patterns
+        // themselves), not the sanitized ones.
+        let names: Vec<String> = path.iter().map(|playlist| options.normalize.apply(&playlist.name)).collect();
+        let ids: Vec<u64> = path.iter().map(|playlist| playlist.persistent_id).collect();
+        let virtual_path = names.join("/");
+        virtual_paths.push(virtual_path.clone());
+        let rename = options.renames.iter().position(|(renamed, _)| *renamed == virtual_path);
+        let name = match rename {
+            Some(i) => {
+                used_renames[i] = true;
+                &options.renames[i].1
+            }
+            None => &playlist.name,
+        };
+        // System playlists go in a folder of their own, laid out below it as they would be otherwise.
+        let is_system = !options.system_playlists_dir.is_empty()
+            && playlist.kind.is_system()
+            && options.include_system.contains(&playlist.kind);
+        let parent_dir = parent_dirs.last().map_or(playlists_dir, PathBuf::as_path);
+        let parent_dir = match is_system {
+            true => system_dir.join(parent_dir.strip_prefix(playlists_dir).unwrap()),
+            false => parent_dir.to_owned(),
+        };
+        // Folders below `dir_depth` aren't mirrored as directories; their names become part of
+        // the file names of the playlists inside them instead.
+        let is_directory = depth < dir_depth;
+        let collapsed = depth + usize::from(is_system) > dir_depth;
+        let full_name = disk_name(name, id);
+        // Collapsed names are shortened once joined.
+        let file_name = match collapsed {
+            true => full_name.clone(),
+            false => {
+                let levels = match is_directory {
+                    true => heights.get(&id).map_or(0, |&height| height.min(dir_depth - depth)),
+                    false => 0,
+                };
+                fit_name(&parent_dir, full_name.clone(), id, levels)
+            }
+        };
+        if file_name != full_name {
+            info!("Shortened the name of playlist {} with path {:?} to {:?} to fit --max-path-len.", playlist.name, virtual_path, file_name);
+        }
+        let mut current_path = parent_dir.join(file_name);
+        let not_included = included.is_some_and(|included| !included.contains(&id));
+        let is_folder = playlist.kind.is_folder();
+        let as_playlist = playlist.kind.is_generic() || options.include_system.contains(&playlist.kind);
+        // The master playlist is hidden too, but is never exported anyway.
+        let hidden = (as_playlist || is_folder) && is_hidden(&path, options);
+        let ignored = not_included || hidden || (options.is_ignored)(&names, &ids, is_folder);
+        let smart_filtered = as_playlist && match playlist.is_smart {
+            true => options.skip_smart,
+            false => options.only_smart,
+        };
+        let exported = !ignored && !smart_filtered && (as_playlist || is_folder);
+        let stem = match collapsed {
+            false => current_path.clone(),
+            true => {
+                let relative_path = current_path.strip_prefix(playlists_dir).unwrap();
+                let dir = playlists_dir.join(relative_path.iter().take(dir_depth).collect::<PathBuf>());
+                let collapsed: PathBuf = relative_path.iter().skip(dir_depth).collect();
+                let full_name = limit_name(&flat_file_name(&collapsed).to_string_lossy(), id);
+                let file_name = fit_name(&dir, full_name.clone(), id, 0);
+                if file_name != full_name {
+                    info!("Shortened the name of playlist {} with path {:?} to {:?} to fit --max-path-len.", playlist.name, virtual_path, file_name);
+                }
+                dir.join(file_name)
+            }
+        };
+        let stem = match exported {
+            true => {
+                let unique = first_free_stem(&stem, max_stem_len, |path| stems.contains(path));
+                if unique != stem {
+                    info!("Playlist {} with path {:?} collides with an earlier one, using {:?}.", playlist.name, virtual_path, unique);
+                    if is_directory { current_path = unique.clone(); }
+                }
+                stems.insert(unique.clone());
+                unique
+            }
+            false => stem,
+        };
+        // The longest path written for it: its file, or a folder's directory.
+        let longest_path = match is_folder && !options.folder_playlists {
+            false => Some(playlist_path(&stem, longest_extension)),
+            true if is_directory => Some(current_path.clone()),
+            true => None,
+        };
+        let too_long = longest_path.filter(|_| exported)
+            .map(|path| {
+                let len = path_len(&path);
+                (path, len)
+            })
+            .filter(|&(_, len)| options.max_path_len.is_some_and(|max_len| len > max_len));
+        if not_included {
+            info!("Not including playlist {} with path {:?}.", playlist.name, virtual_path);
+            steps.push(skip(playlist, "not_included"));
+        }
+        else if hidden {
+            info!("Skipping hidden playlist {} with path {:?}.", playlist.name, virtual_path);
+            steps.push(skip(playlist, "hidden"));
+        }
+        else if ignored {
+            info!("Ignoring playlist {} with path {:?}.", playlist.name, virtual_path);
+            steps.push(skip(playlist, "ignored"));
+            // Its children may be re-included, in which case the folder is still needed to hold them.
+            if is_folder && is_directory {
+                pending_folders.push(PlannedFolder { playlist_id: id, path: current_path.clone() });
+            }
+        }
+        else if smart_filtered {
+            let smart = if playlist.is_smart { "smart" } else { "non-smart" };
+            info!("Skipping {} playlist {} with path {:?}.", smart, playlist.name, virtual_path);
+            steps.push(skip(playlist, "smart_filtered"));
+        }
+        else if let Some((long_path, len)) = too_long {
+            if on_long_path == OnLongPath::Error {
+                return Err(PlanError::PathTooLong { playlist: playlist.name.clone(), virtual_path, path: long_path, len });
+            }
+            warn!(
+                event = "playlist_skipped", reason = "path_too_long", path:% = long_path.display(), length = len;
+                "Skipping playlist {} with path {:?}, as it would be written at {:?}, {} characters long, more than --max-path-len.",
+                playlist.name, virtual_path, long_path, len
+            );
+            steps.push(skip(playlist, "path_too_long"));
+        }
+        else if is_folder {
+            if options.folder_playlists {
+                open_folders.push(OpenFolder::new(playlist, virtual_path, stem));
+            }
+
+            // Folders inside a folder that is still pending have to wait for it.
+            let deferred = options.skip_empty || !pending_folders.is_empty();
+            let folder = PlannedFolder { playlist_id: id, path: current_path.clone() };
+            match (!is_directory, deferred) {
+                (true, _) => {},
+                (false, true) => pending_folders.push(folder),
+                (false, false) => steps.push(PlaylistStep::CreateFolder(folder)),
+            }
+        }
+        else if as_playlist {
+            for folder in &mut open_folders {
+                folder.extend(&playlist.items);
+            }
+
+            let mut planned = planner.playlist(playlist.clone(), virtual_path, PlaylistOrigin::Library, playlist.items.clone(), stem, options.skip_empty)?;
+            // The folders are made by the first playlist written into them, which may not be this
+            // one if it turns out to be empty.
+            planned.folders = match options.skip_empty {
+                true => pending_folders.clone(),
+                false => std::mem::take(&mut pending_folders),
+            };
+            planned.dir = planned.stem.parent().filter(|_| is_system).map(Path::to_path_buf);
+            steps.push(PlaylistStep::Write(Box::new(planned)));
+        }
+        else if let PlaylistKind::Unknown(kind) = playlist.kind {
+            steps.push(skip(playlist, "unknown_kind"));
+            unknown_kinds.push(kind);
+        }
+
+        match entered {
+            true => parent_dirs.push(current_path),
+            false => { path.pop(); }
+        }
+        Ok::<_, PlanError>(ControlFlow::Continue(()))
+    })?;
+    let unused = options.renames.iter().zip(used_renames).filter(|(_, used)| !used);
+    for ((renamed, _), _) in unused {
+        let last = |path: &str| path.rsplit('/').next().unwrap_or_default().to_lowercase();
+        let near: Vec<String> = virtual_paths.iter()
+            .filter(|path| path.to_lowercase() == renamed.to_lowercase() || last(path) == last(renamed))
+            .take(3)
+            .map(|path| format!("{:?}", path))
+            .collect();
+        match near.is_empty() {
+            true => warn!("The rename file names playlist {:?}, which isn't in the library.", renamed),
+            false => warn!(
+                "The rename file names playlist {:?}, which isn't in the library; did you mean {}?",
+                renamed, near.join(" or ")
+            ),
+        }
+    }
+    if !unknown_kinds.is_empty() {
+        let count = unknown_kinds.len();
+        unknown_kinds.sort_unstable();
+        unknown_kinds.dedup();
+        let kinds: Vec<String> = unknown_kinds.iter().map(u64::to_string).collect();
+        info!(
+            "{} playlists with unrecognized Distinguished Kind values were skipped (kinds: {}).",
+            count, kinds.join(", ")
+        );
+    }
+    while let Some(folder) = open_folders.pop() {
+        steps.push(PlaylistStep::Write(Box::new(planner.close_folder(folder)?)));
+    }
+
+    let master = library.playlists.values().find(|playlist| playlist.kind == PlaylistKind::Master);
+    match (&options.export_master, master) {
+        (Some(name), Some(master)) => {
+            let stem = first_free_stem(&playlists_dir.join(disk_name(name, master.persistent_id)), max_stem_len, |path| stems.contains(path));
+            stems.insert(stem.clone());
+            let playlist = Playlist { name: name.clone(), ..master.clone() };
+            let planned = planner.playlist(playlist, name.clone(), PlaylistOrigin::Master, master.items.clone(), stem, false)?;
+            info!(
+                "Exported the master playlist as {} with {} of its {} entries.",
+                name, planned.entries.len(), master.items.len()
+            );
+            steps.push(PlaylistStep::Write(Box::new(planned)));
+        }
+        (Some(_), None) => warn!("Not exporting the master playlist, as the library doesn't have one."),
+        (None, _) => {}
+    }
+
+    // Generated playlists, by name and whether they only hold loved tracks.
+    let generated = options.all_tracks_playlist.as_deref()
+        .map(|name| (name, false))
+        .into_iter()
+        .chain(options.library_playlist.then_some(("Library", false)))
+        .chain(options.loved_playlist.as_deref().map(|name| (name, true)));
+    for (name, loved) in generated {
+        let mut items: Vec<u64> = paths.keys()
+            .filter(|id| !loved || library.tracks[id].loved)
+            .copied()
+            .collect();
+        if items.is_empty() {
+            match loved {
+                true => info!("Not generating playlist {} of loved tracks, as none were copied.", name),
+                false => info!("Not generating playlist {} of all tracks, as no tracks were copied.", name),
+            }
+            continue;
+        }
+
+        items.sort_by(|a, b| library_order(&library.tracks[a], &library.tracks[b]).then(a.cmp(b)));
+        let stem = first_free_stem(&playlists_dir.join(disk_name(name, 0)), max_stem_len, |path| stems.contains(path));
+        stems.insert(stem.clone());
+        let playlist = made_up_playlist(name, items);
+        let items = playlist.items.clone();
+        steps.push(PlaylistStep::Write(Box::new(planner.playlist(playlist, name.to_owned(), PlaylistOrigin::Generated, items, stem, true)?)));
+    }
+
+    if options.podcasts {
+        let mut shows: BTreeMap<&str, Vec<u64>> = BTreeMap::new();
+        for id in paths.keys().filter(|id| library.tracks[id].podcast) {
+            shows.entry(show_name(&library.tracks[id])).or_default().push(*id);
+        }
+        let released = |id: &u64| {
+            let track = &library.tracks[id];
+            (track.release_date.or(track.date_added), *id)
+        };
+        let new_since = SystemTime::now()
+            .checked_sub(Duration::from_secs(options.new_episodes_days.saturating_mul(86_400)))
+            .unwrap_or(UNIX_EPOCH);
+        let mut new_episodes: Vec<u64> = shows.values().flatten()
+            .filter(|id| library.tracks[id].date_added.is_some_and(|added| added >= new_since))
+            .copied()
+            .collect();
+        new_episodes.sort_by_key(released);
+        let podcasts: Vec<(&str, Vec<u64>)> = shows.into_iter()
+            .map(|(show, mut episodes)| {
+                episodes.sort_by_key(released);
+                (show, episodes)
+            })
+            .chain(Some(("New Episodes", new_episodes)).filter(|(_, episodes)| !episodes.is_empty()))
+            .collect();
+        let folder = disk_name("Podcasts", 0);
+        for (name, items) in podcasts {
+            let stem = match dir_depth {
+                0 => playlists_dir.join(limit_name(&format!("{} - {}", folder, disk_name(name, 0)), 0)),
+                _ => playlists_dir.join(&folder).join(disk_name(name, 0)),
+            };
+            let stem = first_free_stem(&stem, max_stem_len, |path| stems.contains(path));
+            stems.insert(stem.clone());
+            let playlist = made_up_playlist(name, items);
+            let items = playlist.items.clone();
+            let mut planned = planner.playlist(playlist, name.to_owned(), PlaylistOrigin::Podcast, items, stem, true)?;
+            planned.dir = planned.stem.parent().filter(|dir| *dir != playlists_dir).map(Path::to_path_buf);
+            steps.push(PlaylistStep::Write(Box::new(planned)));
+        }
+    }
+    Ok(steps)
+}
+
+/// A playlist that isn't in the library, of the tracks `items`.
+fn made_up_playlist(name: &str, items: Vec<u64>) -> Playlist {
+    Playlist {
+        persistent_id: 0,
+        parent_id: None,
+        name: name.to_owned(),
+        kind: PlaylistKind::Generic,
+        is_smart: false,
+        is_visible: true,
+        items,
+        order_key: usize::MAX,
+    }
+}
+
+/// What working out the entries of each playlist takes.
+struct Planner<'a> {
+    library: &'a Library,
+    options: &'a Options,
+    /// Where each track to sync is, relative to the output if it is copied.
+    paths: &'a HashMap<u64, PathBuf>,
+}
+
+impl Planner<'_> {
+    /// Plans writing `items` as `playlist` in every format, at `stem` plus the format's extension.
+    /// Its entries are only worked out if it has any, which with `skip_if_empty` are needed for it
+    /// to be written at all.
+    fn playlist(&self, playlist: Playlist, virtual_path: String, origin: PlaylistOrigin, items: Vec<u64>, stem: PathBuf, skip_if_empty: bool) -> Result<PlannedPlaylist, PlanError> {
+        let options = self.options;
+        let root_prefix = match options.entry_paths {
+            EntryPaths::Relative => root_prefix(&stem),
+            EntryPaths::Absolute => PathBuf::from(Component::RootDir.as_os_str()),
+        };
+        let (entries, dropped) = match skip_if_empty && !items.iter().any(|id| self.paths.contains_key(id)) {
+            true => (Vec::new(), 0),
+            false => self.entries(&playlist, &root_prefix, &items)?,
+        };
+        let files = options.formats.iter()
+            .map(|&format| PlaylistFile { format, path: playlist_path(&stem, format.extension(&options.format)) })
+            .collect();
+        Ok(PlannedPlaylist {
+            playlist,
+            virtual_path,
+            origin,
+            stem,
+            files,
+            items,
+            entries,
+            dropped,
+            skip_if_empty,
+            only_synced: matches!(origin, PlaylistOrigin::Generated | PlaylistOrigin::Podcast),
+            folders: Vec::new(),
+            dir: None,
+        })
+    }
+
+    /// Plans the combined playlist of a folder once all of its descendants have been visited.
+    fn close_folder(&self, folder: OpenFolder<'_>) -> Result<PlannedPlaylist, PlanError> {
+        self.playlist(folder.playlist.clone(), folder.virtual_path, PlaylistOrigin::Folder, folder.items, folder.stem, self.options.skip_empty)
+    }
+
+    /// Builds the entries of `playlist` along with the IDs of their tracks, leaving out tracks that
+    /// aren't synced unless `Options::strict` is set, in which case they are an error. Also returns
+    /// how many were left out for tracks that are missing, rather than excluded. With
+    /// `Options::dedupe_entries`, only the first entry of each track is kept.
+    fn entries(&self, playlist: &Playlist, root_prefix: &Path, items: &[u64]) -> Result<(Vec<(u64, Entry)>, usize), PlanError> {
+        let (library, options) = (self.library, self.options);
+        // Before anything is looked up, so that a missing track is only warned about once.
+        let unique: Vec<u64>;
+        let items = match options.dedupe_entries {
+            true => {
+                let mut seen = HashSet::new();
+                unique = items.iter().copied().filter(|&id| seen.insert(id)).collect();
+                if unique.len() < items.len() {
+                    debug!("Left out {} repeated entries from playlist {}.", items.len() - unique.len(), playlist.name);
+                }
+                &unique[..]
+            }
+            false => items,
+        };
+        let mut entries = Vec::with_capacity(items.len());
+        let mut dropped = 0;
+        for resolved in library.tracks_by_id(items) {
+            let (track_id, track) = match resolved {
+                Ok((track_id, track)) if self.paths.contains_key(&track_id) => (track_id, track),
+                // Tracks without a local file can't be synced, which is expected rather than an error.
+                Ok((_, track)) if track.location.is_none() || options.exclusion(track).is_some() => continue,
+                dangling => {
+                    let (track_id, track) = match dangling {
+                        Ok((track_id, track)) => (track_id, Some(track.name.clone())),
+                        Err(MissingTrack { track_id }) => (track_id, None),
+                    };
+                    let error = PlanError::DanglingEntry { playlist: playlist.name.clone(), track_id, track };
+                    if options.strict {
+                        error!("{}.", capitalized(&error));
+                        return Err(error);
+                    }
+                    warn!("{}; leaving it out.", capitalized(&error));
+                    dropped += 1;
+                    continue;
+                }
+            };
+            let path = match options.copy_tracks {
+                true => root_prefix.join(&self.paths[&track_id]),
+                false => self.paths[&track_id].clone(),
+            };
+            if path.to_str().is_none() {
+                // Only tracks referred to where they are can get here, as copies are named in UTF-8.
+                warn!(
+                    "Track {} ({}) is at {:?}, which isn't valid UTF-8; playlist {} can only refer to it with the invalid bytes replaced, which players may not find.",
+                    track_id, track.name, path, playlist.name
+                );
+            }
+            entries.push((track_id, Entry {
+                path: options.path_separator.render(&path),
+                title: match options.ascii {
+                    true => names::to_ascii(&options.title_format.render(track)),
+                    false => options.normalize.apply(&options.title_format.render(track)),
+                },
+                duration_ms: track.played_duration_ms(),
+                start_ms: track.start_time_ms.filter(|&start_ms| start_ms > 0),
+                stop_ms: track.stop_time_ms.filter(|&stop_ms| track.duration_ms.is_none_or(|duration_ms| stop_ms < duration_ms)),
+                track: match track.persistent_id {
+                    Some(id) => TrackRef::PersistentId(id),
+                    None => TrackRef::TrackId(track_id),
+                },
+            }));
+        }
+
+        let available = entries.len();
+        if let Some(max_tracks) = options.max_tracks_per_playlist {
+            entries.truncate(max_tracks);
+        }
+        if let Some(max_duration) = options.max_playlist_duration {
+            // Tracks without a known duration count as zero-length, so they never push a playlist over.
+            let mut elapsed = 0;
+            let within = entries.iter().take_while(|(_, entry)| {
+                elapsed += u64::from(entry.duration_ms.unwrap_or(0));
+                elapsed <= max_duration
+            }).count();
+            entries.truncate(within);
+        }
+        if entries.len() < available {
+            info!(
+                "Dropped {} of {} entries from playlist {} to stay within the playlist limits.",
+                available - entries.len(), available, playlist.name
+            );
+        }
+        if !options.sort_entries.0.is_empty() {
+            // A stable sort, so that entries which tie keep their order in the playlist.
+            entries.sort_by(|(a, _), (b, _)| options.sort_entries.compare(&library.tracks[a], &library.tracks[b]));
+        }
+        if let Some(seed) = options.shuffle_seed {
+            // Mixing in the playlist's id keeps its order reproducible however the rest of the library changes.
+            shuffle(&mut entries, seed ^ playlist.persistent_id);
+        }
+        Ok((entries, dropped))
+    }
+}
+
+/// `error` as the start of a sentence of the log.
+fn capitalized(error: &PlanError) -> String {
+    let message = error.to_string();
+    let mut chars = message.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => message,
+    }
+}
+
+/// Orders tracks by artist, album, disc and track number and then name, ignoring case. Tracks
+/// missing one of these sort after the ones that have it.
+fn library_order(a: &Track, b: &Track) -> Ordering {
+    fn text(value: &Option<String>) -> Option<String> {
+        value.as_ref().filter(|value| !value.is_empty()).map(|value| value.to_lowercase())
+    }
+
+    let artist = |track: &Track| text(&track.artist).or_else(|| text(&track.album_artist));
+    missing_last(artist(a), artist(b))
+        .then_with(|| missing_last(text(&a.album), text(&b.album)))
+        .then_with(|| missing_last(a.disc_number, b.disc_number))
+        .then_with(|| missing_last(a.track_number, b.track_number))
+        .then_with(|| a.name.to_lowercase().cmp(&b.name.to_lowercase()))
+}
+
+/// Which of `Options::roots` is the first of `path`, if any.
+fn root_of(path: &[&Playlist], options: &Options) -> Option<usize> {
+    let top = path[0];
+    let name = options.normalize.apply(&top.name);
+    options.roots.iter().position(|root| {
+        let root = options.normalize.apply(root);
+        top.kind.is_folder() && match options.ignore_case {
+            true => root.to_lowercase() == name.to_lowercase(),
+            false => root == name,
+        }
+    })
+}
+
+/// Finds the folders of `Options::roots` and everything in them, failing if one isn't a top-level
+/// folder of the library.
+pub(super) fn rooted_playlists(library: &Library, options: &Options) -> Result<HashSet<u64>, PlanError> {
+    let mut rooted = HashSet::new();
+    let mut top_folders = Vec::new();
+    let mut used = vec![false; options.roots.len()];
+    library.visit_playlists_with_path(|path| {
+        if path.len() == 1 && path[0].kind.is_folder() {
+            top_folders.push(path[0].name.clone());
+        }
+        if let Some(i) = root_of(path, options) {
+            used[i] = true;
+            rooted.insert(path[path.len() - 1].persistent_id);
+        }
+    });
+    match options.roots.iter().zip(used).find(|(_, used)| !used) {
+        Some((root, _)) => Err(PlanError::UnknownRoot { root: root.clone(), top_folders }),
+        None => Ok(rooted),
+    }
+}
+
+/// Whether the last playlist of `path` or one of the folders it is in is hidden, and left out.
+fn is_hidden(path: &[&Playlist], options: &Options) -> bool {
+    !options.include_hidden && path.iter().any(|playlist| !playlist.is_visible)
+}
+
+/// Collects the tracks in the included playlists, or all of them if `included` is `None`, that
+/// would be exported, leaving out ignored ones.
+pub(super) fn referenced_tracks(library: &Library, included: Option<&HashSet<u64>>, options: &Options) -> HashSet<u64> {
+    let mut tracks = HashSet::new();
+    library.visit_playlists_with_path(|path| {
+        let playlist = path[path.len() - 1];
+        if playlist.kind == PlaylistKind::Master && options.export_master.is_some() {
+            tracks.extend(playlist.items.iter().copied());
+            return;
+        }
+        let as_playlist = playlist.kind.is_generic() || options.include_system.contains(&playlist.kind);
+        if !as_playlist || included.is_some_and(|included| !included.contains(&playlist.persistent_id)) || is_hidden(path, options) {
+            return;
+        }
+        let names: Vec<String> = path.iter().map(|playlist| options.normalize.apply(&playlist.name)).collect();
+        let ids: Vec<u64> = path.iter().map(|playlist| playlist.persistent_id).collect();
+        if !(options.is_ignored)(&names, &ids, false) {
+            tracks.extend(playlist.items.iter().copied());
+        }
+    });
+    tracks
+}
+
+/// The path of a playlist file, from its `stem` and the extension of its format. The extension is
+/// added after the whole stem rather than in place of whatever follows its last dot, as the names
+/// of playlists, like "Mix Vol. 2", and of the folders flat playlists' stems hold may have dots in
+/// them.
+fn playlist_path(stem: &Path, extension: &str) -> PathBuf {
+    let mut name = stem.as_os_str().to_owned();
+    name.push(".");
+    name.push(extension);
+    PathBuf::from(name)
+}
+
+/// The relative path from the directory a playlist at `stem` is written to back up to the output,
+/// which `stem` is relative to. Entries for copied tracks are prefixed with it.
+pub fn root_prefix(stem: &Path) -> PathBuf {
+    let playlist_dir = stem.parent().unwrap_or(stem);
+    (0..playlist_dir.components().count()).map(|_| Component::ParentDir).collect()
+}
+
+/// A folder whose combined playlist is still collecting the items of its descendants.
+struct OpenFolder<'a> {
+    playlist: &'a Playlist,
+    virtual_path: String,
+    stem: PathBuf,
+    items: Vec<u64>,
+    seen: HashSet<u64>,
+}
+
+impl<'a> OpenFolder<'a> {
+    fn new(playlist: &'a Playlist, virtual_path: String, stem: PathBuf) -> Self {
+        OpenFolder { playlist, virtual_path, stem, items: Vec::new(), seen: HashSet::new() }
+    }
+
+    fn extend(&mut self, items: &[u64]) {
+        for &id in items {
+            if self.seen.insert(id) {
+                self.items.push(id);
+            }
+        }
+    }
+}
+
+/// Shuffles `items` in place (Fisher-Yates), driven by a SplitMix64 generator seeded with `seed`.
+fn shuffle<T>(items: &mut [T], seed: u64) {
+    let mut state = seed;
+    let mut next = || {
+        state = state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    };
+    for i in (1..items.len()).rev() {
+        let j = (next() % (i as u64 + 1)) as usize;
+        items.swap(i, j);
+    }
+}
@@ -0,0 +1,258 @@
+//! Templates for where copied tracks go, such as `{album_artist|artist}/{album}/{track:02} {name}.{ext}`.
+
+use std::{fmt::Write as _, path::PathBuf, str::FromStr};
+
+use crate::Track;
+
+/// A template for the path of a track's file, relative to the tracks folder.
+///
+/// Each `/`-separated component is made of text and fields in braces. A field may list others to
+/// fall back to, as in `{album_artist|artist}`, or end in quoted text to use when none of them is
+/// set, as in `{genre|"Misc"}`. Otherwise a missing text field becomes e.g. `Unknown Album`, and a
+/// missing number 0. Numbers can be padded with zeros to a width, as in `{track:02}`. If the
+/// template doesn't use `{ext}`, the extension of the file is added to the end.
+#[derive(Clone, Debug)]
+pub struct PathTemplate {
+    components: Vec<Vec<Part>>,
+    has_extension: bool,
+}
+
+#[derive(Clone, Debug)]
+enum Part {
+    Literal(String),
+    Field { alternatives: Vec<Alternative>, width: usize },
+}
+
+#[derive(Clone, Debug)]
+enum Alternative {
+    Field(Field),
+    Text(String),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Field {
+    Name,
+    Artist,
+    AlbumArtist,
+    Album,
+    Genre,
+    Composer,
+    Work,
+    Movement,
+    MovementNumber,
+    Disc,
+    DiscCount,
+    Track,
+    TrackCount,
+    Id,
+    PersistentId,
+    Extension,
+}
+
+const FIELDS: &[(&str, Field)] = &[
+    ("name", Field::Name),
+    ("title", Field::Name),
+    ("artist", Field::Artist),
+    ("album_artist", Field::AlbumArtist),
+    ("album", Field::Album),
+    ("genre", Field::Genre),
+    ("composer", Field::Composer),
+    ("work", Field::Work),
+    ("movement", Field::Movement),
+    ("movement_number", Field::MovementNumber),
+    ("disc", Field::Disc),
+    ("disc_count", Field::DiscCount),
+    ("track", Field::Track),
+    ("track_count", Field::TrackCount),
+    ("id", Field::Id),
+    ("persistent_id", Field::PersistentId),
+    ("ext", Field::Extension),
+];
+
+/// The track a template is expanded for, with what isn't kept in `Track` itself.
+pub(crate) struct TemplateTrack<'a> {
+    pub(crate) id: u64,
+    pub(crate) track: &'a Track,
+    /// The extension of its file, without the dot.
+    pub(crate) extension: Option<&'a str>,
+}
+
+impl Field {
+    fn value(self, track: &TemplateTrack<'_>) -> Option<String> {
+        fn text(value: &Option<String>) -> Option<String> {
+            value.as_deref().map(str::trim).filter(|value| !value.is_empty()).map(str::to_owned)
+        }
+        let t = track.track;
+        match self {
+            Field::Name => Some(t.name.trim()).filter(|name| !name.is_empty()).map(str::to_owned),
+            Field::Artist => text(&t.artist),
+            Field::AlbumArtist => text(&t.album_artist),
+            Field::Album => text(&t.album),
+            Field::Genre => text(&t.genre),
+            Field::Composer => text(&t.composer),
+            Field::Work => text(&t.work),
+            Field::Movement => text(&t.movement_name),
+            Field::MovementNumber => t.movement_number.map(|n| n.to_string()),
+            Field::Disc => t.disc_number.map(|n| n.to_string()),
+            Field::DiscCount => t.disc_count.map(|n| n.to_string()),
+            Field::Track => t.track_number.map(|n| n.to_string()),
+            Field::TrackCount => t.track_count.map(|n| n.to_string()),
+            Field::Id => Some(track.id.to_string()),
+            Field::PersistentId => t.persistent_id.map(|id| format!("{:016X}", id)),
+            Field::Extension => track.extension.filter(|extension| !extension.is_empty()).map(str::to_owned),
+        }
+    }
+
+    /// What the field becomes when the track doesn't have it.
+    fn default_value(self) -> &'static str {
+        match self {
+            Field::Name => "Unknown Title",
+            Field::Artist => "Unknown Artist",
+            Field::AlbumArtist => "Unknown Album Artist",
+            Field::Album => "Unknown Album",
+            Field::Genre => "Unknown Genre",
+            Field::Composer => "Unknown Composer",
+            Field::Work => "Unknown Work",
+            Field::Movement => "Unknown Movement",
+            Field::Disc | Field::DiscCount | Field::Track | Field::TrackCount | Field::MovementNumber | Field::Id => "0",
+            Field::PersistentId => "0000000000000000",
+            Field::Extension => "",
+        }
+    }
+}
+
+impl PathTemplate {
+    /// Expands the template for `track`. Each component is passed through `component` once
+    /// expanded, which should make it a valid file name.
+    pub(crate) fn render(&self, track: &TemplateTrack<'_>, component: impl Fn(&str) -> String) -> PathBuf {
+        let last = self.components.len() - 1;
+        self.components.iter().enumerate()
+            .map(|(i, parts)| {
+                let mut expanded = String::new();
+                for part in parts {
+                    match part {
+                        Part::Literal(text) => expanded.push_str(text),
+                        Part::Field { alternatives, width } => expanded.push_str(&expand(alternatives, *width, track)),
+                    }
+                }
+                if i == last && !self.has_extension {
+                    if let Some(extension) = track.extension.filter(|extension| !extension.is_empty()) {
+                        let _ = write!(expanded, ".{}", extension);
+                    }
+                }
+                component(&expanded)
+            })
+            .collect()
+    }
+}
+
+fn expand(alternatives: &[Alternative], width: usize, track: &TemplateTrack<'_>) -> String {
+    let value = alternatives.iter().find_map(|alternative| match alternative {
+        Alternative::Field(field) => field.value(track),
+        Alternative::Text(text) => Some(text.clone()),
+    });
+    let value = value.unwrap_or_else(|| match alternatives.last() {
+        Some(Alternative::Field(field)) => field.default_value().to_owned(),
+        _ => String::new(),
+    });
+    match !value.is_empty() && value.bytes().all(|b| b.is_ascii_digit()) {
+        true => format!("{:0>width$}", value, width = width),
+        false => value,
+    }
+}
+
+impl FromStr for PathTemplate {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let error = |message: String| format!("{} in path template \"{}\"", message, s);
+        if s.starts_with('/') {
+            return Err(error("a leading '/'".to_owned()));
+        }
+        let mut components = Vec::new();
+        let mut has_extension = false;
+        for component in split_components(s).map_err(error)? {
+            let parts = parse_component(component).map_err(error)?;
+            let literal: Option<String> = parts.iter()
+                .map(|part| match part {
+                    Part::Literal(text) => Some(text.as_str()),
+                    Part::Field { .. } => None,
+                })
+                .collect();
+            match literal.as_deref() {
+                Some("") => return Err(error("an empty folder name".to_owned())),
+                Some(".") | Some("..") => return Err(error(format!("the folder \"{}\"", component))),
+                _ => {}
+            }
+            has_extension |= parts.iter().any(|part| matches!(
+                part,
+                Part::Field { alternatives, .. } if alternatives.iter().any(|alternative| matches!(alternative, Alternative::Field(Field::Extension)))
+            ));
+            components.push(parts);
+        }
+        Ok(PathTemplate { components, has_extension })
+    }
+}
+
+/// Splits a template at the slashes outside of braces, which may hold them in quoted text.
+fn split_components(s: &str) -> Result<Vec<&str>, String> {
+    let (mut components, mut start, mut depth) = (Vec::new(), 0, 0);
+    for (i, c) in s.char_indices() {
+        match c {
+            '{' => depth += 1,
+            '}' if depth > 0 => depth -= 1,
+            '}' => return Err("an unmatched '}'".to_owned()),
+            '/' if depth == 0 => {
+                components.push(&s[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    if depth > 0 {
+        return Err("an unclosed '{'".to_owned());
+    }
+    components.push(&s[start..]);
+    Ok(components)
+}
+
+fn parse_component(component: &str) -> Result<Vec<Part>, String> {
+    let mut parts = Vec::new();
+    let mut rest = component;
+    while let Some(start) = rest.find('{') {
+        if start > 0 {
+            parts.push(Part::Literal(rest[..start].to_owned()));
+        }
+        let end = start + rest[start..].find('}').ok_or_else(|| "an unclosed '{'".to_owned())?;
+        parts.push(parse_field(&rest[start + 1..end])?);
+        rest = &rest[end + 1..];
+    }
+    if !rest.is_empty() {
+        parts.push(Part::Literal(rest.to_owned()));
+    }
+    Ok(parts)
+}
+
+/// Parses what is between the braces of a field, like `album_artist|artist` or `track:02`.
+fn parse_field(field: &str) -> Result<Part, String> {
+    let (alternatives, width) = match field.rsplit_once(':') {
+        Some((alternatives, width)) if !width.contains('"') => {
+            let width = width.parse().map_err(|_| format!("an invalid width \"{}\" in {{{}}}", width, field))?;
+            (alternatives, width)
+        }
+        _ => (field, 0),
+    };
+    let alternatives = alternatives.split('|')
+        .map(|alternative| {
+            let alternative = alternative.trim();
+            if let Some(text) = alternative.strip_prefix('"').and_then(|text| text.strip_suffix('"')) {
+                return Ok(Alternative::Text(text.to_owned()));
+            }
+            FIELDS.iter()
+                .find(|(name, _)| *name == alternative)
+                .map(|&(_, field)| Alternative::Field(field))
+                .ok_or_else(|| format!("an unknown field \"{}\"", alternative))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(Part::Field { alternatives, width })
+}
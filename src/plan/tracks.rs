@@ -0,0 +1,531 @@
+//! Picking out the tracks to sync and naming their copies, which only works on what the library
+//! says and on the files of its tracks, not on any output.
+
+use std::{collections::{HashMap, HashSet}, ffi::OsStr, fs, path::{Path, PathBuf}};
+use log::*;
+
+use crate::{Library, Track, date, names, sha256};
+use super::{Exclusion, InPlaceTrack, Options, PODCASTS_DIR, SkippedTrack, TrackCopy, TrackNames, template::TemplateTrack};
+
+/// A track with a usable local file, before its copy is named.
+#[derive(Clone)]
+struct Candidate<'a> {
+    id: u64,
+    track: &'a Track,
+    local_path: PathBuf,
+    size: Option<u64>,
+}
+
+/// Picks out the tracks to copy, leaving out those that aren't `selected`, are excluded or have no
+/// usable local file, and names their copies. `playlists_selected` tells whether `selected` comes
+/// from a selection of playlists rather than all of them, for the log.
+pub(super) fn plan_tracks(
+    library: &Library,
+    options: &Options,
+    selected: Option<&HashSet<u64>>,
+    playlists_selected: bool,
+) -> (Vec<TrackCopy>, Vec<SkippedTrack>) {
+    if !library.skipped_tracks.is_empty() {
+        info!("Skipped {} tracks with no local file.", library.skipped_tracks.len());
+    }
+
+    let mut candidates = Vec::new();
+    let mut skipped = Vec::new();
+    let mut unselected = 0;
+    let mut filtered = 0;
+    let mut disabled = 0;
+    let mut protected = 0;
+    let (mut missing, mut empty) = (Vec::new(), Vec::new());
+    // In order of their IDs, so that every run goes through them, and logs them, the same way.
+    let mut track_ids: Vec<u64> = library.tracks.keys().copied().collect();
+    track_ids.sort_unstable();
+    for (track_id, track) in track_ids.iter().map(|track_id| (track_id, &library.tracks[track_id])) {
+        let location = match &track.location {
+            Some(location) => location,
+            None => continue,
+        };
+        let skip = |reason| SkippedTrack { track_id: *track_id, reason, source: None, error: None };
+        if selected.is_some_and(|selected| !selected.contains(track_id)) {
+            skipped.push(skip("unselected"));
+            unselected += 1;
+            continue;
+        }
+        match options.exclusion(track) {
+            Some(Exclusion::Filtered) => { skipped.push(skip("filtered")); filtered += 1; continue; }
+            Some(Exclusion::Disabled) => { skipped.push(skip("disabled")); disabled += 1; continue; }
+            Some(Exclusion::Protected) => {
+                info!(
+                    event = "track_skipped", track_id, reason = "protected", src = location.as_str();
+                    "Skipping DRM-protected track {} at {}", track.name, location
+                );
+                skipped.push(skip("protected"));
+                protected += 1;
+                continue;
+            }
+            None => {},
+        }
+        match track.local_path_remapped(&options.remap_hosts) {
+            Ok(Some(local_path)) if options.check_sources => match fs::metadata(&local_path) {
+                Err(e) => {
+                    skipped.push(SkippedTrack { source: Some(local_path.clone()), error: Some(e.to_string()), ..skip("missing_source") });
+                    missing.push((*track_id, track, local_path, e));
+                }
+                Ok(metadata) if metadata.len() == 0 => {
+                    skipped.push(SkippedTrack { source: Some(local_path.clone()), ..skip("empty_source") });
+                    empty.push((*track_id, track, local_path));
+                }
+                Ok(metadata) => candidates.push(Candidate { id: *track_id, track, local_path, size: Some(metadata.len()) }),
+            }
+            Ok(Some(local_path)) => {
+                let size = track.size_bytes.or_else(|| fs::metadata(&local_path).ok().map(|metadata| metadata.len()));
+                candidates.push(Candidate { id: *track_id, track, local_path, size });
+            }
+            Ok(None) => {
+                warn!(
+                    event = "track_skipped", track_id, reason = "unknown_location", src = location.as_str();
+                    "Ignoring path with unknown schema or host: {}", location
+                );
+                skipped.push(skip("unknown_location"));
+            }
+            Err(e) => {
+                warn!(
+                    event = "track_skipped", track_id, reason = "invalid_location", src = location.as_str();
+                    "Ignoring track {}, as its {}", track.name, e
+                );
+                skipped.push(skip("invalid_location"));
+            }
+        }
+    }
+
+    if unselected > 0 {
+        match playlists_selected {
+            false => info!("Left out {} tracks that aren't in any exported playlist.", unselected),
+            true => info!("Left out {} tracks that aren't in the selected playlists.", unselected),
+        }
+    }
+    if filtered > 0 {
+        info!("Left out {} tracks that didn't pass the track filters.", filtered);
+    }
+    if disabled > 0 {
+        info!("Left out {} tracks that are unchecked in iTunes.", disabled);
+    }
+    if protected > 0 {
+        warn!(
+            "Skipped {} DRM-protected tracks, which won't play outside Apple's software; pass --include-protected to copy them anyway.",
+            protected
+        );
+    }
+    if !missing.is_empty() {
+        warn!("Left out {} tracks whose files are missing or can't be read:", missing.len());
+        for (track_id, track, local_path, e) in missing {
+            warn!(
+                event = "track_skipped", track_id, reason = "missing_source", src:% = local_path.display(), error:% = e;
+                "    {} at {:?}: {}", track.name, local_path, e
+            );
+        }
+    }
+    if !empty.is_empty() {
+        warn!("Left out {} tracks whose files are empty:", empty.len());
+        for (track_id, track, local_path) in empty {
+            warn!(
+                event = "track_skipped", track_id, reason = "empty_source", src:% = local_path.display();
+                "    {} at {:?}", track.name, local_path
+            );
+        }
+    }
+
+    // Tracks sharing a file share its copy too, so only the owners' paths are kept apart.
+    let shared = shared_files(&candidates);
+    let owners: Vec<Candidate<'_>> = candidates.iter().filter(|candidate| !shared.contains_key(&candidate.id)).cloned().collect();
+    let destinations = destinations(&owners, options);
+    if !shared.is_empty() {
+        info!("{} tracks are parts of the file of another track, which is only copied once for them all.", shared.len());
+    }
+    let tracks = candidates.into_iter()
+        .map(|candidate| {
+            let owner = shared.get(&candidate.id).copied();
+            TrackCopy {
+                track_id: candidate.id,
+                destination: destinations[&owner.unwrap_or(candidate.id)].clone(),
+                source: candidate.local_path,
+                size_bytes: candidate.size,
+                shares_with: owner,
+            }
+        })
+        .collect();
+    (tracks, skipped)
+}
+
+/// The candidates whose file is also that of another, as with the parts of one file that iTunes
+/// makes with start and stop times, each with the track among them with the lowest track ID,
+/// whose copy they share.
+fn shared_files(candidates: &[Candidate<'_>]) -> HashMap<u64, u64> {
+    let mut owners: HashMap<&Path, u64> = HashMap::new();
+    for candidate in candidates {
+        let owner = owners.entry(&candidate.local_path).or_insert(candidate.id);
+        *owner = (*owner).min(candidate.id);
+    }
+    candidates.iter()
+        .map(|candidate| (candidate.id, owners[candidate.local_path.as_path()]))
+        .filter(|(track_id, owner)| track_id != owner)
+        .collect()
+}
+
+/// The tracks that playlists refer to where they are, without copying: every selected one that
+/// isn't excluded, at its own location, or relative to `Options::base_dir`.
+pub(super) fn in_place_tracks(library: &Library, options: &Options, selected: Option<&HashSet<u64>>) -> Vec<InPlaceTrack> {
+    let mut track_ids: Vec<u64> = library.tracks.keys().copied().collect();
+    track_ids.sort_unstable();
+    let mut tracks = Vec::new();
+    for (track_id, track) in track_ids.iter().map(|track_id| (*track_id, &library.tracks[track_id])) {
+        let location = match &track.location {
+            Some(_) if selected.is_some_and(|selected| !selected.contains(&track_id)) => continue,
+            Some(location) if options.exclusion(track).is_none() => location,
+            _ => continue,
+        };
+        match track.local_path_remapped(&options.remap_hosts) {
+            Ok(Some(path)) => {
+                let path = match &options.base_dir {
+                    Some(base_dir) => match path.strip_prefix(base_dir) {
+                        Ok(relative) => relative.to_owned(),
+                        Err(_) => {
+                            warn!("Track at {:?} is outside of base directory {:?}; using its full path.", path, base_dir);
+                            path
+                        }
+                    },
+                    None => path,
+                };
+                tracks.push(InPlaceTrack { track_id, path });
+            }
+            Ok(None) => warn!(
+                event = "track_skipped", track_id, reason = "unknown_location", src = location.as_str();
+                "Ignoring path with unknown schema or host: {}", location
+            ),
+            Err(e) => warn!(
+                event = "track_skipped", track_id, reason = "invalid_location", src = location.as_str();
+                "Ignoring track {}, as its {}", track.name, e
+            ),
+        }
+    }
+    tracks
+}
+
+/// Where each candidate is copied to, relative to the output. Names only depend on the candidates,
+/// so that each run picks the same ones.
+fn destinations(candidates: &[Candidate<'_>], options: &Options) -> HashMap<u64, PathBuf> {
+    if !options.podcasts {
+        return music_destinations(candidates, options);
+    }
+    let (episodes, music): (Vec<Candidate<'_>>, Vec<Candidate<'_>>) = candidates.iter().cloned()
+        .partition(|candidate| candidate.track.podcast);
+    let mut paths = music_destinations(&music, options);
+    paths.extend(episode_destinations(&episodes, options));
+    paths
+}
+
+/// Where the candidates go in the tracks folder.
+fn music_destinations(candidates: &[Candidate<'_>], options: &Options) -> HashMap<u64, PathBuf> {
+    let tracks_dir = &options.tracks_dir;
+    // Copies are always named in UTF-8, so that playlists can refer to them exactly.
+    let utf8 = |candidate: &Candidate<'_>, part: &OsStr| {
+        if part.to_str().is_none() {
+            warn!(
+                "The file of track {} ({}) at {:?} has a name that isn't valid UTF-8; naming its copy with the invalid bytes replaced.",
+                candidate.id, candidate.track.name, candidate.local_path
+            );
+        }
+        part.to_string_lossy().into_owned()
+    };
+    let by_id = |candidate: &Candidate<'_>| {
+        let mut destination = tracks_dir.join(candidate.id.to_string());
+        if let Some(extension) = candidate.local_path.extension() {
+            destination.set_extension(utf8(candidate, extension));
+        }
+        destination
+    };
+    let component = |name: &str| track_path_component(name, options);
+    let paths: Vec<Option<PathBuf>> = if let Some(template) = &options.path_template {
+        candidates.iter()
+            .map(|candidate| {
+                let extension = candidate.local_path.extension().map(|extension| utf8(candidate, extension));
+                let track = TemplateTrack { id: candidate.id, track: candidate.track, extension: extension.as_deref() };
+                Some(tracks_dir.join(template.render(&track, component)))
+            })
+            .collect()
+    }
+    else {
+        let folder = match options.track_names {
+            TrackNames::Id => return candidates.iter().map(|candidate| (candidate.id, by_id(candidate))).collect(),
+            TrackNames::PersistentId => return by_persistent_id(candidates, tracks_dir, by_id),
+            TrackNames::Original => |_: &Track, _: &Options| PathBuf::new(),
+            TrackNames::Album => album_folder,
+        };
+        candidates.iter()
+            .map(|candidate| candidate.local_path.file_name()
+                .map(|name| component(&utf8(candidate, name)))
+                .filter(|name| !name.is_empty())
+                .map(|name| tracks_dir.join(folder(candidate.track, options)).join(name)))
+            .collect()
+    };
+    let paths = candidates.iter().zip(paths)
+        .map(|(candidate, path)| (candidate.id, path.unwrap_or_else(|| by_id(candidate))))
+        .collect();
+    unique_paths(candidates, paths)
+}
+
+/// Where podcast episodes go with `Options::podcasts`: in a folder for their show, named after
+/// when they came out (or were added, if the library doesn't know) and their title.
+fn episode_destinations(candidates: &[Candidate<'_>], options: &Options) -> HashMap<u64, PathBuf> {
+    let component = |name: &str| track_path_component(name, options);
+    let paths = candidates.iter()
+        .map(|candidate| {
+            let track = candidate.track;
+            let mut name = match track.release_date.or(track.date_added) {
+                Some(date) => format!("{} {}", &date::rfc3339(date)[..10], track.name.trim()),
+                None => track.name.trim().to_owned(),
+            };
+            if let Some(extension) = candidate.local_path.extension() {
+                name.push('.');
+                name.push_str(&extension.to_string_lossy());
+            }
+            (candidate.id, Path::new(PODCASTS_DIR).join(component(show_name(track))).join(component(&name)))
+        })
+        .collect();
+    unique_paths(candidates, paths)
+}
+
+/// The podcast show an episode is of.
+pub(super) fn show_name(track: &Track) -> &str {
+    track.album.as_deref().map(str::trim).filter(|album| !album.is_empty()).unwrap_or("Unknown Podcast")
+}
+
+/// Makes the paths of the candidates unique, warning of those that had to be renamed.
+fn unique_paths(candidates: &[Candidate<'_>], paths: Vec<(u64, PathBuf)>) -> HashMap<u64, PathBuf> {
+    let destinations = unique_destinations(paths);
+    if !destinations.renamed.is_empty() {
+        let tracks: Vec<String> = destinations.renamed.iter()
+            .map(|(id, original)| format!(
+                "{} ({}) as {:?} instead of {:?}",
+                candidates.iter().find(|candidate| candidate.id == *id).map_or("", |candidate| &candidate.track.name),
+                id, destinations.paths[id], original
+            ))
+            .collect();
+        warn!(
+            event = "destination_collisions", tracks = destinations.renamed.len();
+            "Renamed the copies of {} tracks that would have had the same path as another's: {}.",
+            destinations.renamed.len(), tracks.join(", ")
+        );
+    }
+    destinations.paths
+}
+
+/// Names each candidate after its persistent ID, or its track ID if it has none or shares it with
+/// another.
+fn by_persistent_id(candidates: &[Candidate<'_>], tracks_dir: &Path, by_id: impl Fn(&Candidate<'_>) -> PathBuf) -> HashMap<u64, PathBuf> {
+    let mut counts: HashMap<u64, usize> = HashMap::new();
+    for persistent_id in candidates.iter().filter_map(|candidate| candidate.track.persistent_id) {
+        *counts.entry(persistent_id).or_default() += 1;
+    }
+    let (mut missing, mut shared) = (0, 0);
+    let paths = candidates.iter().map(|candidate| {
+        let destination = match candidate.track.persistent_id {
+            Some(persistent_id) if counts[&persistent_id] == 1 => {
+                let mut destination = tracks_dir.join(format!("{:016X}", persistent_id));
+                if let Some(extension) = candidate.local_path.extension() {
+                    destination.set_extension(extension);
+                }
+                destination
+            }
+            Some(_) => {
+                shared += 1;
+                by_id(candidate)
+            }
+            None => {
+                debug!("Track {} ({}) has no persistent ID, so naming it by its track ID.", candidate.track.name, candidate.id);
+                missing += 1;
+                by_id(candidate)
+            }
+        };
+        (candidate.id, destination)
+    }).collect();
+    if missing > 0 {
+        warn!("Named {} tracks by their track ID instead, as they have no persistent ID.", missing);
+    }
+    if shared > 0 {
+        warn!("Named {} tracks by their track ID instead, as they share their persistent ID with another.", shared);
+    }
+    paths
+}
+
+/// Turns a name from the library into a folder or file name in the path of a track's copy, the way
+/// playlist names are: normalized, transliterated to ASCII with `Options::ascii`, sanitized and
+/// shortened to `Options::max_name_len`, keeping the extension of file names. Shortened names end
+/// in part of a digest of the whole name to keep them apart.
+fn track_path_component(name: &str, options: &Options) -> String {
+    let mut normalized = options.normalize.apply(name);
+    if options.ascii {
+        let ascii = names::to_ascii(&normalized);
+        if ascii != normalized {
+            debug!("Using ASCII name {:?} for {:?} in the path of a track.", ascii, name);
+        }
+        normalized = ascii;
+    }
+    let sanitized = options.sanitize.apply(&normalized);
+    let max_len = match options.max_name_len {
+        Some(max_len) if sanitized.len() > max_len => max_len,
+        _ => return sanitized,
+    };
+    // Only short extensions, so that a folder like "Vol. 2" isn't taken for a name with one.
+    let (stem, extension) = match sanitized.rfind('.') {
+        Some(dot) if dot > 0 && sanitized.len() - dot <= 6 && !sanitized[dot..].contains(' ') => sanitized.split_at(dot),
+        _ => (sanitized.as_str(), ""),
+    };
+    let mut hasher = sha256::Sha256::new();
+    hasher.update(sanitized.as_bytes());
+    let suffix = hasher.finish().to_string()[..6].to_ascii_uppercase();
+    names::truncate(stem, max_len.saturating_sub(extension.len()), &suffix) + extension
+}
+
+/// The folder for the album of `track`, under one for its album artist or else its artist. Albums
+/// of various artists go in the compilations folder instead, unless they have an album artist.
+fn album_folder(track: &Track, options: &Options) -> PathBuf {
+    fn text(field: &Option<String>) -> Option<&str> {
+        field.as_deref().map(str::trim).filter(|text| !text.is_empty())
+    }
+    let component = |name: &str| track_path_component(name, options);
+    let artist = match text(&track.album_artist) {
+        Some(album_artist) => album_artist,
+        None if track.compilation && options.group_compilations => &options.compilations_folder,
+        None => text(&track.artist).unwrap_or("Unknown Artist"),
+    };
+    let album = text(&track.album).unwrap_or("Unknown Album");
+    Path::new(&component(artist)).join(component(album))
+}
+
+/// Where tracks are copied to, relative to the output directory, once no two share a path.
+#[derive(Debug, Default)]
+pub struct Destinations {
+    /// The path of each track, by its track ID.
+    pub paths: HashMap<u64, PathBuf>,
+    /// The tracks that were given another path than the one asked for, with that path, in the
+    /// order they were given.
+    pub renamed: Vec<(u64, PathBuf)>,
+}
+
+/// Makes the paths of tracks, given by their track IDs, unique, ignoring case as many players'
+/// file systems do. Of the tracks sharing a path, the one with the lowest track ID keeps it, so
+/// that a track added later doesn't rename the copy of one synced already, and the rest get their
+/// track ID added to the stem.
+pub fn unique_destinations(tracks: Vec<(u64, PathBuf)>) -> Destinations {
+    let key = |path: &Path| path.to_string_lossy().to_lowercase();
+    let mut owners: HashMap<String, u64> = HashMap::new();
+    for (id, path) in &tracks {
+        let owner = owners.entry(key(path)).or_insert(*id);
+        *owner = (*owner).min(*id);
+    }
+    let mut taken: HashSet<String> = owners.keys().cloned().collect();
+    let mut destinations = Destinations { paths: HashMap::with_capacity(tracks.len()), renamed: Vec::new() };
+    for (id, path) in tracks {
+        if owners[&key(&path)] == id {
+            destinations.paths.insert(id, path);
+            continue;
+        }
+        // Numbered too in the unlikely case that another track's path already ends in this ID.
+        let unique = (1..)
+            .map(|n| {
+                let mut stem = path.file_stem().unwrap_or_default().to_owned();
+                match n {
+                    1 => stem.push(format!("-{}", id)),
+                    n => stem.push(format!("-{}-{}", id, n)),
+                }
+                let mut unique = path.with_file_name(stem);
+                if let Some(extension) = path.extension() {
+                    unique.set_extension(extension);
+                }
+                unique
+            })
+            .find(|unique| !taken.contains(&key(unique)))
+            .unwrap();
+        taken.insert(key(&unique));
+        destinations.paths.insert(id, unique);
+        destinations.renamed.push((id, path));
+    }
+    destinations
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn destinations(tracks: &[(u64, &str)]) -> Destinations {
+        unique_destinations(tracks.iter().map(|&(id, path)| (id, PathBuf::from(path))).collect())
+    }
+
+    fn path(destinations: &Destinations, id: u64) -> &str {
+        destinations.paths[&id].to_str().unwrap()
+    }
+
+    #[test]
+    fn the_lowest_of_three_keeps_the_name() {
+        let destinations = destinations(&[(30, "tracks/Song.mp3"), (10, "tracks/Song.mp3"), (20, "tracks/Song.mp3"), (4, "tracks/Other.mp3")]);
+        assert_eq!(path(&destinations, 10), "tracks/Song.mp3");
+        assert_eq!(path(&destinations, 20), "tracks/Song-20.mp3");
+        assert_eq!(path(&destinations, 30), "tracks/Song-30.mp3");
+        assert_eq!(path(&destinations, 4), "tracks/Other.mp3");
+        let renamed: Vec<u64> = destinations.renamed.iter().map(|(id, _)| *id).collect();
+        assert_eq!(renamed, [30, 20]);
+        assert!(destinations.renamed.iter().all(|(_, path)| path == Path::new("tracks/Song.mp3")));
+    }
+
+    #[test]
+    fn names_are_the_same_whatever_order_tracks_come_in() {
+        let tracks = [(3, "a/Song.mp3"), (1, "a/song.MP3"), (2, "a/SONG.mp3"), (5, "b/Song.mp3")];
+        let forward = destinations(&tracks);
+        let mut reversed = tracks;
+        reversed.reverse();
+        let reversed = destinations(&reversed);
+        for id in [1, 2, 3, 5] {
+            assert_eq!(path(&forward, id), path(&reversed, id));
+        }
+        // Case is ignored, as on most players' file systems.
+        assert_eq!(path(&forward, 1), "a/song.MP3");
+        assert_eq!(path(&forward, 2), "a/SONG-2.mp3");
+        assert_eq!(path(&forward, 3), "a/Song-3.mp3");
+        assert_eq!(path(&forward, 5), "b/Song.mp3");
+    }
+
+    #[test]
+    fn renamed_paths_dont_take_other_tracks_paths() {
+        let numbered = destinations(&[(1, "Song.mp3"), (2, "Song.mp3"), (3, "Song-2.mp3")]);
+        assert_eq!(path(&numbered, 1), "Song.mp3");
+        assert_eq!(path(&numbered, 2), "Song-2-2.mp3");
+        assert_eq!(path(&numbered, 3), "Song-2.mp3");
+        assert_eq!(numbered.renamed.len(), 1);
+        let without_extension = destinations(&[(1, "Notes"), (2, "Notes")]);
+        assert_eq!(path(&without_extension, 2), "Notes-2");
+    }
+
+    #[test]
+    fn tracks_without_a_persistent_id_of_their_own_are_named_by_track_id() {
+        let track = |persistent_id| Track { persistent_id, ..Track::default() };
+        // Two sharing theirs, one with one of its own, one without any, and one without an extension.
+        let tracks = [
+            (track(Some(0xA1)), "a.mp3"),
+            (track(Some(0xA1)), "b.mp3"),
+            (track(Some(0xA3)), "c.m4a"),
+            (track(None), "d.flac"),
+            (track(Some(0xA5)), "e"),
+        ];
+        let candidates: Vec<Candidate<'_>> = tracks.iter().zip(1..)
+            .map(|((track, file), id)| Candidate { id, track, local_path: PathBuf::from(file), size: None })
+            .collect();
+        let paths = by_persistent_id(&candidates, Path::new("tracks"), |candidate| PathBuf::from(format!("tracks/{}", candidate.id)));
+        let path = |id| paths[&id].to_str().unwrap();
+        assert_eq!(paths.len(), 5);
+        assert_eq!(path(1), "tracks/1");
+        assert_eq!(path(2), "tracks/2");
+        assert_eq!(path(3), "tracks/00000000000000A3.m4a");
+        assert_eq!(path(4), "tracks/4");
+        assert_eq!(path(5), "tracks/00000000000000A5");
+    }
+}
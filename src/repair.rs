@@ -0,0 +1,155 @@
+//! Moving the copies of tracks already in the output to where they go now, for `m3trans repair`.
+
+use std::{collections::{HashMap, HashSet}, fs::{self, File}, io, path::{Path, PathBuf}};
+use log::*;
+
+use crate::args::Args;
+use crate::report::{Action, ActionKind};
+use crate::sha256::{self, Digest};
+use crate::sync::{check_strict, create_output_dir};
+use crate::sync::copy::PlannedTrack;
+use crate::sync::sink::Sink;
+
+/// For `m3trans repair`, moves files in the tracks folder that are copies of tracks to sync, but
+/// aren't where those go now, into their place, returning the tracks moved. A file is taken for a
+/// track's copy if it is named after its track ID or persistent ID, as with `--names id` or
+/// `persistent-id`, and has the size of its file, or else if it has the same contents. Files that
+/// are copies of none of the tracks are listed, to delete by hand if they aren't wanted.
+pub(crate) fn repair_tracks(
+    planned: &[PlannedTrack<'_>],
+    remote_path_of: &HashMap<u64, PathBuf>,
+    tracks_dir: &Path,
+    args: &Args,
+    sink: &dyn Sink,
+    actions: &mut Vec<Action>,
+) -> Result<HashSet<u64>, io::Error> {
+    let destinations: HashSet<PathBuf> = remote_path_of.values().map(|path| args.output_path.join(path)).collect();
+    let mut files = Vec::new();
+    list_files(tracks_dir, &mut files);
+    // Those in the place of a track are left for --check-content to compare.
+    let spare: Vec<(PathBuf, u64)> = files.into_iter().filter(|(file, _)| !destinations.contains(file)).collect();
+    // With the sizes of their files as they are now, which the library may not know.
+    let missing: Vec<(&PlannedTrack<'_>, Option<u64>)> = planned.iter()
+        .filter(|planned| !args.output_path.join(&remote_path_of[&planned.id]).exists())
+        .map(|planned| (planned, fs::metadata(&planned.local_path).map(|metadata| metadata.len()).ok()))
+        .collect();
+
+    // The track each file is a copy of, by index into `spare`, and what told it.
+    let mut matches: Vec<(&PlannedTrack<'_>, usize, &str)> = Vec::new();
+    let mut taken = vec![false; spare.len()];
+    let by_name: HashMap<String, usize> = spare.iter().enumerate()
+        .filter_map(|(i, (file, _))| Some((file.file_name()?.to_str()?.to_lowercase(), i)))
+        .collect();
+    let mut unnamed = Vec::new();
+    for &(planned, size) in &missing {
+        let extension = planned.local_path.extension().map_or(String::new(), |extension| format!(".{}", extension.to_string_lossy()));
+        let old_names = [Some(planned.id.to_string()), planned.track.persistent_id.map(|id| format!("{:016X}", id))];
+        let found = old_names.iter().flatten()
+            .filter_map(|name| by_name.get(&format!("{}{}", name, extension).to_lowercase()).copied())
+            .find(|&i| !taken[i] && size == Some(spare[i].1));
+        match found {
+            Some(i) => {
+                taken[i] = true;
+                matches.push((planned, i, "name"));
+            }
+            None => unnamed.push((planned, size)),
+        }
+    }
+    // Only files the size of a track's file are read, and each of them at most once.
+    let digest = |path: &Path| File::open(path).and_then(sha256::digest_reader).map_err(|e| {
+        debug!("Failed to hash {:?}, so not matching it by its contents: {}", path, e);
+    }).ok();
+    let mut digests: HashMap<usize, Option<Digest>> = HashMap::new();
+    for (planned, size) in unnamed {
+        let candidates: Vec<usize> = (0..spare.len()).filter(|&i| !taken[i] && size == Some(spare[i].1)).collect();
+        let source = match candidates.is_empty() {
+            true => continue,
+            false => match digest(&planned.local_path) {
+                Some(source) => source,
+                None => continue,
+            },
+        };
+        if let Some(i) = candidates.into_iter().find(|&i| *digests.entry(i).or_insert_with(|| digest(&spare[i].0)) == Some(source)) {
+            taken[i] = true;
+            matches.push((planned, i, "contents"));
+        }
+    }
+
+    let mut moved = HashSet::new();
+    let mut folders = HashSet::new();
+    for &(planned, i, matched_by) in &matches {
+        let (file, _) = &spare[i];
+        let destination = args.output_path.join(&remote_path_of[&planned.id]);
+        if let Some(folder) = destination.parent().filter(|folder| *folder != tracks_dir && folders.insert(folder.to_path_buf())) {
+            if let Err(e) = create_output_dir(folder, args.dry_run, sink, actions) {
+                error!(
+                    event = "folder_create_failed", track_id = planned.id, dest:% = folder.display(), error:% = e;
+                    "Failed to create folder for track {} at path {:?}: {:?}", planned.track.name, folder, e
+                );
+                check_strict(args)?;
+                continue;
+            }
+        }
+        let action = Action::new(ActionKind::MoveTrack, args.dry_run).track(planned.id, planned.track).source(file).destination(&destination);
+        if args.dry_run {
+            info!("Move file at {:?} to {:?}, found by its {}", file, destination, matched_by);
+            actions.push(action);
+            moved.insert(planned.id);
+            continue;
+        }
+        let result = fs::rename(file, &destination);
+        actions.push(action.result(&result));
+        match result {
+            Ok(()) => {
+                info!(
+                    event = "track_moved", track_id = planned.id, src:% = file.display(), dest:% = destination.display();
+                    "Moved file at {:?} to {:?}, found by its {}", file, destination, matched_by
+                );
+                moved.insert(planned.id);
+            }
+            Err(e) => {
+                error!(
+                    event = "track_move_failed", track_id = planned.id, src:% = file.display(), dest:% = destination.display(), error:% = e;
+                    "Failed to move file at {:?} to {:?}: {:?}", file, destination, e
+                );
+                check_strict(args)?;
+            }
+        }
+    }
+    let found_by = |by: &str| matches.iter().filter(|(_, _, matched_by)| *matched_by == by).count();
+    info!(
+        "Found the copies of {} tracks by their names and {} by their contents, of {} not in place.",
+        found_by("name"), found_by("contents"), missing.len()
+    );
+
+    let unmatched: Vec<&PathBuf> = spare.iter().zip(taken).filter(|(_, taken)| !taken).map(|((file, _), _)| file).collect();
+    for file in &unmatched {
+        info!(event = "file_unmatched", path:% = file.display(); "File {:?} is a copy of none of the tracks being synced.", file);
+    }
+    if !unmatched.is_empty() {
+        warn!(
+            event = "files_unmatched", files = unmatched.len();
+            "{} files in the tracks folder are copies of none of the tracks being synced, as listed in the log; delete them if they aren't wanted.",
+            unmatched.len()
+        );
+    }
+    Ok(moved)
+}
+
+/// Adds the files under `dir` to `files` with their sizes, leaving out hidden ones.
+fn list_files(dir: &Path, files: &mut Vec<(PathBuf, u64)>) {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) => return warn!("Failed to list the files in {:?}: {:?}", dir, e),
+    };
+    for entry in entries.flatten() {
+        if entry.file_name().to_string_lossy().starts_with('.') {
+            continue;
+        }
+        match entry.metadata() {
+            Ok(metadata) if metadata.is_dir() => list_files(&entry.path(), files),
+            Ok(metadata) if metadata.is_file() => files.push((entry.path(), metadata.len())),
+            _ => {}
+        }
+    }
+}
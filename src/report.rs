@@ -0,0 +1,361 @@
+//! What a sync did, as a list of actions, and the reports and file lists written from it.
+
+use std::{collections::BTreeSet, fmt, fs::{self, File}, io::{self, ErrorKind, Write}, path::{Path, PathBuf}};
+use log::*;
+use serde::Serialize;
+use m3trans::{Library, Playlist, Track, plan::PathSeparator};
+
+use crate::args::Args;
+use crate::checksums;
+use crate::context;
+use crate::manifest;
+use crate::sync::sink::write_atomically;
+
+/// Writes the paths of the files in the output, relative to it, one to a line as `rsync
+/// --files-from` reads them: every track and playlist file that was written, or was already there
+/// and left as it was.
+pub(crate) fn write_file_list(path: &Path, args: &Args, actions: &[Action]) -> Result<(), io::Error> {
+    let mut files = BTreeSet::new();
+    for action in actions {
+        let listed = match action.kind {
+            ActionKind::CopyTrack | ActionKind::LinkTrack | ActionKind::MoveTrack | ActionKind::WritePlaylist => {
+                action.status != ActionStatus::Failed
+            }
+            #[cfg(feature = "art")]
+            ActionKind::WriteArt => action.status != ActionStatus::Failed,
+            ActionKind::Skip => matches!(action.reason, Some("unchanged") | Some("exists")),
+            ActionKind::CreateDir | ActionKind::Delete => false,
+        };
+        let destination = match action.destination.as_deref().filter(|_| listed) {
+            Some(destination) => Path::new(destination),
+            None => continue,
+        };
+        // Folders of playlists kept from earlier runs are skipped as existing too.
+        if let Some(relative) = destination.strip_prefix(&args.output_path).ok().filter(|_| !destination.is_dir()) {
+            files.insert(PathSeparator::Slash.render(relative));
+        }
+    }
+    // Written without an action of its own.
+    if args.checksums && args.output_path.join(checksums::FILE_NAME).is_file() {
+        files.insert(checksums::FILE_NAME.to_owned());
+    }
+    if !args.no_manifest && args.output_path.join(manifest::FILE_NAME).is_file() {
+        files.insert(manifest::FILE_NAME.to_owned());
+    }
+    if args.dry_run {
+        info!("Write list of {} files at path {:?}", files.len(), path);
+        return Ok(());
+    }
+    let mut contents = String::new();
+    for file in &files {
+        contents.push_str(file);
+        contents.push('\n');
+    }
+    write_atomically(path, contents.as_bytes())?;
+    info!(
+        "Wrote list of {} files at {:?}; transfer them with: rsync -a --copy-links --files-from={:?} {:?} DESTINATION",
+        files.len(), path, path, args.output_path
+    );
+    Ok(())
+}
+
+/// Something a sync did or, in a dry run, would do, as reported by `--output-format json`.
+#[derive(Serialize)]
+pub(crate) struct Action {
+    pub(crate) kind: ActionKind,
+    /// Why something was skipped.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) reason: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) track_id: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) playlist_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) source: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) destination: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) size_bytes: Option<u64>,
+    pub(crate) status: ActionStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) error: Option<String>,
+    /// The track IDs of the entries of a playlist file, for the report.
+    #[serde(skip)]
+    pub(crate) track_ids: Option<Vec<u64>>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum ActionKind {
+    CopyTrack,
+    LinkTrack,
+    MoveTrack,
+    WritePlaylist,
+    #[cfg(feature = "art")]
+    WriteArt,
+    CreateDir,
+    Skip,
+    Delete,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum ActionStatus {
+    /// Not carried out, as this is a dry run.
+    Planned,
+    Done,
+    Failed,
+}
+
+impl Action {
+    pub(crate) fn new(kind: ActionKind, dry_run: bool) -> Action {
+        Action {
+            kind,
+            reason: None,
+            track_id: None,
+            playlist_id: None,
+            name: None,
+            source: None,
+            destination: None,
+            size_bytes: None,
+            status: match dry_run {
+                true => ActionStatus::Planned,
+                false => ActionStatus::Done,
+            },
+            error: None,
+            track_ids: None,
+        }
+    }
+
+    pub(crate) fn skip(reason: &'static str) -> Action {
+        Action { reason: Some(reason), ..Action::new(ActionKind::Skip, false) }
+    }
+
+    pub(crate) fn track(mut self, track_id: u64, track: &Track) -> Action {
+        self.track_id = Some(track_id);
+        self.name = Some(track.name.clone());
+        self
+    }
+
+    pub(crate) fn playlist(mut self, playlist: &Playlist) -> Action {
+        self.playlist_id = Some(format!("{:016X}", playlist.persistent_id));
+        self.name = Some(playlist.name.clone());
+        self
+    }
+
+    pub(crate) fn entries(mut self, track_ids: &[u64]) -> Action {
+        self.track_ids = Some(track_ids.to_vec());
+        self
+    }
+
+    pub(crate) fn source(mut self, path: &Path) -> Action {
+        self.source = Some(path.to_string_lossy().into_owned());
+        self
+    }
+
+    pub(crate) fn destination(mut self, path: &Path) -> Action {
+        self.destination = Some(path.to_string_lossy().into_owned());
+        self
+    }
+
+    /// Marks the action as failed if `result` is an error.
+    pub(crate) fn result<T>(mut self, result: &Result<T, io::Error>) -> Action {
+        if let Err(e) = result {
+            self.status = ActionStatus::Failed;
+            self.error = Some(e.to_string());
+        }
+        self
+    }
+}
+
+/// The delimiter of the report at `path`, and the path of the report of playlist files next to it.
+fn report_layout(path: &Path) -> (char, PathBuf) {
+    let delimiter = match path.extension().and_then(|ext| ext.to_str()) {
+        Some(ext) if ext.eq_ignore_ascii_case("tsv") => '\t',
+        _ => ',',
+    };
+    let mut name = path.file_stem().unwrap_or_default().to_os_string();
+    name.push("-playlists");
+    if let Some(ext) = path.extension() {
+        name.push(".");
+        name.push(ext);
+    }
+    (delimiter, path.with_file_name(name))
+}
+
+/// What came of an action, as the report puts it.
+pub(crate) fn outcome(action: &Action) -> &'static str {
+    match (action.kind, action.status, action.reason) {
+        (_, ActionStatus::Failed, _) => "failed",
+        (ActionKind::CopyTrack, ActionStatus::Planned, _) => "would_copy",
+        (ActionKind::CopyTrack, ActionStatus::Done, _) => "copied",
+        (ActionKind::LinkTrack, ActionStatus::Planned, _) => "would_link",
+        (ActionKind::LinkTrack, ActionStatus::Done, _) => "linked",
+        (ActionKind::MoveTrack, ActionStatus::Planned, _) => "would_move",
+        (ActionKind::MoveTrack, ActionStatus::Done, _) => "moved",
+        (ActionKind::WritePlaylist, ActionStatus::Planned, _) => "would_write",
+        (ActionKind::WritePlaylist, ActionStatus::Done, _) => "written",
+        #[cfg(feature = "art")]
+        (ActionKind::WriteArt, ActionStatus::Planned, _) => "would_write",
+        #[cfg(feature = "art")]
+        (ActionKind::WriteArt, ActionStatus::Done, _) => "written",
+        (ActionKind::Delete, ActionStatus::Planned, _) => "would_delete",
+        (ActionKind::Delete, ActionStatus::Done, _) => "deleted",
+        (ActionKind::CreateDir, ActionStatus::Planned, _) => "would_create",
+        (ActionKind::CreateDir, ActionStatus::Done, _) => "created",
+        // Left out on purpose, rather than because of how things stand.
+        (_, _, Some("unselected" | "filtered" | "disabled" | "not_included" | "ignored" | "smart_filtered")) => "ignored",
+        _ => "skipped",
+    }
+}
+
+/// The tracks and playlist files among `actions`, which are the rows of the report.
+fn report_rows(actions: &[Action]) -> (impl Iterator<Item = &Action>, impl Iterator<Item = &Action>) {
+    let tracks = actions.iter().filter(|action| action.track_id.is_some() && action.kind != ActionKind::CreateDir);
+    // The index, and stale playlist files that were deleted, belong to no playlist.
+    let playlists = actions.iter().filter(|action| action.playlist_id.is_some() || matches!(action.kind, ActionKind::WritePlaylist | ActionKind::Delete));
+    (tracks, playlists)
+}
+
+/// Reads the report of an earlier sync written to `path` with --report, for --compare-report.
+pub(crate) fn read_report(path: &Path) -> Result<crate::drift::Report, io::Error> {
+    let (delimiter, playlists_path) = report_layout(path);
+    let tracks = fs::read_to_string(path).map_err(context(format!("failed to read report {:?}", path)))?;
+    let playlists = fs::read_to_string(&playlists_path).map_err(context(format!("failed to read report {:?}", playlists_path)))?;
+    crate::drift::Report::parse(&tracks, &playlists, delimiter)
+        .map_err(|e| io::Error::new(ErrorKind::InvalidData, format!("failed to read report {:?}: {}", path, e)))
+}
+
+/// The report `actions` would be written as, to compare with an earlier one.
+pub(crate) fn actions_report(actions: &[Action]) -> crate::drift::Report {
+    let (track_actions, playlist_actions) = report_rows(actions);
+    let text = |field: &Option<String>| field.clone().unwrap_or_default();
+    crate::drift::Report {
+        tracks: track_actions
+            .map(|action| crate::drift::TrackRow {
+                track_id: action.track_id.unwrap_or_default(),
+                name: text(&action.name),
+                destination: text(&action.destination),
+                action: outcome(action).to_owned(),
+                reason: action.reason.unwrap_or_default().to_owned(),
+            })
+            .collect(),
+        playlists: playlist_actions
+            .map(|action| crate::drift::PlaylistRow {
+                playlist_id: text(&action.playlist_id),
+                name: text(&action.name),
+                destination: text(&action.destination),
+                action: outcome(action).to_owned(),
+                reason: action.reason.unwrap_or_default().to_owned(),
+                tracks: action.track_ids.clone(),
+            })
+            .collect(),
+    }
+}
+
+/// What --output-format json prints with --compare-report.
+#[derive(Serialize)]
+pub(crate) struct ComparedActions<'a> {
+    pub(crate) actions: &'a [Action],
+    pub(crate) drift: &'a crate::drift::Drift,
+}
+
+/// Prints what changed since an earlier report, in the style of `m3trans diff`.
+pub(crate) fn print_drift(drift: &crate::drift::Drift) -> Result<(), io::Error> {
+    if !drift.compared_playlists {
+        warn!("The earlier report doesn't list the entries of playlists, so they weren't compared.");
+    }
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    for track in &drift.new_tracks {
+        writeln!(out, "+ track {} ({})", track.name, track.track_id)?;
+    }
+    for track in &drift.removed_tracks {
+        writeln!(out, "- track {} ({}), no longer in the library", track.name, track.track_id)?;
+    }
+    for playlist in &drift.changed_playlists {
+        match (playlist.added_tracks, playlist.removed_tracks) {
+            (0, 0) => writeln!(out, "~ playlist {} reordered ({})", playlist.name, playlist.playlist_id)?,
+            (added, removed) => writeln!(
+                out, "~ playlist {} gained {} and lost {} tracks ({})",
+                playlist.name, added, removed, playlist.playlist_id
+            )?,
+        }
+    }
+    for file in &drift.missing_files {
+        match file.name.is_empty() {
+            true => writeln!(out, "! missing {}", file.path)?,
+            false => writeln!(out, "! missing {} ({})", file.path, file.name)?,
+        }
+    }
+    if drift.is_empty() {
+        writeln!(out, "No changes since the earlier report.")?;
+    }
+    Ok(())
+}
+
+/// Writes the tracks among `actions` to a report at `path`, and the playlist files to another next
+/// to it.
+pub(crate) fn write_report(path: &Path, actions: &[Action], library: &Library) -> Result<(), io::Error> {
+    let (delimiter, playlists_path) = report_layout(path);
+    let (track_actions, playlist_actions) = report_rows(actions);
+    let create = |path: &Path| File::create(path)
+        .map(|file| crate::csv::Writer::new(io::BufWriter::new(file), delimiter))
+        .map_err(context(format!("failed to create report {:?}", path)));
+    let finish = |path: &Path, writer: crate::csv::Writer<io::BufWriter<File>>| writer.into_inner().flush()
+        .map_err(context(format!("failed to write report {:?}", path)));
+    let text = |field: &Option<String>| field.clone().unwrap_or_default();
+
+    let mut tracks = create(path)?;
+    let failed = |e| context(format!("failed to write report {:?}", path))(e);
+    tracks.write_record(["track_id", "name", "artist", "source", "destination", "action", "reason", "bytes", "error"])
+        .map_err(failed)?;
+    for action in track_actions {
+        let track_id = action.track_id.unwrap_or_default();
+        let artist = library.tracks.get(&track_id).and_then(|track| track.artist.clone()).unwrap_or_default();
+        let fields = [
+            track_id.to_string(),
+            text(&action.name),
+            artist,
+            text(&action.source),
+            text(&action.destination),
+            outcome(action).to_owned(),
+            action.reason.unwrap_or_default().to_owned(),
+            action.size_bytes.map(|size| size.to_string()).unwrap_or_default(),
+            text(&action.error),
+        ];
+        tracks.write_record(fields.iter().map(String::as_str)).map_err(failed)?;
+    }
+    finish(path, tracks)?;
+
+    let mut playlists = create(&playlists_path)?;
+    let failed = |e| context(format!("failed to write report {:?}", playlists_path))(e);
+    playlists.write_record(["playlist_id", "name", "destination", "action", "reason", "error", "tracks"]).map_err(failed)?;
+    for action in playlist_actions {
+        let track_ids = action.track_ids.iter().flatten().map(u64::to_string).collect::<Vec<_>>().join(" ");
+        let fields = [
+            text(&action.playlist_id),
+            text(&action.name),
+            text(&action.destination),
+            outcome(action).to_owned(),
+            action.reason.unwrap_or_default().to_owned(),
+            text(&action.error),
+            track_ids,
+        ];
+        playlists.write_record(fields.iter().map(String::as_str)).map_err(failed)?;
+    }
+    finish(&playlists_path, playlists)?;
+    info!("Wrote a report of the sync to {:?} and {:?}.", path, playlists_path);
+    Ok(())
+}
+
+/// Formats a persistent ID as it appears in the library, for log fields.
+pub(crate) struct Hex(pub(crate) u64);
+
+impl fmt::Display for Hex {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:016X}", self.0)
+    }
+}
@@ -0,0 +1,170 @@
+//! `m3trans self-test`, which syncs a small made-up library and checks what it writes.
+
+use std::{collections::HashMap, ffi::{OsStr, OsString}, fs::{self, File}, io::{self, Write}, path::Path};
+use structopt::StructOpt;
+use m3trans::Track;
+
+use crate::args::{Args, SelfTestArgs, parse_args};
+use crate::context;
+use crate::manifest;
+use crate::sync::run_sync;
+
+/// The tracks of the library `m3trans self-test` makes up, by track ID and name, with where their
+/// files are in its media folder. Their names need escaping in locations and file names.
+const SELF_TEST_TRACKS: &[(u64, &str, &str)] = &[
+    (1, "Song One", "Band A/Song One.mp3"),
+    (2, "Trés Bien", "Ümlaut/Trés Bien.m4a"),
+    (3, "What?", "Odd #1/100% [Live].flac"),
+    (4, "Song, With Comma", "Band A/Song, With Comma.mp3"),
+];
+
+/// The playlist files `m3trans self-test` expects, relative to the output, with the tracks of
+/// their entries. The library has them in folders, some with names that aren't valid file names.
+const SELF_TEST_PLAYLISTS: &[(&str, &[u64])] = &[
+    ("playlists/Top.m3u8", &[1, 2]),
+    ("playlists/Rock & Roll/Drum & Bass _ Jungle.m3u8", &[3]),
+    ("playlists/Rock & Roll/Deep/Mix Vol. 2.m3u8", &[1, 3, 1]),
+    ("playlists/Café del Mar.m3u8", &[2, 4]),
+];
+
+/// Makes up a library of `SELF_TEST_TRACKS` in a temporary folder, with files of made-up contents
+/// the size of short songs, and syncs it into another folder there just as m3trans syncs any
+/// library. Then checks that every track was copied and every playlist written with the entries
+/// it should have, printing how each check went, and fails if any didn't pass.
+pub(crate) fn run_self_test(args: &SelfTestArgs) -> Result<(), io::Error> {
+    let dir = std::env::temp_dir().join(format!("m3trans-self-test-{}", std::process::id()));
+    // Left from an earlier test that happened to have the same process ID.
+    if dir.exists() {
+        fs::remove_dir_all(&dir).map_err(context(format!("failed to remove {:?}", dir)))?;
+    }
+
+    let mut builder = m3trans::LibraryBuilder::new();
+    let mut sources = HashMap::new();
+    for &(id, name, file) in SELF_TEST_TRACKS {
+        let path = dir.join("media").join(file);
+        let contents = self_test_audio(id);
+        fs::create_dir_all(path.parent().unwrap_or(&dir))
+            .and_then(|()| fs::write(&path, &contents))
+            .map_err(context(format!("failed to write {:?}", path)))?;
+        let path_text = path.to_str().ok_or_else(|| io::Error::other(format!("the temporary folder {:?} isn't valid UTF-8", dir)))?;
+        builder.track_with(id, Track {
+            name: name.to_owned(),
+            location: Some(format!("file://localhost/{}", path_text.replace('\\', "/").trim_start_matches('/'))),
+            duration_ms: Some(self_test_duration_ms(id)),
+            size_bytes: Some(contents.len() as u64),
+            ..Track::default()
+        });
+        sources.insert(id, contents);
+    }
+    builder
+        .playlist("Top", [1, 2])
+        .folder("Rock & Roll", |folder| {
+            folder.playlist("Drum & Bass / Jungle", [3]).folder("Deep", |deep| {
+                deep.playlist("Mix Vol. 2", [1, 3, 1]);
+            });
+        })
+        .playlist("Café del Mar", [2, 4]);
+    let library_path = dir.join("Library.xml");
+    File::create(&library_path)
+        .and_then(|file| builder.build().to_writer_xml(io::BufWriter::new(file)).map_err(io::Error::other))
+        .map_err(context(format!("failed to write {:?}", library_path)))?;
+    // So that no m3trans.toml in the current folder changes how it is synced.
+    let config_path = dir.join("m3trans.toml");
+    fs::write(&config_path, "").map_err(context(format!("failed to write {:?}", config_path)))?;
+
+    let output_path = dir.join("output");
+    let cli = vec![
+        OsString::from("m3trans"),
+        library_path.into_os_string(),
+        output_path.clone().into_os_string(),
+        OsString::from("--config"),
+        config_path.into_os_string(),
+    ];
+    let synced = parse_args(cli, Args::clap).and_then(|args| run_sync(&args));
+
+    let mut checks: Vec<(String, Result<(), String>)> = vec![("sync".to_owned(), synced.map_err(|e| e.to_string()))];
+    let copy_of = |id: u64| {
+        let &(_, name, file) = SELF_TEST_TRACKS.iter().find(|(track_id, ..)| *track_id == id).expect("the playlists only have these tracks");
+        let extension = Path::new(file).extension().and_then(OsStr::to_str).unwrap_or_default();
+        (name, format!("tracks/{}.{}", id, extension))
+    };
+    for &(id, ..) in SELF_TEST_TRACKS {
+        let (name, copy) = copy_of(id);
+        let result = match fs::read(output_path.join(&copy)) {
+            Ok(contents) if contents == sources[&id] => Ok(()),
+            Ok(_) => Err("it differs from the track's file".to_owned()),
+            Err(e) => Err(e.to_string()),
+        };
+        checks.push((format!("track {} is copied to {}", name, copy), result));
+    }
+    for &(playlist, items) in SELF_TEST_PLAYLISTS {
+        let prefix = "../".repeat(Path::new(playlist).components().count() - 1);
+        let expected: Vec<String> = items.iter()
+            .flat_map(|&id| {
+                let (name, copy) = copy_of(id);
+                [format!("#EXTINF:{},{}", self_test_duration_ms(id) / 1000, name), format!("{}{}", prefix, copy)]
+            })
+            .collect();
+        let result = match fs::read_to_string(output_path.join(playlist)) {
+            Ok(contents) => {
+                let entries: Vec<String> = contents.lines()
+                    .filter(|line| line.starts_with("#EXTINF:") || !line.starts_with('#'))
+                    .map(str::to_owned)
+                    .collect();
+                match entries == expected {
+                    true => Ok(()),
+                    false => Err(format!("its entries are {:?} rather than {:?}", entries, expected)),
+                }
+            }
+            Err(e) => Err(e.to_string()),
+        };
+        checks.push((format!("playlist {} is written", playlist), result));
+    }
+    let result = match fs::read_to_string(output_path.join(manifest::FILE_NAME)) {
+        Ok(contents) => match SELF_TEST_TRACKS.iter().find(|(id, ..)| !contents.contains(&format!("\"track_id\": {},", id))) {
+            Some((id, ..)) => Err(format!("it doesn't list track {}", id)),
+            None => Ok(()),
+        },
+        Err(e) => Err(e.to_string()),
+    };
+    checks.push((format!("{} lists every track", manifest::FILE_NAME), result));
+
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    for (check, result) in &checks {
+        match result {
+            Ok(()) => writeln!(out, "ok    {}", check)?,
+            Err(e) => writeln!(out, "FAIL  {}: {}", check, e)?,
+        }
+    }
+    let failures = checks.iter().filter(|(_, result)| result.is_err()).count();
+    if failures > 0 {
+        return Err(io::Error::other(format!(
+            "the self-test failed {} of {} checks; what it wrote is left in {:?}",
+            failures, checks.len(), dir
+        )));
+    }
+    writeln!(out, "Passed all {} checks.", checks.len())?;
+    match args.keep {
+        true => writeln!(out, "What the self-test wrote is left in {:?}.", dir),
+        false => fs::remove_dir_all(&dir).map_err(context(format!("failed to remove {:?}", dir))),
+    }
+}
+
+/// How long track `id` of the self-test lasts, in whole seconds so that it is written exactly.
+fn self_test_duration_ms(id: u64) -> u32 {
+    180_000 + 1000 * id as u32
+}
+
+/// Made-up contents for the file of track `id` of the self-test, different for every track.
+fn self_test_audio(id: u64) -> Vec<u8> {
+    let mut state = id;
+    let mut next = || {
+        // xorshift64*, which never gets stuck at zero as long as it doesn't start there.
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        state.wrapping_mul(0x2545_F491_4F6C_DD1D).to_le_bytes()
+    };
+    (0..256 * 1024 / 8).flat_map(|_| next()).collect()
+}
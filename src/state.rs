@@ -0,0 +1,102 @@
+//! The state m3trans keeps in each output directory between runs, so that later runs can reuse
+//! what earlier ones worked out.
+//!
+//! It's a text file of tab-separated lines, each starting with what kind of record it is. Lines of
+//! kinds that aren't known are ignored, so that older versions can read the state of newer ones.
+
+use std::{collections::HashMap, fs, io::{self, BufRead, BufReader, BufWriter, Write}, path::{Path, PathBuf}, time::{Duration, UNIX_EPOCH}};
+
+use crate::sha256::Digest;
+
+/// The name of the state file, in the output directory.
+pub(crate) const FILE_NAME: &str = "m3trans.state";
+
+#[derive(Debug, Default)]
+pub(crate) struct State {
+    /// The digests of source files, by their path.
+    pub(crate) hashes: HashMap<PathBuf, FileHash>,
+    /// The digest of what the last sync that succeeded in full was made from, so that a run with
+    /// nothing new to sync can stop early.
+    pub(crate) fingerprint: Option<Digest>,
+}
+
+/// The digest of a file, along with its size and modification time to tell whether it still holds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct FileHash {
+    pub(crate) size: u64,
+    /// The time since the Unix epoch.
+    pub(crate) modified: Duration,
+    pub(crate) digest: Digest,
+}
+
+impl FileHash {
+    /// Whether the file with this metadata is probably still the one that was hashed.
+    pub(crate) fn matches(&self, metadata: &fs::Metadata) -> bool {
+        self.size == metadata.len() && Some(self.modified) == modified(metadata)
+    }
+}
+
+/// The modification time of a file since the Unix epoch, where the platform has one.
+pub(crate) fn modified(metadata: &fs::Metadata) -> Option<Duration> {
+    metadata.modified().ok()?.duration_since(UNIX_EPOCH).ok()
+}
+
+impl State {
+    /// Reads the state file at `path`, which is empty if there is none yet. Lines that can't be
+    /// parsed are left out, as all of it can be worked out again.
+    pub(crate) fn read(path: &Path) -> Result<State, io::Error> {
+        let file = match fs::File::open(path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(State::default()),
+            Err(e) => return Err(e),
+        };
+        let mut state = State::default();
+        for line in BufReader::new(file).lines() {
+            let line = line?;
+            let mut fields = line.split('\t');
+            match fields.next() {
+                Some("hash") => {
+                    if let Some((path, hash)) = parse_hash(fields) {
+                        state.hashes.insert(path, hash);
+                    }
+                }
+                Some("fingerprint") => state.fingerprint = fields.next().and_then(|digest| digest.parse().ok()),
+                _ => {}
+            }
+        }
+        Ok(state)
+    }
+
+    /// Replaces the state file at `path` with this state.
+    pub(crate) fn write(&self, path: &Path) -> Result<(), io::Error> {
+        let mut writer = BufWriter::new(fs::File::create(path)?);
+        writeln!(writer, "# The state of m3trans between runs, rewritten by each. Safe to delete.")?;
+        if let Some(fingerprint) = self.fingerprint {
+            writeln!(writer, "fingerprint\t{}", fingerprint)?;
+        }
+        let mut hashes: Vec<_> = self.hashes.iter()
+            .filter_map(|(path, hash)| Some((path.to_str()?, hash)))
+            .collect();
+        hashes.sort_unstable_by_key(|(path, _)| *path);
+        for (path, hash) in hashes {
+            // Paths are last, so that they may hold tabs.
+            writeln!(
+                writer, "hash\t{}\t{}.{:09}\t{}\t{}",
+                hash.size, hash.modified.as_secs(), hash.modified.subsec_nanos(), hash.digest, path
+            )?;
+        }
+        writer.into_inner().map_err(io::IntoInnerError::into_error)?.sync_all()
+    }
+}
+
+fn parse_hash<'a>(mut fields: impl Iterator<Item = &'a str>) -> Option<(PathBuf, FileHash)> {
+    let size = fields.next()?.parse().ok()?;
+    let (secs, nanos) = fields.next()?.split_once('.')?;
+    let modified = Duration::new(secs.parse().ok()?, nanos.parse().ok()?);
+    let digest = fields.next()?.parse().ok()?;
+    let path = fields.collect::<Vec<_>>().join("\t");
+    match path.is_empty() {
+        true => None,
+        false => Some((PathBuf::from(path), FileHash { size, modified, digest })),
+    }
+}
@@ -0,0 +1,133 @@
+//! Totals over a whole library, for deciding what to sync.
+
+use std::collections::HashMap;
+
+use serde::Serialize;
+
+use m3trans::Library;
+
+/// How many of the largest tracks are listed.
+const LARGEST_TRACKS: usize = 10;
+
+#[derive(Debug, Serialize)]
+pub(crate) struct LibraryStats {
+    pub(crate) tracks: usize,
+    /// Of the tracks that have a duration.
+    pub(crate) duration_ms: u64,
+    /// Of the tracks that have a size, as of the export.
+    pub(crate) size_bytes: u64,
+    /// Most tracks first.
+    pub(crate) genres: Vec<Group>,
+    /// Most tracks first.
+    pub(crate) composers: Vec<Group>,
+    /// Most tracks first.
+    pub(crate) formats: Vec<Group>,
+    /// Most playlists first.
+    pub(crate) playlist_kinds: Vec<KindCount>,
+    /// Over regular and smart playlists, leaving out folders and system playlists.
+    pub(crate) average_playlist_tracks: f64,
+    /// Largest first.
+    pub(crate) largest_tracks: Vec<LargeTrack>,
+}
+
+/// The tracks sharing a genre, composer or format.
+#[derive(Debug, Serialize)]
+pub(crate) struct Group {
+    /// Missing for tracks without a genre or composer, or in a format that isn't known.
+    pub(crate) name: Option<String>,
+    pub(crate) tracks: usize,
+    pub(crate) duration_ms: u64,
+    pub(crate) size_bytes: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct KindCount {
+    /// As in `m3trans export`, with every unknown kind counted together.
+    pub(crate) kind: &'static str,
+    pub(crate) playlists: usize,
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct LargeTrack {
+    pub(crate) track_id: u64,
+    pub(crate) name: String,
+    pub(crate) artist: Option<String>,
+    pub(crate) size_bytes: u64,
+}
+
+pub(crate) fn stats(library: &Library) -> LibraryStats {
+    let mut track_ids: Vec<u64> = library.tracks.keys().copied().collect();
+    track_ids.sort_unstable();
+    let (mut duration_ms, mut size_bytes) = (0, 0);
+    let mut genres: HashMap<Option<String>, Group> = HashMap::new();
+    let mut composers: HashMap<Option<String>, Group> = HashMap::new();
+    let mut formats: HashMap<Option<String>, Group> = HashMap::new();
+    for &id in &track_ids {
+        let track = &library.tracks[&id];
+        let (track_duration, track_size) = (track.duration_ms.map_or(0, u64::from), track.size_bytes.unwrap_or(0));
+        duration_ms += track_duration;
+        size_bytes += track_size;
+        let genre = track.genre.clone().filter(|genre| !genre.trim().is_empty());
+        let composer = track.composer.clone().filter(|composer| !composer.trim().is_empty());
+        let format = track.file_format().map(|format| format.name().to_owned());
+        for (groups, name) in [(&mut genres, genre), (&mut composers, composer), (&mut formats, format)] {
+            let group = groups.entry(name.clone())
+                .or_insert_with(|| Group { name, tracks: 0, duration_ms: 0, size_bytes: 0 });
+            group.tracks += 1;
+            group.duration_ms += track_duration;
+            group.size_bytes += track_size;
+        }
+    }
+
+    let mut kinds: HashMap<&'static str, usize> = HashMap::new();
+    let (mut listed_playlists, mut listed_tracks) = (0, 0);
+    for playlist in library.playlists.values() {
+        *kinds.entry(playlist.kind.name()).or_default() += 1;
+        if playlist.kind.is_generic() {
+            listed_playlists += 1;
+            // Entries for tracks that aren't in the library are left out of playlists when written.
+            listed_tracks += library.playlist_tracks(playlist).filter(Result::is_ok).count();
+        }
+    }
+    let mut playlist_kinds: Vec<KindCount> = kinds.into_iter()
+        .map(|(kind, playlists)| KindCount { kind, playlists })
+        .collect();
+    playlist_kinds.sort_by(|a, b| b.playlists.cmp(&a.playlists).then(a.kind.cmp(b.kind)));
+
+    let mut largest: Vec<u64> = track_ids.iter().copied()
+        .filter(|id| library.tracks[id].size_bytes.is_some())
+        .collect();
+    // By size, and then by ID so that ties always come out the same.
+    largest.sort_by_key(|id| (std::cmp::Reverse(library.tracks[id].size_bytes), *id));
+    let largest_tracks = largest.into_iter().take(LARGEST_TRACKS)
+        .map(|id| {
+            let track = &library.tracks[&id];
+            LargeTrack { track_id: id, name: track.name.clone(), artist: track.artist.clone(), size_bytes: track.size_bytes.unwrap_or(0) }
+        })
+        .collect();
+
+    LibraryStats {
+        tracks: track_ids.len(),
+        duration_ms,
+        size_bytes,
+        genres: sorted_groups(genres),
+        composers: sorted_groups(composers),
+        formats: sorted_groups(formats),
+        playlist_kinds,
+        average_playlist_tracks: match listed_playlists {
+            0 => 0.0,
+            count => listed_tracks as f64 / count as f64,
+        },
+        largest_tracks,
+    }
+}
+
+/// Most tracks first, and otherwise by name, with the nameless last.
+fn sorted_groups(groups: HashMap<Option<String>, Group>) -> Vec<Group> {
+    let mut groups: Vec<Group> = groups.into_values().collect();
+    groups.sort_by(|a, b| b.tracks.cmp(&a.tracks).then_with(|| match (&a.name, &b.name) {
+        (Some(a), Some(b)) => a.cmp(b),
+        (a, b) => b.is_some().cmp(&a.is_some()),
+    }));
+    groups
+}
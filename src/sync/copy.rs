@@ -0,0 +1,798 @@
+//! Copying the planned tracks into an output directory.
+
+use std::{collections::{BTreeMap, HashMap, HashSet}, fs::{self, File}, io::{self, ErrorKind}, path::{Path, PathBuf}, time::{Duration, Instant}};
+use log::*;
+use m3trans::{Library, Track, observer::TrackOutcome, plan::{PathSeparator, SyncPlan, TrackCopy}};
+
+use crate::args::{Args, output_dir, relative_dir};
+use crate::checksums;
+use crate::interrupt::interrupted;
+use crate::context;
+use crate::repair::repair_tracks;
+use crate::report::{Action, ActionKind, ActionStatus};
+use crate::sha256::{self, Digest};
+use crate::state::{self, FileHash, State};
+use crate::sync::{check_strict, create_output_dir};
+use crate::sync::sink::{Sink, link_atomically, remove_temp_files, with_retries};
+
+/// The name album art is written to in the folders of tracks, before its extension.
+#[cfg(feature = "art")]
+const COVER_STEM: &str = "cover";
+
+/// A track to copy to each output directory.
+#[derive(Clone)]
+pub(crate) struct PlannedTrack<'a> {
+    pub(crate) id: u64,
+    pub(crate) track: &'a Track,
+    pub(crate) local_path: PathBuf,
+    /// Tracks that were added to the library by hand may lack a size, as may files that are gone.
+    pub(crate) size: Option<u64>,
+}
+
+/// Fails if the output folder is inside the library's media folder, where its copies could be
+/// taken for the library's own files, going by where both are once symbolic links are resolved.
+pub(crate) fn check_outside_media(library: &Library, args: &Args) -> Result<(), io::Error> {
+    let music_folder = match library.music_folder_path(&args.remap_hosts) {
+        Ok(Some(music_folder)) if music_folder.exists() => resolve(&music_folder),
+        _ => return Ok(()),
+    };
+    let output_path = resolve(&args.output_path);
+    if output_path.starts_with(&music_folder) {
+        return Err(io::Error::new(ErrorKind::InvalidInput, format!(
+            "the output folder {:?} is inside the library's media folder {:?}; pass --allow-overlap to sync there anyway",
+            output_path, music_folder
+        )));
+    }
+    Ok(())
+}
+
+/// Fails if any of the planned tracks would be copied over its own file, as when the output is
+/// where the library keeps its files.
+fn check_not_over_sources(planned: &[PlannedTrack<'_>], remote_path_of: &HashMap<u64, PathBuf>, args: &Args) -> Result<(), io::Error> {
+    for planned in planned {
+        let destination = args.output_path.join(&remote_path_of[&planned.id]);
+        // Files that aren't there yet can't be the source.
+        if !destination.exists() || resolve(&destination) != resolve(&planned.local_path) {
+            continue;
+        }
+        return Err(io::Error::new(ErrorKind::InvalidInput, format!(
+            "track {} at {:?} would be copied over itself at {:?}; pass --allow-overlap to sync anyway",
+            planned.track.name, planned.local_path, destination
+        )));
+    }
+    Ok(())
+}
+
+/// `path` made absolute, with symbolic links resolved as far as it exists.
+fn resolve(path: &Path) -> PathBuf {
+    let mut missing = Vec::new();
+    let mut existing = path;
+    loop {
+        let candidate = match existing.as_os_str().is_empty() {
+            true => Path::new("."),
+            false => existing,
+        };
+        if let Ok(resolved) = fs::canonicalize(candidate) {
+            return missing.iter().rev().fold(resolved, |resolved, name| resolved.join(name));
+        }
+        match (existing.parent(), existing.file_name()) {
+            (Some(parent), Some(name)) => {
+                missing.push(name);
+                existing = parent;
+            }
+            _ => return std::path::absolute(path).unwrap_or_else(|_| path.to_owned()),
+        }
+    }
+}
+
+/// Copies the planned tracks into the tracks folder of the output directory one at a time, keeping
+/// what it takes to report on them all once they are done.
+pub(crate) struct TrackCopier<'a> {
+    library: &'a Library,
+    args: &'a Args,
+    sink: &'a dyn Sink,
+    /// The tracks to copy, in the order of the plan.
+    pub(crate) planned: Vec<PlannedTrack<'a>>,
+    tracks_dir: PathBuf,
+    state_path: PathBuf,
+    state: State,
+    /// The hashes of files from the last run, of which only those hashed again are kept.
+    cached: HashMap<PathBuf, FileHash>,
+    /// Tracks moved into place by --repair.
+    moved: HashSet<u64>,
+    estimated_bytes: u64,
+    digests: HashMap<u64, Digest>,
+    /// Where the first copy of each of those contents went, for the rest to link to.
+    first_copies: HashMap<Digest, PathBuf>,
+    linked: usize,
+    saved_bytes: u64,
+    /// Tracks whose files were already there and left alone.
+    kept: HashSet<u64>,
+    /// Folders inside the tracks folder, for tracks named after their albums.
+    folders: HashSet<PathBuf>,
+    conflicts: usize,
+    /// With --check-content, the files found unchanged, and those copied over ones that differed.
+    unchanged: usize,
+    updated: usize,
+    /// Without it, the files kept by --append-only whose tracks' files were modified since.
+    modified_since: usize,
+    copied: usize,
+    copied_bytes: u64,
+    /// Only of the tracks that were really copied, as cloning takes next to no time.
+    timed_bytes: u64,
+    copy_time: Duration,
+}
+
+impl<'a> TrackCopier<'a> {
+    /// Gets the tracks folder of the output directory ready for the tracks of `plan`, checking
+    /// that they fit.
+    pub(crate) fn start(library: &'a Library, plan: &SyncPlan, args: &'a Args, sink: &'a dyn Sink, actions: &mut Vec<Action>) -> Result<Self, io::Error> {
+        let tracks_dir = output_dir(&args.output_path, &args.tracks_dir);
+        create_output_dir(&tracks_dir, args.dry_run, sink, actions)
+            .map_err(context(format!("failed to create tracks folder {:?}", tracks_dir)))?;
+        // Files of others may end in .tmp too, and --append-only leaves everything it finds alone.
+        if tracks_dir.is_dir() && !args.append_only {
+            remove_temp_files(&tracks_dir, args.dry_run);
+        }
+
+        let planned: Vec<PlannedTrack<'a>> = plan.tracks.iter()
+            .map(|copy| PlannedTrack {
+                id: copy.track_id,
+                track: &library.tracks[&copy.track_id],
+                local_path: copy.source.clone(),
+                size: copy.size_bytes,
+            })
+            .collect();
+        let remote_path_of: HashMap<u64, PathBuf> = plan.tracks.iter().map(|copy| (copy.track_id, copy.destination.clone())).collect();
+        let shared: HashSet<u64> = plan.tracks.iter().filter(|copy| copy.shares_with.is_some()).map(|copy| copy.track_id).collect();
+        let owners: Vec<PlannedTrack<'_>> = planned.iter().filter(|planned| !shared.contains(&planned.id)).cloned().collect();
+        // Staged tracks are links to their sources, which is as it should be.
+        if !args.allow_overlap && args.output_archive.is_none() && args.emit_file_list.is_none() {
+            check_not_over_sources(&planned, &remote_path_of, args)?;
+        }
+
+        let state_path = args.output_path.join(state::FILE_NAME);
+        let mut state = match args.dedupe_content || args.checksums || args.check_content {
+            true => State::read(&state_path).unwrap_or_else(|e| {
+                warn!("Failed to read the sync state at {:?}, starting over: {}", state_path, e);
+                State::default()
+            }),
+            false => State::default(),
+        };
+        // Only the hashes of files hashed this run are kept.
+        let cached = std::mem::take(&mut state.hashes);
+        // Recorded again once the whole sync has succeeded.
+        state.fingerprint = None;
+        // Before the space check, as what is moved takes no more of it.
+        let moved = match args.repair && tracks_dir.is_dir() {
+            true => repair_tracks(&owners, &remote_path_of, &tracks_dir, args, sink, actions)?,
+            false => HashSet::new(),
+        };
+
+        let (mut to_copy, mut estimated_bytes) = (0, 0);
+        // What copying takes from the free space, as files already there are replaced.
+        let (mut unknown_sizes, mut replaced_bytes) = (0, 0);
+        for planned in &planned {
+            let existing = fs::metadata(args.output_path.join(&remote_path_of[&planned.id])).ok();
+            if (args.append_only && existing.is_some()) || moved.contains(&planned.id) || shared.contains(&planned.id) {
+                continue;
+            }
+            to_copy += 1;
+            estimated_bytes += planned.size.unwrap_or(0);
+            unknown_sizes += usize::from(planned.size.is_none());
+            replaced_bytes += existing.map_or(0, |metadata| metadata.len());
+        }
+        info!("About to copy {} tracks, ~{}.", to_copy, format_size(estimated_bytes));
+        if !args.ignore_space_check && args.emit_file_list.is_none() && to_copy > 0 {
+            check_space(&tracks_dir, estimated_bytes.saturating_sub(replaced_bytes), unknown_sizes == to_copy, args.dry_run)?;
+        }
+
+        let digests = match args.dedupe_content {
+            true => {
+                let to_copy = planned.iter()
+                    .filter(|planned| !shared.contains(&planned.id))
+                    .filter(|planned| !(args.append_only && args.output_path.join(&remote_path_of[&planned.id]).exists()));
+                let (digests, hashes) = duplicate_digests(to_copy, &cached);
+                state.hashes = hashes;
+                digests
+            }
+            false => HashMap::new(),
+        };
+
+        Ok(TrackCopier {
+            library,
+            args,
+            sink,
+            planned,
+            tracks_dir,
+            state_path,
+            state,
+            cached,
+            moved,
+            estimated_bytes,
+            digests,
+            first_copies: HashMap::new(),
+            linked: 0,
+            saved_bytes: 0,
+            kept: HashSet::new(),
+            folders: HashSet::new(),
+            conflicts: 0,
+            unchanged: 0,
+            updated: 0,
+            modified_since: 0,
+            copied: 0,
+            copied_bytes: 0,
+            timed_bytes: 0,
+            copy_time: Duration::ZERO,
+        })
+    }
+
+    /// Copies a track, returning what came of it. Fails in strict mode if it can't be copied,
+    /// which ends the sync.
+    pub(crate) fn copy(&mut self, copy: &TrackCopy, actions: &mut Vec<Action>) -> Result<TrackOutcome, io::Error> {
+        let first = actions.len();
+        self.copy_inner(copy, actions)?;
+        Ok(outcome(&actions[first..]))
+    }
+
+    fn copy_inner(&mut self, copy: &TrackCopy, actions: &mut Vec<Action>) -> Result<(), io::Error> {
+        let (args, sink) = (self.args, self.sink);
+        let (track_id, local_path, size) = (copy.track_id, &copy.source, copy.size_bytes);
+        let track = &self.library.tracks[&track_id];
+        let remote_path = &copy.destination;
+        let full_remote_path = args.output_path.join(remote_path);
+        let action = |kind| {
+            let mut action = Action::new(kind, args.dry_run).track(track_id, track).source(local_path).destination(&full_remote_path);
+            action.size_bytes = size;
+            action
+        };
+
+        if copy.shares_with.is_some() {
+            // Given the path of the copy it shares once that is made.
+            actions.push(Action { reason: Some("shared_file"), ..action(ActionKind::Skip) });
+            return Ok(());
+        }
+        if args.append_only && full_remote_path.exists() {
+            let same = match args.check_content {
+                true => same_content(local_path, &full_remote_path, &self.cached, &mut self.state.hashes),
+                false => same_size(local_path, &full_remote_path),
+            };
+            if same {
+                self.modified_since += usize::from(!args.check_content && is_newer(local_path, &full_remote_path));
+                info!(
+                    event = "track_skipped", track_id, reason = "exists", dest:% = full_remote_path.display();
+                    "Keeping existing file at {:?}", full_remote_path
+                );
+                actions.push(Action { reason: Some("exists"), ..action(ActionKind::Skip) });
+            }
+            else {
+                warn!(
+                    event = "track_skipped", track_id, reason = "conflict", src:% = local_path.display(), dest:% = full_remote_path.display();
+                    "Not overwriting existing file at {:?}, which differs from {:?}",
+                    full_remote_path, local_path
+                );
+                actions.push(Action { reason: Some("conflict"), ..action(ActionKind::Skip) });
+                self.conflicts += 1;
+            }
+            self.kept.insert(track_id);
+            return Ok(());
+        }
+        // Not really moved in a dry run, so there is nothing there yet to compare.
+        if args.dry_run && self.moved.contains(&track_id) {
+            self.kept.insert(track_id);
+            return Ok(());
+        }
+        let replacing = args.check_content && full_remote_path.is_file();
+        if replacing && same_content(local_path, &full_remote_path, &self.cached, &mut self.state.hashes) {
+            info!(
+                event = "track_skipped", track_id, reason = "unchanged", dest:% = full_remote_path.display();
+                "Keeping existing file at {:?}, which has the same contents as {:?}", full_remote_path, local_path
+            );
+            actions.push(Action { reason: Some("unchanged"), ..action(ActionKind::Skip) });
+            self.unchanged += 1;
+            self.kept.insert(track_id);
+            return Ok(());
+        }
+
+        if let Some(folder) = full_remote_path.parent().filter(|folder| *folder != self.tracks_dir) {
+            if self.folders.insert(folder.to_owned()) {
+                if let Err(e) = create_output_dir(folder, args.dry_run, sink, actions) {
+                    error!(
+                        event = "folder_create_failed", track_id, dest:% = folder.display(), error:% = e;
+                        "Failed to create folder for track {} at path {:?}: {:?}", track.name, folder, e
+                    );
+                    check_strict(args)?;
+                }
+            }
+        }
+
+        let digest = self.digests.get(&track_id).copied();
+        if let Some(first_copy) = digest.and_then(|digest| self.first_copies.get(&digest)) {
+            let result = match args.dry_run {
+                false => link_atomically(first_copy, &full_remote_path),
+                true => Ok(()),
+            };
+            match result {
+                Ok(()) => {
+                    if args.dry_run {
+                        info!("Link file at {:?} to identical {:?}", full_remote_path, first_copy);
+                    }
+                    else {
+                        debug!(
+                            event = "track_linked", track_id, src:% = first_copy.display(), dest:% = full_remote_path.display();
+                            "Linked file at {:?} to identical {:?}", full_remote_path, first_copy
+                        );
+                    }
+                    actions.push(action(ActionKind::LinkTrack).source(first_copy));
+                    self.linked += 1;
+                    self.saved_bytes += size.unwrap_or(0);
+                    return Ok(());
+                }
+                Err(e) => debug!(
+                    "Couldn't link file at {:?} to identical {:?}, copying it instead: {}",
+                    full_remote_path, first_copy, e
+                ),
+            }
+        }
+
+        match args.dry_run {
+            false => {
+                let started = Instant::now();
+                let result = with_retries(
+                    args.retries,
+                    || sink.copy_file(local_path, &full_remote_path),
+                    interrupted,
+                    || format!("copy file at {:?} to {:?}", local_path, full_remote_path),
+                );
+                let elapsed = started.elapsed();
+                actions.push(action(ActionKind::CopyTrack).result(&result));
+                match result {
+                    Ok((bytes, true)) => debug!(
+                        event = "track_copied", track_id, src:% = local_path.display(), dest:% = full_remote_path.display(), bytes, cloned = true;
+                        "{} file at {:?} to {:?}",
+                        if args.emit_file_list.is_some() { "Symlinked" } else { "Cloned" }, local_path, full_remote_path
+                    ),
+                    Ok((bytes, false)) => debug!(
+                        event = "track_copied", track_id, src:% = local_path.display(), dest:% = full_remote_path.display(), bytes, cloned = false,
+                        bytes_per_sec = bytes_per_sec(bytes, elapsed);
+                        "Copied file at {:?} to {:?} at {}/s", local_path, full_remote_path, format_size(bytes_per_sec(bytes, elapsed))
+                    ),
+                    // Cut short by Ctrl-C, which is reported once the sync stops.
+                    Err(_) if interrupted() => return Ok(()),
+                    Err(_) => {},
+                }
+                match result {
+                    Ok((bytes, cloned)) => {
+                        self.copied += 1;
+                        self.copied_bytes += bytes;
+                        self.updated += usize::from(replacing);
+                        if let Some(digest) = digest {
+                            self.first_copies.entry(digest).or_insert_with(|| full_remote_path.clone());
+                        }
+                        if !cloned {
+                            self.timed_bytes += bytes;
+                            self.copy_time += elapsed;
+                        }
+                    }
+                    Err(e) => {
+                        error!(
+                            event = "track_copy_failed", track_id, src:% = local_path.display(), dest:% = full_remote_path.display(), error:% = e;
+                            "Failed to copy file at {:?} to {:?}: {:?}",
+                            local_path, full_remote_path, e
+                        );
+                        check_strict(args)?;
+                    }
+                }
+            }
+            true => {
+                info!(
+                    event = "track_copy_planned", track_id, src:% = local_path.display(), dest:% = full_remote_path.display();
+                    "Copy file at {:?} to {:?}", local_path, full_remote_path
+                );
+                actions.push(action(ActionKind::CopyTrack));
+                if let Some(digest) = digest {
+                    self.first_copies.entry(digest).or_insert_with(|| full_remote_path.clone());
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Sums up the tracks copied, and writes their checksums and the state of the output, given
+    /// where each track that made it to the output is relative to it.
+    pub(crate) fn finish(&mut self, remote_paths: &HashMap<u64, PathBuf>, stopped: bool) -> Result<(), io::Error> {
+        let args = self.args;
+        if stopped {
+            warn!(event = "interrupted", tracks = self.copied; "Interrupted by user after {} tracks.", self.copied);
+        }
+        if self.linked > 0 {
+            info!(
+                "{} {} tracks identical to others instead of copying them, saving {}.",
+                if args.dry_run { "Would hard link" } else { "Hard linked" }, self.linked, format_size(self.saved_bytes)
+            );
+        }
+        // Not after an interruption, as it would leave out the tracks not copied yet.
+        if args.checksums && !args.dry_run && !interrupted() {
+            let tracks = Tracks { planned: &self.planned, remote_paths, kept: &self.kept };
+            if let Err(e) = write_checksums(args, &tracks, &self.cached, &mut self.state.hashes, self.sink) {
+                error!("Failed to write the checksums of the tracks: {}", e);
+                check_strict(args)?;
+            }
+        }
+        if (args.dedupe_content || args.checksums || args.check_content) && !args.dry_run {
+            // --append-only only writes a state where there is none yet, never over one.
+            if args.append_only && self.state_path.exists() {
+                info!("Keeping the existing sync state at {:?}, as --append-only overwrites nothing.", self.state_path);
+            }
+            else if let Err(e) = self.state.write(&self.state_path) {
+                warn!("Failed to save the sync state at {:?}: {}", self.state_path, e);
+            }
+        }
+        if !args.dry_run {
+            info!(
+                event = "tracks_copied", tracks = self.copied, bytes = self.copied_bytes, estimated_bytes = self.estimated_bytes;
+                "Copied {} tracks, {} of an estimated {}.",
+                self.copied, format_size(self.copied_bytes), format_size(self.estimated_bytes)
+            );
+            match args.bwlimit {
+                Some(limit) if self.timed_bytes > 0 => info!(
+                    "Copied tracks at an average of {}/s, limited to {}/s.",
+                    format_size(bytes_per_sec(self.timed_bytes, self.copy_time)), format_size(limit)
+                ),
+                _ if self.timed_bytes > 0 => debug!("Copied tracks at an average of {}/s.", format_size(bytes_per_sec(self.timed_bytes, self.copy_time))),
+                _ => {}
+            }
+        }
+
+        if args.check_content && !args.dry_run {
+            info!(
+                "Copied {} new tracks and {} updated ones, keeping {} unchanged.",
+                self.copied - self.updated, self.updated, self.unchanged
+            );
+        }
+        if self.modified_since > 0 {
+            warn!(
+                "Kept {} existing files the same size as their tracks' files, which were modified since; pass --check-content to compare their contents.",
+                self.modified_since
+            );
+        }
+        if self.conflicts > 0 {
+            warn!("Left {} conflicting existing files untouched; resolve them manually.", self.conflicts);
+        }
+        Ok(())
+    }
+}
+
+/// What came of a track, from the `actions` taken for it.
+fn outcome(actions: &[Action]) -> TrackOutcome {
+    // The folders made for it come before what was done with the track itself.
+    let last = actions.iter().rev()
+        .find(|action| matches!(action.kind, ActionKind::CopyTrack | ActionKind::LinkTrack | ActionKind::Skip));
+    match last {
+        Some(action) if action.status == ActionStatus::Failed => TrackOutcome::Failed,
+        Some(action) if action.status == ActionStatus::Planned => TrackOutcome::Planned,
+        Some(Action { kind: ActionKind::CopyTrack, size_bytes, .. }) => TrackOutcome::Copied(size_bytes.unwrap_or(0)),
+        Some(Action { kind: ActionKind::LinkTrack, .. }) => TrackOutcome::Linked,
+        _ => TrackOutcome::Kept,
+    }
+}
+
+/// For --extract-art, writes the picture embedded in the first track of each folder of tracks
+/// next to it, unless the folder has a cover already. Failing to is only a warning.
+#[cfg(feature = "art")]
+pub(crate) fn extract_art(planned: &[PlannedTrack<'_>], remote_paths: &HashMap<u64, PathBuf>, args: &Args, sink: &dyn Sink, actions: &mut Vec<Action>) {
+    use m3trans::art;
+
+    let order = |planned: &PlannedTrack<'_>| (planned.track.disc_number.unwrap_or(0), planned.track.track_number.unwrap_or(0), planned.id);
+    let mut firsts: BTreeMap<&Path, &PlannedTrack<'_>> = BTreeMap::new();
+    for planned in planned {
+        let folder = match remote_paths.get(&planned.id).and_then(|path| path.parent()) {
+            Some(folder) if folder != relative_dir(&args.tracks_dir) => folder,
+            _ => continue,
+        };
+        let first = firsts.entry(folder).or_insert(planned);
+        if order(planned) < order(first) {
+            *first = planned;
+        }
+    }
+    if firsts.is_empty() {
+        warn!("Not extracting album art, as no tracks are in folders of their own; pass --names album or a --path-template with folders.");
+        return;
+    }
+
+    let (mut written, mut without_art) = (0, 0);
+    for (folder, planned) in firsts {
+        let folder = args.output_path.join(folder);
+        if ["jpg", "png"].iter().any(|extension| folder.join(format!("{}.{}", COVER_STEM, extension)).exists()) {
+            continue;
+        }
+        let picture = match art::front_cover(&planned.local_path) {
+            Ok(Some(picture)) => picture,
+            Ok(None) => {
+                debug!("Track {} ({}) at {:?} has no embedded picture for {:?}.", planned.id, planned.track.name, planned.local_path, folder);
+                without_art += 1;
+                continue;
+            }
+            Err(e) => {
+                warn!("Failed to read the album art of track {} ({}) at {:?}: {}", planned.id, planned.track.name, planned.local_path, e);
+                continue;
+            }
+        };
+        let too_large = picture.dimensions()
+            .filter(|&(width, height)| args.art_max_size.is_some_and(|max| width.max(height) > max));
+        if let Some((width, height)) = too_large {
+            warn!(
+                "Not writing album art to {:?}, as the picture of track {} ({}) is {}x{}, larger than --art-max-size.",
+                folder, planned.id, planned.track.name, width, height
+            );
+            continue;
+        }
+        let path = folder.join(format!("{}.{}", COVER_STEM, picture.format.extension()));
+        let mut action = Action::new(ActionKind::WriteArt, args.dry_run).source(&planned.local_path).destination(&path);
+        action.size_bytes = Some(picture.data.len() as u64);
+        if args.dry_run {
+            info!("Write album art from {:?} to {:?}", planned.local_path, path);
+            actions.push(action);
+            written += 1;
+            continue;
+        }
+        let result = sink.write_file(&path, &picture.data);
+        actions.push(action.result(&result));
+        match result {
+            Ok(_) => {
+                debug!("Wrote album art from {:?} to {:?}", planned.local_path, path);
+                written += 1;
+            }
+            Err(e) => warn!("Failed to write album art to {:?}: {}", path, e),
+        }
+    }
+    info!(
+        "{} album art to {} folders, leaving {} whose first tracks have no pictures.",
+        if args.dry_run { "Would write" } else { "Wrote" }, written, without_art
+    );
+}
+
+/// The tracks in the output after copying, for `write_checksums`.
+struct Tracks<'a> {
+    planned: &'a [PlannedTrack<'a>],
+    /// Where each track that made it to the output is, relative to it.
+    remote_paths: &'a HashMap<u64, PathBuf>,
+    /// The tracks whose files were already there, and so weren't copied from their source.
+    kept: &'a HashSet<u64>,
+}
+
+/// Writes the digest of the file of every track in the output to its checksums manifest. Tracks
+/// copied this run are hashed from their source files, which they match, reusing the hashes in
+/// `cached` of files that haven't changed since; those are added to `hashes` for the next run.
+/// Files that were already there keep their digests from the last manifest, or are read back if
+/// it has none.
+fn write_checksums(
+    args: &Args,
+    tracks: &Tracks<'_>,
+    cached: &HashMap<PathBuf, FileHash>,
+    hashes: &mut HashMap<PathBuf, FileHash>,
+    sink: &dyn Sink,
+) -> Result<(), io::Error> {
+    let manifest_path = args.output_path.join(checksums::FILE_NAME);
+    let previous: HashMap<String, Digest> = match fs::read_to_string(&manifest_path) {
+        Ok(manifest) => manifest.lines().filter_map(checksums::parse_line).collect(),
+        Err(e) if e.kind() == ErrorKind::NotFound => HashMap::new(),
+        Err(e) => {
+            warn!("Failed to read the checksums from the last run at {:?}, so reading files again: {}", manifest_path, e);
+            HashMap::new()
+        }
+    };
+
+    let (mut entries, mut reused) = (Vec::new(), 0);
+    for planned in tracks.planned {
+        let remote_path = match tracks.remote_paths.get(&planned.id) {
+            Some(remote_path) => remote_path,
+            None => continue,
+        };
+        let name = PathSeparator::Slash.render(remote_path);
+        let digest = match tracks.kept.contains(&planned.id) {
+            true => match previous.get(&name) {
+                Some(digest) => Ok(*digest),
+                None => File::open(args.output_path.join(remote_path)).and_then(sha256::digest_reader),
+            },
+            false => source_digest(&planned.local_path, cached, hashes),
+        };
+        match digest {
+            Ok(digest) => {
+                reused += usize::from(previous.get(&name) == Some(&digest));
+                entries.push((name, digest));
+            }
+            Err(e) => warn!("Failed to hash the file of track {} at {:?}, so leaving it out of the checksums: {}", planned.track.name, remote_path, e),
+        }
+    }
+    entries.sort_unstable_by(|(a, _), (b, _)| a.cmp(b));
+    // Tracks that share a file are listed once.
+    entries.dedup_by(|(a, _), (b, _)| a == b);
+
+    let mut contents = Vec::new();
+    checksums::write(&mut contents, &entries)?;
+    sink.write_file(&manifest_path, &contents).map_err(context(format!("failed to write {:?}", manifest_path)))?;
+    info!("Wrote the checksums of {} track files to {:?}, {} of them unchanged.", entries.len(), manifest_path, reused);
+    Ok(())
+}
+
+/// The digest of the file at `path`, from `cached` if it hasn't changed since it was hashed. Files
+/// are added to `hashes` either way.
+fn source_digest(path: &Path, cached: &HashMap<PathBuf, FileHash>, hashes: &mut HashMap<PathBuf, FileHash>) -> Result<Digest, io::Error> {
+    let metadata = fs::metadata(path)?;
+    let still_holds = |hash: &&FileHash| hash.matches(&metadata);
+    if let Some(hash) = hashes.get(path).filter(still_holds).or_else(|| cached.get(path).filter(still_holds)).copied() {
+        hashes.insert(path.to_owned(), hash);
+        return Ok(hash.digest);
+    }
+    let digest = sha256::digest_reader(File::open(path)?)?;
+    if let Some(modified) = state::modified(&metadata) {
+        hashes.insert(path.to_owned(), FileHash { size: metadata.len(), modified, digest });
+    }
+    Ok(digest)
+}
+
+/// Fails if `needed_bytes` is more than the free space on the volume holding `dir`, or only warns in
+/// a dry run, and warns as well if it comes within 5% of it.
+fn check_space(dir: &Path, needed_bytes: u64, sizes_unknown: bool, dry_run: bool) -> Result<(), io::Error> {
+    if sizes_unknown {
+        info!("The sizes of the tracks to copy are unknown, so skipping the check for free space.");
+        return Ok(());
+    }
+    // In a dry run the folder may not exist yet.
+    let available = match dir.ancestors().find(|dir| dir.is_dir()).and_then(available_space) {
+        Some(available) => available,
+        None => {
+            info!("Couldn't find out the free space on the volume of {:?}, so skipping the check for it.", dir);
+            return Ok(());
+        }
+    };
+    if needed_bytes > available {
+        let message = format!(
+            "the tracks to copy need ~{}, but only {} is free on the volume of {:?}; pass --ignore-space-check to copy them anyway",
+            format_size(needed_bytes), format_size(available), dir
+        );
+        match dry_run {
+            false => return Err(io::Error::new(ErrorKind::StorageFull, message)),
+            true => warn!("Syncing would fail, as {}.", message),
+        }
+    }
+    else if needed_bytes > available - available / 20 {
+        warn!(
+            "The tracks to copy need ~{}, which nearly fills the {} free on the volume of {:?}.",
+            format_size(needed_bytes), format_size(available), dir
+        );
+    }
+    Ok(())
+}
+
+/// The space available to this user on the volume holding `path`, where it can be found out.
+#[cfg(unix)]
+fn available_space(path: &Path) -> Option<u64> {
+    use std::{convert::TryFrom, ffi::CString, mem::MaybeUninit, os::unix::ffi::OsStrExt};
+    let path = CString::new(path.as_os_str().as_bytes()).ok()?;
+    let mut stat = MaybeUninit::<libc::statvfs>::uninit();
+    if unsafe { libc::statvfs(path.as_ptr(), stat.as_mut_ptr()) } != 0 {
+        return None;
+    }
+    let stat = unsafe { stat.assume_init() };
+    // Their types differ between platforms.
+    #[allow(clippy::useless_conversion)]
+    u64::try_from(stat.f_bavail).ok()?.checked_mul(u64::try_from(stat.f_frsize).ok()?)
+}
+
+#[cfg(windows)]
+fn available_space(path: &Path) -> Option<u64> {
+    use std::{iter, os::windows::ffi::OsStrExt};
+    extern "system" {
+        fn GetDiskFreeSpaceExW(directory: *const u16, available: *mut u64, total: *mut u64, free: *mut u64) -> i32;
+    }
+    let path: Vec<u16> = path.as_os_str().encode_wide().chain(iter::once(0)).collect();
+    let mut available = 0;
+    match unsafe { GetDiskFreeSpaceExW(path.as_ptr(), &mut available, std::ptr::null_mut(), std::ptr::null_mut()) } {
+        0 => None,
+        _ => Some(available),
+    }
+}
+
+#[cfg(not(any(unix, windows)))]
+fn available_space(_path: &Path) -> Option<u64> {
+    None
+}
+
+/// Hashes the files of the planned tracks that are the same size as another, to find those with
+/// identical contents. Hashes from earlier runs in `cached` are reused for files that haven't
+/// changed since. Returns the digests of tracks sharing their contents with another, and the
+/// hashes of every file hashed, to be cached in turn.
+fn duplicate_digests<'a>(
+    planned: impl Iterator<Item = &'a PlannedTrack<'a>>,
+    cached: &HashMap<PathBuf, FileHash>,
+) -> (HashMap<u64, Digest>, HashMap<PathBuf, FileHash>) {
+    // Ordered, so that files are hashed in the same order each run.
+    let mut by_size: BTreeMap<u64, Vec<(&PlannedTrack<'_>, fs::Metadata)>> = BTreeMap::new();
+    for planned in planned {
+        // Files that can't be read fail when copied, and are reported then.
+        if let Ok(metadata) = fs::metadata(&planned.local_path) {
+            by_size.entry(metadata.len()).or_default().push((planned, metadata));
+        }
+    }
+
+    let mut hashes = HashMap::new();
+    let mut by_digest: HashMap<Digest, Vec<u64>> = HashMap::new();
+    for (planned, metadata) in by_size.into_values().filter(|same_size| same_size.len() > 1).flatten() {
+        let hash = match cached.get(&planned.local_path).filter(|hash| hash.matches(&metadata)) {
+            Some(hash) => *hash,
+            None => {
+                let digest = match File::open(&planned.local_path).and_then(sha256::digest_reader) {
+                    Ok(digest) => digest,
+                    Err(e) => {
+                        warn!("Failed to hash file at {:?}, so copying it regardless: {}", planned.local_path, e);
+                        continue;
+                    }
+                };
+                match state::modified(&metadata) {
+                    Some(modified) => FileHash { size: metadata.len(), modified, digest },
+                    None => {
+                        by_digest.entry(digest).or_default().push(planned.id);
+                        continue;
+                    }
+                }
+            }
+        };
+        by_digest.entry(hash.digest).or_default().push(planned.id);
+        hashes.insert(planned.local_path.clone(), hash);
+    }
+
+    let digests = by_digest.into_iter()
+        .filter(|(_, track_ids)| track_ids.len() > 1)
+        .flat_map(|(digest, track_ids)| track_ids.into_iter().map(move |track_id| (track_id, digest)))
+        .collect();
+    (digests, hashes)
+}
+
+/// Formats a byte count in decimal units, as file managers and device specs do.
+pub(crate) fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KB", "MB", "GB", "TB"];
+    if bytes < 1000 {
+        return format!("{} B", bytes);
+    }
+    let mut size = bytes as f64 / 1000.0;
+    let mut unit = 0;
+    while size >= 1000.0 && unit < UNITS.len() - 1 {
+        size /= 1000.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", size, UNITS[unit])
+}
+
+pub(crate) fn bytes_per_sec(bytes: u64, elapsed: Duration) -> u64 {
+    (bytes as f64 / elapsed.as_secs_f64().max(1e-6)) as u64
+}
+
+fn same_size(a: &Path, b: &Path) -> bool {
+    match (fs::metadata(a), fs::metadata(b)) {
+        (Ok(a), Ok(b)) => a.len() == b.len(),
+        _ => false,
+    }
+}
+
+/// Whether the files at `a` and `b` have the same digest, only hashing them if they are the same
+/// size. Digests are reused from `cached` and added to `hashes` like those of sources.
+fn same_content(a: &Path, b: &Path, cached: &HashMap<PathBuf, FileHash>, hashes: &mut HashMap<PathBuf, FileHash>) -> bool {
+    if !same_size(a, b) {
+        return false;
+    }
+    match (source_digest(a, cached, hashes), source_digest(b, cached, hashes)) {
+        (Ok(a), Ok(b)) => a == b,
+        (Err(e), _) | (_, Err(e)) => {
+            debug!("Failed to compare the contents of {:?} and {:?}, so taking them to differ: {}", a, b, e);
+            false
+        }
+    }
+}
+
+/// Whether the file at `a` was modified after the one at `b`, where both times are known.
+fn is_newer(a: &Path, b: &Path) -> bool {
+    let modified = |path: &Path| fs::metadata(path).and_then(|metadata| metadata.modified()).ok();
+    matches!((modified(a), modified(b)), (Some(a), Some(b)) if a > b)
+}
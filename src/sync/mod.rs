@@ -0,0 +1,888 @@
+//! Syncing a library into each output: locking and logging, planning the sync with the library's
+//! `plan` module, and carrying the plan out through the copying of tracks and writing of
+//! playlists in the submodules.
+
+use std::{collections::{HashMap, HashSet}, fmt, fs::{self, File, OpenOptions}, io::{self, BufReader, ErrorKind, BufRead, IsTerminal, Write}, path::{Path, PathBuf}, time::{Duration, SystemTime, UNIX_EPOCH}};
+use log::*;
+use simplelog::{ColorChoice, CombinedLogger, Config, SharedLogger, TermLogger, TerminalMode, WriteLogger};
+use m3trans::{Library, Playlist, format::{self, Entry}, observer::{NoObserver, SyncObserver, TrackOutcome}, names::Normalization, plan::{self, OnLongPath, PathSeparator, PlanError, PlannedPlaylist, SyncPlan, SyncReport, Target, TrackCopy, build_plan}};
+
+use crate::args::{Args, OutputFormat, output_dir, relative_dir};
+use crate::checksums;
+use crate::commands::{is_stdin, read_library};
+use crate::interrupt::interrupted;
+use crate::context;
+use crate::date;
+use crate::ignore;
+use crate::json_log::JsonLogger;
+use crate::lock::{self, Lock};
+use crate::manifest;
+use crate::report::{Action, ActionKind, ActionStatus, ComparedActions, actions_report, print_drift, read_report, write_file_list, write_report};
+use crate::sha256::{self, Digest};
+use crate::state::{self, State};
+#[cfg(feature = "art")]
+use crate::sync::copy::extract_art;
+use crate::sync::copy::{PlannedTrack, TrackCopier, check_outside_media};
+use crate::sync::playlists::{PlaylistWriter, included_playlists};
+use crate::sync::sink::{Archive, Directory, RateLimit, Sink, Staging};
+use crate::warnings::{self, WarningLimiter};
+
+pub(crate) mod copy;
+pub(crate) mod playlists;
+pub(crate) mod sink;
+
+/// A digest of what the sync into `output_path` is made from besides the files of the tracks: the
+/// library file, the ignore, include and rename files, and the arguments. There is none for a
+/// library read from standard input, which can't be read again to compare, or if one of the files
+/// can't be read.
+fn sync_fingerprint(args: &Args, output_path: &Path) -> Result<Option<Digest>, io::Error> {
+    if is_stdin(&args.library_file) {
+        return Ok(None);
+    }
+    let mut hasher = sha256::Sha256::new();
+    for argument in &args.given_arguments {
+        hasher.update(argument.to_string_lossy().as_bytes());
+        hasher.update(b"\0");
+    }
+    let library = args.library_file.as_path();
+    let metadata = match fs::metadata(library) {
+        Ok(metadata) => metadata,
+        Err(_) => return Ok(None),
+    };
+    hasher.update(format!("{:?}\0{}\0{:?}\0", library, metadata.len(), state::modified(&metadata)).as_bytes());
+    let selection = selection_files(args, output_path);
+    let files = [Some(library.to_owned()), selection.ignore_file, selection.include_file, args.rename_file.clone()];
+    for file in files.iter().flatten() {
+        // Whether the file is there matters as well, as adding one changes what is synced.
+        match File::open(file).and_then(sha256::digest_reader) {
+            Ok(digest) => hasher.update(&digest.0),
+            Err(e) if e.kind() == ErrorKind::NotFound => hasher.update(b"missing"),
+            Err(e) => return Err(context(format!("failed to read {:?}", file))(e)),
+        }
+    }
+    Ok(Some(hasher.finish()))
+}
+
+/// Records in the state of the output at `output_path` what its sync was made from, or with
+/// `None` that it has to be done again.
+fn record_fingerprint(output_path: &Path, fingerprint: Option<Digest>) {
+    let state_path = output_path.join(state::FILE_NAME);
+    let mut state = State::read(&state_path).unwrap_or_default();
+    if state.fingerprint == fingerprint {
+        return;
+    }
+    state.fingerprint = fingerprint;
+    if let Err(e) = state.write(&state_path) {
+        warn!("Failed to save the sync state at {:?}: {}", state_path, e);
+    }
+}
+
+/// Renames the log at `path` to `<path>.1`, and the earlier ones to the number after theirs, so
+/// that `keep` are kept. The oldest, `<path>.<keep>`, is deleted.
+fn rotate_logs(path: &Path, keep: usize) -> Result<(), io::Error> {
+    let numbered = |n: usize| {
+        let mut name = path.as_os_str().to_owned();
+        name.push(format!(".{}", n));
+        PathBuf::from(name)
+    };
+    let ignore_missing = |result: Result<(), io::Error>| match result {
+        Err(e) if e.kind() != ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    };
+    let oldest = numbered(keep);
+    ignore_missing(fs::remove_file(&oldest).map_err(context(format!("failed to delete {:?}", oldest))))?;
+    let rename = |from: &Path, to: &Path| fs::rename(from, to).map_err(context(format!("failed to rename {:?}", from)));
+    for n in (1..keep).rev() {
+        ignore_missing(rename(&numbered(n), &numbered(n + 1)))?;
+    }
+    ignore_missing(rename(path, &numbered(1)))
+}
+
+/// Whether `path` is missing, empty, or has the tracks or playlists folders, log or lock file of an
+/// earlier run, so that writing into it won't mix m3trans's files with others.
+fn looks_like_output(path: &Path, args: &Args) -> bool {
+    match fs::read_dir(path) {
+        Ok(mut entries) => entries.next().is_none()
+            || output_dir(path, &args.tracks_dir).is_dir()
+            || (output_dir(path, &args.playlists_dir) != path && output_dir(path, &args.playlists_dir).is_dir())
+            || path.join("m3trans.log").is_file()
+            || path.join(state::FILE_NAME).is_file()
+            || path.join(checksums::FILE_NAME).is_file()
+            || path.join(manifest::FILE_NAME).is_file()
+            || path.join(lock::FILE_NAME).is_file(),
+        Err(_) => true,
+    }
+}
+
+/// The lock files of a sync: one in each output folder, or one next to the archive.
+fn lock_paths(args: &Args) -> Vec<PathBuf> {
+    match &args.output_archive {
+        Some(archive) => {
+            let mut path = archive.as_os_str().to_owned();
+            path.push(".lock");
+            vec![PathBuf::from(path)]
+        }
+        None => args.output_paths.iter().map(|output_path| output_path.join(lock::FILE_NAME)).collect(),
+    }
+}
+
+/// Asks on the terminal whether to go ahead with what `summary` describes, unless `yes` was given.
+/// Fails if the answer is anything but yes, or if standard input isn't a terminal to ask on.
+pub(crate) fn confirm(summary: &str, yes: bool) -> Result<(), io::Error> {
+    if yes {
+        return Ok(());
+    }
+    if !io::stdin().is_terminal() {
+        return Err(io::Error::other(format!(
+            "{} Not going ahead, as standard input isn't a terminal to ask on; pass --yes to go ahead anyway.",
+            summary
+        )));
+    }
+
+    eprint!("{}\nProceed? [y/N] ", summary);
+    io::stderr().flush()?;
+    let mut answer = String::new();
+    io::stdin().read_line(&mut answer)?;
+    match answer.trim().to_ascii_lowercase().as_str() {
+        "y" | "yes" => Ok(()),
+        _ => Err(io::Error::other("stopped without making changes")),
+    }
+}
+
+pub(crate) fn run_sync(args: &Args) -> Result<(), io::Error> {
+    let terminal_level = match (args.quiet, args.verbose) {
+        (true, _) => LevelFilter::Error,
+        (false, 0) => LevelFilter::Warn,
+        (false, 1) => LevelFilter::Info,
+        (false, _) => LevelFilter::Debug,
+    };
+    // Standard output is kept for the report when there is one.
+    let terminal_mode = match args.output_format {
+        OutputFormat::Text => TerminalMode::Mixed,
+        OutputFormat::Json => TerminalMode::Stderr,
+    };
+    // Checked before the log file is written there, which would make any folder look used.
+    if let Some(archive) = args.output_archive.as_ref().filter(|archive| archive.exists()) {
+        confirm(&format!("The archive {:?} already exists, and will be replaced.", archive), args.yes)?;
+    }
+    for output_path in &args.output_paths {
+        if !args.dry_run && args.output_archive.is_none() && !looks_like_output(output_path, args) {
+            confirm(&format!(
+                "The output folder {:?} already has files in it, and doesn't look like an earlier output of \
+                m3trans. Tracks and playlists will be written into it alongside them, replacing any with the \
+                same names.",
+                output_path
+            ), args.yes)?;
+        }
+    }
+    // Taken before anything is written, the log file included, and held until the run ends.
+    let mut locks = Vec::new();
+    let mut stale_locks = Vec::new();
+    if !args.dry_run && !args.no_lock {
+        let max_age = Duration::from_secs(args.lock_max_age.saturating_mul(3600));
+        for lock_path in lock_paths(args) {
+            if let Some(dir) = lock_path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+                fs::create_dir_all(dir).map_err(context(format!("failed to create output folder {:?}", dir)))?;
+            }
+            let (lock, stale) = Lock::acquire(&lock_path, max_age)
+                .map_err(context(format!("failed to lock {:?}", lock_path)))?;
+            locks.push(lock);
+            stale_locks.extend(stale.map(|stale| (lock_path, stale)));
+        }
+    }
+    // Not for a dry run, which leaves no state, nor an archive, which has none, nor --append-only,
+    // which would have to overwrite the state to record it.
+    let fingerprints: Vec<Result<Option<Digest>, io::Error>> = args.output_paths.iter()
+        .map(|output_path| match args.dry_run || args.output_archive.is_some() || args.append_only {
+            true => Ok(None),
+            false => sync_fingerprint(args, output_path),
+        })
+        .collect();
+    // What is asked for besides the sync itself would be missing if it stopped here.
+    let extra_output = args.report.is_some() || args.emit_file_list.is_some() || args.output_format == OutputFormat::Json;
+    // Known before the log file is opened, so that a run with nothing to do leaves the log of the
+    // last one as it was. A folder that isn't there, or has no state, may be another device in the
+    // same place.
+    let unchanged = !args.force && !extra_output && args.output_paths.iter().zip(&fingerprints).all(|(output_path, fingerprint)| {
+        match fingerprint {
+            Ok(Some(fingerprint)) => output_path.is_dir()
+                && State::read(&output_path.join(state::FILE_NAME)).is_ok_and(|state| state.fingerprint == Some(*fingerprint)),
+            _ => false,
+        }
+    });
+    let mut loggers: Vec<Box<dyn SharedLogger>> = vec![
+        TermLogger::new(terminal_level, Config::default(), terminal_mode, ColorChoice::Auto),
+    ];
+    // A dry run leaves the output directories untouched, so only writes a log file if asked for
+    // one, as does writing an archive. Otherwise it goes in the first of them.
+    let log_path = match (&args.log_file, args.dry_run) {
+        _ if args.no_log_file || unchanged => None,
+        (Some(path), _) => Some(path.clone()),
+        (None, true) => None,
+        (None, false) if args.output_archive.is_some() => None,
+        (None, false) => {
+            let output_path = &args.output_paths[0];
+            fs::create_dir_all(output_path)
+                .map_err(context(format!("failed to create output folder {:?}", output_path)))?;
+            Some(output_path.join("m3trans.log"))
+        }
+    };
+    // Only logged once the log is set up.
+    let mut rotate_error = None;
+    if let Some(log_path) = log_path {
+        if let Some(keep) = args.log_keep.filter(|&keep| keep > 0) {
+            rotate_error = rotate_logs(&log_path, keep).err();
+        }
+        // --append-only adds to the log of earlier runs too, rather than replacing it.
+        let log_file = match args.log_append || args.append_only {
+            true => OpenOptions::new().create(true).append(true).open(&log_path),
+            false => File::create(&log_path),
+        };
+        let log_file = log_file.map_err(context(format!("failed to create log file {:?}", log_path)))?;
+        loggers.push(match args.log_format {
+            OutputFormat::Text => WriteLogger::new(args.log_level, Config::default(), log_file),
+            OutputFormat::Json => JsonLogger::new(args.log_level, log_file),
+        });
+    }
+    let combined = CombinedLogger::new(loggers);
+    let shown = match args.verbose_warnings {
+        true => usize::MAX,
+        false => warnings::SHOWN,
+    };
+    log::set_max_level(combined.level());
+    let (limiter, warning_counts) = WarningLimiter::new(combined, shown);
+    log::set_boxed_logger(Box::new(limiter)).map_err(|e| io::Error::other(format!("failed to set up logging: {}", e)))?;
+    if let Some(e) = rotate_error {
+        warn!("Failed to rotate the log files, so replacing the last log instead: {}", e);
+    }
+    for (lock_path, stale) in stale_locks {
+        warn!("Took over the lock file at {:?}, as {}.", lock_path, stale);
+    }
+    // Marks where each run starts, which matters most when appending to the logs of earlier ones.
+    let arguments: Vec<String> = std::env::args_os().skip(1).map(|arg| arg.to_string_lossy().into_owned()).collect();
+    info!(
+        event = "run_started", version = env!("CARGO_PKG_VERSION"), arguments:% = arguments.join(" ");
+        "Started m3trans {} at {} with arguments: {}",
+        env!("CARGO_PKG_VERSION"), date::rfc3339(SystemTime::now()), arguments.join(" ")
+    );
+
+    if args.auto_library {
+        info!("Using the library found at {:?}.", args.library_file);
+    }
+    if unchanged {
+        info!(
+            event = "sync_skipped";
+            "The library is unchanged since the last sync, so there is nothing to do; pass --force to sync anyway."
+        );
+        return Ok(());
+    }
+    let fingerprints: Vec<Option<Digest>> = fingerprints.into_iter()
+        .map(|fingerprint| fingerprint.unwrap_or_else(|e| {
+            debug!("Couldn't take the fingerprint of the sync, so syncing in full: {}", e);
+            None
+        }))
+        .collect();
+    // Loaded after setting up logging, so that problems with individual tracks are reported.
+    let library = read_library(&args.library_file)?;
+    let earlier_report = args.compare_report.as_deref().map(read_report).transpose()?;
+    if let Some(age) = library.exported_at.and_then(|date| SystemTime::now().duration_since(date).ok()) {
+        match age.as_secs() / 86_400 {
+            0 => info!("Syncing library exported today."),
+            1 => info!("Syncing library exported yesterday."),
+            days => info!("Syncing library exported {} days ago.", days),
+        }
+    }
+
+    let format_options = format_options(args);
+    let shuffle_seed = shuffle_seed(args);
+    let mut actions = Vec::new();
+
+    // One output directory failing leaves the others to go on, unless --strict was given.
+    let several = args.output_paths.len() > 1;
+    let mut result = Ok(());
+    let mut failed_outputs = 0;
+    // Planned again only for an output with ignore or include files of its own.
+    let mut planned: Option<(SelectionFiles, SyncPlan)> = None;
+    for (output_path, fingerprint) in args.output_paths.iter().zip(fingerprints) {
+        if interrupted() {
+            break;
+        }
+        let args = Args { output_path: output_path.clone(), ..args.clone() };
+        let selection = selection_files(&args, output_path);
+        if planned.as_ref().is_none_or(|(planned_selection, _)| *planned_selection != selection) {
+            let (ignore_rules, included) = read_selection(&library, &args, &selection)?;
+            let options = plan_options(&args, shuffle_seed, included, ignore_rules, format_options.clone());
+            let plan = plan_sync(&library, &options, &args, &actions)?;
+            actions.extend(plan.skipped.iter().map(|skipped| {
+                let mut action = Action::skip(skipped.reason).track(skipped.track_id, &library.tracks[&skipped.track_id]);
+                if let Some(source) = &skipped.source {
+                    action = action.source(source);
+                }
+                Action { error: skipped.error.clone(), ..action }
+            }));
+            planned = Some((selection, plan));
+        }
+        let plan = &planned.as_ref().unwrap().1;
+        let mut output_actions = Vec::new();
+        let output_result = sync_output(&library, &args, plan, &format_options, &mut output_actions, &mut NoObserver);
+        let failures = output_actions.iter().filter(|action| action.status == ActionStatus::Failed).count();
+        if let Some(fingerprint) = fingerprint {
+            record_fingerprint(output_path, Some(fingerprint).filter(|_| output_result.is_ok() && failures == 0 && !interrupted()));
+        }
+        if several {
+            let succeeded = |kind| output_actions.iter()
+                .filter(|action| action.kind == kind && action.status != ActionStatus::Failed)
+                .count();
+            let (tracks, playlists) = (succeeded(ActionKind::CopyTrack), succeeded(ActionKind::WritePlaylist));
+            match (&output_result, failures) {
+                (Ok(()), 0) => info!("Synced to {:?}: {} tracks copied, {} playlist files written.", output_path, tracks, playlists),
+                (Ok(()), _) => warn!(
+                    "Synced to {:?}: {} tracks copied, {} playlist files written, {} failures.",
+                    output_path, tracks, playlists, failures
+                ),
+                (Err(e), _) => warn!("Failed to sync to {:?}: {}", output_path, e),
+            }
+        }
+        actions.append(&mut output_actions);
+
+        match output_result {
+            Err(e) if several && !is_strict_stop(&e) => failed_outputs += 1,
+            Err(e) => {
+                result = Err(e);
+                break;
+            }
+            Ok(()) => {}
+        }
+    }
+
+    for (kind, count) in warning_counts.past(shown) {
+        warn!(
+            "Logged {} warnings of kind {}, the first {} as warnings and the rest at info level; pass --verbose-warnings to see them all.",
+            count, kind, shown
+        );
+    }
+    // Reported even when stopped early, so that what did get done is known.
+    let drift = earlier_report.map(|earlier| {
+        // What is in an archive can't be looked for yet, as it is only being written.
+        let exists = |path: &Path| args.output_archive.is_some() || path.exists();
+        crate::drift::compare(&earlier, &actions_report(&actions), &library, exists)
+    });
+    match (args.output_format, &drift) {
+        (OutputFormat::Json, Some(drift)) => {
+            let stdout = io::stdout();
+            crate::json::to_writer_pretty(stdout.lock(), &ComparedActions { actions: &actions, drift })?;
+        }
+        (OutputFormat::Json, None) => {
+            let stdout = io::stdout();
+            crate::json::to_writer_pretty(stdout.lock(), &actions)?;
+        }
+        (OutputFormat::Text, Some(drift)) => print_drift(drift)?,
+        (OutputFormat::Text, None) => {}
+    }
+    if let Some(report_path) = &args.report {
+        write_report(report_path, &actions, &library)?;
+    }
+    result?;
+    if interrupted() {
+        return Err(io::Error::new(ErrorKind::Interrupted, "interrupted by user"));
+    }
+    if failed_outputs > 0 {
+        return Err(io::Error::other(SyncFailures::Outputs { failed: failed_outputs, total: args.output_paths.len() }));
+    }
+    let failures = actions.iter().filter(|action| action.status == ActionStatus::Failed).count();
+    if failures > 0 {
+        return Err(io::Error::other(SyncFailures::Files(failures)));
+    }
+
+    Ok(())
+}
+
+/// Plans the sync with `options`, turning what keeps it from being planned into the error to end
+/// the run with, after writing the report of the `actions` so far if one is asked for.
+fn plan_sync(library: &Library, options: &plan::Options, args: &Args, actions: &[Action]) -> Result<SyncPlan, io::Error> {
+    match build_plan(library, options) {
+        Ok(plan) => Ok(plan),
+        Err(PlanError::UnknownRoot { root, top_folders }) => {
+            let message = match top_folders.is_empty() {
+                true => format!("--root {:?} isn't a folder of the library, which has none at the top level", root),
+                false => {
+                    let top_folders: Vec<String> = top_folders.iter().map(|name| format!("{:?}", name)).collect();
+                    format!("--root {:?} isn't a top-level folder of the library; those are {}", root, top_folders.join(", "))
+                }
+            };
+            Err(io::Error::new(ErrorKind::InvalidInput, message))
+        }
+        Err(e) => {
+            let e = match e {
+                PlanError::PathTooLong { playlist, virtual_path, path, len } => io::Error::new(ErrorKind::InvalidInput, format!(
+                    "playlist {} with path {:?} would be written at {:?}, {} characters long, more than --max-path-len",
+                    playlist, virtual_path, path, len
+                )),
+                // Logged where it was found.
+                _ => io::Error::other(SyncFailures::Strict),
+            };
+            if let Some(report_path) = &args.report {
+                write_report(report_path, actions, library)?;
+            }
+            Err(e)
+        }
+    }
+}
+
+/// The ignore and include files of a sync, where there are any to read.
+#[derive(PartialEq)]
+struct SelectionFiles {
+    ignore_file: Option<PathBuf>,
+    include_file: Option<PathBuf>,
+}
+
+/// The ignore and include files of the sync into `output_path`: those given, or else the
+/// .m3ignore and .m3include at the root of the output if it has them, or else those next to the
+/// library. There is no include file when --playlist picks the playlists.
+fn selection_files(args: &Args, output_path: &Path) -> SelectionFiles {
+    let file_or_default = |file: &Option<PathBuf>, default_name: &str| match file {
+        Some(path) => Some(path.clone()),
+        // Not in an archive, which is only being written.
+        None if args.output_archive.is_none() && output_path.join(default_name).is_file() => Some(output_path.join(default_name)),
+        // A library read from standard input has nothing next to it.
+        None if is_stdin(&args.library_file) => {
+            info!("Reading the library from standard input, so not looking for {} next to it.", default_name);
+            None
+        }
+        None => Some(args.library_file.with_file_name(default_name)),
+    };
+    SelectionFiles {
+        ignore_file: file_or_default(&args.ignore_file, ".m3ignore"),
+        include_file: match args.playlists.is_empty() {
+            true => file_or_default(&args.include_file, ".m3include"),
+            false => None,
+        },
+    }
+}
+
+/// The ignore rules and the playlists to export of a sync, from `selection` and the command line.
+fn read_selection(library: &Library, args: &Args, selection: &SelectionFiles) -> Result<(Vec<ignore::Rule>, Option<HashSet<u64>>), io::Error> {
+    let mut ignore_rules = match &selection.ignore_file {
+        Some(ignore_path) => parse_ignores(ignore_path, args)?,
+        None => Vec::new(),
+    };
+    // Unlike those in the file, a rule typed on the command line that can't be parsed is an error.
+    for rule in &args.ignores {
+        let parsed = ignore::Rule::parse(&args.normalize.apply(rule), args.ignore_case).map_err(|e| io::Error::new(
+            ErrorKind::InvalidInput,
+            format!("invalid --ignore rule {:?}: {}", rule, e),
+        ))?;
+        ignore_rules.extend(parsed);
+    }
+    let include_pats = match args.playlists.is_empty() {
+        true => match &selection.include_file {
+            Some(include_path) => parse_patterns(include_path, args.normalize)?,
+            None => Vec::new(),
+        },
+        false => args.playlists.iter()
+            .map(|pattern| glob::Pattern::new(&args.normalize.apply(pattern)).map_err(|e| io::Error::new(
+                ErrorKind::InvalidInput,
+                format!("invalid --playlist pattern {:?}: {}", pattern, e),
+            )))
+            .collect::<Result<_, _>>()?,
+    };
+    let included = match include_pats.is_empty() {
+        true => None,
+        false => Some(included_playlists(library, &include_pats, args)),
+    };
+    Ok((ignore_rules, included))
+}
+
+/// The seed to shuffle playlists with for --shuffle, the same for every output.
+fn shuffle_seed(args: &Args) -> Option<u64> {
+    let shuffle_seed = args.shuffle.map(|seed| seed.unwrap_or_else(|| {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+        now.as_secs() ^ u64::from(now.subsec_nanos())
+    }));
+    if let Some(seed) = shuffle_seed {
+        info!("Shuffling playlists with seed {}; pass --shuffle {} to repeat this order.", seed, seed);
+    }
+    shuffle_seed
+}
+
+/// The options of the sync that `build_plan` takes, from `args` and the playlists picked by
+/// include patterns and ignore rules.
+fn plan_options(
+    args: &Args,
+    shuffle_seed: Option<u64>,
+    included: Option<HashSet<u64>>,
+    ignore_rules: Vec<ignore::Rule>,
+    format: format::Options,
+) -> plan::Options {
+    plan::Options {
+        include_disabled: args.include_disabled,
+        include_protected: args.include_protected,
+        min_rating: args.min_rating,
+        min_playcount: args.min_playcount,
+        added_since: args.added_since,
+        include_missing: args.include_missing,
+        only_loved: args.only_loved,
+        filter: args.filter.clone(),
+        // Playlists picked on the command line are all that is wanted, so their tracks are too.
+        only_referenced: args.only_referenced || !args.playlists.is_empty() || !args.roots.is_empty(),
+        remap_hosts: args.remap_hosts.clone(),
+        check_sources: args.preflight || args.dry_run,
+        copy_tracks: !args.no_copy,
+        base_dir: args.base_dir.clone(),
+        tracks_dir: relative_dir(&args.tracks_dir),
+        track_names: args.track_names,
+        path_template: args.path_template.clone(),
+        compilations_folder: args.compilations_folder.clone(),
+        group_compilations: !args.no_compilation_grouping,
+        podcasts: args.podcasts,
+        new_episodes_days: args.new_episodes_days.unwrap_or(7),
+        normalize: args.normalize,
+        ascii: args.ascii,
+        sanitize: args.sanitize,
+        max_name_len: args.max_name_len,
+        playlists_dir: relative_dir(&args.playlists_dir),
+        included,
+        is_ignored: Box::new(move |names, ids, is_folder| ignore::is_ignored(&ignore_rules, names, ids, is_folder)),
+        roots: args.roots.clone(),
+        ignore_case: args.ignore_case,
+        include_hidden: args.include_hidden,
+        include_system: args.include_system.clone(),
+        system_playlists_dir: args.system_playlists_dir.clone(),
+        skip_smart: args.skip_smart,
+        only_smart: args.only_smart,
+        max_depth: match args.flat_playlists {
+            true => Some(0),
+            false => args.max_depth,
+        },
+        max_path_len: args.max_path_len,
+        on_long_path: args.on_long_path.unwrap_or(OnLongPath::Shorten),
+        renames: args.renames.clone(),
+        formats: args.formats.clone(),
+        format,
+        folder_playlists: args.folder_playlists,
+        skip_empty: args.skip_empty,
+        export_master: args.export_master.as_ref().map(|name| name.clone().unwrap_or_else(|| "Library".to_owned())),
+        all_tracks_playlist: args.all_tracks_playlist.as_ref().map(|name| name.clone().unwrap_or_else(|| "All Tracks".to_owned())),
+        library_playlist: args.library_playlist,
+        loved_playlist: args.loved_playlist.as_ref().map(|name| name.clone().unwrap_or_else(|| "Loved".to_owned())),
+        entry_paths: args.entry_paths,
+        path_separator: args.path_separator,
+        title_format: args.title_format.clone(),
+        dedupe_entries: args.dedupe_entries,
+        max_tracks_per_playlist: args.max_tracks_per_playlist,
+        max_playlist_duration: args.max_playlist_duration,
+        sort_entries: args.sort_entries.clone(),
+        shuffle_seed,
+        strict: args.strict,
+    }
+}
+
+/// How playlists are written, from `args`.
+fn format_options(args: &Args) -> format::Options {
+    format::Options {
+        bom: args.m3u_bom,
+        extinf: !args.no_extinf,
+        omit_unknown_extinf: args.omit_unknown_extinf,
+        encoding: args.playlist_encoding,
+        substitute: args.encoding_substitute.clone(),
+        encode_paths: args.encode_entries,
+        m3u_extension: args.playlist_ext,
+        generator: Some(concat!("m3trans ", env!("CARGO_PKG_VERSION")).to_owned()).filter(|_| args.stamp),
+        generated_at: Some(SystemTime::now()).filter(|_| args.stamp_time),
+        line_endings: args.line_endings,
+        entry_comments: args.entry_comments,
+        duration_style: args.duration_style,
+        player_hints: args.player_hints,
+        ascii: args.ascii,
+        path_separator: args.path_separator.as_char(),
+    }
+}
+
+/// Carries out `plan` into `args.output_path`.
+fn sync_output(
+    library: &Library,
+    args: &Args,
+    plan: &SyncPlan,
+    format_options: &format::Options,
+    actions: &mut Vec<Action>,
+    observer: &mut dyn SyncObserver,
+) -> Result<(), io::Error> {
+    let archive = match &args.output_archive {
+        Some(path) => Some(Archive::create(path, &args.output_path, args.compression)
+            .map_err(context(format!("failed to create archive {:?}", path)))?),
+        None => None,
+    };
+    let directory = Directory {
+        reflink: !args.no_reflink,
+        copy_buffer: args.copy_buffer,
+        limit: args.bwlimit.map(RateLimit::new),
+    };
+    let sink: &dyn Sink = match &archive {
+        Some(archive) => archive,
+        None if args.emit_file_list.is_some() => &Staging,
+        None => &directory,
+    };
+    let first_action = actions.len();
+
+    if !args.allow_overlap && archive.is_none() && !args.no_copy {
+        check_outside_media(library, args)?;
+    }
+    let mut target = SyncTarget { library, args, sink, format_options, tracks: None, playlists: None, actions, first_action };
+    plan::execute(plan, library, &args.output_path, &mut target, observer)?;
+    if let Some(archive) = archive {
+        let path = &args.output_path;
+        archive.finish().map_err(context(format!("failed to finish archive {:?}", path)))?;
+        info!("Wrote archive {:?}.", path);
+    }
+    if let Some(list_path) = &args.emit_file_list {
+        write_file_list(list_path, args, &actions[first_action..])
+            .map_err(context(format!("failed to write file list {:?}", list_path)))?;
+    }
+    Ok(())
+}
+
+/// Carries out a plan into `args.output_path`, taking note of everything it does as actions.
+struct SyncTarget<'a> {
+    library: &'a Library,
+    args: &'a Args,
+    sink: &'a dyn Sink,
+    format_options: &'a format::Options,
+    tracks: Option<TrackCopier<'a>>,
+    playlists: Option<PlaylistWriter<'a>>,
+    actions: &'a mut Vec<Action>,
+    /// Where the actions of this output start.
+    first_action: usize,
+}
+
+impl<'a> SyncTarget<'a> {
+    fn playlists(&mut self) -> &mut PlaylistWriter<'a> {
+        self.playlists.as_mut().expect("playlists are written once they are started")
+    }
+}
+
+impl Target for SyncTarget<'_> {
+    fn stopped(&self) -> bool {
+        interrupted()
+    }
+
+    fn start_tracks(&mut self, plan: &SyncPlan) -> Result<(), io::Error> {
+        self.tracks = Some(TrackCopier::start(self.library, plan, self.args, self.sink, self.actions)?);
+        Ok(())
+    }
+
+    fn copy_track(&mut self, copy: &TrackCopy) -> Result<TrackOutcome, io::Error> {
+        self.tracks.as_mut().expect("tracks are copied once they are started").copy(copy, self.actions)
+    }
+
+    fn finish_tracks(&mut self, report: &SyncReport) -> Result<(), io::Error> {
+        let tracks = self.tracks.as_mut().expect("tracks are finished once they are started");
+        tracks.finish(&report.synced, report.stopped)?;
+        #[cfg(feature = "art")]
+        if self.args.extract_art && !interrupted() {
+            extract_art(&tracks.planned, &report.synced, self.args, self.sink, self.actions);
+        }
+        Ok(())
+    }
+
+    fn start_playlists(&mut self, plan: &SyncPlan) -> Result<(), io::Error> {
+        let options = self.format_options.clone();
+        self.playlists = Some(PlaylistWriter::start(self.library, self.args, plan, options, self.sink, self.actions)?);
+        Ok(())
+    }
+
+    fn create_folder(&mut self, playlist: &Playlist, path: &Path) -> Result<(), io::Error> {
+        let path = self.args.output_path.join(path);
+        self.playlists().create_folder(playlist, &path)
+    }
+
+    fn create_dir(&mut self, path: &Path) -> Result<(), io::Error> {
+        let path = self.args.output_path.join(path);
+        self.playlists().create_dir(&path)
+    }
+
+    fn skip_playlist(&mut self, playlist: &Playlist, reason: &'static str) {
+        self.playlists().skip(playlist, reason);
+    }
+
+    fn skip_empty(&mut self, playlist: &PlannedPlaylist) {
+        self.playlists().skip_empty(playlist);
+    }
+
+    fn drop_entry(&mut self, playlist: &PlannedPlaylist, track_id: u64) -> Result<(), io::Error> {
+        self.playlists().drop_entry(playlist, track_id)
+    }
+
+    fn write_playlist(&mut self, playlist: &PlannedPlaylist, entries: &[(u64, Entry)]) -> Result<Vec<(PathBuf, bool)>, io::Error> {
+        self.playlists().write(playlist, entries)
+    }
+
+    fn finish(&mut self, report: &SyncReport) -> Result<(), io::Error> {
+        if let Some(playlists) = self.playlists.take() {
+            playlists.finish(report.stopped, self.actions)?;
+        }
+        if !self.args.no_manifest && !report.stopped && !interrupted() {
+            let planned = self.tracks.as_ref().map_or(&[][..], |tracks| &tracks.planned);
+            write_manifest(self.args, planned, &report.synced, &self.actions[self.first_action..], self.sink)?;
+        }
+        Ok(())
+    }
+}
+
+/// Writes the manifest of the `planned` tracks that were `synced` and the playlist files written
+/// among `actions`, or in a dry run only reports that it would. With --no-copy, no tracks are in
+/// the output to list.
+fn write_manifest(
+    args: &Args,
+    planned: &[PlannedTrack<'_>],
+    synced: &HashMap<u64, PathBuf>,
+    actions: &[Action],
+    sink: &dyn Sink,
+) -> Result<(), io::Error> {
+    let mut tracks = Vec::new();
+    for planned in planned {
+        let path = match synced.get(&planned.id) {
+            Some(path) => path,
+            None => continue,
+        };
+        tracks.push(manifest::ManifestTrack {
+            track_id: planned.id,
+            persistent_id: planned.track.persistent_id.map(|id| format!("{:016X}", id)),
+            source: planned.local_path.to_string_lossy().into_owned(),
+            path: PathSeparator::Slash.render(path),
+            size_bytes: planned.size,
+            duration_ms: planned.track.duration_ms,
+        });
+    }
+    tracks.sort_unstable_by(|a, b| a.path.cmp(&b.path));
+
+    let mut playlists = Vec::new();
+    for action in actions {
+        let written = match action.kind {
+            ActionKind::WritePlaylist => action.status != ActionStatus::Failed,
+            ActionKind::Skip => action.reason == Some("unchanged"),
+            _ => false,
+        };
+        let (persistent_id, destination) = match (&action.playlist_id, &action.destination) {
+            (Some(persistent_id), Some(destination)) if written => (persistent_id, Path::new(destination)),
+            _ => continue,
+        };
+        if let Ok(relative) = destination.strip_prefix(&args.output_path) {
+            playlists.push(manifest::ManifestPlaylist {
+                persistent_id: persistent_id.clone(),
+                name: action.name.clone().unwrap_or_default(),
+                path: PathSeparator::Slash.render(relative),
+            });
+        }
+    }
+
+    let path = args.output_path.join(manifest::FILE_NAME);
+    // --append-only only writes a manifest where there is none yet, never over one.
+    if args.append_only && path.exists() {
+        info!("Keeping the existing manifest at {:?}, as --append-only overwrites nothing.", path);
+        return Ok(());
+    }
+    if args.dry_run {
+        info!("Write manifest of {} tracks and {} playlist files at path {:?}", tracks.len(), playlists.len(), path);
+        return Ok(());
+    }
+    let manifest = manifest::Manifest::new(tracks, playlists);
+    let mut contents = crate::json::to_string_pretty(&manifest).map_err(io::Error::other)?.into_bytes();
+    contents.push(b'\n');
+    sink.write_file(&path, &contents).map_err(context(format!("failed to write manifest {:?}", path)))?;
+    debug!("Wrote the manifest of {} tracks and {} playlist files to {:?}.", manifest.tracks.len(), manifest.playlists.len(), path);
+    Ok(())
+}
+
+/// Tracks, playlists or whole output directories that couldn't be synced. Each is logged where it
+/// fails, so this only sums them up, or under --strict tells that the sync stopped at the first.
+#[derive(Debug)]
+pub(crate) enum SyncFailures {
+    Files(usize),
+    Outputs { failed: usize, total: usize },
+    Strict,
+}
+
+impl fmt::Display for SyncFailures {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SyncFailures::Files(count) => write!(f, "finished, but {} files failed to sync", count),
+            SyncFailures::Outputs { failed, total } =>
+                write!(f, "finished, but syncing to {} of {} output folders failed", failed, total),
+            SyncFailures::Strict => f.write_str("stopped at the first file that failed to sync, as --strict was given"),
+        }
+    }
+}
+
+impl std::error::Error for SyncFailures {}
+
+/// Ends the sync after a failure has been logged, if --strict was given.
+pub(crate) fn check_strict(args: &Args) -> Result<(), io::Error> {
+    match args.strict {
+        true => Err(io::Error::other(SyncFailures::Strict)),
+        false => Ok(()),
+    }
+}
+
+fn is_strict_stop(e: &io::Error) -> bool {
+    e.get_ref()
+        .and_then(|inner| inner.downcast_ref::<SyncFailures>())
+        .is_some_and(|failures| matches!(failures, SyncFailures::Strict))
+}
+
+fn parse_ignores(path: &Path, args: &Args) -> Result<Vec<ignore::Rule>, io::Error> {
+    match File::open(path) {
+        Ok(file) => {
+            let reader = BufReader::new(file);
+            let mut rules = Vec::new();
+            for line in reader.lines() {
+                let line = line.map_err(context(format!("failed to read ignore file {:?}", path)))?;
+                match ignore::Rule::parse(&args.normalize.apply(&line), args.ignore_case) {
+                    Ok(rule) => rules.extend(rule),
+                    Err(e) => warn!("Discarding invalid ignore rule \"{}\": {}", line, e),
+                }
+            }
+            Ok(rules)
+        },
+        Err(nf) if nf.kind() == ErrorKind::NotFound => Ok(Vec::new()),
+        Err(e) => Err(context(format!("failed to open ignore file {:?}", path))(e)),
+    }
+}
+
+fn parse_patterns(path: &Path, normalize: Normalization) -> Result<Vec<glob::Pattern>, io::Error> {
+    match File::open(path) {
+        Ok(file) => {
+            let reader = BufReader::new(file);
+            let mut patterns = Vec::new();
+            for line in reader.lines() {
+                let line = line.map_err(context(format!("failed to read include file {:?}", path)))?;
+                match glob::Pattern::new(&normalize.apply(&line)) {
+                    Ok(pattern) => patterns.push(pattern),
+                    Err(e) => warn!("Discarding invalid pattern \"{}\" in {:?}: {:?}", line, path, e),
+                }
+            }
+            Ok(patterns)
+        },
+        Err(nf) if nf.kind() == ErrorKind::NotFound => Ok(Vec::new()),
+        Err(e) => Err(context(format!("failed to open include file {:?}", path))(e)),
+    }
+}
+
+/// Creates `dir` and any missing parents, or in a dry run only reports that it would.
+pub(crate) fn create_output_dir(dir: &Path, dry_run: bool, sink: &dyn Sink, actions: &mut Vec<Action>) -> Result<(), io::Error> {
+    if dir.is_dir() {
+        return Ok(());
+    }
+    let action = Action::new(ActionKind::CreateDir, dry_run).destination(dir);
+    match dry_run {
+        false => {
+            let result = sink.create_dir_all(dir);
+            actions.push(action.result(&result));
+            result
+        }
+        true => {
+            info!("Create folder {:?}", dir);
+            actions.push(action);
+            Ok(())
+        }
+    }
+}
@@ -0,0 +1,743 @@
+//! Writing the playlists of a plan into an output.
+
+use std::{collections::{HashMap, HashSet}, fs, io, mem, path::{Path, PathBuf}};
+use log::*;
+use m3trans::{Library, Playlist, format::{self, Encoding, Entry, Format, IndexEntry, IndexKind}, names, plan::{PODCASTS_DIR, PathSeparator, PlannedPlaylist, PlaylistOrigin, SyncPlan, root_prefix}};
+
+use crate::args::{Args, Mode, output_dir};
+use crate::context;
+use crate::ignore;
+use crate::manifest;
+use crate::report::{Action, ActionKind, ActionStatus, Hex};
+use crate::sync::{check_strict, confirm, create_output_dir};
+use crate::sync::sink::{Sink, is_same_file, link_atomically, remove_temp_files};
+
+/// Returns `path` itself if it is not taken, otherwise the first untaken sibling of the form
+/// `<stem> (N).<extension>` for N >= 2, with the stem cut short so that the file name is at most
+/// `max_len` bytes if given.
+fn first_free(path: &Path, max_len: Option<usize>, mut is_taken: impl FnMut(&Path) -> bool) -> PathBuf {
+    if !is_taken(path) { return path.to_owned(); }
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let extension = path.extension().map(|extension| format!(".{}", extension.to_string_lossy())).unwrap_or_default();
+    let max_len = max_len.map(|max_len| max_len.saturating_sub(extension.len()));
+    (2..).map(|n| {
+        let name = names::with_suffix(&stem, &format!(" ({})", n), max_len);
+        path.with_file_name(name + &extension)
+    }).find(|candidate| !is_taken(candidate)).unwrap()
+}
+
+/// `path` without the ` (N)` that [`first_free`] may have put at the end of its stem.
+fn unversioned(path: &Path) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let base = stem.strip_suffix(')')
+        .and_then(|rest| rest.rsplit_once(" ("))
+        .filter(|(_, n)| !n.is_empty() && n.bytes().all(|b| b.is_ascii_digit()))
+        .map(|(base, _)| base);
+    match base {
+        Some(base) => {
+            let extension = path.extension().map(|extension| format!(".{}", extension.to_string_lossy())).unwrap_or_default();
+            path.with_file_name(base.to_owned() + &extension)
+        }
+        None => path.to_owned(),
+    }
+}
+
+/// Where --append-only writes what would go to `path`: there if nothing is there yet or what is
+/// there `is_same`, otherwise at the first version numbered from the name without a version of
+/// its own that is free or already holds the same, so that a rerun writes nothing new.
+fn append_only_path(path: &Path, max_len: Option<usize>, is_same: impl Fn(&[u8]) -> bool) -> PathBuf {
+    let is_taken = |candidate: &Path| fs::read(candidate)
+        .map_or_else(|e| e.kind() != io::ErrorKind::NotFound, |existing| !is_same(&existing));
+    if !is_taken(path) { return path.to_owned(); }
+    // The name without a version belongs to another playlist when this one's has one already.
+    let base = unversioned(path);
+    first_free(&base, max_len, |candidate| candidate == base || candidate == path || is_taken(candidate))
+}
+
+pub(crate) struct PlaylistWriter<'a> {
+    library: &'a Library,
+    args: &'a Args,
+    sink: &'a dyn Sink,
+    /// Where the file of each track playlists may refer to is, for --mode folders to link to.
+    sources: HashMap<u64, PathBuf>,
+    playlists_dir: PathBuf,
+    /// Only missing in a dry run, which has nothing there to clean up or prune.
+    playlists_dir_exists: bool,
+    options: format::Options,
+    index: Vec<IndexEntry>,
+    /// Every playlist file and folder produced (or, in dry-run, planned) this run.
+    written: HashSet<PathBuf>,
+    actions: Vec<Action>,
+    /// The playlists that left out entries for tracks missing from the library or the output,
+    /// with how many.
+    dropped: Vec<(String, usize)>,
+    /// The entries left out of the next playlist for tracks that failed to sync.
+    failed_entries: usize,
+}
+
+impl<'a> PlaylistWriter<'a> {
+    /// Creates the playlists folder, and cleans up what an earlier run left there half written.
+    pub(crate) fn start(
+        library: &'a Library,
+        args: &'a Args,
+        plan: &SyncPlan,
+        options: format::Options,
+        sink: &'a dyn Sink,
+        actions: &mut Vec<Action>,
+    ) -> Result<Self, io::Error> {
+        let playlists_dir = output_dir(&args.output_path, &args.playlists_dir);
+        create_output_dir(&playlists_dir, args.dry_run, sink, actions)
+            .map_err(context(format!("failed to create playlists folder {:?}", playlists_dir)))?;
+        let playlists_dir_exists = playlists_dir.is_dir();
+        if playlists_dir_exists && !args.append_only {
+            remove_temp_files(&playlists_dir, args.dry_run);
+        }
+        let sources = match plan.copy_tracks {
+            true => plan.tracks.iter().map(|copy| (copy.track_id, args.output_path.join(&copy.destination))).collect(),
+            false => plan.in_place.iter().map(|track| (track.track_id, track.path.clone())).collect(),
+        };
+        Ok(PlaylistWriter {
+            library,
+            args,
+            sink,
+            sources,
+            playlists_dir,
+            playlists_dir_exists,
+            options,
+            index: Vec::new(),
+            written: HashSet::new(),
+            actions: Vec::new(),
+            dropped: Vec::new(),
+            failed_entries: 0,
+        })
+    }
+
+    /// Writes `entries` as the planned playlist in every selected format, returning the files
+    /// written relative to the output and whether each changed. Fails in strict mode if the
+    /// playlist can't be written in full, which ends the sync.
+    pub(crate) fn write(&mut self, planned: &PlannedPlaylist, entries: &[(u64, Entry)]) -> Result<Vec<(PathBuf, bool)>, io::Error> {
+        let args = self.args;
+        let playlist = &planned.playlist;
+        let dropped = planned.dropped + mem::take(&mut self.failed_entries);
+        if dropped > 0 {
+            self.dropped.push((playlist.name.clone(), dropped));
+        }
+        let stem = args.output_path.join(&planned.stem);
+        if args.mode == Mode::Folders {
+            self.write_folder(playlist, entries, &stem)?;
+            let mut indexed = index_entry(playlist, IndexKind::Playlist, &[&stem], &self.playlists_dir);
+            if args.html_index {
+                indexed.entries = entries.iter().map(|(_, entry)| entry.clone()).collect();
+            }
+            self.index.push(indexed);
+            return Ok(Vec::new());
+        }
+        let (track_ids, entries): (Vec<u64>, Vec<Entry>) = entries.iter().cloned().unzip();
+
+        let mut files = Vec::with_capacity(planned.files.len());
+        let mut written = Vec::with_capacity(planned.files.len());
+        for file in &planned.files {
+            let format = file.format;
+            let playlist_path = args.output_path.join(&file.path);
+            // Archives are written anew, with nothing to compare against.
+            let ignore_comments = args.ignore_entry_comments && args.output_archive.is_none() && format == Format::M3u;
+            let playlist_path = match args.append_only {
+                true => {
+                    let mut contents = Vec::new();
+                    let rendered = format.write(&mut contents, playlist, &entries, &self.options).is_ok();
+                    append_only_path(&playlist_path, args.max_name_len, |existing| rendered && match ignore_comments {
+                        true => format::without_entry_comments(existing) == format::without_entry_comments(&contents),
+                        false => existing == &contents[..],
+                    })
+                }
+                false => playlist_path,
+            };
+            match args.dry_run {
+                false => {
+                    if format.uses_encoding() && self.options.encoding != Encoding::Utf8 {
+                        for entry in &entries {
+                            if !self.options.encoding.can_encode(&entry.title) || !self.options.encoding.can_encode(&entry.path) {
+                                warn!(
+                                    event = "entry_not_encodable", playlist_id:% = Hex(playlist.persistent_id), title = entry.title.as_str();
+                                    "Entry {} of playlist {} can't be represented in {:?}; substituting characters.",
+                                    entry.title, playlist.name, self.options.encoding
+                                );
+                            }
+                        }
+                    }
+
+                    let result = write_playlist(self.sink, format, &playlist_path, playlist, &entries, &self.options, ignore_comments);
+                    if let Ok(changed) = result {
+                        written.push((playlist_path.strip_prefix(&args.output_path).unwrap().to_owned(), changed));
+                    }
+                    if let Ok(false) = result {
+                        debug!("Playlist {} at path {:?} is unchanged", playlist.name, playlist_path);
+                        self.actions.push(Action::skip("unchanged").playlist(playlist).destination(&playlist_path).entries(&track_ids));
+                    }
+                    else {
+                        self.actions.push(
+                            Action::new(ActionKind::WritePlaylist, false).playlist(playlist).destination(&playlist_path).entries(&track_ids).result(&result)
+                        );
+                    }
+                    if let Err(e) = result {
+                        error!(
+                            event = "playlist_write_failed", playlist_id:% = Hex(playlist.persistent_id), dest:% = playlist_path.display(), error:% = e;
+                            "Failed to generate {} for playlist {} at path {:?}: {:?}",
+                            format.extension(&self.options), playlist.name, playlist_path, e
+                        );
+                        check_strict(args)?;
+                    }
+                }
+                true => {
+                    info!(
+                        event = "playlist_write_planned", playlist_id:% = Hex(playlist.persistent_id), dest:% = playlist_path.display();
+                        "Generate {} for playlist {} at path {:?}",
+                        format.extension(&self.options), playlist.name, playlist_path
+                    );
+                    self.actions.push(Action::new(ActionKind::WritePlaylist, true).playlist(playlist).destination(&playlist_path).entries(&track_ids));
+                }
+            }
+            self.written.insert(playlist_path.clone());
+            files.push(playlist_path);
+        }
+        let mut indexed = index_entry(playlist, IndexKind::Playlist, &files, &self.playlists_dir);
+        if args.html_index {
+            indexed.entries = entries;
+        }
+        self.index.push(indexed);
+        Ok(written)
+    }
+
+    pub(crate) fn skip(&mut self, playlist: &Playlist, reason: &'static str) {
+        self.actions.push(Action::skip(reason).playlist(playlist));
+    }
+
+    /// Takes note of a playlist left out as none of its tracks were synced.
+    pub(crate) fn skip_empty(&mut self, planned: &PlannedPlaylist) {
+        let playlist = &planned.playlist;
+        if planned.origin == PlaylistOrigin::Folder {
+            info!("Skipping empty combined playlist for folder {}.", playlist.name);
+            return;
+        }
+        match playlist.is_empty() {
+            true => info!("Skipping empty playlist {} with path {:?}.", playlist.name, planned.virtual_path),
+            false => info!("Skipping playlist {} with path {:?}, as none of its tracks were synced.", playlist.name, planned.virtual_path),
+        }
+        self.actions.push(Action::skip("empty").playlist(playlist));
+    }
+
+    /// Leaves out the entry of a track that failed to sync, which is an error in strict mode.
+    pub(crate) fn drop_entry(&mut self, planned: &PlannedPlaylist, track_id: u64) -> Result<(), io::Error> {
+        let message = format!(
+            "Playlist {} references track {} ({}), which isn't in the output",
+            planned.playlist.name, track_id, self.library.tracks[&track_id].name
+        );
+        match self.args.strict {
+            true => {
+                error!("{}.", message);
+                check_strict(self.args)?;
+            }
+            false => warn!("{}; leaving it out.", message),
+        }
+        self.failed_entries += 1;
+        Ok(())
+    }
+
+    /// Writes the index and pages of the playlists and prunes those of earlier runs, once all of
+    /// them are written, and sums up what was done. When `stopped`, nothing else is written or
+    /// pruned, as it would be based on only some of the playlists.
+    pub(crate) fn finish(mut self, stopped: bool, actions: &mut Vec<Action>) -> Result<(), io::Error> {
+        let args = self.args;
+        if stopped {
+            let files = self.written.len();
+            warn!(event = "interrupted", playlist_files = files; "Interrupted by user after writing {} playlist files.", files);
+            actions.append(&mut self.actions);
+            return Ok(());
+        }
+
+        // Folders flattened away by --flat-playlists or --max-depth aren't in the index, so the
+        // playlists inside them have no parent there.
+        let folders: HashSet<String> = self.index.iter()
+            .filter(|indexed| matches!(indexed.kind, IndexKind::Folder))
+            .map(|indexed| indexed.persistent_id.clone())
+            .collect();
+        for indexed in &mut self.index {
+            if indexed.parent_id.as_ref().is_some_and(|id| !folders.contains(id)) {
+                indexed.parent_id = None;
+            }
+        }
+
+        // Folders of tracks have no files for the index to list.
+        if args.formats.contains(&Format::Json) && args.mode == Mode::Playlists {
+            let mut contents = Vec::new();
+            let rendered = format::write_index(&mut contents, &self.index);
+            let index_path = self.playlists_dir.join("index.json");
+            let index_path = match args.append_only {
+                true => append_only_path(&index_path, args.max_name_len, |existing| rendered.is_ok() && existing == &contents[..]),
+                false => index_path,
+            };
+            let action = Action::new(ActionKind::WritePlaylist, args.dry_run).destination(&index_path);
+            match args.dry_run {
+                false => {
+                    let result = rendered.and_then(|()| self.sink.write_file(&index_path, &contents));
+                    match result {
+                        Ok(false) => self.actions.push(Action::skip("unchanged").destination(&index_path)),
+                        _ => self.actions.push(action.result(&result)),
+                    }
+                    if let Err(e) = result {
+                        error!(
+                            event = "index_write_failed", dest:% = index_path.display(), error:% = e;
+                            "Failed to generate playlist index at path {:?}: {:?}", index_path, e
+                        );
+                        check_strict(args)?;
+                    }
+                }
+                true => {
+                    info!("Generate playlist index at path {:?}", index_path);
+                    self.actions.push(action);
+                }
+            }
+            self.written.insert(index_path);
+        }
+        if args.html_index {
+            self.write_html()?;
+        }
+
+        if !self.dropped.is_empty() {
+            let total: usize = self.dropped.iter().map(|(_, dropped)| dropped).sum();
+            let playlists: Vec<String> = self.dropped.iter().map(|(name, dropped)| format!("{} ({})", name, dropped)).collect();
+            warn!(
+                event = "entries_dropped", entries = total, playlists = self.dropped.len();
+                "Left out {} entries for missing tracks from {} playlists: {}.", total, self.dropped.len(), playlists.join(", ")
+            );
+        }
+        if !args.dry_run {
+            let count = |matches: fn(&Action) -> bool| self.actions.iter().filter(|action| matches(action)).count();
+            info!(
+                "Wrote {} playlist files, leaving {} unchanged.",
+                count(|action| action.kind == ActionKind::WritePlaylist && action.status == ActionStatus::Done),
+                count(|action| action.reason == Some("unchanged")),
+            );
+        }
+        actions.append(&mut self.actions);
+        if args.prune_playlists && self.playlists_dir_exists {
+            prune_playlists(&self.playlists_dir, &self.written, args, actions)?;
+        }
+        Ok(())
+    }
+
+    /// Writes index.html at the root of the output, and with --html-pages the page of each
+    /// playlist next to its files. Fails in strict mode if any of them can't be written, which
+    /// ends the sync.
+    fn write_html(&mut self) -> Result<(), io::Error> {
+        let args = self.args;
+        let index = mem::take(&mut self.index);
+        let playlists_dir = PathSeparator::Slash.render(self.playlists_dir.strip_prefix(&args.output_path).unwrap());
+        let pages = args.html_pages && args.mode == Mode::Playlists;
+
+        let mut contents = Vec::new();
+        format::write_html_index(&mut contents, &index, &playlists_dir, pages, &self.options)?;
+        self.write_html_file(&args.output_path.join("index.html"), "HTML index", &contents)?;
+        for entry in index.iter().filter(|_| pages) {
+            let page = match format::html_page_path(entry) {
+                Some(page) => self.playlists_dir.join(page),
+                None => continue,
+            };
+            let home = PathSeparator::Slash.render(&root_prefix(page.strip_prefix(&args.output_path).unwrap()).join("index.html"));
+            contents.clear();
+            format::write_html_page(&mut contents, entry, &home, &self.options)?;
+            self.write_html_file(&page, &format!("HTML page of playlist {}", entry.name), &contents)?;
+        }
+        Ok(())
+    }
+
+    fn write_html_file(&mut self, path: &Path, what: &str, contents: &[u8]) -> Result<(), io::Error> {
+        let args = self.args;
+        let action = Action::new(ActionKind::WritePlaylist, args.dry_run).destination(path);
+        match args.dry_run {
+            false => {
+                let result = self.sink.write_file(path, contents);
+                match result {
+                    Ok(false) => self.actions.push(Action::skip("unchanged").destination(path)),
+                    _ => self.actions.push(action.result(&result)),
+                }
+                if let Err(e) = result {
+                    error!(
+                        event = "index_write_failed", dest:% = path.display(), error:% = e;
+                        "Failed to generate {} at path {:?}: {:?}", what, path, e
+                    );
+                    check_strict(args)?;
+                }
+            }
+            true => {
+                info!("Generate {} at path {:?}", what, path);
+                self.actions.push(action);
+            }
+        }
+        self.written.insert(path.to_owned());
+        Ok(())
+    }
+
+    /// Puts the tracks of `entries` in the folder at `stem`, numbered in order and named after
+    /// their titles, removing the ones left from earlier runs that are numbered otherwise. Fails in
+    /// strict mode if any of them can't be linked or copied, which ends the sync.
+    fn write_folder(&mut self, playlist: &Playlist, entries: &[(u64, Entry)], stem: &Path) -> Result<(), io::Error> {
+        let args = self.args;
+        if !stem.is_dir() {
+            let action = Action::new(ActionKind::CreateDir, args.dry_run).playlist(playlist).destination(stem);
+            match args.dry_run {
+                false => {
+                    let result = self.sink.create_dir_all(stem);
+                    self.actions.push(action.result(&result));
+                    if let Err(e) = result {
+                        error!(
+                            event = "folder_create_failed", playlist_id:% = Hex(playlist.persistent_id), dest:% = stem.display(), error:% = e;
+                            "Failed to generate folder for playlist {} at path {:?}: {:?}", playlist.name, stem, e
+                        );
+                        return check_strict(args);
+                    }
+                }
+                true => {
+                    info!("Generate folder for playlist {} at path {:?}", playlist.name, stem);
+                    self.actions.push(action);
+                }
+            }
+        }
+        self.written.insert(stem.to_owned());
+
+        // At least three digits, so that players sorting names as text keep the order.
+        let width = entries.len().to_string().len().max(3);
+        let mut names = HashSet::new();
+        for (i, (track_id, entry)) in entries.iter().enumerate() {
+            let source = self.sources[track_id].clone();
+            let mut name = args.sanitize.apply(&format!("{:0width$} - {}", i + 1, entry.title, width = width));
+            if let Some(extension) = source.extension() {
+                name.push('.');
+                name.push_str(&extension.to_string_lossy());
+            }
+            let destination = stem.join(&name);
+            names.insert(name);
+            self.written.insert(destination.clone());
+            let track = &self.library.tracks[track_id];
+            let action = |kind| Action::new(kind, args.dry_run).playlist(playlist).track(*track_id, track).source(&source).destination(&destination);
+
+            if is_same_file(&source, &destination) {
+                self.actions.push(Action::skip("unchanged").playlist(playlist).track(*track_id, track).destination(&destination));
+                continue;
+            }
+            if args.dry_run {
+                info!("Link file at {:?} to {:?} for playlist {}", destination, source, playlist.name);
+                self.actions.push(action(ActionKind::LinkTrack));
+                continue;
+            }
+            let result = match link_atomically(&source, &destination) {
+                Ok(()) => Ok(ActionKind::LinkTrack),
+                Err(e) => {
+                    debug!("Couldn't link file at {:?} to {:?}, copying it instead: {}", destination, source, e);
+                    self.sink.copy_file(&source, &destination).map(|_| ActionKind::CopyTrack)
+                }
+            };
+            match result {
+                Ok(kind) => self.actions.push(action(kind)),
+                Err(e) => {
+                    error!(
+                        event = "track_copy_failed", track_id = *track_id, src:% = source.display(), dest:% = destination.display(), error:% = e;
+                        "Failed to copy file at {:?} to {:?} for playlist {}: {:?}", source, destination, playlist.name, e
+                    );
+                    self.actions.push(action(ActionKind::CopyTrack).result::<()>(&Err(e)));
+                    check_strict(args)?;
+                }
+            }
+        }
+
+        // Tracks that moved elsewhere in the playlist or left it would otherwise play on.
+        let stale = fs::read_dir(stem).into_iter().flatten().flatten()
+            .filter(|entry| entry.file_type().is_ok_and(|file_type| file_type.is_file()))
+            .map(|entry| entry.path())
+            .filter(|path| is_numbered_entry(path) && !path.file_name().is_some_and(|name| names.contains(&*name.to_string_lossy())));
+        for path in stale {
+            let action = Action::new(ActionKind::Delete, args.dry_run).playlist(playlist).destination(&path);
+            match args.dry_run {
+                false => {
+                    let result = fs::remove_file(&path);
+                    self.actions.push(action.result(&result));
+                    match result {
+                        Ok(()) => debug!(event = "stale_removed", path:% = path.display(); "Removed stale entry of playlist {} at {:?}", playlist.name, path),
+                        Err(e) => warn!(
+                            event = "stale_remove_failed", path:% = path.display(), error:% = e;
+                            "Failed to remove stale entry of playlist {} at {:?}: {:?}", playlist.name, path, e
+                        ),
+                    }
+                }
+                true => {
+                    info!("Remove stale entry of playlist {} at {:?}", playlist.name, path);
+                    self.actions.push(action);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Fails in strict mode if the folder can't be created, which ends the sync.
+    pub(crate) fn create_folder(&mut self, playlist: &Playlist, path: &Path) -> Result<(), io::Error> {
+        let args = self.args;
+        if path.is_dir() {
+            info!("Reusing existing folder for playlist folder {} at path {:?}", playlist.name, path);
+            self.actions.push(Action::skip("exists").playlist(playlist).destination(path));
+        }
+        else {
+            let action = Action::new(ActionKind::CreateDir, args.dry_run).playlist(playlist).destination(path);
+            match args.dry_run {
+                false => {
+                    let result = self.sink.create_dir(path);
+                    self.actions.push(action.result(&result));
+                    if let Err(e) = result {
+                        error!(
+                            event = "folder_create_failed", playlist_id:% = Hex(playlist.persistent_id), dest:% = path.display(), error:% = e;
+                            "Failed to generate folder for playlist folder {} at path {:?}: {:?}",
+                            playlist.name, path, e
+                        );
+                        check_strict(args)?;
+                    }
+                }
+                true => {
+                    info!("Generate folder for playlist folder {} at path {:?}", playlist.name, path);
+                    self.actions.push(action);
+                }
+            }
+        }
+        self.written.insert(path.to_owned());
+        self.index.push(index_entry(playlist, IndexKind::Folder, &[path], &self.playlists_dir));
+        Ok(())
+    }
+
+    /// Creates a folder that isn't a playlist folder of the library, like that of system playlists,
+    /// along with any it is in.
+    pub(crate) fn create_dir(&mut self, path: &Path) -> Result<(), io::Error> {
+        let args = self.args;
+        if let Err(e) = create_output_dir(path, args.dry_run, self.sink, &mut self.actions) {
+            error!(
+                event = "folder_create_failed", dest:% = path.display(), error:% = e;
+                "Failed to create folder at path {:?}: {:?}", path, e
+            );
+            check_strict(args)?;
+        }
+        self.written.insert(path.to_owned());
+        Ok(())
+    }
+}
+
+/// Removes playlist files under `dir` that weren't written this run, along with any folders that
+/// end up empty, after asking whether to.
+pub(crate) fn prune_playlists(dir: &Path, written: &HashSet<PathBuf>, args: &Args, actions: &mut Vec<Action>) -> Result<(), io::Error> {
+    let mut stale = Vec::new();
+    // Inside the playlists folder when that is the output folder itself, or holds the tracks one.
+    let others = [
+        output_dir(&args.output_path, &args.tracks_dir),
+        args.output_path.join(PODCASTS_DIR),
+        args.output_path.join(manifest::FILE_NAME),
+    ];
+    stale_playlists(dir, written, &others, args.mode, &mut stale);
+    if stale.is_empty() {
+        return Ok(());
+    }
+    if !args.dry_run {
+        confirm(&format!(
+            "{} playlist files and folders in {:?} weren't written this run and will be deleted.",
+            stale.len(), dir
+        ), args.yes)?;
+    }
+
+    for path in stale {
+        let action = Action::new(ActionKind::Delete, args.dry_run).destination(&path);
+        match args.dry_run {
+            false => {
+                let result = match path.is_dir() {
+                    true => fs::remove_dir(&path),
+                    false => fs::remove_file(&path),
+                };
+                actions.push(action.result(&result));
+                match result {
+                    Ok(()) => info!(event = "stale_removed", path:% = path.display(); "Removed stale playlist file or folder at {:?}", path),
+                    Err(e) => {
+                        warn!(
+                            event = "stale_remove_failed", path:% = path.display(), error:% = e;
+                            "Failed to remove stale playlist file or folder at {:?}: {:?}", path, e
+                        );
+                        check_strict(args)?;
+                    }
+                }
+            }
+            true => {
+                info!("Remove stale playlist file or folder at {:?}", path);
+                actions.push(action);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Collects the playlist files under `dir` that weren't written this run, along with any folders
+/// that would end up empty, each folder after its contents. In folders mode, the numbered tracks
+/// of playlist folders count as playlist files. The `others`, folders of tracks and the manifest,
+/// are left alone.
+/// Returns whether `dir` itself would be left empty.
+fn stale_playlists(dir: &Path, written: &HashSet<PathBuf>, others: &[PathBuf], mode: Mode, stale: &mut Vec<PathBuf>) -> bool {
+    const PLAYLIST_EXTENSIONS: &[&str] = &["m3u8", "m3u", "pls", "xspf", "json", "html"];
+
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) => {
+            warn!("Failed to look for stale playlists in {:?}: {:?}", dir, e);
+            return false;
+        }
+    };
+    let mut empty = true;
+    for entry in entries.flatten() {
+        let path = entry.path();
+        let is_dir = entry.file_type().is_ok_and(|file_type| file_type.is_dir());
+        let is_stale = match is_dir {
+            true if others.contains(&path) => false,
+            true => stale_playlists(&path, written, others, mode, stale) && !written.contains(&path),
+            false => !written.contains(&path) && !others.contains(&path) && (path.extension()
+                .and_then(|extension| extension.to_str())
+                .is_some_and(|extension| PLAYLIST_EXTENSIONS.contains(&extension.to_ascii_lowercase().as_str()))
+                || mode == Mode::Folders && is_numbered_entry(&path)),
+        };
+        match is_stale {
+            true => stale.push(path),
+            false => empty = false,
+        }
+    }
+    empty
+}
+
+/// Finds the playlists selected by include patterns: those matching a pattern themselves, those
+/// inside a matching folder, and the folders containing either.
+pub(crate) fn included_playlists(library: &Library, include_pats: &[glob::Pattern], args: &Args) -> HashSet<u64> {
+    let options = ignore::match_options(args.ignore_case);
+    let mut included = HashSet::new();
+    let mut matched_depth = None;
+    let mut used = vec![false; include_pats.len()];
+    library.visit_playlists_with_path(|path| {
+        let depth = path.len() - 1;
+        if matched_depth.is_some_and(|matched| matched >= depth) {
+            matched_depth = None;
+        }
+
+        let names: Vec<String> = path.iter().map(|playlist| args.normalize.apply(&playlist.name)).collect();
+        let virtual_path = names.join("/");
+        let mut matches = false;
+        for (pat, used) in include_pats.iter().zip(&mut used) {
+            if pat.matches_with(&virtual_path, options) {
+                *used = true;
+                matches = true;
+            }
+        }
+        if matched_depth.is_none() && matches {
+            matched_depth = Some(depth);
+            included.extend(path[..depth].iter().map(|playlist| playlist.persistent_id));
+        }
+        if matched_depth.is_some() {
+            included.insert(path[depth].persistent_id);
+        }
+    });
+    for (pat, _) in include_pats.iter().zip(used).filter(|(_, used)| !used) {
+        warn!("Pattern {:?} doesn't match any playlist.", pat.as_str());
+    }
+    included
+}
+
+/// Whether the file at `path` is named like the tracks `--mode folders` puts in playlist folders,
+/// with a number and ` - ` in front.
+fn is_numbered_entry(path: &Path) -> bool {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let digits = name.bytes().take_while(u8::is_ascii_digit).count();
+    digits > 0 && name[digits..].starts_with(" - ")
+}
+
+fn write_playlist(
+    sink: &dyn Sink,
+    format: Format,
+    path: &Path,
+    playlist: &Playlist,
+    entries: &[Entry],
+    options: &format::Options,
+    ignore_comments: bool,
+) -> Result<bool, io::Error> {
+    let mut contents = Vec::new();
+    format.write(&mut contents, playlist, entries, options)?;
+    if ignore_comments && format == Format::M3u {
+        let unchanged = fs::read(path)
+            .is_ok_and(|existing| format::without_entry_comments(&existing) == format::without_entry_comments(&contents));
+        if unchanged {
+            return Ok(false);
+        }
+    }
+    sink.write_file(path, &contents)
+}
+
+fn index_entry(playlist: &Playlist, kind: IndexKind, files: &[impl AsRef<Path>], playlists_dir: &Path) -> IndexEntry {
+    IndexEntry {
+        name: playlist.name.clone(),
+        persistent_id: format!("{:016X}", playlist.persistent_id),
+        parent_id: playlist.parent_id.map(|id| format!("{:016X}", id)),
+        kind,
+        files: files.iter()
+            .map(|file| PathSeparator::Slash.render(file.as_ref().strip_prefix(playlists_dir).unwrap()))
+            .collect(),
+        entries: Vec::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn first_free_numbers_names_within_the_limit() {
+        let taken = [PathBuf::from("out/Mix.m3u8"), PathBuf::from("out/Mix (2).m3u8")];
+        let is_taken = |path: &Path| taken.iter().any(|taken| taken == path);
+        assert_eq!(first_free(Path::new("out/Free.m3u8"), None, is_taken), Path::new("out/Free.m3u8"));
+        assert_eq!(first_free(Path::new("out/Mix.m3u8"), None, is_taken), Path::new("out/Mix (3).m3u8"));
+        // "Mix (2).m3u8" is 12 bytes, so the stem is cut to fit 11.
+        assert_eq!(first_free(Path::new("out/Mix.m3u8"), Some(11), is_taken), Path::new("out/Mi (2).m3u8"));
+
+        let taken = [PathBuf::from("out/éé.m3u")];
+        let is_taken = |path: &Path| taken.iter().any(|taken| taken == path);
+        assert_eq!(first_free(Path::new("out/éé.m3u"), Some(11), is_taken), Path::new("out/é (2).m3u"));
+    }
+
+    #[test]
+    fn unversioned_drops_only_a_numbered_suffix() {
+        assert_eq!(unversioned(Path::new("out/Mix (12).m3u8")), Path::new("out/Mix.m3u8"));
+        assert_eq!(unversioned(Path::new("out/Mix (2) (3).m3u8")), Path::new("out/Mix (2).m3u8"));
+        for path in ["out/Mix.m3u8", "out/Mix (Live).m3u8", "out/Mix ().m3u8", "out/Mix(2).m3u8"] {
+            assert_eq!(unversioned(Path::new(path)), Path::new(path));
+        }
+    }
+
+    #[test]
+    fn append_only_path_reuses_a_version_with_the_same_contents() {
+        let dir = std::env::temp_dir().join(format!("m3trans-append-only-path-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("Mix.m3u8");
+        let same_as = |contents: &'static [u8]| move |existing: &[u8]| existing == contents;
+
+        assert_eq!(append_only_path(&path, None, same_as(b"one")), path);
+        fs::write(&path, b"one").unwrap();
+        assert_eq!(append_only_path(&path, None, same_as(b"one")), path);
+        assert_eq!(append_only_path(&path, None, same_as(b"two")), dir.join("Mix (2).m3u8"));
+        fs::write(dir.join("Mix (2).m3u8"), b"two").unwrap();
+        assert_eq!(append_only_path(&path, None, same_as(b"two")), dir.join("Mix (2).m3u8"));
+        assert_eq!(append_only_path(&path, None, same_as(b"three")), dir.join("Mix (3).m3u8"));
+
+        // A playlist named "Mix (2)" next to "Mix" is versioned from "Mix" rather than piling up
+        // suffixes, never taking another's name.
+        let sibling = dir.join("Mix (2).m3u8");
+        assert_eq!(append_only_path(&sibling, None, same_as(b"three")), dir.join("Mix (3).m3u8"));
+        assert_eq!(append_only_path(&sibling, None, same_as(b"one")), dir.join("Mix (3).m3u8"));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,464 @@
+//! Where a sync writes its files, and the ways of writing them that survive being interrupted.
+
+use std::{cell::RefCell, fs::{self, File}, io::{self, ErrorKind, Read, Write}, path::{Path, PathBuf}, sync::Mutex, time::{Duration, Instant}};
+use log::*;
+use m3trans::plan::PathSeparator;
+
+use crate::interrupt::interrupted;
+use crate::zip::{self, ZipWriter};
+
+/// Where a sync writes its files: the output directory itself, or an archive standing in for it.
+/// Paths are given in full, under `Args::output_path`, either way.
+pub(crate) trait Sink {
+    /// Creates the directory at `path`, which fails if there is one already.
+    fn create_dir(&self, path: &Path) -> Result<(), io::Error>;
+
+    /// Creates the directory at `path` along with any missing parents, unless it exists already.
+    fn create_dir_all(&self, path: &Path) -> Result<(), io::Error>;
+
+    /// Copies the file at `from` to `to`, returning the number of bytes copied and whether it was
+    /// cloned instead.
+    fn copy_file(&self, from: &Path, to: &Path) -> Result<(u64, bool), io::Error>;
+
+    /// Writes the file at `path`, returning whether it was written, as files that already have
+    /// these contents may be left alone.
+    fn write_file(&self, path: &Path, contents: &[u8]) -> Result<bool, io::Error>;
+}
+
+/// The output directory, with symbolic links to the files of tracks in place of copies, for
+/// another program to transfer.
+pub(crate) struct Staging;
+
+impl Sink for Staging {
+    fn create_dir(&self, path: &Path) -> Result<(), io::Error> {
+        fs::create_dir(path)
+    }
+
+    fn create_dir_all(&self, path: &Path) -> Result<(), io::Error> {
+        fs::create_dir_all(path)
+    }
+
+    fn copy_file(&self, from: &Path, to: &Path) -> Result<(u64, bool), io::Error> {
+        symlink_atomically(from, to).map(|()| (0, true))
+    }
+
+    fn write_file(&self, path: &Path, contents: &[u8]) -> Result<bool, io::Error> {
+        write_atomically(path, contents)
+    }
+}
+
+pub(crate) struct Directory {
+    pub(crate) reflink: bool,
+    pub(crate) copy_buffer: Option<usize>,
+    pub(crate) limit: Option<RateLimit>,
+}
+
+impl Sink for Directory {
+    fn create_dir(&self, path: &Path) -> Result<(), io::Error> {
+        fs::create_dir(path)
+    }
+
+    fn create_dir_all(&self, path: &Path) -> Result<(), io::Error> {
+        fs::create_dir_all(path)
+    }
+
+    fn copy_file(&self, from: &Path, to: &Path) -> Result<(u64, bool), io::Error> {
+        copy_atomically(from, to, self.reflink, self.copy_buffer, self.limit.as_ref())
+    }
+
+    fn write_file(&self, path: &Path, contents: &[u8]) -> Result<bool, io::Error> {
+        write_atomically(path, contents)
+    }
+}
+
+/// A zip archive, whose entries are named by their paths relative to `root`.
+pub(crate) struct Archive {
+    pub(crate) root: PathBuf,
+    pub(crate) compression: zip::Compression,
+    pub(crate) zip: RefCell<ZipWriter>,
+}
+
+impl Archive {
+    pub(crate) fn create(path: &Path, root: &Path, compression: zip::Compression) -> Result<Archive, io::Error> {
+        Ok(Archive { root: root.to_owned(), compression, zip: RefCell::new(ZipWriter::create(path)?) })
+    }
+
+    fn entry_name(&self, path: &Path) -> String {
+        let relative = path.strip_prefix(&self.root).expect("archive entries are under its root");
+        PathSeparator::Slash.render(relative)
+    }
+
+    pub(crate) fn finish(self) -> Result<(), io::Error> {
+        self.zip.into_inner().finish().map(drop)
+    }
+}
+
+impl Sink for Archive {
+    fn create_dir(&self, path: &Path) -> Result<(), io::Error> {
+        self.zip.borrow_mut().add_dir(&self.entry_name(path))
+    }
+
+    fn create_dir_all(&self, path: &Path) -> Result<(), io::Error> {
+        // Directories are made for every path in an archive once it's unpacked.
+        match path == self.root {
+            true => Ok(()),
+            false => self.create_dir(path),
+        }
+    }
+
+    fn copy_file(&self, from: &Path, to: &Path) -> Result<(u64, bool), io::Error> {
+        let file = File::open(from)?;
+        let bytes = self.zip.borrow_mut().add_file(&self.entry_name(to), file, self.compression)?;
+        Ok((bytes, false))
+    }
+
+    fn write_file(&self, path: &Path, contents: &[u8]) -> Result<bool, io::Error> {
+        self.zip.borrow_mut().add_file(&self.entry_name(path), io::Cursor::new(contents), self.compression)?;
+        Ok(true)
+    }
+}
+
+/// Whether `a` and `b` are the same file, as hard links to one another are.
+#[cfg(unix)]
+pub(crate) fn is_same_file(a: &Path, b: &Path) -> bool {
+    use std::os::unix::fs::MetadataExt;
+    match (fs::metadata(a), fs::metadata(b)) {
+        (Ok(a), Ok(b)) => a.dev() == b.dev() && a.ino() == b.ino(),
+        _ => false,
+    }
+}
+
+/// Whether `a` and `b` are the same file. Without a way to tell hard links apart here, files that
+/// are the same size and modified at the same time are taken to be.
+#[cfg(not(unix))]
+pub(crate) fn is_same_file(a: &Path, b: &Path) -> bool {
+    match (fs::metadata(a), fs::metadata(b)) {
+        (Ok(a), Ok(b)) => a.len() == b.len() && a.modified().ok().is_some() && a.modified().ok() == b.modified().ok(),
+        _ => false,
+    }
+}
+
+const TEMP_SUFFIX: &str = ".tmp";
+
+fn temp_path(path: &Path) -> PathBuf {
+    let mut temp_name = path.as_os_str().to_owned();
+    temp_name.push(TEMP_SUFFIX);
+    PathBuf::from(temp_name)
+}
+
+/// Runs `attempt` until it succeeds, retrying up to `retries` times, each after twice as long a
+/// wait as the last. Stops early once `stopped`, as when interrupted, returning the last error.
+/// `what` describes the attempt for the log.
+pub(crate) fn with_retries<T>(
+    retries: u32,
+    mut attempt: impl FnMut() -> Result<T, io::Error>,
+    stopped: impl Fn() -> bool,
+    what: impl Fn() -> String,
+) -> Result<T, io::Error> {
+    const FIRST_DELAY: Duration = Duration::from_millis(250);
+
+    let (mut tries, mut delay) = (0, FIRST_DELAY);
+    loop {
+        tries += 1;
+        match attempt() {
+            Err(e) if tries <= retries && !stopped() => {
+                debug!("Failed to {} on attempt {} of {}, retrying in {:?}: {}", what(), tries, retries + 1, delay, e);
+                std::thread::sleep(delay);
+                delay *= 2;
+            }
+            result => return result,
+        }
+    }
+}
+
+/// Copies `from` to `to` by way of a temporary file next to it, so that a copy cut short never
+/// leaves a truncated file at `to`. If `reflink` is set the file is cloned when the file system
+/// allows, and copied otherwise, through a buffer of `buffer_size` bytes if one is given. Returns
+/// the number of bytes copied, and whether it was cloned.
+fn copy_atomically(
+    from: &Path,
+    to: &Path,
+    reflink: bool,
+    buffer_size: Option<usize>,
+    limit: Option<&RateLimit>,
+) -> Result<(u64, bool), io::Error> {
+    let temp_path = temp_path(to);
+    let cloned = reflink && match clone_file(from, &temp_path) {
+        Ok(()) => true,
+        Err(e) => {
+            trace!("Couldn't clone {:?}, copying it instead: {}", from, e);
+            let _ = fs::remove_file(&temp_path);
+            false
+        }
+    };
+    let result = match (cloned, buffer_size, limit) {
+        (true, _, _) => fs::metadata(&temp_path).map(|metadata| metadata.len()),
+        (false, None, None) => fs::copy(from, &temp_path),
+        (false, buffer_size, limit) => copy_buffered(from, &temp_path, buffer_size.unwrap_or(LIMITED_BUFFER_SIZE), limit),
+    };
+    let result = result.and_then(|bytes| fs::rename(&temp_path, to).map(|()| (bytes, cloned)));
+    if result.is_err() {
+        let _ = fs::remove_file(&temp_path);
+    }
+    result
+}
+
+/// The buffer tracks are copied through under a rate limit, unless --copy-buffer is given, small
+/// enough to pace even slow rates smoothly.
+const LIMITED_BUFFER_SIZE: usize = 64 << 10;
+
+/// Copies `from` to `to` a buffer of `buffer_size` bytes at a time, stopping early if interrupted,
+/// and waiting after each for `limit` if there is one.
+fn copy_buffered(from: &Path, to: &Path, buffer_size: usize, limit: Option<&RateLimit>) -> Result<u64, io::Error> {
+    let mut source = File::open(from)?;
+    let mut dest = File::create(to)?;
+    let mut buffer = vec![0; buffer_size];
+    let mut copied = 0;
+    loop {
+        if interrupted() {
+            return Err(io::Error::new(ErrorKind::Interrupted, "interrupted by user"));
+        }
+        let read = match source.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(read) => read,
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        dest.write_all(&buffer[..read])?;
+        copied += read as u64;
+        if let Some(limit) = limit {
+            limit.take(read as u64);
+        }
+    }
+    dest.set_permissions(source.metadata()?.permissions())?;
+    Ok(copied)
+}
+
+/// A token bucket shared by all copies, which holds up to a second's worth of bytes. It starts
+/// empty, so that the first copies don't burst past the rate.
+pub(crate) struct RateLimit {
+    pub(crate) bytes_per_sec: u64,
+    /// The bytes that may be copied without waiting, which go negative when a copy overdraws
+    /// them, and when they were last topped up.
+    bucket: Mutex<(f64, Instant)>,
+}
+
+impl RateLimit {
+    pub(crate) fn new(bytes_per_sec: u64) -> RateLimit {
+        RateLimit { bytes_per_sec, bucket: Mutex::new((0.0, Instant::now())) }
+    }
+
+    /// Takes `bytes` that were just copied out of the bucket, sleeping until it is no longer in
+    /// debt. Copies made meanwhile see the debt and wait their turn after this one.
+    pub(crate) fn take(&self, bytes: u64) {
+        let rate = self.bytes_per_sec as f64;
+        let wait = {
+            let mut bucket = self.bucket.lock().unwrap_or_else(|e| e.into_inner());
+            let (tokens, topped_up) = &mut *bucket;
+            let now = Instant::now();
+            *tokens = (*tokens + now.duration_since(*topped_up).as_secs_f64() * rate).min(rate) - bytes as f64;
+            *topped_up = now;
+            match *tokens < 0.0 {
+                true => Duration::from_secs_f64(-*tokens / rate),
+                false => Duration::ZERO,
+            }
+        };
+        if !wait.is_zero() && !interrupted() {
+            std::thread::sleep(wait);
+        }
+    }
+}
+
+/// Makes `to` a copy-on-write clone of `from`, sharing its blocks on disk until either changes.
+/// This fails where the file system doesn't support it, or the two are on different volumes.
+#[cfg(target_os = "linux")]
+fn clone_file(from: &Path, to: &Path) -> Result<(), io::Error> {
+    use std::os::unix::io::AsRawFd;
+    // _IOW(0x94, 9, int), which libc doesn't define.
+    const FICLONE: u32 = 0x4004_9409;
+
+    let source = File::open(from)?;
+    let dest = File::create(to)?;
+    // The argument type of ioctl's request differs between C libraries, hence the inferred cast.
+    if unsafe { libc::ioctl(dest.as_raw_fd(), FICLONE as _, source.as_raw_fd()) } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    dest.set_permissions(source.metadata()?.permissions())
+}
+
+#[cfg(target_os = "macos")]
+fn clone_file(from: &Path, to: &Path) -> Result<(), io::Error> {
+    use std::{ffi::CString, os::unix::ffi::OsStrExt};
+    let c_path = |path: &Path| CString::new(path.as_os_str().as_bytes())
+        .map_err(|e| io::Error::new(ErrorKind::InvalidInput, e));
+
+    // clonefile keeps the permissions, and fails if `to` exists, which leftover temporary files
+    // were removed beforehand not to.
+    match unsafe { libc::clonefile(c_path(from)?.as_ptr(), c_path(to)?.as_ptr(), 0) } {
+        0 => Ok(()),
+        _ => Err(io::Error::last_os_error()),
+    }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+fn clone_file(_from: &Path, _to: &Path) -> Result<(), io::Error> {
+    Err(io::Error::new(ErrorKind::Unsupported, "cloning files isn't supported on this platform"))
+}
+
+/// Makes `to` a hard link to `from`, replacing whatever is there by way of a temporary link, so
+/// that a link cut short never leaves `to` missing.
+pub(crate) fn link_atomically(from: &Path, to: &Path) -> Result<(), io::Error> {
+    let temp_path = temp_path(to);
+    let _ = fs::remove_file(&temp_path);
+    let result = fs::hard_link(from, &temp_path).and_then(|()| fs::rename(&temp_path, to));
+    if result.is_err() {
+        let _ = fs::remove_file(&temp_path);
+    }
+    result
+}
+
+/// Makes `to` a symbolic link to `from`, by its absolute path, replacing whatever is there by way
+/// of a temporary link like `link_atomically`.
+fn symlink_atomically(from: &Path, to: &Path) -> Result<(), io::Error> {
+    let from = std::path::absolute(from)?;
+    let temp_path = temp_path(to);
+    let _ = fs::remove_file(&temp_path);
+    let result = symlink(&from, &temp_path).and_then(|()| fs::rename(&temp_path, to));
+    if result.is_err() {
+        let _ = fs::remove_file(&temp_path);
+    }
+    result
+}
+
+#[cfg(unix)]
+fn symlink(from: &Path, to: &Path) -> Result<(), io::Error> {
+    std::os::unix::fs::symlink(from, to)
+}
+
+#[cfg(windows)]
+fn symlink(from: &Path, to: &Path) -> Result<(), io::Error> {
+    std::os::windows::fs::symlink_file(from, to)
+}
+
+#[cfg(not(any(unix, windows)))]
+fn symlink(_from: &Path, _to: &Path) -> Result<(), io::Error> {
+    Err(io::Error::new(ErrorKind::Unsupported, "symbolic links aren't supported on this platform"))
+}
+
+/// Writes the file at `path` by way of a temporary file next to it, so that an interrupted run
+/// never leaves a truncated file behind. If the file already has exactly these contents it's left
+/// alone, keeping its modification time for players that rescan whatever changed. Returns whether
+/// the file was written.
+pub(crate) fn write_atomically(path: &Path, contents: &[u8]) -> Result<bool, io::Error> {
+    if fs::read(path).is_ok_and(|existing| existing == contents) {
+        return Ok(false);
+    }
+
+    let temp_path = temp_path(path);
+    let result = File::create(&temp_path).and_then(|mut file| {
+        file.write_all(contents)?;
+        file.sync_all()
+    });
+    if let Err(e) = result {
+        let _ = fs::remove_file(&temp_path);
+        return Err(e);
+    }
+    fs::rename(&temp_path, path).map(|()| true).map_err(|e| io::Error::new(
+        e.kind(),
+        format!("failed to rename {:?} to {:?}: {}", temp_path, path, e),
+    ))
+}
+
+/// Removes temporary files left in `dir` by an earlier run that was interrupted mid-write.
+pub(crate) fn remove_temp_files(dir: &Path, dry_run: bool) {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) => return warn!("Failed to look for leftover temporary files in {:?}: {:?}", dir, e),
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        match entry.file_type() {
+            Ok(file_type) if file_type.is_dir() => remove_temp_files(&path, dry_run),
+            Ok(_) if path.to_string_lossy().ends_with(TEMP_SUFFIX) => match dry_run {
+                false => match fs::remove_file(&path) {
+                    Ok(()) => info!("Removed leftover temporary file at {:?}", path),
+                    Err(e) => warn!("Failed to remove leftover temporary file at {:?}: {:?}", path, e),
+                }
+                true => info!("Remove leftover temporary file at {:?}", path),
+            },
+            _ => {},
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+
+    fn failure() -> io::Error {
+        io::Error::other("device busy")
+    }
+
+    #[test]
+    fn with_retries_succeeds_after_a_failure() {
+        let tries = Cell::new(0);
+        let result = with_retries(2, || {
+            tries.set(tries.get() + 1);
+            match tries.get() {
+                1 => Err(failure()),
+                n => Ok(n),
+            }
+        }, || false, String::new);
+        assert_eq!(result.unwrap(), 2);
+        assert_eq!(tries.get(), 2);
+    }
+
+    #[test]
+    fn with_retries_gives_up_after_the_last_retry() {
+        let tries = Cell::new(0);
+        let result: Result<(), _> = with_retries(2, || {
+            tries.set(tries.get() + 1);
+            Err(failure())
+        }, || false, String::new);
+        assert_eq!(result.unwrap_err().to_string(), "device busy");
+        assert_eq!(tries.get(), 3);
+    }
+
+    #[test]
+    fn with_retries_stops_retrying_once_stopped() {
+        let tries = Cell::new(0);
+        let result: Result<(), _> = with_retries(5, || {
+            tries.set(tries.get() + 1);
+            Err(failure())
+        }, || true, String::new);
+        assert!(result.is_err());
+        assert_eq!(tries.get(), 1);
+    }
+
+    #[test]
+    fn with_retries_copies_again_from_the_start() {
+        let dir = std::env::temp_dir().join(format!("m3trans-retries-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let (from, to) = (dir.join("track.mp3"), dir.join("copy.mp3"));
+        fs::write(&from, b"the whole track").unwrap();
+
+        // The first attempt gets part of the way, leaving what it copied behind.
+        let tries = Cell::new(0);
+        let result = with_retries(1, || {
+            tries.set(tries.get() + 1);
+            match tries.get() {
+                1 => {
+                    fs::write(temp_path(&to), b"the wh").unwrap();
+                    Err(failure())
+                }
+                _ => copy_atomically(&from, &to, false, None, None),
+            }
+        }, || false, String::new);
+        assert_eq!(result.unwrap(), (15, false));
+        assert_eq!(fs::read(&to).unwrap(), b"the whole track");
+        assert!(!temp_path(&to).exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
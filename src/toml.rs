@@ -0,0 +1,270 @@
+//! A minimal parser for the flat subset of TOML used by config files: `key = value` pairs of
+//! strings, integers, booleans and arrays of those, without tables.
+
+use std::{fmt, iter::Peekable, str::Chars};
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Value {
+    String(String),
+    Integer(i64),
+    Boolean(bool),
+    Array(Vec<Value>),
+}
+
+#[derive(Debug)]
+pub(crate) struct Error {
+    /// The line the error was found on, counting from 1.
+    pub(crate) line: usize,
+    pub(crate) message: String,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// Parses a document into its key-value pairs, in the order they appear.
+pub(crate) fn parse(text: &str) -> Result<Vec<(String, Value)>, Error> {
+    let mut parser = Parser { chars: text.chars().peekable(), line: 1 };
+    let mut pairs: Vec<(String, Value)> = Vec::new();
+    loop {
+        parser.skip_whitespace(true);
+        let c = match parser.chars.peek() {
+            Some(&c) => c,
+            None => return Ok(pairs),
+        };
+        if c == '[' {
+            return Err(parser.error("tables aren't supported; put every key at the top level"));
+        }
+
+        let line = parser.line;
+        let key = parser.key()?;
+        parser.skip_whitespace(false);
+        parser.expect('=')?;
+        parser.skip_whitespace(false);
+        let value = parser.value()?;
+        parser.skip_whitespace(false);
+        match parser.chars.next() {
+            None | Some('\n') => parser.line += 1,
+            Some(c) => return Err(parser.error(format!("expected the end of the line, found {:?}", c))),
+        }
+
+        if pairs.iter().any(|(other, _)| *other == key) {
+            return Err(Error { line, message: format!("key {:?} is given more than once", key) });
+        }
+        pairs.push((key, value));
+    }
+}
+
+struct Parser<'a> {
+    chars: Peekable<Chars<'a>>,
+    line: usize,
+}
+
+impl Parser<'_> {
+    fn error(&self, message: impl Into<String>) -> Error {
+        Error { line: self.line, message: message.into() }
+    }
+
+    fn expect(&mut self, expected: char) -> Result<(), Error> {
+        match self.chars.next() {
+            Some(c) if c == expected => Ok(()),
+            Some(c) => Err(self.error(format!("expected {:?}, found {:?}", expected, c))),
+            None => Err(self.error(format!("expected {:?}, found the end of the file", expected))),
+        }
+    }
+
+    /// Skips spaces and comments, and line breaks too if `newlines` is set. A comment runs up to,
+    /// but not including, the end of its line.
+    fn skip_whitespace(&mut self, newlines: bool) {
+        while let Some(&c) = self.chars.peek() {
+            match c {
+                ' ' | '\t' | '\r' => {},
+                '\n' if newlines => self.line += 1,
+                '#' => {
+                    while self.chars.peek().is_some_and(|&c| c != '\n') {
+                        self.chars.next();
+                    }
+                    continue;
+                }
+                _ => return,
+            }
+            self.chars.next();
+        }
+    }
+
+    fn key(&mut self) -> Result<String, Error> {
+        match self.chars.peek() {
+            Some('"') => { self.chars.next(); self.basic_string() },
+            Some('\'') => { self.chars.next(); self.literal_string() },
+            _ => {
+                let mut key = String::new();
+                while let Some(&c) = self.chars.peek().filter(|c| c.is_ascii_alphanumeric() || **c == '-' || **c == '_') {
+                    key.push(c);
+                    self.chars.next();
+                }
+                match key.is_empty() {
+                    true => Err(self.error("expected a key")),
+                    false => Ok(key),
+                }
+            }
+        }
+    }
+
+    fn value(&mut self) -> Result<Value, Error> {
+        match self.chars.peek() {
+            Some('"') => { self.chars.next(); self.basic_string().map(Value::String) },
+            Some('\'') => { self.chars.next(); self.literal_string().map(Value::String) },
+            Some('[') => { self.chars.next(); self.array() },
+            Some(_) => {
+                let mut word = String::new();
+                while let Some(&c) = self.chars.peek().filter(|c| c.is_ascii_alphanumeric() || matches!(c, '+' | '-' | '_' | '.')) {
+                    word.push(c);
+                    self.chars.next();
+                }
+                match word.as_str() {
+                    "true" => Ok(Value::Boolean(true)),
+                    "false" => Ok(Value::Boolean(false)),
+                    "" => Err(self.error("expected a value")),
+                    _ => word.replace('_', "").parse().map(Value::Integer)
+                        .map_err(|_| self.error(format!("{:?} isn't a string, integer, boolean or array", word))),
+                }
+            }
+            None => Err(self.error("expected a value, found the end of the file")),
+        }
+    }
+
+    /// Parses the rest of an array after its opening bracket. Arrays may span several lines.
+    fn array(&mut self) -> Result<Value, Error> {
+        let mut items = Vec::new();
+        loop {
+            self.skip_whitespace(true);
+            if self.chars.peek() == Some(&']') {
+                self.chars.next();
+                return Ok(Value::Array(items));
+            }
+            items.push(self.value()?);
+            self.skip_whitespace(true);
+            match self.chars.next() {
+                Some(',') => {},
+                Some(']') => return Ok(Value::Array(items)),
+                Some(c) => return Err(self.error(format!("expected ',' or ']' in array, found {:?}", c))),
+                None => return Err(self.error("unclosed array")),
+            }
+        }
+    }
+
+    /// Parses the rest of a double-quoted string after its opening quote.
+    fn basic_string(&mut self) -> Result<String, Error> {
+        let mut string = String::new();
+        loop {
+            match self.chars.next() {
+                Some('"') => return Ok(string),
+                Some('\\') => {
+                    let escaped = match self.chars.next() {
+                        Some('"') => '"',
+                        Some('\\') => '\\',
+                        Some('n') => '\n',
+                        Some('t') => '\t',
+                        Some('r') => '\r',
+                        Some(c @ ('u' | 'U')) => {
+                            let digits: String = self.chars.by_ref().take(if c == 'u' { 4 } else { 8 }).collect();
+                            u32::from_str_radix(&digits, 16).ok()
+                                .and_then(char::from_u32)
+                                .ok_or_else(|| self.error(format!("invalid unicode escape \\{}{}", c, digits)))?
+                        }
+                        Some(c) => return Err(self.error(format!("invalid escape \\{}", c))),
+                        None => return Err(self.error("unclosed string")),
+                    };
+                    string.push(escaped);
+                }
+                Some('\n') | None => return Err(self.error("unclosed string")),
+                Some(c) => string.push(c),
+            }
+        }
+    }
+
+    /// Parses the rest of a single-quoted string, which has no escapes, after its opening quote.
+    fn literal_string(&mut self) -> Result<String, Error> {
+        let mut string = String::new();
+        loop {
+            match self.chars.next() {
+                Some('\'') => return Ok(string),
+                Some('\n') | None => return Err(self.error("unclosed string")),
+                Some(c) => string.push(c),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn error(text: &str) -> (usize, String) {
+        let error = parse(text).unwrap_err();
+        (error.line, error.message)
+    }
+
+    #[test]
+    fn parses_pairs_in_order() {
+        let text = "\
+# A comment line
+output = \"/media/Player\"   # and a trailing one
+'literal key' = 'C:\\Music'
+max-depth = 1_000
+offset = -3
+strict = true
+dry_run = false
+\"quoted\\tkey\" = \"\\u00e9\\U0001F3B5\\n\"
+";
+        let pairs = parse(text).unwrap();
+        assert_eq!(pairs, [
+            ("output".to_owned(), Value::String("/media/Player".to_owned())),
+            ("literal key".to_owned(), Value::String("C:\\Music".to_owned())),
+            ("max-depth".to_owned(), Value::Integer(1000)),
+            ("offset".to_owned(), Value::Integer(-3)),
+            ("strict".to_owned(), Value::Boolean(true)),
+            ("dry_run".to_owned(), Value::Boolean(false)),
+            ("quoted\tkey".to_owned(), Value::String("\u{e9}\u{1F3B5}\n".to_owned())),
+        ]);
+        assert_eq!(parse("").unwrap(), []);
+        assert_eq!(parse("a = 1").unwrap(), [("a".to_owned(), Value::Integer(1))]);
+    }
+
+    #[test]
+    fn parses_arrays_across_lines() {
+        let pairs = parse("formats = [\n  \"m3u8\",  # the first\n  'xspf',\n]\nempty = []\nnested = [[1, 2], [true]]\n").unwrap();
+        let string = |s: &str| Value::String(s.to_owned());
+        assert_eq!(pairs[0].1, Value::Array(vec![string("m3u8"), string("xspf")]));
+        assert_eq!(pairs[1].1, Value::Array(Vec::new()));
+        assert_eq!(
+            pairs[2].1,
+            Value::Array(vec![Value::Array(vec![Value::Integer(1), Value::Integer(2)]), Value::Array(vec![Value::Boolean(true)])])
+        );
+    }
+
+    #[test]
+    fn errors_name_their_line() {
+        assert_eq!(error("a = 1\n\nb = yes\n"), (3, "\"yes\" isn't a string, integer, boolean or array".to_owned()));
+        assert_eq!(error("a = \"open\nb = 1"), (1, "unclosed string".to_owned()));
+        assert_eq!(error("a = 1 2"), (1, "expected the end of the line, found '2'".to_owned()));
+        assert_eq!(error("a 1"), (1, "expected '=', found '1'".to_owned()));
+        assert_eq!(error("a ="), (1, "expected a value, found the end of the file".to_owned()));
+        assert_eq!(error("= 1"), (1, "expected a key".to_owned()));
+        assert_eq!(error("a = \"\\q\""), (1, "invalid escape \\q".to_owned()));
+        assert_eq!(error("a = \"\\uD800\""), (1, "invalid unicode escape \\uD800".to_owned()));
+        assert_eq!(error("a = [1\n2]"), (2, "expected ',' or ']' in array, found '2'".to_owned()));
+        assert_eq!(error("a = [1,"), (1, "expected a value, found the end of the file".to_owned()));
+        assert_eq!(parse("x = y").unwrap_err().to_string(), "line 1: \"y\" isn't a string, integer, boolean or array");
+    }
+
+    #[test]
+    fn rejects_duplicate_keys_and_tables() {
+        assert_eq!(error("a = 1\nb = 2\na = 3\n"), (3, "key \"a\" is given more than once".to_owned()));
+        assert_eq!(error("a = 1\n[sync]\nb = 2\n"), (2, "tables aren't supported; put every key at the top level".to_owned()));
+    }
+}
@@ -0,0 +1,83 @@
+//! Keeps warnings that repeat by the thousand from burying the rest. Past the first few of a kind,
+//! warnings are logged at info level instead, so that they only reach the log file, and how many
+//! there were of each kind is counted to sum up at the end.
+//!
+//! A warning's kind is its `event` key, followed by its `reason` if it has one, as in
+//! `track_skipped/unknown_location`. Warnings without an event are always logged as they are.
+
+use std::{collections::BTreeMap, sync::{Arc, Mutex}};
+
+use log::{kv, Level, Log, Metadata, Record};
+
+/// How many warnings of each kind are logged as warnings by default.
+pub(crate) const SHOWN: usize = 5;
+
+/// Wraps a logger, passing it warnings past the first `shown` of their kind at info level.
+pub(crate) struct WarningLimiter {
+    inner: Box<dyn Log>,
+    shown: usize,
+    counts: WarningCounts,
+}
+
+/// How many warnings of each kind were logged, shared with the `WarningLimiter` that counts them.
+#[derive(Clone, Default)]
+pub(crate) struct WarningCounts(Arc<Mutex<BTreeMap<String, usize>>>);
+
+impl WarningLimiter {
+    pub(crate) fn new(inner: Box<dyn Log>, shown: usize) -> (Self, WarningCounts) {
+        let counts = WarningCounts::default();
+        (WarningLimiter { inner, shown, counts: counts.clone() }, counts)
+    }
+}
+
+impl WarningCounts {
+    /// The kinds of which more than `shown` warnings were logged, with how many in all.
+    pub(crate) fn past(&self, shown: usize) -> Vec<(String, usize)> {
+        let counts = self.0.lock().unwrap_or_else(|e| e.into_inner());
+        counts.iter().filter(|&(_, &count)| count > shown).map(|(kind, &count)| (kind.clone(), count)).collect()
+    }
+}
+
+impl Log for WarningLimiter {
+    fn enabled(&self, metadata: &Metadata<'_>) -> bool {
+        self.inner.enabled(metadata)
+    }
+
+    fn log(&self, record: &Record<'_>) {
+        let kind = match record.level() {
+            Level::Warn => kind(record),
+            _ => None,
+        };
+        let count = kind.map(|kind| {
+            let mut counts = self.counts.0.lock().unwrap_or_else(|e| e.into_inner());
+            let count = counts.entry(kind).or_insert(0);
+            *count += 1;
+            *count
+        });
+        match count {
+            Some(count) if count > self.shown => self.inner.log(&Record::builder()
+                .args(*record.args())
+                .level(Level::Info)
+                .target(record.target())
+                .module_path(record.module_path())
+                .file(record.file())
+                .line(record.line())
+                .key_values(record.key_values())
+                .build()),
+            _ => self.inner.log(record),
+        }
+    }
+
+    fn flush(&self) {
+        self.inner.flush()
+    }
+}
+
+fn kind(record: &Record<'_>) -> Option<String> {
+    let key_values = record.key_values();
+    let event = key_values.get(kv::Key::from_str("event"))?;
+    Some(match key_values.get(kv::Key::from_str("reason")) {
+        Some(reason) => format!("{}/{}", event, reason),
+        None => event.to_string(),
+    })
+}
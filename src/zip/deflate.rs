@@ -0,0 +1,377 @@
+//! A simple DEFLATE compressor (RFC 1951), using the fixed Huffman codes and greedy LZ77 matching.
+
+use std::io::{self, Write};
+
+/// How much input goes in each block. Matches don't reach back past the start of their block.
+const BLOCK_SIZE: usize = 64 * 1024;
+const WINDOW_SIZE: usize = 32 * 1024;
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 258;
+/// How many earlier positions with the same hash to try matching, trading speed for size.
+const MAX_CHAIN: usize = 16;
+const HASH_BITS: u32 = 15;
+const NONE: usize = usize::MAX;
+
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131, 163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0];
+const DISTANCE_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537, 2049, 3073, 4097,
+    6145, 8193, 12289, 16385, 24577,
+];
+const DISTANCE_EXTRA: [u8; 30] = [0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13];
+
+fn hash(bytes: &[u8]) -> usize {
+    let key = u32::from(bytes[0]) << 16 | u32::from(bytes[1]) << 8 | u32::from(bytes[2]);
+    (key.wrapping_mul(2_654_435_761) >> (32 - HASH_BITS)) as usize
+}
+
+/// Compresses what is written to it into `writer`, which gets the last of it on `finish`.
+pub(super) struct Deflater<W: Write> {
+    writer: W,
+    input: Vec<u8>,
+    bits: u64,
+    bit_count: u32,
+    output: Vec<u8>,
+}
+
+impl<W: Write> Deflater<W> {
+    pub(super) fn new(writer: W) -> Self {
+        Deflater { writer, input: Vec::with_capacity(BLOCK_SIZE), bits: 0, bit_count: 0, output: Vec::new() }
+    }
+
+    pub(super) fn write(&mut self, mut data: &[u8]) -> Result<(), io::Error> {
+        while !data.is_empty() {
+            let taken = data.len().min(BLOCK_SIZE - self.input.len());
+            self.input.extend_from_slice(&data[..taken]);
+            data = &data[taken..];
+            if self.input.len() == BLOCK_SIZE {
+                self.compress_block(false)?;
+            }
+        }
+        Ok(())
+    }
+
+    pub(super) fn finish(mut self) -> Result<W, io::Error> {
+        self.compress_block(true)?;
+        if self.bit_count > 0 {
+            self.output.push(self.bits as u8);
+        }
+        self.writer.write_all(&self.output)?;
+        Ok(self.writer)
+    }
+
+    fn compress_block(&mut self, last: bool) -> Result<(), io::Error> {
+        let data = std::mem::take(&mut self.input);
+        self.write_bits(u32::from(last), 1);
+        // Compressed with the fixed Huffman codes.
+        self.write_bits(1, 2);
+
+        // The latest position of each hash of the next three bytes, and for every position the one
+        // before it with the same hash.
+        let mut head = vec![NONE; 1 << HASH_BITS];
+        let mut prev = vec![NONE; data.len()];
+        let insert = |i: usize, head: &mut [usize], prev: &mut [usize]| {
+            if i + MIN_MATCH <= data.len() {
+                let h = hash(&data[i..]);
+                prev[i] = head[h];
+                head[h] = i;
+            }
+        };
+
+        let mut i = 0;
+        while i < data.len() {
+            let (mut best_len, mut best_distance) = (0, 0);
+            if i + MIN_MATCH <= data.len() {
+                let max_len = MAX_MATCH.min(data.len() - i);
+                let mut candidate = head[hash(&data[i..])];
+                let mut chain = 0;
+                while candidate != NONE && i - candidate <= WINDOW_SIZE && chain < MAX_CHAIN {
+                    let len = data[candidate..].iter().zip(&data[i..i + max_len]).take_while(|(a, b)| a == b).count();
+                    if len > best_len {
+                        best_len = len;
+                        best_distance = i - candidate;
+                        if len == max_len {
+                            break;
+                        }
+                    }
+                    candidate = prev[candidate];
+                    chain += 1;
+                }
+            }
+
+            if best_len >= MIN_MATCH {
+                self.write_match(best_len, best_distance);
+                for j in i..i + best_len {
+                    insert(j, &mut head, &mut prev);
+                }
+                i += best_len;
+            }
+            else {
+                self.write_literal(u16::from(data[i]));
+                insert(i, &mut head, &mut prev);
+                i += 1;
+            }
+        }
+        // The end of the block.
+        self.write_literal(256);
+
+        self.writer.write_all(&self.output)?;
+        self.output.clear();
+        self.input = data;
+        self.input.clear();
+        Ok(())
+    }
+
+    fn write_match(&mut self, len: usize, distance: usize) {
+        let code = LENGTH_BASE.iter().rposition(|&base| usize::from(base) <= len).unwrap();
+        self.write_literal(257 + code as u16);
+        self.write_bits((len - usize::from(LENGTH_BASE[code])) as u32, LENGTH_EXTRA[code].into());
+
+        let code = DISTANCE_BASE.iter().rposition(|&base| usize::from(base) <= distance).unwrap();
+        self.write_huffman(code as u32, 5);
+        self.write_bits((distance - usize::from(DISTANCE_BASE[code])) as u32, DISTANCE_EXTRA[code].into());
+    }
+
+    /// Writes a literal byte, the end of a block or a length code, in the fixed literal/length code.
+    fn write_literal(&mut self, symbol: u16) {
+        let symbol = u32::from(symbol);
+        match symbol {
+            0..=143 => self.write_huffman(0x30 + symbol, 8),
+            144..=255 => self.write_huffman(0x190 + symbol - 144, 9),
+            256..=279 => self.write_huffman(symbol - 256, 7),
+            _ => self.write_huffman(0xC0 + symbol - 280, 8),
+        }
+    }
+
+    /// Huffman codes are packed starting from their most significant bit, unlike everything else.
+    fn write_huffman(&mut self, code: u32, len: u32) {
+        self.write_bits(code.reverse_bits() >> (32 - len), len);
+    }
+
+    fn write_bits(&mut self, value: u32, count: u32) {
+        self.bits |= u64::from(value) << self.bit_count;
+        self.bit_count += count;
+        while self.bit_count >= 8 {
+            self.output.push(self.bits as u8);
+            self.bits >>= 8;
+            self.bit_count -= 8;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Reads bits least significant first, as DEFLATE packs them.
+    struct Bits<'a> {
+        data: &'a [u8],
+        position: usize,
+    }
+
+    impl Bits<'_> {
+        fn bit(&mut self) -> u32 {
+            let byte = self.data[self.position / 8];
+            let bit = u32::from(byte >> (self.position % 8)) & 1;
+            self.position += 1;
+            bit
+        }
+
+        fn bits(&mut self, count: u32) -> u32 {
+            (0..count).fold(0, |value, i| value | self.bit() << i)
+        }
+    }
+
+    /// A canonical Huffman code, as the count of codes of each length and the symbols in order
+    /// of their codes.
+    struct Huffman {
+        counts: [u16; 16],
+        symbols: Vec<u16>,
+    }
+
+    impl Huffman {
+        fn new(lengths: &[u8]) -> Self {
+            let mut counts = [0; 16];
+            lengths.iter().for_each(|&len| counts[usize::from(len)] += 1);
+            counts[0] = 0;
+            let mut symbols = Vec::new();
+            for len in 1..16 {
+                symbols.extend((0..lengths.len() as u16).filter(|&symbol| lengths[usize::from(symbol)] == len));
+            }
+            Huffman { counts, symbols }
+        }
+
+        fn decode(&self, bits: &mut Bits<'_>) -> u16 {
+            let (mut code, mut first, mut index) = (0, 0, 0);
+            for len in 1..16 {
+                code |= bits.bit() as i32;
+                let count = i32::from(self.counts[len]);
+                if code - first < count {
+                    return self.symbols[(index + code - first) as usize];
+                }
+                index += count;
+                first = (first + count) << 1;
+                code <<= 1;
+            }
+            panic!("invalid Huffman code");
+        }
+    }
+
+    /// An inflater written from RFC 1951 alone, to check the compressor against.
+    fn inflate(data: &[u8]) -> Vec<u8> {
+        let mut bits = Bits { data, position: 0 };
+        let mut output = Vec::new();
+        loop {
+            let last = bits.bit() == 1;
+            match bits.bits(2) {
+                0 => {
+                    bits.position = bits.position.div_ceil(8) * 8;
+                    let len = bits.bits(16) as usize;
+                    assert_eq!(bits.bits(16) as usize, !len & 0xFFFF);
+                    let start = bits.position / 8;
+                    output.extend_from_slice(&data[start..start + len]);
+                    bits.position += len * 8;
+                }
+                kind @ (1 | 2) => {
+                    let (lengths, distances) = match kind {
+                        1 => {
+                            let mut lengths = [8; 288];
+                            lengths[144..256].fill(9);
+                            lengths[256..280].fill(7);
+                            (Huffman::new(&lengths), Huffman::new(&[5; 30]))
+                        }
+                        _ => dynamic_codes(&mut bits),
+                    };
+                    loop {
+                        let symbol = usize::from(lengths.decode(&mut bits));
+                        match symbol {
+                            0..=255 => output.push(symbol as u8),
+                            256 => break,
+                            _ => {
+                                let code = symbol - 257;
+                                let len = usize::from(LENGTH_BASE[code]) + bits.bits(LENGTH_EXTRA[code].into()) as usize;
+                                let code = usize::from(distances.decode(&mut bits));
+                                let distance = usize::from(DISTANCE_BASE[code]) + bits.bits(DISTANCE_EXTRA[code].into()) as usize;
+                                assert!(distance <= output.len(), "distance {} reaches back before the start", distance);
+                                for _ in 0..len {
+                                    output.push(output[output.len() - distance]);
+                                }
+                            }
+                        }
+                    }
+                }
+                _ => panic!("invalid block type"),
+            }
+            if last {
+                return output;
+            }
+        }
+    }
+
+    fn dynamic_codes(bits: &mut Bits<'_>) -> (Huffman, Huffman) {
+        const ORDER: [usize; 19] = [16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15];
+        let literals = bits.bits(5) as usize + 257;
+        let distances = bits.bits(5) as usize + 1;
+        let code_lengths = bits.bits(4) as usize + 4;
+        let mut lengths = [0; 19];
+        for &i in &ORDER[..code_lengths] {
+            lengths[i] = bits.bits(3) as u8;
+        }
+        let code = Huffman::new(&lengths);
+        let mut lengths = Vec::with_capacity(literals + distances);
+        while lengths.len() < literals + distances {
+            match code.decode(bits) {
+                len @ 0..=15 => lengths.push(len as u8),
+                16 => {
+                    let previous = *lengths.last().unwrap();
+                    lengths.extend(std::iter::repeat_n(previous, 3 + bits.bits(2) as usize));
+                }
+                17 => lengths.extend(std::iter::repeat_n(0, 3 + bits.bits(3) as usize)),
+                _ => lengths.extend(std::iter::repeat_n(0, 11 + bits.bits(7) as usize)),
+            }
+        }
+        (Huffman::new(&lengths[..literals]), Huffman::new(&lengths[literals..]))
+    }
+
+    fn deflate(data: &[u8], piece: usize) -> Vec<u8> {
+        let mut deflater = Deflater::new(Vec::new());
+        data.chunks(piece.max(1)).try_for_each(|chunk| deflater.write(chunk)).unwrap();
+        deflater.finish().unwrap()
+    }
+
+    #[test]
+    fn inflater_reads_stored_blocks() {
+        // A final stored block of "hi", as zlib writes it at level 0.
+        assert_eq!(inflate(&[0x01, 0x02, 0x00, 0xFD, 0xFF, b'h', b'i']), b"hi");
+    }
+
+    #[test]
+    fn inflater_reads_blocks_with_fixed_codes() {
+        // Python's `zlib.compressobj(9, zlib.DEFLATED, -15, 9, zlib.Z_FIXED)`, which writes raw
+        // DEFLATE with no zlib header, on the sentence twice over, the second time as a match.
+        let compressed = [
+            0x0B, 0xC9, 0x48, 0x55, 0x28, 0x2C, 0xCD, 0x4C, 0xCE, 0x56, 0x48, 0x2A, 0xCA, 0x2F, 0xCF, 0x53,
+            0x48, 0xCB, 0xAF, 0x50, 0xC8, 0x2A, 0xCD, 0x2D, 0x28, 0x56, 0xC8, 0x2F, 0x4B, 0x2D, 0x52, 0x28,
+            0x01, 0x4A, 0xE7, 0x24, 0x56, 0x55, 0x2A, 0xA4, 0xE4, 0xA7, 0xEB, 0x29, 0x84, 0x90, 0xA0, 0x18,
+            0x00,
+        ];
+        let text = b"The quick brown fox jumps over the lazy dog. The quick brown fox jumps over the lazy dog.";
+        assert_eq!(inflate(&compressed), text);
+    }
+
+    #[test]
+    fn inflater_reads_blocks_with_dynamic_codes() {
+        // Python's `zlib.compressobj(9, zlib.DEFLATED, -15)` on twelve entries of a playlist, which
+        // zlib gives codes of their own.
+        let compressed = [
+            0x5D, 0xD0, 0xBD, 0x0A, 0xC2, 0x40, 0x10, 0x45, 0xE1, 0x3E, 0x4F, 0x11, 0x48, 0x2B, 0x9B, 0x9D,
+            0xFD, 0x1F, 0x7B, 0x05, 0x1B, 0xAB, 0x14, 0xB6, 0x21, 0xA5, 0x08, 0xA2, 0xBE, 0x3F, 0x22, 0x4E,
+            0xC2, 0xCD, 0xED, 0x4E, 0xF1, 0x55, 0x67, 0x38, 0xDD, 0xA6, 0xCB, 0xF5, 0x7C, 0x94, 0xE6, 0x0F,
+            0xD3, 0x6B, 0x5E, 0xEE, 0xBD, 0xEF, 0x9C, 0x1B, 0x3F, 0xBF, 0x7C, 0x8F, 0xDE, 0x3D, 0x9E, 0xB1,
+            0x1B, 0x36, 0x23, 0x66, 0x04, 0x8C, 0x90, 0x09, 0x66, 0x02, 0x98, 0x40, 0x26, 0x9A, 0x89, 0x60,
+            0x22, 0x99, 0x64, 0x26, 0x81, 0x49, 0x64, 0xB2, 0x99, 0x0C, 0x26, 0x93, 0x29, 0x66, 0x0A, 0x98,
+            0x42, 0xA6, 0x9A, 0xA9, 0x60, 0x2A, 0x99, 0x66, 0xA6, 0x81, 0x69, 0x64, 0xD4, 0x8C, 0x82, 0xD1,
+            0xBD, 0xD1, 0xF5, 0xB3, 0xE0, 0x68, 0xA1, 0xD3, 0xBA, 0x9D, 0xDE, 0xAD, 0xFE, 0xBF, 0xFE, 0x02,
+        ];
+        let playlist: String = (0..12).map(|i| format!("#EXTINF:{},Track {}\n../tracks/{}.mp3\n", 180 + i, i, i)).collect();
+        assert_eq!(inflate(&compressed), playlist.as_bytes());
+    }
+
+    #[test]
+    fn round_trips_empty_and_short_input() {
+        assert_eq!(inflate(&deflate(b"", 1)), b"");
+        assert_eq!(inflate(&deflate(b"a", 1)), b"a");
+        let text = b"The quick brown fox jumps over the lazy dog.";
+        assert_eq!(inflate(&deflate(text, 7)), text);
+    }
+
+    #[test]
+    fn round_trips_repetitive_input_with_long_matches() {
+        let data: Vec<u8> = b"abcabcabc".iter().cycle().take(10_000).copied().chain([b'x'; 1000]).collect();
+        let compressed = deflate(&data, data.len());
+        assert!(compressed.len() < data.len() / 20, "compressed to {} bytes", compressed.len());
+        assert_eq!(inflate(&compressed), data);
+    }
+
+    #[test]
+    fn round_trips_data_spanning_several_blocks() {
+        // Pseudo-random bytes from a linear congruential generator, with runs repeated from
+        // further back than a block.
+        let mut state = 12345u32;
+        let mut data: Vec<u8> = (0..150_000)
+            .map(|_| {
+                state = state.wrapping_mul(1_103_515_245).wrapping_add(12345);
+                (state >> 16) as u8 % 16 + b'a'
+            })
+            .collect();
+        let repeated = data[1000..5000].to_vec();
+        data.extend_from_slice(&repeated);
+        data.extend_from_slice(&repeated);
+        assert!(data.len() > 2 * BLOCK_SIZE);
+        for piece in [1000, BLOCK_SIZE, data.len()] {
+            assert_eq!(inflate(&deflate(&data, piece)), data, "written in pieces of {} bytes", piece);
+        }
+    }
+}
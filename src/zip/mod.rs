@@ -0,0 +1,279 @@
+//! A minimal writer of zip archives, storing or deflating each file.
+//!
+//! ZIP64 isn't supported, so archives are limited to 4 GiB and 65535 entries.
+
+use std::{collections::HashSet, convert::TryFrom, fs::File, io::{self, BufWriter, Read, Seek, SeekFrom, Write}, path::Path, str::FromStr, time::{SystemTime, UNIX_EPOCH}};
+
+use crate::date::civil_date;
+
+mod deflate;
+
+const LOCAL_HEADER: u32 = 0x0403_4b50;
+const CENTRAL_HEADER: u32 = 0x0201_4b50;
+const END_OF_CENTRAL_DIRECTORY: u32 = 0x0605_4b50;
+/// Names are UTF-8.
+const FLAGS: u16 = 1 << 11;
+/// Version 2.0, which has directories and deflate, made on Unix so that permissions are kept.
+const VERSION: u16 = 20;
+const MADE_BY: u16 = 3 << 8 | VERSION;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Compression {
+    Store,
+    Deflate,
+}
+
+impl Compression {
+    fn method(self) -> u16 {
+        match self {
+            Compression::Store => 0,
+            Compression::Deflate => 8,
+        }
+    }
+}
+
+impl FromStr for Compression {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "store" => Ok(Compression::Store),
+            "deflate" => Ok(Compression::Deflate),
+            other => Err(format!("unknown compression \"{}\"", other)),
+        }
+    }
+}
+
+struct CentralEntry {
+    name: String,
+    method: u16,
+    crc: u32,
+    compressed_size: u32,
+    size: u32,
+    offset: u32,
+    is_dir: bool,
+}
+
+pub(crate) struct ZipWriter {
+    file: BufWriter<File>,
+    entries: Vec<CentralEntry>,
+    names: HashSet<String>,
+    /// The modification time given to every entry, as MS-DOS time and date.
+    dos_time: (u16, u16),
+}
+
+impl ZipWriter {
+    /// Creates the archive at `path`, replacing any file there.
+    pub(crate) fn create(path: &Path) -> Result<ZipWriter, io::Error> {
+        Ok(ZipWriter {
+            file: BufWriter::new(File::create(path)?),
+            entries: Vec::new(),
+            names: HashSet::new(),
+            dos_time: dos_time(SystemTime::now()),
+        })
+    }
+
+    /// Adds a directory named `name`, with `/` between its components, unless it was added already.
+    pub(crate) fn add_dir(&mut self, name: &str) -> Result<(), io::Error> {
+        let name = format!("{}/", name.trim_end_matches('/'));
+        if self.names.contains(&name) {
+            return Ok(());
+        }
+        let offset = self.offset()?;
+        self.write_local_header(&name, Compression::Store.method())?;
+        self.push_entry(CentralEntry { name, method: 0, crc: 0, compressed_size: 0, size: 0, offset, is_dir: true });
+        Ok(())
+    }
+
+    /// Adds a file named `name` with the rest of what `data` holds. Deflated files that would come
+    /// out no smaller are stored instead, as most audio files would. Returns the size of the file.
+    pub(crate) fn add_file(&mut self, name: &str, mut data: impl Read + Seek, compression: Compression) -> Result<u64, io::Error> {
+        if self.names.contains(name) {
+            return Err(io::Error::new(io::ErrorKind::AlreadyExists, format!("{:?} is already in the archive", name)));
+        }
+        let offset = self.offset()?;
+        let start = data.stream_position()?;
+        self.write_local_header(name, compression.method())?;
+        let data_start = self.file.stream_position()?;
+
+        let (crc, size) = match compression {
+            Compression::Store => read_all(&mut data, |chunk| self.file.write_all(chunk))?,
+            Compression::Deflate => {
+                let mut deflater = deflate::Deflater::new(&mut self.file);
+                let read = read_all(&mut data, |chunk| deflater.write(chunk))?;
+                deflater.finish()?;
+                read
+            }
+        };
+        let compressed_size = self.file.stream_position()? - data_start;
+
+        if compression == Compression::Deflate && compressed_size >= size {
+            // Whatever is left past the stored file is written over by the entries after it, and
+            // cut off by `finish` if it's last.
+            self.file.seek(SeekFrom::Start(u64::from(offset)))?;
+            data.seek(SeekFrom::Start(start))?;
+            return self.add_file(name, data, Compression::Store);
+        }
+
+        let too_large = || io::Error::other(format!("{:?} is too large for a zip archive without ZIP64", name));
+        let size = u32::try_from(size).map_err(|_| too_large())?;
+        let compressed_size = u32::try_from(compressed_size).map_err(|_| too_large())?;
+        // Filled in now that they're known.
+        self.file.seek(SeekFrom::Start(u64::from(offset) + 14))?;
+        self.file.write_all(&crc.to_le_bytes())?;
+        self.file.write_all(&compressed_size.to_le_bytes())?;
+        self.file.write_all(&size.to_le_bytes())?;
+        self.file.seek(SeekFrom::Start(data_start + u64::from(compressed_size)))?;
+
+        let method = compression.method();
+        self.push_entry(CentralEntry { name: name.to_owned(), method, crc, compressed_size, size, offset, is_dir: false });
+        Ok(size.into())
+    }
+
+    /// Writes the central directory, which lists the entries, and returns the finished file.
+    pub(crate) fn finish(mut self) -> Result<File, io::Error> {
+        let too_many = || io::Error::other("too many files for a zip archive without ZIP64");
+        let start = self.offset()?;
+        for entry in &self.entries {
+            let external_attributes: u32 = match entry.is_dir {
+                true => 0o040_755 << 16 | 0x10,
+                false => 0o100_644 << 16,
+            };
+            let mut header = Vec::with_capacity(46 + entry.name.len());
+            header.extend_from_slice(&CENTRAL_HEADER.to_le_bytes());
+            header.extend_from_slice(&MADE_BY.to_le_bytes());
+            header.extend_from_slice(&VERSION.to_le_bytes());
+            header.extend_from_slice(&FLAGS.to_le_bytes());
+            header.extend_from_slice(&entry.method.to_le_bytes());
+            header.extend_from_slice(&self.dos_time.0.to_le_bytes());
+            header.extend_from_slice(&self.dos_time.1.to_le_bytes());
+            header.extend_from_slice(&entry.crc.to_le_bytes());
+            header.extend_from_slice(&entry.compressed_size.to_le_bytes());
+            header.extend_from_slice(&entry.size.to_le_bytes());
+            header.extend_from_slice(&(entry.name.len() as u16).to_le_bytes());
+            // The lengths of the extra field and comment, the disk number and internal attributes.
+            header.extend_from_slice(&[0; 8]);
+            header.extend_from_slice(&external_attributes.to_le_bytes());
+            header.extend_from_slice(&entry.offset.to_le_bytes());
+            header.extend_from_slice(entry.name.as_bytes());
+            self.file.write_all(&header)?;
+        }
+        let end = self.offset()?;
+        let count = u16::try_from(self.entries.len()).map_err(|_| too_many())?;
+
+        let mut record = Vec::with_capacity(22);
+        record.extend_from_slice(&END_OF_CENTRAL_DIRECTORY.to_le_bytes());
+        // This disk and the one the central directory starts on.
+        record.extend_from_slice(&[0; 4]);
+        record.extend_from_slice(&count.to_le_bytes());
+        record.extend_from_slice(&count.to_le_bytes());
+        record.extend_from_slice(&(end - start).to_le_bytes());
+        record.extend_from_slice(&start.to_le_bytes());
+        // The length of the comment.
+        record.extend_from_slice(&[0; 2]);
+        self.file.write_all(&record)?;
+
+        let end = self.file.stream_position()?;
+        let file = self.file.into_inner().map_err(io::IntoInnerError::into_error)?;
+        file.set_len(end)?;
+        file.sync_all()?;
+        Ok(file)
+    }
+
+    /// Where the next entry starts, which must fit in 32 bits.
+    fn offset(&mut self) -> Result<u32, io::Error> {
+        u32::try_from(self.file.stream_position()?)
+            .map_err(|_| io::Error::other("the archive is larger than 4 GiB, which needs ZIP64"))
+    }
+
+    /// Writes the header before an entry's data, leaving its CRC and sizes to be filled in after.
+    fn write_local_header(&mut self, name: &str, method: u16) -> Result<(), io::Error> {
+        let name_len = u16::try_from(name.len())
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, format!("{:?} is too long a name for a zip archive", name)))?;
+        let mut header = Vec::with_capacity(30 + name.len());
+        header.extend_from_slice(&LOCAL_HEADER.to_le_bytes());
+        header.extend_from_slice(&VERSION.to_le_bytes());
+        header.extend_from_slice(&FLAGS.to_le_bytes());
+        header.extend_from_slice(&method.to_le_bytes());
+        header.extend_from_slice(&self.dos_time.0.to_le_bytes());
+        header.extend_from_slice(&self.dos_time.1.to_le_bytes());
+        header.extend_from_slice(&[0; 12]);
+        header.extend_from_slice(&name_len.to_le_bytes());
+        // The length of the extra field.
+        header.extend_from_slice(&[0; 2]);
+        header.extend_from_slice(name.as_bytes());
+        self.file.write_all(&header)
+    }
+
+    fn push_entry(&mut self, entry: CentralEntry) {
+        self.names.insert(entry.name.clone());
+        self.entries.push(entry);
+    }
+}
+
+/// Passes everything left in `data` to `write` a chunk at a time, returning its CRC and size.
+fn read_all(mut data: impl Read, mut write: impl FnMut(&[u8]) -> Result<(), io::Error>) -> Result<(u32, u64), io::Error> {
+    let mut crc = Crc32::default();
+    let mut size = 0;
+    let mut buffer = vec![0; 64 * 1024];
+    loop {
+        let read = match data.read(&mut buffer) {
+            Ok(0) => return Ok((crc.finish(), size)),
+            Ok(read) => read,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        crc.update(&buffer[..read]);
+        size += read as u64;
+        write(&buffer[..read])?;
+    }
+}
+
+/// MS-DOS time and date in UTC, which only go to even seconds and back to 1980.
+fn dos_time(time: SystemTime) -> (u16, u16) {
+    let secs = time.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+    let (year, month, day) = civil_date((secs / 86_400) as i64);
+    let secs_of_day = secs % 86_400;
+    let (hour, minute, two_secs) = (secs_of_day / 3600, secs_of_day / 60 % 60, secs_of_day % 60 / 2);
+    let time = hour << 11 | minute << 5 | two_secs;
+    let date = match year {
+        1980..=2107 => (year - 1980) << 9 | month << 5 | day,
+        _ => 1 << 5 | 1,
+    };
+    (time as u16, date as u16)
+}
+
+/// The CRC-32 that zip archives check their files with.
+struct Crc32 {
+    table: [u32; 256],
+    crc: u32,
+}
+
+impl Default for Crc32 {
+    fn default() -> Self {
+        let mut table = [0; 256];
+        for (n, entry) in table.iter_mut().enumerate() {
+            let mut c = n as u32;
+            for _ in 0..8 {
+                c = match c & 1 {
+                    1 => 0xEDB8_8320 ^ (c >> 1),
+                    _ => c >> 1,
+                };
+            }
+            *entry = c;
+        }
+        Crc32 { table, crc: !0 }
+    }
+}
+
+impl Crc32 {
+    fn update(&mut self, data: &[u8]) {
+        for &byte in data {
+            self.crc = self.table[((self.crc ^ u32::from(byte)) & 0xFF) as usize] ^ (self.crc >> 8);
+        }
+    }
+
+    fn finish(&self) -> u32 {
+        !self.crc
+    }
+}
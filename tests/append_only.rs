@@ -0,0 +1,122 @@
+//! Syncing with --append-only into an output that earlier syncs left files in, which it must
+//! neither overwrite, move nor delete.
+
+mod common;
+
+use std::{fs, path::Path, process::Command};
+
+use common::{command, files, location, printed, sync, temp_dir, write_library};
+use m3trans::LibraryBuilder;
+
+/// Writes the library of `write_library` again with the playlist Top holding `top` instead.
+fn write_top(dir: &Path, top: &[u64]) {
+    let media = dir.join("media");
+    let mut builder = LibraryBuilder::new();
+    for (id, name) in [(1, "One"), (2, "Two")] {
+        builder.track(id, name, &location(&media.join(format!("{}.mp3", name))), 1000);
+    }
+    builder.playlist("Top", top.iter().copied()).folder("Rock", |rock| {
+        rock.playlist("Both", [2, 1]).folder("Deep", |deep| {
+            deep.playlist("One", [1]);
+        });
+    });
+    builder.build().to_writer_xml(fs::File::create(dir.join("Library.xml")).unwrap()).unwrap();
+}
+
+/// Syncs with --append-only and --check-content, which keeps a state, logging to the default log
+/// file in the output.
+fn sync_appending(dir: &Path) {
+    let output = command(dir).args(["--append-only", "--check-content"]).output().unwrap();
+    assert!(output.status.success(), "m3trans failed:\n{}", printed(&output));
+}
+
+#[test]
+fn a_rerun_writes_nothing_new_and_keeps_what_was_there() {
+    let dir = temp_dir("append-only-rerun");
+    write_library(&dir);
+    let output = dir.join("output");
+    // Files of the user's, named like those a sync would clean up.
+    fs::create_dir_all(output.join("tracks")).unwrap();
+    fs::create_dir_all(output.join("playlists")).unwrap();
+    fs::write(output.join("tracks/notes.tmp"), "mine").unwrap();
+    fs::write(output.join("playlists/draft.m3u8.tmp"), "mine").unwrap();
+
+    sync_appending(&dir);
+    let first = files(&output);
+    for name in ["manifest.json", "m3trans.state", "m3trans.log", "tracks/notes.tmp", "playlists/draft.m3u8.tmp"] {
+        assert!(first.contains_key(Path::new(name)), "{} is missing: {:?}", name, first.keys());
+    }
+
+    sync_appending(&dir);
+    let second = files(&output);
+    assert_eq!(first.keys().collect::<Vec<_>>(), second.keys().collect::<Vec<_>>(), "the rerun wrote new files");
+    for (path, (contents, modified)) in first.iter().filter(|(path, _)| path.as_os_str() != "m3trans.log") {
+        assert!(second[path].0 == *contents && second[path].1 == *modified, "{:?} was rewritten", path);
+    }
+    let (first_log, second_log) = (&first[Path::new("m3trans.log")].0, &second[Path::new("m3trans.log")].0);
+    assert!(second_log.len() > first_log.len() && second_log.starts_with(first_log), "the log wasn't added to");
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn a_changed_playlist_is_versioned_from_its_own_name() {
+    let dir = temp_dir("append-only-versions");
+    write_library(&dir);
+    let playlists = dir.join("output/playlists");
+    sync(&dir, &["--append-only"]);
+    let top = fs::read(playlists.join("Top.m3u8")).unwrap();
+
+    write_top(&dir, &[2]);
+    sync(&dir, &["--append-only"]);
+    assert_eq!(fs::read(playlists.join("Top.m3u8")).unwrap(), top);
+    let changed = fs::read(playlists.join("Top (2).m3u8")).unwrap();
+    assert_ne!(changed, top);
+
+    // Nothing new for a playlist that is the same as one of its versions.
+    sync(&dir, &["--append-only"]);
+    write_top(&dir, &[1, 2]);
+    sync(&dir, &["--append-only"]);
+    assert!(!playlists.join("Top (3).m3u8").exists());
+
+    write_top(&dir, &[1]);
+    sync(&dir, &["--append-only"]);
+    assert!(playlists.join("Top (3).m3u8").exists());
+    assert!(!playlists.join("Top (2) (2).m3u8").exists());
+    assert_eq!(fs::read(playlists.join("Top (2).m3u8")).unwrap(), changed);
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn options_that_overwrite_are_rejected() {
+    let dir = temp_dir("append-only-rejected");
+    write_library(&dir);
+    let rejected: [(&[&str], &str); 5] = [
+        (&["--prune-playlists"], "--prune-playlists"),
+        (&["--mode", "folders"], "--mode folders"),
+        (&["--log-keep", "3"], "--log-keep"),
+        (&["--checksums"], "--checksums"),
+        (&["--html-index"], "--html-index"),
+    ];
+    for (args, option) in rejected {
+        let output = command(&dir).args(["--no-log-file", "--append-only"]).args(args).output().unwrap();
+        let printed = printed(&output);
+        assert_eq!(output.status.code(), Some(1), "{:?} was accepted:\n{}", args, printed);
+        let message = format!("so it can't be used with {}, which", option);
+        assert!(printed.contains(&message), "{:?}:\n{}", args, printed);
+    }
+
+    let output = Command::new(env!("CARGO_BIN_EXE_m3trans"))
+        .arg("repair").arg(dir.join("Library.xml")).arg(dir.join("output"))
+        .arg("--config").arg(dir.join("m3trans.toml"))
+        .args(["--no-log-file", "--append-only"])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(1));
+    assert!(printed(&output).contains("m3trans repair can't be used with --append-only"), "{}", printed(&output));
+    // Nothing was synced before giving up.
+    assert!(!dir.join("output").exists());
+
+    fs::remove_dir_all(&dir).unwrap();
+}
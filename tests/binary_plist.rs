@@ -0,0 +1,63 @@
+//! Libraries saved as binary property lists, which must read the same as XML ones.
+//!
+//! fixtures/library.bplist is fixtures/library.xml converted with Python's plistlib:
+//! `plistlib.dump(plistlib.load(xml), bplist, fmt=plistlib.FMT_BINARY)`.
+
+use std::{io::Cursor, path::PathBuf};
+
+use m3trans::{Library, LoadError, PlistFormat};
+
+fn fixture(name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures").join(name)
+}
+
+fn rewritten(library: &Library) -> String {
+    let mut xml = Vec::new();
+    library.to_writer_xml(&mut xml).unwrap();
+    String::from_utf8(xml).unwrap()
+}
+
+#[test]
+fn binary_and_xml_libraries_read_the_same() {
+    let xml = Library::from_path(fixture("library.xml")).unwrap();
+    let binary = Library::from_path(fixture("library.bplist")).unwrap();
+    assert_eq!(binary.tracks.len(), 3);
+    assert_eq!(binary.tracks[&3].location, None);
+    assert_eq!(binary.tracks[&2].name, "Trés Bien");
+    let mut names: Vec<&str> = binary.playlists.values().map(|playlist| playlist.name.as_str()).collect();
+    names.sort_unstable();
+    assert_eq!(names, ["Drum & Bass", "Library", "Rock", "Top"]);
+    assert_eq!(rewritten(&binary), rewritten(&xml));
+
+    let bytes = std::fs::read(fixture("library.bplist")).unwrap();
+    let streamed = Library::from_reader_streaming(Cursor::new(bytes)).unwrap();
+    assert_eq!(rewritten(&streamed), rewritten(&xml));
+}
+
+#[test]
+fn the_format_is_told_by_how_the_file_starts() {
+    let bytes = std::fs::read(fixture("library.bplist")).unwrap();
+    let mut reader = Cursor::new(&bytes);
+    assert_eq!(PlistFormat::detect(&mut reader).unwrap(), PlistFormat::Binary);
+    assert_eq!(reader.position(), 0);
+    assert_eq!(PlistFormat::detect(Cursor::new(b"<?xml")).unwrap(), PlistFormat::Xml);
+    assert_eq!(PlistFormat::detect(Cursor::new(b"")).unwrap(), PlistFormat::Xml);
+}
+
+#[test]
+fn errors_name_the_format_read() {
+    let cases: [(&[u8], PlistFormat, &str); 2] = [
+        (b"bplist00 cut short", PlistFormat::Binary, "invalid binary property list"),
+        (b"<?xml version=\"1.0\"?><plist version=\"1.0\"><dict><key>Tracks", PlistFormat::Xml, "invalid XML property list"),
+    ];
+    for (bytes, format, message) in cases {
+        match Library::from_reader(Cursor::new(bytes)) {
+            Err(e @ LoadError::Plist(..)) => {
+                assert!(matches!(e, LoadError::Plist(read, _) if read == format));
+                assert!(e.to_string().starts_with(message), "{}", e);
+            }
+            Err(e) => panic!("{:?} failed with {}", format, e),
+            Ok(_) => panic!("{:?} was read", format),
+        }
+    }
+}
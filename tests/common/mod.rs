@@ -0,0 +1,83 @@
+//! What the tests that run m3trans on a library of their own share.
+
+// Each test uses only some of these.
+#![allow(dead_code)]
+
+use std::{collections::BTreeMap, fs, path::{Path, PathBuf}, process::{Command, Output}, time::SystemTime};
+
+use m3trans::LibraryBuilder;
+
+/// A new, empty temporary folder for the test `name`, with an empty m3trans.toml in it so that
+/// none in the current folder changes how it is synced.
+pub fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("m3trans-{}-test-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("m3trans.toml"), "").unwrap();
+    dir
+}
+
+/// The location of `path` as the library gives it.
+pub fn location(path: &Path) -> String {
+    format!("file://localhost/{}", path.to_str().unwrap().replace('\\', "/").trim_start_matches('/'))
+}
+
+/// Writes a library of two tracks in playlists nested in folders to `dir`, returning its path.
+pub fn write_library(dir: &Path) -> PathBuf {
+    let media = dir.join("media");
+    fs::create_dir_all(&media).unwrap();
+    let mut builder = LibraryBuilder::new();
+    for (id, name) in [(1, "One"), (2, "Two")] {
+        let path = media.join(format!("{}.mp3", name));
+        fs::write(&path, name.repeat(100)).unwrap();
+        builder.track(id, name, &location(&path), 1000);
+    }
+    builder.playlist("Top", [1, 2]).folder("Rock", |rock| {
+        rock.playlist("Both", [2, 1]).folder("Deep", |deep| {
+            deep.playlist("One", [1]);
+        });
+    });
+    let library_path = dir.join("Library.xml");
+    builder.build().to_writer_xml(fs::File::create(&library_path).unwrap()).unwrap();
+    library_path
+}
+
+/// m3trans, given the library and output in `dir` and its config file.
+pub fn command(dir: &Path) -> Command {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_m3trans"));
+    command.arg(dir.join("Library.xml")).arg(dir.join("output")).arg("--config").arg(dir.join("m3trans.toml"));
+    command
+}
+
+/// What m3trans printed, out and err alike.
+pub fn printed(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stdout).into_owned() + &String::from_utf8_lossy(&output.stderr)
+}
+
+/// Runs m3trans with `args` after the library and output, without a log file, returning what it
+/// logged.
+pub fn sync(dir: &Path, args: &[&str]) -> String {
+    let output = command(dir).args(["--no-log-file", "--verbose"]).args(args).output().unwrap();
+    let log = printed(&output);
+    assert!(output.status.success(), "m3trans failed:\n{}", log);
+    log
+}
+
+/// The contents and modification time of every file in `dir`, by its path relative to it.
+pub fn files(dir: &Path) -> BTreeMap<PathBuf, (Vec<u8>, SystemTime)> {
+    let mut files = BTreeMap::new();
+    let mut pending = vec![dir.to_owned()];
+    while let Some(next) = pending.pop() {
+        for entry in fs::read_dir(&next).unwrap() {
+            let path = entry.unwrap().path();
+            match path.is_dir() {
+                true => pending.push(path),
+                false => {
+                    let modified = fs::metadata(&path).unwrap().modified().unwrap();
+                    files.insert(path.strip_prefix(dir).unwrap().to_owned(), (fs::read(&path).unwrap(), modified));
+                }
+            }
+        }
+    }
+    files
+}
@@ -0,0 +1,47 @@
+//! Plans of the same library, which must come out the same however its maps happen to be ordered.
+
+use m3trans::{Library, LibraryBuilder, plan::{self, TrackNames}};
+
+/// A library with tracks added in a scrambled order, several of them with the same file name in
+/// different folders, so that which keeps the name depends on nothing but their IDs.
+fn library() -> Library {
+    let mut builder = LibraryBuilder::new();
+    for i in 0..60u64 {
+        let id = (i * 37) % 60 + 1;
+        let location = format!("file://localhost/Music/Artist {}/Song {}.mp3", id % 7, id % 5);
+        builder.track(id, &format!("Song {}", id), &location, 1000 * id as u32);
+    }
+    builder
+        .playlist("All", 1..=60)
+        .folder("Odd", |odd| {
+            odd.playlist("Odd", (1..=60).step_by(2)).playlist("Reversed", (1..=60).rev());
+        })
+        .build()
+}
+
+fn planned(options: &plan::Options) -> String {
+    format!("{:#?}", plan::build_plan(&library(), options).unwrap())
+}
+
+#[test]
+fn plans_are_the_same_every_time() {
+    for track_names in [TrackNames::Id, TrackNames::Original, TrackNames::Album] {
+        let options = plan::Options { track_names, ..plan::Options::default() };
+        let first = planned(&options);
+        for _ in 0..5 {
+            assert!(planned(&options) == first, "plans differ with {:?}", track_names);
+        }
+    }
+}
+
+#[test]
+fn tracks_are_copied_in_order_of_their_ids_and_the_lowest_keeps_a_shared_name() {
+    let options = plan::Options { track_names: TrackNames::Original, ..plan::Options::default() };
+    let sync_plan = plan::build_plan(&library(), &options).unwrap();
+    let ids: Vec<u64> = sync_plan.tracks.iter().map(|copy| copy.track_id).collect();
+    assert_eq!(ids, (1..=60).collect::<Vec<u64>>());
+    // Tracks 5, 10, ... 60 are all "Song 0.mp3"; the first of them keeps the name.
+    let destination = |id: usize| sync_plan.tracks[id - 1].destination.to_str().unwrap().to_owned();
+    assert_eq!(destination(5), "tracks/Song 0.mp3");
+    assert_ne!(destination(10), "tracks/Song 0.mp3");
+}
@@ -0,0 +1,53 @@
+//! Syncing with --tracks-dir and --playlists-dir naming folders other than the defaults, down to
+//! the output folder itself for playlists, which the entries of playlists must still lead from.
+
+mod common;
+
+use std::{fs, path::{Path, PathBuf}};
+
+use common::{files, sync, temp_dir, write_library};
+
+/// The playlist files in `output`, by their paths relative to it.
+fn playlists(output: &Path) -> Vec<PathBuf> {
+    files(output).into_keys().filter(|path| path.extension().is_some_and(|extension| extension == "m3u8")).collect()
+}
+
+/// Checks that every entry of every playlist in `output` is a file in `tracks_dir` there.
+fn check_entries(output: &Path, tracks_dir: &str) {
+    for playlist in playlists(output) {
+        let contents = fs::read_to_string(output.join(&playlist)).unwrap();
+        let entries: Vec<&str> = contents.lines().filter(|line| !line.is_empty() && !line.starts_with('#')).collect();
+        assert!(!entries.is_empty(), "{:?} has no entries", playlist);
+        for entry in entries {
+            let track = output.join(&playlist).parent().unwrap().join(entry);
+            assert!(track.is_file(), "entry {:?} of {:?} isn't a file", entry, playlist);
+            let track = fs::canonicalize(track).unwrap();
+            assert!(track.starts_with(fs::canonicalize(output.join(tracks_dir)).unwrap()), "{:?} isn't in {}", track, tracks_dir);
+        }
+    }
+}
+
+#[test]
+fn entries_lead_to_tracks_in_renamed_folders() {
+    for (name, tracks_dir, playlists_dir) in [("renamed", "MUSIC", "PLAYLISTS"), ("nested", "Media/Music", "Lists/All"), ("root", "MUSIC", ".")] {
+        let dir = temp_dir(&format!("dir-names-{}", name));
+        write_library(&dir);
+        let output = dir.join("output");
+        let args = ["--tracks-dir", tracks_dir, "--playlists-dir", playlists_dir, "--prune-playlists", "--yes"];
+        sync(&dir, &args);
+        let written = playlists(&output);
+        assert_eq!(written.len(), 3, "{}: {:?}", name, written);
+        let playlists_root = Path::new(playlists_dir).components().filter(|component| component.as_os_str() != ".").collect::<PathBuf>();
+        assert!(written.iter().all(|playlist| playlist.starts_with(&playlists_root)), "{}: {:?}", name, written);
+        assert!(output.join(&playlists_root).join("Rock/Deep/One.m3u8").exists());
+        check_entries(&output, tracks_dir);
+
+        // A rerun finds what it wrote where it wrote it, pruning none of it.
+        let before = files(&output);
+        let log = sync(&dir, &args.iter().copied().chain(["--force"]).collect::<Vec<_>>());
+        assert!(log.contains("Wrote 0 playlist files, leaving 3 unchanged."), "{}: {}", name, log);
+        assert_eq!(before.keys().collect::<Vec<_>>(), files(&output).keys().collect::<Vec<_>>(), "{}", name);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
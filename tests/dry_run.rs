@@ -0,0 +1,86 @@
+//! Dry runs, which must leave the output exactly as they found it: no files written, replaced,
+//! cleaned up or locked, and no log.
+
+mod common;
+
+use std::{fs, path::{Path, PathBuf}};
+
+use common::{command, files, printed, sync, temp_dir, write_library};
+use m3trans::LibraryBuilder;
+
+/// Every folder in `dir`, by its path relative to it.
+fn folders(dir: &Path) -> Vec<PathBuf> {
+    let mut folders = Vec::new();
+    let mut pending = vec![dir.to_owned()];
+    while let Some(next) = pending.pop() {
+        for entry in fs::read_dir(&next).unwrap() {
+            let path = entry.unwrap().path();
+            if path.is_dir() {
+                folders.push(path.strip_prefix(dir).unwrap().to_owned());
+                pending.push(path);
+            }
+        }
+    }
+    folders.sort();
+    folders
+}
+
+/// Runs a dry run of everything that would change the output, checking that it succeeds.
+fn dry_run(dir: &Path) -> String {
+    let output = command(dir)
+        .args(["--dry-run", "--verbose", "--prune-playlists", "--check-content", "--checksums", "--yes"])
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "the dry run failed:\n{}", printed(&output));
+    printed(&output)
+}
+
+#[test]
+fn a_dry_run_leaves_an_earlier_output_untouched() {
+    let dir = temp_dir("dry-run");
+    write_library(&dir);
+    sync(&dir, &["--checksums"]);
+    let output = dir.join("output");
+    // What a sync would clean up, prune or replace.
+    fs::write(output.join("m3trans.log"), "the last run's log").unwrap();
+    fs::write(output.join("tracks/half.mp3.tmp"), "half a track").unwrap();
+    fs::write(output.join("playlists/Old.m3u8"), "#EXTM3U\n").unwrap();
+    fs::write(output.join("playlists/Top.m3u8"), "#EXTM3U\nchanged by hand\n").unwrap();
+    let mut builder = LibraryBuilder::new();
+    builder.track(3, "Three", &common::location(&dir.join("media/One.mp3")), 1000);
+    builder.playlist("New", [3]);
+    builder.build().to_writer_xml(fs::File::create(dir.join("Library.xml")).unwrap()).unwrap();
+    let (before, folders_before) = (files(&output), folders(&output));
+    #[cfg(unix)]
+    let permissions = {
+        use std::os::unix::fs::PermissionsExt;
+        // Only enforced for users other than root, which the comparisons below still catch.
+        let permissions = fs::metadata(&output).unwrap().permissions();
+        fs::set_permissions(&output, fs::Permissions::from_mode(0o555)).unwrap();
+        permissions
+    };
+
+    let log = dry_run(&dir);
+    #[cfg(unix)]
+    fs::set_permissions(&output, permissions).unwrap();
+    assert!(log.contains("Generate"), "the dry run planned nothing:\n{}", log);
+    let after = files(&output);
+    assert_eq!(before.keys().collect::<Vec<_>>(), after.keys().collect::<Vec<_>>());
+    for (path, (contents, modified)) in &before {
+        assert!(after[path].0 == *contents, "{:?} changed", path);
+        assert_eq!(after[path].1, *modified, "{:?} was written", path);
+    }
+    assert_eq!(folders(&output), folders_before);
+    assert!(!output.join(".m3trans.lock").exists());
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn a_dry_run_creates_no_output() {
+    let dir = temp_dir("dry-run-new");
+    write_library(&dir);
+    dry_run(&dir);
+    assert!(!dir.join("output").exists());
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,50 @@
+//! Playlists that list the same track more than once, as DJ sets do on purpose.
+
+use m3trans::{LibraryBuilder, plan::{self, PlaylistOrigin, PlaylistStep}};
+
+/// The tracks of the entries of each playlist written, by name.
+fn entries(options: &plan::Options) -> Vec<(String, Vec<u64>)> {
+    let library = LibraryBuilder::new()
+        .track(1, "One", "file://localhost/Music/One.mp3", 1000)
+        .track(2, "Two", "file://localhost/Music/Two.mp3", 1000)
+        .track(3, "Three", "file://localhost/Music/Three.mp3", 1000)
+        .folder("Sets", |sets| {
+            sets.playlist("Friday", [1, 2, 1, 1]).playlist("Saturday", [3, 1, 2, 3]);
+        })
+        .build();
+    let sync_plan = plan::build_plan(&library, options).unwrap();
+    sync_plan.playlists.iter()
+        .filter_map(|step| match step {
+            PlaylistStep::Write(planned) => Some(planned),
+            _ => None,
+        })
+        .map(|planned| {
+            let name = match planned.origin {
+                PlaylistOrigin::Folder => format!("{} (folder)", planned.playlist.name),
+                _ => planned.playlist.name.clone(),
+            };
+            (name, planned.entries.iter().map(|(id, _)| *id).collect())
+        })
+        .collect()
+}
+
+#[test]
+fn duplicates_are_kept_as_they_are_by_default() {
+    let options = plan::Options::default();
+    assert_eq!(entries(&options), [("Friday".to_owned(), vec![1, 2, 1, 1]), ("Saturday".to_owned(), vec![3, 1, 2, 3])]);
+}
+
+#[test]
+fn deduping_keeps_the_first_entry_of_each_track() {
+    let options = plan::Options { dedupe_entries: true, ..plan::Options::default() };
+    assert_eq!(entries(&options), [("Friday".to_owned(), vec![1, 2]), ("Saturday".to_owned(), vec![3, 1, 2])]);
+}
+
+#[test]
+fn folder_playlists_always_dedupe() {
+    let options = plan::Options { folder_playlists: true, ..plan::Options::default() };
+    let entries = entries(&options);
+    assert!(entries.contains(&("Sets (folder)".to_owned(), vec![1, 2, 3])), "{:?}", entries);
+    // Without touching the playlists inside.
+    assert!(entries.contains(&("Friday".to_owned(), vec![1, 2, 1, 1])));
+}
@@ -0,0 +1,69 @@
+//! The comments playlists can carry before each entry, which must lead a tool reading them back to
+//! the very track of the entry.
+
+use std::{fs, path::Path};
+
+use m3trans::{Library, LibraryBuilder, Track, format::{self, EntryComments, TrackRef}, observer::NoObserver, plan::{self, Output}};
+
+/// The track an entry comment refers to in `library`, as a tool reading the playlist would find it.
+fn resolve(library: &Library, track: TrackRef) -> Option<u64> {
+    match track {
+        TrackRef::PersistentId(persistent_id) => library.tracks.iter()
+            .find(|(_, track)| track.persistent_id == Some(persistent_id))
+            .map(|(id, _)| *id),
+        TrackRef::TrackId(id) => library.tracks.contains_key(&id).then_some(id),
+    }
+}
+
+#[test]
+fn entry_comments_lead_back_to_the_tracks_of_their_entries() {
+    let track = |name: &str, persistent_id| Track {
+        name: name.to_owned(),
+        persistent_id,
+        location: Some(format!("file:///Music/{}.mp3", name)),
+        duration_ms: Some(1000),
+        ..Track::default()
+    };
+    // Persistent IDs that look like track IDs, and a track without one.
+    let library = LibraryBuilder::new()
+        .track_with(1, track("One", Some(2)))
+        .track_with(2, track("Two", Some(0x9F2A_0000_0000_0001)))
+        .track_with(3, track("Three", None))
+        .playlist("Mix", [3, 1, 2, 1, 9])
+        .build();
+    let format = format::Options { entry_comments: Some(EntryComments::Id), ..format::Options::default() };
+    let options = plan::Options { copy_tracks: false, format: format.clone(), ..plan::Options::default() };
+    let sync_plan = plan::build_plan(&library, &options).unwrap();
+
+    let output = std::env::temp_dir().join(format!("m3trans-entry-comments-test-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&output);
+    plan::execute(&sync_plan, &library, &output, &mut Output { path: output.clone(), options: format }, &mut NoObserver).unwrap();
+    let contents = fs::read_to_string(output.join("playlists/Mix.m3u8")).unwrap();
+
+    // Each comment comes before the #EXTINF line and the path of its entry.
+    let mut resolved = Vec::new();
+    let lines: Vec<&str> = contents.lines().collect();
+    for (i, line) in lines.iter().enumerate().filter(|(_, line)| line.starts_with("#M3TRANS:")) {
+        let id = resolve(&library, format::parse_entry_comment(line).unwrap()).unwrap();
+        assert!(lines[i + 1].starts_with("#EXTINF:"), "{}", contents);
+        let file = Path::new(lines[i + 2]).file_stem().unwrap().to_str().unwrap();
+        assert_eq!(file, library.tracks[&id].name, "{}", contents);
+        resolved.push(id);
+    }
+    assert_eq!(resolved, [3, 1, 2, 1]);
+    assert!(contents.contains("#M3TRANS:track=0000000000000002\n"), "{}", contents);
+    assert!(contents.contains("#M3TRANS:track_id=3\n"), "{}", contents);
+    // Without the comments, the playlist is as it would be without the option.
+    let plain = format::without_entry_comments(contents.as_bytes());
+    assert_eq!(String::from_utf8(plain).unwrap().lines().count(), 1 + 1 + 4 * 2);
+    fs::remove_dir_all(&output).unwrap();
+}
+
+#[test]
+fn only_well_formed_comments_are_read() {
+    assert_eq!(format::parse_entry_comment("#M3TRANS:track=9F2A000000000001"), Some(TrackRef::PersistentId(0x9F2A_0000_0000_0001)));
+    assert_eq!(format::parse_entry_comment("#M3TRANS:track_id=12\r"), Some(TrackRef::TrackId(12)));
+    for line in ["#M3TRANS:track=XYZ", "#M3TRANS:track_id=-1", "#M3TRANS:other=1", "#EXTINF:1,One", "../tracks/1.mp3", "#M3TRANS:"] {
+        assert_eq!(format::parse_entry_comment(line), None, "{}", line);
+    }
+}
@@ -0,0 +1,70 @@
+//! The exit status of m3trans, which scripts go by: 0 for a sync that went through, 2 for one that
+//! finished with files that failed to sync or stopped at the first with --strict, and 1 for one
+//! that couldn't start.
+
+mod common;
+
+use std::{fs, path::Path};
+
+use common::{command, location, printed, temp_dir, write_library};
+use m3trans::LibraryBuilder;
+
+/// Writes a library of two tracks in one playlist to `dir`, the second of which can't be copied as
+/// its file is a folder.
+fn write_failing_library(dir: &Path) {
+    let media = dir.join("media");
+    fs::create_dir_all(media.join("Two.mp3")).unwrap();
+    fs::write(media.join("One.mp3"), "One".repeat(100)).unwrap();
+    let mut builder = LibraryBuilder::new();
+    builder.track(1, "One", &location(&media.join("One.mp3")), 1000);
+    builder.track(2, "Two", &location(&media.join("Two.mp3")), 1000);
+    builder.playlist("Both", [1, 2]);
+    builder.build().to_writer_xml(fs::File::create(dir.join("Library.xml")).unwrap()).unwrap();
+}
+
+/// Runs m3trans with `args`, returning its exit status and what it printed.
+fn run(dir: &Path, args: &[&str]) -> (Option<i32>, String) {
+    let output = command(dir).arg("--no-log-file").args(args).output().unwrap();
+    (output.status.code(), printed(&output))
+}
+
+#[test]
+fn a_clean_sync_exits_with_0() {
+    let dir = temp_dir("exit-clean");
+    write_library(&dir);
+    let (status, printed) = run(&dir, &[]);
+    assert_eq!(status, Some(0), "{}", printed);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn failed_tracks_exit_with_2() {
+    let dir = temp_dir("exit-failures");
+    write_failing_library(&dir);
+    let (status, printed) = run(&dir, &[]);
+    assert_eq!(status, Some(2), "{}", printed);
+    assert!(printed.contains("finished, but 1 files failed to sync"), "{}", printed);
+    // The rest of the sync went through.
+    assert!(dir.join("output/playlists/Both.m3u8").exists());
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn a_strict_sync_stops_with_2() {
+    let dir = temp_dir("exit-strict");
+    write_failing_library(&dir);
+    let (status, printed) = run(&dir, &["--strict"]);
+    assert_eq!(status, Some(2), "{}", printed);
+    assert!(printed.contains("as --strict was given"), "{}", printed);
+    // Stopped before writing playlists that would leave out the track.
+    assert!(!dir.join("output/playlists/Both.m3u8").exists());
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn a_sync_that_cant_start_exits_with_1() {
+    let dir = temp_dir("exit-no-library");
+    let (status, printed) = run(&dir, &[]);
+    assert_eq!(status, Some(1), "{}", printed);
+    fs::remove_dir_all(&dir).unwrap();
+}
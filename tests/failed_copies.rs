@@ -0,0 +1,38 @@
+//! Syncing a library with a track that can't be copied, which must still write its playlists, left
+//! without the entry for that track, rather than stopping partway through.
+
+mod common;
+
+use std::fs;
+
+use common::{command, location, printed, temp_dir};
+use m3trans::LibraryBuilder;
+
+#[test]
+fn playlists_leave_out_tracks_that_failed_to_copy() {
+    let dir = temp_dir("failed-copies");
+    let media = dir.join("media");
+    // The file of Two is a folder, which can't be read like one.
+    fs::create_dir_all(media.join("Two.mp3")).unwrap();
+    fs::write(media.join("One.mp3"), "One".repeat(100)).unwrap();
+    fs::write(media.join("Three.mp3"), "Three".repeat(100)).unwrap();
+    let mut builder = LibraryBuilder::new();
+    for (id, name) in [(1, "One"), (2, "Two"), (3, "Three")] {
+        builder.track(id, name, &location(&media.join(format!("{}.mp3", name))), 1000);
+    }
+    builder.playlist("Mix", [1, 2, 3]);
+    builder.build().to_writer_xml(fs::File::create(dir.join("Library.xml")).unwrap()).unwrap();
+
+    let output = command(&dir).args(["--no-log-file", "--verbose"]).output().unwrap();
+    let log = printed(&output);
+    assert!(!log.contains("panicked"), "{}", log);
+    assert_eq!(output.status.code(), Some(2), "{}", log);
+    assert!(log.contains("Playlist Mix references track 2 (Two), which isn't in the output; leaving it out."), "{}", log);
+
+    let playlist = fs::read_to_string(dir.join("output/playlists/Mix.m3u8")).unwrap();
+    let entries: Vec<&str> = playlist.lines().filter(|line| !line.is_empty() && !line.starts_with('#')).collect();
+    assert_eq!(entries, ["../tracks/1.mp3", "../tracks/3.mp3"]);
+    assert!(!dir.join("output/tracks/2.mp3").exists());
+
+    fs::remove_dir_all(&dir).unwrap();
+}
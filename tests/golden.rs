@@ -0,0 +1,114 @@
+//! The exact output of m3trans for a small library, checked against the copies kept in
+//! fixtures/golden. After a change meant to alter the output, run the tests with M3TRANS_BLESS=1
+//! to write the new copies, and check their diff before committing them.
+
+mod common;
+
+use std::{fs, path::{Path, PathBuf}};
+
+use common::{location, sync, temp_dir};
+use m3trans::{LibraryBuilder, Track};
+
+/// Writes to `dir` a library like fixtures/library.xml, of two tracks whose files are there: Top of
+/// Song One, and Rock/Drum & Bass of Trés Bien and Song One.
+fn write_library(dir: &Path) {
+    let media = dir.join("media");
+    fs::create_dir_all(&media).unwrap();
+    let song_one = media.join("Song One.mp3");
+    let tres_bien = media.join("Trés Bien.m4a");
+    fs::write(&song_one, "Song One".repeat(100)).unwrap();
+    fs::write(&tres_bien, "Trés Bien".repeat(100)).unwrap();
+    let mut builder = LibraryBuilder::new();
+    builder.track_with(1, Track {
+        name: "Song One".to_owned(),
+        artist: Some("Band A".to_owned()),
+        album: Some("First".to_owned()),
+        location: Some(location(&song_one)),
+        duration_ms: Some(241_333),
+        ..Track::default()
+    });
+    builder.track(2, "Trés Bien", &location(&tres_bien), 180_000);
+    builder.playlist("Top", [1]).folder("Rock", |rock| {
+        rock.playlist("Drum & Bass", [2, 1]);
+    });
+    builder.build().to_writer_xml(fs::File::create(dir.join("Library.xml")).unwrap()).unwrap();
+}
+
+/// Syncs the library into a new temporary folder for `case` with `args`, returning the folder.
+fn synced(case: &str, args: &[&str]) -> PathBuf {
+    let dir = temp_dir(&format!("golden-{}", case));
+    write_library(&dir);
+    sync(&dir, args);
+    dir
+}
+
+/// Checks that each of `paths` in the output in `dir` is byte for byte its copy in
+/// fixtures/golden/`case`, or with M3TRANS_BLESS set, writes them there instead.
+fn assert_golden(dir: &Path, case: &str, paths: &[&str]) {
+    let golden = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/golden").join(case);
+    for path in paths {
+        let written = without_version(fs::read(dir.join("output").join(path)).unwrap());
+        let golden = golden.join(path);
+        if std::env::var_os("M3TRANS_BLESS").is_some() {
+            fs::create_dir_all(golden.parent().unwrap()).unwrap();
+            fs::write(&golden, &written).unwrap();
+            continue;
+        }
+        let expected = fs::read(&golden).unwrap_or_else(|e| panic!("can't read {:?}: {}", golden, e));
+        assert!(written == expected, "{} differs from {:?}:\n{}", path, golden, String::from_utf8_lossy(&written));
+    }
+    fs::remove_dir_all(dir).unwrap();
+}
+
+/// `contents` with the version of m3trans that --stamp notes replaced by VERSION, so that the
+/// golden copies needn't change with every release.
+fn without_version(contents: Vec<u8>) -> Vec<u8> {
+    let stamp = concat!("m3trans ", env!("CARGO_PKG_VERSION")).as_bytes();
+    let mut replaced = Vec::with_capacity(contents.len());
+    let mut rest = &contents[..];
+    while let Some(start) = rest.windows(stamp.len()).position(|window| window == stamp) {
+        replaced.extend_from_slice(&rest[..start]);
+        replaced.extend_from_slice(b"m3trans VERSION");
+        rest = &rest[start + stamp.len()..];
+    }
+    replaced.extend_from_slice(rest);
+    replaced
+}
+
+#[test]
+fn pls_playlists() {
+    let dir = synced("pls", &["--format", "pls"]);
+    assert_golden(&dir, "pls", &["playlists/Top.pls", "playlists/Rock/Drum & Bass.pls"]);
+}
+
+#[test]
+fn xspf_playlists_with_percent_encoded_locations() {
+    // Named after their files, the tracks have a space and an é to encode.
+    let dir = synced("xspf", &["--format", "xspf", "--names", "original"]);
+    assert_golden(&dir, "xspf", &["playlists/Top.xspf", "playlists/Rock/Drum & Bass.xspf"]);
+}
+
+#[test]
+fn html_index_and_pages() {
+    let dir = synced("html", &["--html-index", "--html-pages"]);
+    assert_golden(&dir, "html", &["index.html", "playlists/Top.html", "playlists/Rock/Drum & Bass.html"]);
+}
+
+#[test]
+fn rockbox_preset() {
+    let dir = synced("rockbox", &["--preset", "rockbox"]);
+    assert_golden(&dir, "rockbox", &["Playlists/Top.m3u8", "Playlists/Rock/Drum & Bass.m3u8"]);
+
+    // Options given explicitly still win over the preset.
+    let dir = synced("rockbox-relative", &["--preset", "rockbox", "--entry-paths", "relative"]);
+    let top = fs::read_to_string(dir.join("output/Playlists/Top.m3u8")).unwrap();
+    assert!(top.ends_with("\n../tracks/1.mp3\n"), "{}", top);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn header_blocks() {
+    // As .m3u, UTF-8 playlists say what they are encoded in as well.
+    let dir = synced("header", &["--stamp", "--playlist-ext", "m3u", "--format", "m3u8", "--format", "xspf"]);
+    assert_golden(&dir, "header", &["playlists/Top.m3u", "playlists/Rock/Drum & Bass.m3u", "playlists/Top.xspf"]);
+}
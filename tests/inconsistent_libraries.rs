@@ -0,0 +1,113 @@
+//! Libraries that contradict themselves, as exports made mid-edit or by other tools can, which
+//! must still load and sync as far as they make sense.
+
+use std::{fs, path::{Path, PathBuf}};
+
+use m3trans::{Library, MissingTrack, ValidationIssue, format, observer::NoObserver, plan::{self, Output, PlanError, PlannedPlaylist, PlaylistStep, TrackNames}};
+
+fn fixture(name: &str) -> Library {
+    Library::from_path(PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures").join(name)).unwrap()
+}
+
+/// The playlists `sync_plan` writes.
+fn written(sync_plan: &plan::SyncPlan) -> Vec<&PlannedPlaylist> {
+    sync_plan.playlists.iter()
+        .filter_map(|step| match step {
+            PlaylistStep::Write(planned) => Some(&**planned),
+            _ => None,
+        })
+        .collect()
+}
+
+#[test]
+fn the_first_of_playlists_sharing_a_persistent_id_is_kept() {
+    let library = fixture("duplicate_ids.xml");
+    assert_eq!(library.playlists.len(), 2);
+    assert_eq!(library.playlists[&0x10].name, "Rock");
+    assert!(library.playlists[&0x10].kind.is_folder());
+    // What is in the folder by its persistent ID stays in the one kept.
+    let mut visited = Vec::new();
+    library.visit_playlists(|id, depth| visited.push((id, depth)));
+    assert_eq!(visited, [(0x10, 0), (0x20, 1)]);
+    assert_eq!(library.virtual_path(&library.playlists[&0x20]), "Rock/Concerts");
+
+    let issues = library.validate();
+    assert!(issues.contains(&ValidationIssue::DuplicatePlaylistPersistentId {
+        persistent_id: 0x10,
+        kept: "Rock".to_owned(),
+        discarded: "Old Rock".to_owned(),
+    }), "{:?}", issues);
+    assert!(issues.contains(&ValidationIssue::DuplicateTrackPersistentId { persistent_id: 0xA1, track_ids: vec![1, 2] }), "{:?}", issues);
+}
+
+#[test]
+fn tracks_sharing_a_persistent_id_are_named_by_their_track_ids() {
+    let library = fixture("duplicate_ids.xml");
+    let options = plan::Options { track_names: TrackNames::PersistentId, ..plan::Options::default() };
+    let sync_plan = plan::build_plan(&library, &options).unwrap();
+    let mut destinations: Vec<(u64, &Path)> = sync_plan.tracks.iter().map(|copy| (copy.track_id, copy.destination.as_path())).collect();
+    destinations.sort();
+    assert_eq!(destinations, [
+        (1, Path::new("tracks/1.mp3")),
+        (2, Path::new("tracks/2.mp3")),
+        (3, Path::new("tracks/00000000000000A3.m4a")),
+    ]);
+    let names: Vec<&str> = written(&sync_plan).iter().map(|planned| planned.playlist.name.as_str()).collect();
+    assert_eq!(names, ["Concerts"]);
+}
+
+#[test]
+fn entries_for_tracks_that_were_never_defined_are_left_out() {
+    let library = fixture("dangling_tracks.xml");
+    let mid_edit = &library.playlists[&1];
+    let tracks: Vec<Result<u64, MissingTrack>> = library.playlist_tracks(mid_edit).map(|resolved| resolved.map(|(id, _)| id)).collect();
+    assert_eq!(tracks, [Ok(1), Err(MissingTrack { track_id: 404 }), Ok(2), Err(MissingTrack { track_id: 405 })]);
+    let missing = |playlist: &str, playlist_id, track_id| ValidationIssue::MissingTrack { playlist: playlist.to_owned(), playlist_id, track_id };
+    let mut issues = library.validate();
+    issues.sort_by_key(|issue| issue.to_string());
+    assert_eq!(issues, [missing("Gone", 2, 404), missing("Mid Edit", 1, 404), missing("Mid Edit", 1, 405)]);
+
+    let sync_plan = plan::build_plan(&library, &plan::Options::default()).unwrap();
+    let entries: Vec<(&str, Vec<u64>)> = written(&sync_plan).iter()
+        .map(|planned| (planned.playlist.name.as_str(), planned.entries.iter().map(|(id, _)| *id).collect()))
+        .collect();
+    assert_eq!(entries, [("Mid Edit", vec![1, 2]), ("Gone", vec![]), ("Fine", vec![2])]);
+
+    // Unless the sync is to stop at the first.
+    let strict = plan::Options { strict: true, ..plan::Options::default() };
+    match plan::build_plan(&library, &strict) {
+        Err(e @ PlanError::DanglingEntry { .. }) => {
+            assert_eq!(e.to_string(), "playlist Mid Edit references track 404, which isn't in the library");
+        }
+        Err(e) => panic!("failed with {}", e),
+        Ok(_) => panic!("planned a strict sync"),
+    }
+}
+
+#[test]
+fn playlists_count_the_entries_they_leave_out_and_are_written_without_them() {
+    let library = fixture("dangling_tracks.xml");
+    // Referring to the tracks where they are, which needn't be there to write playlists of them.
+    let options = plan::Options { copy_tracks: false, ..plan::Options::default() };
+    let sync_plan = plan::build_plan(&library, &options).unwrap();
+    let dropped: Vec<(&str, usize)> = written(&sync_plan).iter().map(|planned| (planned.playlist.name.as_str(), planned.dropped)).collect();
+    assert_eq!(dropped, [("Mid Edit", 2), ("Gone", 1), ("Fine", 0)]);
+
+    let output = std::env::temp_dir().join(format!("m3trans-dangling-test-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&output);
+    let mut target = Output { path: output.clone(), options: format::Options::default() };
+    let report = plan::execute(&sync_plan, &library, &output, &mut target, &mut NoObserver).unwrap();
+    assert_eq!(report.playlists.len(), 3);
+    // The file names of the entries of playlist `name`.
+    let entries = |name: &str| -> Vec<String> {
+        let contents = fs::read_to_string(output.join("playlists").join(name)).unwrap();
+        contents.lines()
+            .filter(|line| !line.starts_with('#'))
+            .map(|line| Path::new(line).file_name().unwrap().to_string_lossy().into_owned())
+            .collect()
+    };
+    assert_eq!(entries("Mid Edit.m3u8"), ["One.mp3", "Two.mp3"]);
+    assert!(entries("Gone.m3u8").is_empty());
+    assert_eq!(entries("Fine.m3u8"), ["Two.mp3"]);
+    fs::remove_dir_all(&output).unwrap();
+}
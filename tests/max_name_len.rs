@@ -0,0 +1,53 @@
+//! Names shortened to `max_name_len`, which playlists must refer to by the same names as the
+//! files on disk.
+
+use std::path::Path;
+
+use m3trans::{LibraryBuilder, plan::{self, PlaylistStep, TrackNames}};
+
+/// The length in bytes of the last component of `path`.
+fn name_len(path: &Path) -> usize {
+    path.file_name().unwrap().len()
+}
+
+#[test]
+fn multi_byte_names_are_cut_at_a_character_boundary() {
+    // Each é takes two bytes, so an even cut is the only one that keeps the name valid UTF-8.
+    let long = "é".repeat(25);
+    let library = LibraryBuilder::new()
+        .track(1, "Song", &format!("file://localhost/Music/{}.mp3", long), 1000)
+        .playlist(&long, [1])
+        .build();
+    let options = plan::Options { max_name_len: Some(20), track_names: TrackNames::Original, ..plan::Options::default() };
+    let sync_plan = plan::build_plan(&library, &options).unwrap();
+
+    let destination = &sync_plan.tracks[0].destination;
+    assert!(name_len(destination) <= 20, "{:?} is too long", destination);
+    let track_name = destination.file_name().unwrap().to_str().unwrap();
+    assert!(track_name.starts_with("éééé") && track_name.ends_with(".mp3"), "{:?} lost its extension", track_name);
+    assert!(track_name.contains('~'));
+
+    let planned = sync_plan.playlists.iter()
+        .find_map(|step| match step {
+            PlaylistStep::Write(planned) => Some(planned),
+            _ => None,
+        })
+        .unwrap();
+    // The stem leaves room for the extension, and ends with the persistent ID to stay unique.
+    assert_eq!(planned.stem, Path::new("playlists").join("éééé~000001"));
+    assert!(planned.files.iter().all(|file| name_len(&file.path) <= 20));
+    let entries: Vec<&str> = planned.entries.iter().map(|(_, entry)| entry.path.as_str()).collect();
+    assert_eq!(entries, [format!("../{}", destination.to_str().unwrap())]);
+}
+
+#[test]
+fn short_names_are_left_alone() {
+    let library = LibraryBuilder::new()
+        .track(1, "Song", "file://localhost/Music/Song.mp3", 1000)
+        .playlist("Short", [1])
+        .build();
+    let options = plan::Options { max_name_len: Some(20), track_names: TrackNames::Original, ..plan::Options::default() };
+    let sync_plan = plan::build_plan(&library, &options).unwrap();
+    assert_eq!(sync_plan.tracks[0].destination, Path::new("tracks").join("Song.mp3"));
+    assert!(sync_plan.playlists.iter().any(|step| matches!(step, PlaylistStep::Write(planned) if planned.stem == Path::new("playlists/Short"))));
+}
@@ -0,0 +1,61 @@
+//! The `../` prefixes of the entries of playlists nested in folders, which lead from each
+//! playlist's folder back up to the output.
+
+use std::path::PathBuf;
+
+use m3trans::{LibraryBuilder, plan::{self, PlaylistStep}};
+
+/// The entries of playlist `name` in the plan of a library with one playlist at depth 0, 1 and 3.
+fn entries(options: &plan::Options, name: &str) -> (PathBuf, Vec<String>) {
+    let library = LibraryBuilder::new()
+        .track(1, "Song", "file://localhost/Music/Song.mp3", 1000)
+        .playlist("Top", [1])
+        .folder("A", |a| {
+            a.playlist("One", [1]).folder("B", |b| {
+                b.folder("C", |c| {
+                    c.playlist("Three", [1]);
+                });
+            });
+        })
+        .build();
+    let sync_plan = plan::build_plan(&library, options).unwrap();
+    sync_plan.playlists.iter()
+        .find_map(|step| match step {
+            PlaylistStep::Write(planned) if planned.playlist.name == name => {
+                Some((planned.stem.clone(), planned.entries.iter().map(|(_, entry)| entry.path.clone()).collect()))
+            }
+            _ => None,
+        })
+        .unwrap()
+}
+
+#[test]
+fn entries_lead_back_up_from_each_depth() {
+    let options = plan::Options::default();
+    assert_eq!(entries(&options, "Top"), (PathBuf::from("playlists/Top"), vec!["../tracks/1.mp3".to_owned()]));
+    assert_eq!(entries(&options, "One"), (PathBuf::from("playlists/A/One"), vec!["../../tracks/1.mp3".to_owned()]));
+    assert_eq!(entries(&options, "Three"), (PathBuf::from("playlists/A/B/C/Three"), vec!["../../../../tracks/1.mp3".to_owned()]));
+}
+
+#[test]
+fn entries_of_top_level_playlists_in_the_output_itself_have_no_prefix() {
+    let options = plan::Options { playlists_dir: PathBuf::new(), ..plan::Options::default() };
+    assert_eq!(entries(&options, "Top"), (PathBuf::from("Top"), vec!["tracks/1.mp3".to_owned()]));
+    assert_eq!(entries(&options, "One"), (PathBuf::from("A/One"), vec!["../tracks/1.mp3".to_owned()]));
+    assert_eq!(entries(&options, "Three"), (PathBuf::from("A/B/C/Three"), vec!["../../../tracks/1.mp3".to_owned()]));
+}
+
+#[test]
+fn flattened_playlists_are_all_one_level_down() {
+    let options = plan::Options { max_depth: Some(0), ..plan::Options::default() };
+    assert_eq!(entries(&options, "Top").1, ["../tracks/1.mp3"]);
+    assert_eq!(entries(&options, "One").1, ["../tracks/1.mp3"]);
+    assert_eq!(entries(&options, "Three").1, ["../tracks/1.mp3"]);
+}
+
+#[test]
+fn root_prefix_counts_the_folders_a_playlist_is_in() {
+    assert_eq!(plan::root_prefix("Top".as_ref()), PathBuf::new());
+    assert_eq!(plan::root_prefix("playlists/Top".as_ref()), PathBuf::from(".."));
+    assert_eq!(plan::root_prefix("playlists/A/B/C/Three".as_ref()), PathBuf::from("../../../.."));
+}
@@ -0,0 +1,113 @@
+//! The calls a sync makes to its observer, carried out with the plain `plan::Output` target.
+
+use std::{fs, path::{Path, PathBuf}};
+
+use m3trans::{Library, LibraryBuilder, Playlist, Track, format, observer::{Phase, SyncObserver, TrackOutcome}, plan::{self, Output}};
+
+#[derive(Debug, PartialEq)]
+enum Event {
+    Phase(Phase),
+    Started(u64),
+    Finished(u64, TrackOutcome),
+    Written(PathBuf),
+}
+
+#[derive(Default)]
+struct Recorder(Vec<Event>);
+
+impl SyncObserver for Recorder {
+    fn on_phase_changed(&mut self, _output: &Path, phase: Phase) {
+        self.0.push(Event::Phase(phase));
+    }
+
+    fn on_track_started(&mut self, track_id: u64, _track: &Track, _index: usize, _total: usize) {
+        self.0.push(Event::Started(track_id));
+    }
+
+    fn on_track_finished(&mut self, track_id: u64, outcome: TrackOutcome) {
+        self.0.push(Event::Finished(track_id, outcome));
+    }
+
+    fn on_playlist_written(&mut self, _playlist: &Playlist, path: &Path, _changed: bool) {
+        self.0.push(Event::Written(path.to_owned()));
+    }
+}
+
+/// A library of three tracks in `dir`, one of them missing its file, in two playlists.
+fn library(dir: &Path) -> Library {
+    let media = dir.join("media");
+    fs::create_dir_all(&media).unwrap();
+    let mut builder = LibraryBuilder::new();
+    for (id, name) in [(1, "One"), (2, "Two"), (3, "Gone")] {
+        let path = media.join(format!("{}.mp3", name));
+        if id != 3 {
+            fs::write(&path, name).unwrap();
+        }
+        let location = format!("file://localhost/{}", path.to_str().unwrap().replace('\\', "/").trim_start_matches('/'));
+        builder.track(id, name, &location, 1000);
+    }
+    builder.playlist("Both", [1, 2]).folder("Folder", |folder| {
+        folder.playlist("All", [1, 2, 3]);
+    });
+    builder.build()
+}
+
+#[test]
+fn every_started_track_finishes_once_and_phases_dont_interleave() {
+    let dir = std::env::temp_dir().join(format!("m3trans-observer-test-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    let library = library(&dir);
+    let sync_plan = plan::build_plan(&library, &plan::Options::default()).unwrap();
+    let output = dir.join("output");
+    let mut target = Output { path: output.clone(), options: format::Options::default() };
+    let mut recorder = Recorder::default();
+    let report = plan::execute(&sync_plan, &library, &output, &mut target, &mut recorder).unwrap();
+    let events = recorder.0;
+
+    let phases: Vec<Phase> = events.iter()
+        .filter_map(|event| match event {
+            Event::Phase(phase) => Some(*phase),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(phases, [Phase::CopyingTracks, Phase::WritingPlaylists, Phase::Finished]);
+
+    let position = |phase| events.iter().position(|event| *event == Event::Phase(phase)).unwrap();
+    let (writing, finished) = (position(Phase::WritingPlaylists), position(Phase::Finished));
+    assert_eq!(events[0], Event::Phase(Phase::CopyingTracks));
+    assert!(events[..writing].iter().all(|event| !matches!(event, Event::Written(_))));
+    assert!(events[writing..].iter().all(|event| !matches!(event, Event::Started(_) | Event::Finished(..))));
+    assert_eq!(finished, events.len() - 1);
+
+    // Each track started is finished before the next one starts.
+    let mut open = None;
+    let mut started = Vec::new();
+    for event in &events[..writing] {
+        match *event {
+            Event::Started(id) => {
+                assert_eq!(open, None, "track {} started before the last one finished", id);
+                open = Some(id);
+                started.push(id);
+            }
+            Event::Finished(id, _) => assert_eq!(open.take(), Some(id)),
+            _ => {}
+        }
+    }
+    assert_eq!(open, None);
+    let mut planned: Vec<u64> = sync_plan.tracks.iter().map(|copy| copy.track_id).collect();
+    planned.sort_unstable();
+    started.sort_unstable();
+    assert_eq!(started, planned);
+
+    let failed: Vec<u64> = report.outcomes.iter()
+        .filter(|(_, outcome)| *outcome == TrackOutcome::Failed)
+        .map(|(id, _)| *id)
+        .collect();
+    assert_eq!(failed, [3]);
+    assert!(!report.synced.contains_key(&3));
+    let written = events.iter().filter(|event| matches!(event, Event::Written(_))).count();
+    assert_eq!(written, report.playlists.len());
+    assert_eq!(written, 2);
+
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,89 @@
+//! The exact bytes of the playlists m3trans writes, where how they are named or what their entries
+//! look like has gone wrong before.
+
+mod common;
+
+use std::{fs, path::{Path, PathBuf}};
+
+use common::{location, sync, temp_dir, write_library};
+use m3trans::LibraryBuilder;
+
+/// Writes to `dir` a library of the tracks One and Two, with the playlists `playlists` adds.
+fn write_playlists(dir: &Path, playlists: impl FnOnce(&mut LibraryBuilder)) {
+    let media = dir.join("media");
+    fs::create_dir_all(&media).unwrap();
+    let mut builder = LibraryBuilder::new();
+    for (id, name) in [(1, "One"), (2, "Two")] {
+        let path = media.join(format!("{}.mp3", name));
+        fs::write(&path, name.repeat(100)).unwrap();
+        builder.track(id, name, &location(&path), 1000);
+    }
+    playlists(&mut builder);
+    builder.build().to_writer_xml(fs::File::create(dir.join("Library.xml")).unwrap()).unwrap();
+}
+
+#[test]
+fn backslash_separators_reach_every_part_of_an_entry() {
+    let dir = temp_dir("bytes-backslash");
+    write_library(&dir);
+    sync(&dir, &["--path-separator", "backslash", "--names", "album"]);
+    let output = dir.join("output");
+    assert_eq!(
+        fs::read(output.join("playlists/Rock/Deep/One.m3u8")).unwrap(),
+        b"#EXTM3U\n#PLAYLIST:One\n#EXTINF:1,One\n..\\..\\..\\tracks\\Unknown Artist\\Unknown Album\\One.mp3\n",
+    );
+    assert_eq!(
+        fs::read(output.join("playlists/Top.m3u8")).unwrap(),
+        &b"#EXTM3U\n#PLAYLIST:Top\n#EXTINF:1,One\n..\\tracks\\Unknown Artist\\Unknown Album\\One.mp3\n\
+            #EXTINF:1,Two\n..\\tracks\\Unknown Artist\\Unknown Album\\Two.mp3\n"[..],
+    );
+    // The copies themselves are where the native separators put them.
+    assert!(output.join("tracks").join("Unknown Artist").join("Unknown Album").join("One.mp3").is_file());
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn sibling_playlists_of_the_same_name_get_a_file_each() {
+    let dir = temp_dir("bytes-siblings");
+    write_playlists(&dir, |builder| {
+        builder.folder("Rock", |rock| {
+            rock.playlist("Favorites", [1]).playlist("Favorites", [2]);
+        });
+    });
+    let log = sync(&dir, &[]);
+    let rock = dir.join("output/playlists/Rock");
+    assert_eq!(fs::read(rock.join("Favorites.m3u8")).unwrap(), b"#EXTM3U\n#PLAYLIST:Favorites\n#EXTINF:1,One\n../../tracks/1.mp3\n");
+    assert_eq!(fs::read(rock.join("Favorites (2).m3u8")).unwrap(), b"#EXTM3U\n#PLAYLIST:Favorites\n#EXTINF:1,Two\n../../tracks/2.mp3\n");
+    assert_eq!(fs::read_dir(&rock).unwrap().count(), 2);
+    assert!(log.contains(r#"collides with an earlier one, using "playlists/Rock/Favorites (2)""#), "{}", log);
+
+    // The same playlist gets the same file on every run.
+    let log = sync(&dir, &["--force"]);
+    assert!(log.contains("Wrote 0 playlist files, leaving 2 unchanged."), "{}", log);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn dots_in_names_are_kept_before_the_extension() {
+    let dir = temp_dir("bytes-dots");
+    write_playlists(&dir, |builder| {
+        builder.playlist("Mix Vol. 2", [1]).playlist("songs.backup", [2]).playlist("Trailing.", [1]);
+        // Once its leading dot is taken off, as for every name, the first is named like the second.
+        builder.playlist(".m3u8", [2]).playlist("m3u8", [1]);
+        builder.folder("Vol. 1", |folder| {
+            folder.playlist("A.B", [2]);
+        });
+    });
+    sync(&dir, &[]);
+    let playlists = dir.join("output/playlists");
+    let names: Vec<_> = common::files(&playlists).into_keys().collect();
+    let expected = ["Mix Vol. 2.m3u8", "Trailing.m3u8", "Vol. 1/A.B.m3u8", "m3u8 (2).m3u8", "m3u8.m3u8", "songs.backup.m3u8"];
+    assert_eq!(names, expected.map(PathBuf::from));
+    assert_eq!(fs::read(playlists.join("Mix Vol. 2.m3u8")).unwrap(), b"#EXTM3U\n#PLAYLIST:Mix Vol. 2\n#EXTINF:1,One\n../tracks/1.mp3\n");
+    assert_eq!(fs::read(playlists.join("Vol. 1/A.B.m3u8")).unwrap(), b"#EXTM3U\n#PLAYLIST:A.B\n#EXTINF:1,Two\n../../tracks/2.mp3\n");
+    assert_eq!(fs::read(playlists.join("m3u8.m3u8")).unwrap(), b"#EXTM3U\n#PLAYLIST:.m3u8\n#EXTINF:1,Two\n../tracks/2.mp3\n");
+
+    let log = sync(&dir, &["--force"]);
+    assert!(log.contains("Wrote 0 playlist files, leaving 6 unchanged."), "{}", log);
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,86 @@
+//! `m3trans repair` on an output laid out by an older m3trans, which it must bring in line by
+//! moving the copies already there rather than copying the tracks again.
+
+mod common;
+
+use std::{fs, path::{Path, PathBuf}, process::Command};
+
+use common::{files, printed, sync, temp_dir, write_library};
+
+/// Runs `m3trans repair` on the output in `dir` with `args`, returning what it logged.
+fn repair(dir: &Path, args: &[&str]) -> String {
+    let output = Command::new(env!("CARGO_BIN_EXE_m3trans"))
+        .arg("repair").arg(dir.join("Library.xml")).arg(dir.join("output"))
+        .arg("--config").arg(dir.join("m3trans.toml"))
+        .args(["--no-log-file", "--verbose", "--yes"])
+        .args(args)
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "m3trans repair failed:\n{}", printed(&output));
+    printed(&output)
+}
+
+/// The files in the tracks folder of the output in `dir`, by their paths relative to the output.
+fn track_files(output: &Path) -> Vec<PathBuf> {
+    files(output).into_keys().filter(|path| path.starts_with("tracks")).collect()
+}
+
+#[cfg(unix)]
+fn inode(path: &Path) -> u64 {
+    use std::os::unix::fs::MetadataExt;
+    fs::metadata(path).unwrap().ino()
+}
+
+#[test]
+fn repair_moves_old_copies_into_place_and_rewrites_playlists() {
+    let dir = temp_dir("repair");
+    write_library(&dir);
+    // Where the tracks go now, named after their files, from a fresh sync alongside.
+    sync(&dir, &["--names", "original"]);
+    let fresh = dir.join("fresh");
+    fs::rename(dir.join("output"), &fresh).unwrap();
+    let fresh_tracks = track_files(&fresh);
+    assert_eq!(fresh_tracks.len(), 2, "{:?}", fresh_tracks);
+
+    // An old layout: one copy named after its track ID, as with the old default of --names id,
+    // one under a name of its own, a file that is no track's, and a playlist that refers to the
+    // old names.
+    let output = dir.join("output");
+    fs::create_dir_all(output.join("tracks/Old Folder")).unwrap();
+    fs::create_dir_all(output.join("playlists")).unwrap();
+    fs::copy(dir.join("media/One.mp3"), output.join("tracks/1.mp3")).unwrap();
+    fs::copy(dir.join("media/Two.mp3"), output.join("tracks/Old Folder/Second.mp3")).unwrap();
+    fs::write(output.join("tracks/stray.mp3"), "no track's").unwrap();
+    fs::write(output.join("playlists/Top.m3u8"), "#EXTM3U\n../tracks/1.mp3\n../tracks/Old Folder/Second.mp3\n").unwrap();
+    #[cfg(unix)]
+    let inodes = (inode(&output.join("tracks/1.mp3")), inode(&output.join("tracks/Old Folder/Second.mp3")));
+
+    let log = repair(&dir, &["--names", "original"]);
+    assert!(log.contains("Found the copies of 1 tracks by their names and 1 by their contents, of 2 not in place."), "{}", log);
+    assert!(log.contains("stray.mp3"), "the file of no track wasn't listed:\n{}", log);
+    assert!(!log.contains("Copied file"), "tracks were copied again:\n{}", log);
+    let mut expected = fresh_tracks.clone();
+    expected.push(PathBuf::from("tracks/stray.mp3"));
+    expected.sort();
+    assert_eq!(track_files(&output), expected);
+    for track in &fresh_tracks {
+        assert_eq!(fs::read(output.join(track)).unwrap(), fs::read(fresh.join(track)).unwrap(), "{:?}", track);
+    }
+    #[cfg(unix)]
+    assert_eq!((inode(&output.join(&fresh_tracks[0])), inode(&output.join(&fresh_tracks[1]))), inodes);
+    for playlist in ["playlists/Top.m3u8", "playlists/Rock/Both.m3u8", "playlists/Rock/Deep/One.m3u8"] {
+        assert_eq!(fs::read_to_string(output.join(playlist)).unwrap(), fs::read_to_string(fresh.join(playlist)).unwrap(), "{}", playlist);
+    }
+
+    // Once repaired, there is nothing left to move or copy.
+    let before = files(&output);
+    let log = repair(&dir, &["--names", "original", "--force"]);
+    assert!(log.contains("Found the copies of 0 tracks by their names and 0 by their contents, of 0 not in place."), "{}", log);
+    assert!(!log.contains("Copied file"), "{}", log);
+    let after = files(&output);
+    for (path, (contents, modified)) in before.iter().filter(|(path, _)| path.as_os_str() != "m3trans.state") {
+        assert!(after[path].0 == *contents && after[path].1 == *modified, "{:?} was rewritten", path);
+    }
+
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,77 @@
+//! Syncing the same library into the same output twice, as most syncs after the first do.
+
+mod common;
+
+use std::{collections::BTreeMap, fs, path::{Path, PathBuf}, time::SystemTime};
+
+use common::{command, files, printed, sync, temp_dir, write_library};
+
+/// A temporary folder with the library written to it, and the output synced into once.
+fn synced_once(name: &str) -> (PathBuf, BTreeMap<PathBuf, (Vec<u8>, SystemTime)>) {
+    let dir = temp_dir(name);
+    write_library(&dir);
+    sync(&dir, &[]);
+    let files = files(&dir.join("output"));
+    (dir, files)
+}
+
+fn is_playlist(path: &Path) -> bool {
+    path.extension().is_some_and(|extension| extension == "m3u8")
+}
+
+#[test]
+fn unchanged_playlists_keep_their_modification_time() {
+    let (dir, first) = synced_once("unchanged");
+    assert_eq!(first.keys().filter(|path| is_playlist(path)).count(), 3, "{:?}", first.keys());
+
+    // Forced, as m3trans otherwise sees that the library is unchanged and does nothing at all.
+    let log = sync(&dir, &["--force"]);
+    assert!(log.contains("Wrote 0 playlist files, leaving 3 unchanged."), "{}", log);
+    let second = files(&dir.join("output"));
+    for (path, (_, modified)) in first.iter().filter(|(path, _)| is_playlist(path)) {
+        assert_eq!(second[path].1, *modified, "{:?} was rewritten", path);
+    }
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn a_second_sync_succeeds_without_errors_and_writes_the_same() {
+    let (dir, first) = synced_once("rerun");
+
+    // Into the folders, nested ones among them, that the first sync created.
+    let log = sync(&dir, &["--force"]);
+    assert!(!log.contains("[ERROR]"), "the second sync logged errors:\n{}", log);
+    let second = files(&dir.join("output"));
+    assert_eq!(first.keys().collect::<Vec<_>>(), second.keys().collect::<Vec<_>>());
+    for (path, (contents, _)) in &first {
+        // The state records when each sync was.
+        if path.as_os_str() == "m3trans.state" { continue; }
+        assert!(second[path].0 == *contents, "{:?} changed", path);
+    }
+    assert!(second.contains_key(Path::new("playlists/Rock/Deep/One.m3u8")));
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn an_unchanged_library_leaves_the_last_log_as_it_was() {
+    let dir = temp_dir("unchanged-log");
+    write_library(&dir);
+    let log_path = dir.join("output/m3trans.log");
+    let run = || {
+        let output = command(&dir).arg("--verbose").output().unwrap();
+        assert!(output.status.success(), "m3trans failed:\n{}", printed(&output));
+        printed(&output)
+    };
+    run();
+    let log = fs::read(&log_path).unwrap();
+    let modified = fs::metadata(&log_path).unwrap().modified().unwrap();
+
+    let printed = run();
+    assert!(printed.contains("nothing to do"), "{}", printed);
+    assert_eq!(fs::read(&log_path).unwrap(), log);
+    assert_eq!(fs::metadata(&log_path).unwrap().modified().unwrap(), modified);
+
+    fs::remove_dir_all(&dir).unwrap();
+}
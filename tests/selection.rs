@@ -0,0 +1,91 @@
+//! Which playlists a plan writes when some are included and some ignored.
+
+use std::collections::HashSet;
+
+use m3trans::{Library, LibraryBuilder, plan::{self, PlaylistStep}};
+
+/// Persistent IDs: Rock 1, Rock/Keep 2, Rock/Drop 3, Pop 4.
+fn library() -> Library {
+    LibraryBuilder::new()
+        .track(1, "Song", "file://localhost/Music/Song.mp3", 1000)
+        .folder("Rock", |rock| {
+            rock.playlist("Keep", [1]).playlist("Drop", [1]);
+        })
+        .playlist("Pop", [1])
+        .build()
+}
+
+/// The name of each playlist written, and the reason each one skipped was.
+fn outcome(options: &plan::Options) -> (Vec<String>, Vec<(u64, &'static str)>) {
+    let library = library();
+    let sync_plan = plan::build_plan(&library, options).unwrap();
+    let mut written = Vec::new();
+    let mut skipped = Vec::new();
+    for step in &sync_plan.playlists {
+        match step {
+            PlaylistStep::Write(planned) => written.push(planned.playlist.name.clone()),
+            PlaylistStep::Skip { playlist_id, reason } => skipped.push((*playlist_id, *reason)),
+            PlaylistStep::CreateFolder(_) => {}
+        }
+    }
+    (written, skipped)
+}
+
+#[test]
+fn ignoring_wins_over_including() {
+    let options = plan::Options {
+        included: Some(HashSet::from([1, 2, 3])),
+        is_ignored: Box::new(|names, _, _| names.last().is_some_and(|name| name == "Drop")),
+        ..plan::Options::default()
+    };
+    let (written, skipped) = outcome(&options);
+    assert_eq!(written, ["Keep"]);
+    assert_eq!(skipped, [(3, "ignored"), (4, "not_included")]);
+}
+
+#[test]
+fn playlists_in_an_ignored_folder_can_be_included_again() {
+    // As with the rules `Rock/` and `!Rock/Keep`.
+    let options = plan::Options {
+        is_ignored: Box::new(|names, _, is_folder| match names {
+            [rock] => rock == "Rock" && is_folder,
+            [rock, name] => rock == "Rock" && name != "Keep",
+            _ => false,
+        }),
+        ..plan::Options::default()
+    };
+    let library = library();
+    let sync_plan = plan::build_plan(&library, &options).unwrap();
+    let keep = sync_plan.playlists.iter()
+        .find_map(|step| match step {
+            PlaylistStep::Write(planned) if planned.playlist.name == "Keep" => Some(planned),
+            _ => None,
+        })
+        .unwrap();
+    // The ignored folder is still created to hold it.
+    let folders: Vec<u64> = keep.folders.iter().map(|folder| folder.playlist_id).collect();
+    assert_eq!(folders, [1]);
+    let (written, skipped) = outcome(&options);
+    assert_eq!(written, ["Keep", "Pop"]);
+    assert_eq!(skipped, [(1, "ignored"), (3, "ignored")]);
+}
+
+#[test]
+fn only_the_tracks_of_included_playlists_are_referenced() {
+    let library = LibraryBuilder::new()
+        .track(1, "One", "file://localhost/Music/One.mp3", 1000)
+        .track(2, "Two", "file://localhost/Music/Two.mp3", 1000)
+        .playlist("First", [1])
+        .playlist("Second", [2])
+        .build();
+    let options = plan::Options {
+        only_referenced: true,
+        included: Some(HashSet::from([2])),
+        ..plan::Options::default()
+    };
+    let sync_plan = plan::build_plan(&library, &options).unwrap();
+    let copied: Vec<u64> = sync_plan.tracks.iter().map(|copy| copy.track_id).collect();
+    assert_eq!(copied, [2]);
+    let skipped: Vec<(u64, &str)> = sync_plan.skipped.iter().map(|skipped| (skipped.track_id, skipped.reason)).collect();
+    assert_eq!(skipped, [(1, "unselected")]);
+}
@@ -0,0 +1,13 @@
+//! `m3trans self-test`, run as users run it, so that every `cargo test` syncs its made-up library
+//! from start to finish.
+
+use std::process::Command;
+
+#[test]
+fn self_test_passes() {
+    let output = Command::new(env!("CARGO_BIN_EXE_m3trans")).arg("self-test").output().unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "the self-test failed:\n{}{}", stdout, String::from_utf8_lossy(&output.stderr));
+    assert!(!stdout.contains("FAIL"), "{}", stdout);
+    assert!(stdout.contains("Passed all"), "{}", stdout);
+}
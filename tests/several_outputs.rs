@@ -0,0 +1,49 @@
+//! Syncing one library into several output folders in one run, each of which may have ignore and
+//! include files of its own.
+
+mod common;
+
+use std::{fs, path::Path, process::Command};
+
+use common::{printed, temp_dir, write_library};
+
+/// Syncs the library in `dir` into its folders `a` and `b`, pruning playlists no longer synced,
+/// returning what was logged.
+fn sync_both(dir: &Path) -> String {
+    let output = Command::new(env!("CARGO_BIN_EXE_m3trans"))
+        .arg(dir.join("Library.xml")).arg(dir.join("a")).arg(dir.join("b"))
+        .arg("--config").arg(dir.join("m3trans.toml"))
+        .args(["--no-log-file", "--verbose", "--prune-playlists", "--yes"])
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "m3trans failed:\n{}", printed(&output));
+    printed(&output)
+}
+
+#[test]
+fn each_output_reads_its_own_ignore_and_include_files() {
+    let dir = temp_dir("several-outputs");
+    write_library(&dir);
+    fs::create_dir_all(dir.join("b")).unwrap();
+    fs::write(dir.join("b/.m3ignore"), "Top\n").unwrap();
+
+    sync_both(&dir);
+    assert!(dir.join("a/playlists/Top.m3u8").exists());
+    assert!(!dir.join("b/playlists/Top.m3u8").exists());
+    for output in ["a", "b"] {
+        assert!(dir.join(output).join("playlists/Rock/Both.m3u8").exists(), "{} is missing Both", output);
+    }
+
+    let log = sync_both(&dir);
+    assert!(log.contains("nothing to do"), "{}", log);
+    // A new include file in one output changes what is synced there, and only there.
+    fs::write(dir.join("a/.m3include"), "Rock/Both\n").unwrap();
+    let log = sync_both(&dir);
+    assert!(!log.contains("nothing to do"), "{}", log);
+    assert!(dir.join("a/playlists/Rock/Both.m3u8").exists());
+    assert!(!dir.join("a/playlists/Top.m3u8").exists());
+    assert!(!dir.join("a/playlists/Rock/Deep/One.m3u8").exists());
+    assert!(dir.join("b/playlists/Rock/Deep/One.m3u8").exists());
+
+    fs::remove_dir_all(&dir).unwrap();
+}
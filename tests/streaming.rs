@@ -0,0 +1,74 @@
+//! Loading a library as it is read, which must give the same library as loading it whole.
+
+use std::{io::Cursor, path::PathBuf};
+
+use m3trans::{Library, LibraryBuilder, PlaylistKind};
+
+fn rewritten(library: &Library) -> String {
+    let mut xml = Vec::new();
+    library.to_writer_xml(&mut xml).unwrap();
+    String::from_utf8(xml).unwrap()
+}
+
+fn assert_same(xml: &[u8]) {
+    let whole = Library::from_reader(Cursor::new(xml)).unwrap();
+    let streamed = Library::from_reader_streaming(Cursor::new(xml)).unwrap();
+    assert_eq!(rewritten(&streamed), rewritten(&whole));
+    assert_eq!(streamed.music_folder, whole.music_folder);
+    assert_eq!(streamed.persistent_id, whole.persistent_id);
+    assert_eq!(streamed.exported_at, whole.exported_at);
+    assert_eq!(streamed.skipped_tracks, whole.skipped_tracks);
+    assert_eq!(streamed.validate(), whole.validate());
+    let order = |library: &Library| {
+        let mut order = Vec::new();
+        library.visit_playlists(|id, depth| order.push((id, depth)));
+        order
+    };
+    assert_eq!(order(&streamed), order(&whole));
+}
+
+#[test]
+fn the_fixture_streams_the_same() {
+    let path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/library.xml");
+    assert_same(&std::fs::read(path).unwrap());
+}
+
+#[test]
+fn a_built_library_streams_the_same() {
+    // A track in two playlists, one of them twice, a playlist of a track that isn't there, and a
+    // folder nested in another.
+    let library = LibraryBuilder::new()
+        .music_folder("file://localhost/Music/")
+        .track(1, "One", "file://localhost/Music/One.mp3", 1000)
+        .track(2, "Two", "file://localhost/Music/Two%20%231.mp3", 2000)
+        .kind_playlist("Library", PlaylistKind::Master, [1, 2])
+        .folder("Rock", |rock| {
+            rock.playlist("90s", [2, 2, 1]).folder("Live", |live| {
+                live.playlist("Gone", [9]);
+            });
+        })
+        .playlist("", [1])
+        .build();
+    assert_same(rewritten(&library).as_bytes());
+}
+
+#[test]
+fn a_library_with_problems_streams_the_same() {
+    // Two playlists with the same persistent ID, one in a folder that isn't there and a track whose
+    // location isn't UTF-8, which are all issues, and a track without a location, which isn't.
+    let xml = r#"<?xml version="1.0" encoding="UTF-8"?>
+<plist version="1.0"><dict>
+<key>Tracks</key><dict>
+<key>1</key><dict><key>Track ID</key><integer>1</integer><key>Name</key><string>One</string><key>Total Time</key><integer>1000</integer><key>Location</key><string>file:///Music/Caf%E9.mp3</string></dict>
+<key>2</key><dict><key>Track ID</key><integer>2</integer><key>Name</key><string>Cloud</string></dict>
+</dict>
+<key>Playlists</key><array>
+<dict><key>Name</key><string>First</string><key>Playlist Persistent ID</key><string>0000000000000001</string><key>Playlist Items</key><array><dict><key>Track ID</key><integer>1</integer></dict></array></dict>
+<dict><key>Name</key><string>Again</string><key>Playlist Persistent ID</key><string>0000000000000001</string></dict>
+<dict><key>Name</key><string>Orphan</string><key>Playlist Persistent ID</key><string>0000000000000002</string><key>Parent Persistent ID</key><string>00000000000000FF</string></dict>
+</array>
+</dict></plist>"#;
+    assert_same(xml.as_bytes());
+    let issues = Library::from_reader(Cursor::new(xml)).unwrap().validate();
+    assert_eq!(issues.len(), 3, "{:?}", issues);
+}
@@ -0,0 +1,103 @@
+//! Walking the playlists of a library and looking up their tracks, which is all the library is
+//! used for when built without its default features.
+
+use std::{ops::ControlFlow, path::Path};
+
+use m3trans::{Library, LibraryBuilder, MissingTrack, PlaylistEvent, PlaylistKind};
+
+/// Persistent IDs: Library 1, Rock 2, Rock/90s 3, Rock/Live 4, Rock/Live/Encores 5, Pop 6.
+fn library() -> Library {
+    LibraryBuilder::new()
+        .track(1, "One", "file://localhost/Music/One.mp3", 1000)
+        .track(2, "Two", "file://localhost/Music/Two.mp3", 1000)
+        .kind_playlist("Library", PlaylistKind::Master, [1, 2])
+        .folder("Rock", |rock| {
+            rock.playlist("90s", [2, 1]).folder("Live", |live| {
+                live.playlist("Encores", [1, 9]);
+            });
+        })
+        .playlist("Pop", [])
+        .build()
+}
+
+#[test]
+fn playlists_are_visited_depth_first_in_display_order() {
+    let library = library();
+    let mut visited = Vec::new();
+    library.visit_playlists(|id, depth| visited.push((id, depth)));
+    assert_eq!(visited, [(1, 0), (2, 0), (3, 1), (4, 1), (5, 2), (6, 0)]);
+
+    let mut paths = Vec::new();
+    library.visit_playlists_with_path(|path| paths.push(path.iter().map(|playlist| playlist.name.as_str()).collect::<Vec<_>>().join("/")));
+    assert_eq!(paths, ["Library", "Rock", "Rock/90s", "Rock/Live", "Rock/Live/Encores", "Pop"]);
+
+    let mut events = Vec::new();
+    library.walk_playlists(|event| events.push(match event {
+        PlaylistEvent::EnterFolder(folder) => format!("+{}", folder.name),
+        PlaylistEvent::Playlist(playlist) => playlist.name.clone(),
+        PlaylistEvent::LeaveFolder(folder) => format!("-{}", folder.name),
+    }));
+    assert_eq!(events, ["Library", "+Rock", "90s", "+Live", "Encores", "-Live", "-Rock", "Pop"]);
+}
+
+#[test]
+fn playlists_are_found_by_their_paths() {
+    let library = library();
+    let encores = library.find_playlist_by_path("Rock/Live/Encores").unwrap();
+    assert_eq!(encores.persistent_id, 5);
+    assert_eq!(library.virtual_path(encores), "Rock/Live/Encores");
+    assert_eq!(library.find_playlists_by_path("rock/live", true).len(), 1);
+    assert!(library.find_playlist_by_path("rock/live").is_none());
+    let roots: Vec<&str> = library.root_playlists().map(|playlist| playlist.name.as_str()).collect();
+    assert_eq!(roots, ["Library", "Rock", "Pop"]);
+    let children: Vec<u64> = library.children(Some(2)).map(|playlist| playlist.persistent_id).collect();
+    assert_eq!(children, [3, 4]);
+    assert!(library.playlists[&2].kind.is_folder());
+    assert_eq!(library.playlists[&1].kind, PlaylistKind::Master);
+}
+
+#[test]
+fn tracks_are_looked_up_in_playlist_order() {
+    let library = library();
+    let tracks: Vec<Result<(u64, &str), MissingTrack>> = library.playlist_tracks(&library.playlists[&5])
+        .map(|resolved| resolved.map(|(id, track)| (id, track.name.as_str())))
+        .collect();
+    assert_eq!(tracks, [Ok((1, "One")), Err(MissingTrack { track_id: 9 })]);
+    assert_eq!(library.tracks[&2].local_path().unwrap().as_deref(), Some(Path::new("/Music/Two.mp3")));
+}
+
+#[test]
+fn fallible_visits_end_at_the_first_failure_or_break() {
+    let library = library();
+    let mut visited = Vec::new();
+    let result = library.try_visit_playlists(|playlist, _| {
+        visited.push(playlist.persistent_id);
+        if playlist.name == "90s" { Err(playlist.name.as_str()) } else { Ok(ControlFlow::Continue(())) }
+    });
+    assert_eq!(result, Err("90s"));
+    assert_eq!(visited, [1, 2, 3]);
+
+    let mut visited = Vec::new();
+    let result = library.try_visit_playlists(|playlist, _| {
+        visited.push(playlist.persistent_id);
+        Ok::<_, ()>(if playlist.kind.is_folder() { ControlFlow::Break(()) } else { ControlFlow::Continue(()) })
+    });
+    assert_eq!(result, Ok(()));
+    assert_eq!(visited, [1, 2]);
+
+    // A walk that fails inside a folder doesn't leave it, or go on to the playlists after it.
+    let mut events = Vec::new();
+    let result = library.try_walk_playlists(|event| {
+        events.push(match event {
+            PlaylistEvent::EnterFolder(folder) => format!("+{}", folder.name),
+            PlaylistEvent::Playlist(playlist) => playlist.name.clone(),
+            PlaylistEvent::LeaveFolder(folder) => format!("-{}", folder.name),
+        });
+        match event {
+            PlaylistEvent::Playlist(playlist) if playlist.name == "Encores" => Err(playlist.persistent_id),
+            _ => Ok(ControlFlow::Continue(())),
+        }
+    });
+    assert_eq!(result, Err(5));
+    assert_eq!(events, ["Library", "+Rock", "90s", "+Live", "Encores"]);
+}